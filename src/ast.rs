@@ -5,12 +5,94 @@
  * Each type of statement is represented by a struct. The top-level AST is just a list of statements.
  */
 
+use crate::parser::Parser;
+use crate::tokens::Token;
+use crate::utils::{
+    is_glob_pattern, is_identifier_char, is_identifier_start, ContentCodec, Encoding, FindFilter,
+    LineEnding, ReplaceOptions, SplitMode,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AST {
     /// A list of statements to be executed in order.
-    pub statements: Vec<Statement>,
+    pub statements: Vec<Located>,
+}
+
+impl AST {
+    /// The script in canonical form: one statement per line, block bodies
+    /// indented by two spaces, and at most one blank line wherever the source
+    /// had blank lines between statements. Comments are not kept.
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        write_statements(&mut out, &self.statements, 0);
+        out
+    }
+}
+
+/// Append `statements` to `out` at the given block depth.
+fn write_statements(out: &mut String, statements: &[Located], depth: usize) {
+    let indent = "  ".repeat(depth);
+    let mut previous_end = None;
+    for located in statements {
+        if previous_end.is_some_and(|end| located.line > end + 1) {
+            out.push('\n');
+        }
+        previous_end = Some(located.end_line);
+        out.push_str(&format!("{}{}\n", indent, located.statement));
+        match &located.statement {
+            Statement::Watch(s) => write_statements(out, &s.body, depth + 1),
+            Statement::While(s) => write_statements(out, &s.body, depth + 1),
+            Statement::If(s) => write_statements(out, &s.body, depth + 1),
+            Statement::Parallel(s) => write_statements(out, &s.body, depth + 1),
+            Statement::Def(s) => write_token_block(out, &s.body, depth + 1),
+            Statement::Try(s) => {
+                write_statements(out, &s.body, depth + 1);
+                out.push_str(&format!("{}onerror\n", indent));
+                write_token_block(out, &s.handler, depth + 1);
+            }
+            _ => continue,
+        }
+        out.push_str(&format!("{}end\n", indent));
+    }
+}
+
+/// Append a block kept as tokens (a function body or error handler).
+fn write_token_block(out: &mut String, tokens: &[Token], depth: usize) {
+    match Parser::new(tokens.to_vec()).parse() {
+        Ok(ast) => write_statements(out, &ast.statements, depth),
+        Err(e) => out.push_str(&format!("{}# {}\n", "  ".repeat(depth), e)),
+    }
+}
+
+/// A string literal as written in source: `"text"`, or `"""text"""` if the text
+/// contains a quote. The lexer drops a newline right after `"""`, so text with
+/// newlines starts on the line after it, heredoc style.
+struct Quoted<'a>(&'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.0.contains('"') {
+            return write!(f, "\"{}\"", self.0);
+        }
+        let newline = if self.0.contains('\n') { "\n" } else { "" };
+        write!(f, "\"\"\"{}{}\"\"\"", newline, self.0)
+    }
+}
+
+/// A statement together with the source lines it starts and ends on.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Located {
+    pub line: usize,
+    /// The last line of the statement (the `end` of a block).
+    #[serde(default)]
+    pub end_line: usize,
+    pub statement: Statement,
 }
 
 /// A statement in the language.
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Statement {
     Open(OpenStmt),
     Read(ReadStmt),
@@ -29,98 +111,1661 @@ pub enum Statement {
     ListDir(ListDirStmt),
     DumpEnv(DumpEnvStmt),
     Help(HelpStmt),
+    Mkdir(MkdirStmt),
+    Rmdir(RmdirStmt),
+    Head(HeadStmt),
+    Tail(TailStmt),
+    InsertLine(InsertLineStmt),
+    DeleteLine(DeleteLineStmt),
+    Exists(ExistsStmt),
+    Stat(StatStmt),
+    Touch(TouchStmt),
+    Save(SaveStmt),
+    Revert(RevertStmt),
+    Set(SetStmt),
+    CloseAll(CloseAllStmt),
+    Unset(UnsetStmt),
+    HexDump(HexDumpStmt),
+    Size(SizeStmt),
+    SetLineEnd(SetLineEndStmt),
+    Extract(ExtractStmt),
+    SortLines(SortLinesStmt),
+    UniqLines(UniqLinesStmt),
+    ReverseLines(ReverseLinesStmt),
+    NumberLines(NumberLinesStmt),
+    GetLine(GetLineStmt),
+    Diff(DiffStmt),
+    Hash(HashStmt),
+    CheckHash(CheckHashStmt),
+    Encode(EncodeStmt),
+    ZipCreate(ZipCreateStmt),
+    Concat(ConcatStmt),
+    Split(SplitStmt),
+    Grep(GrepStmt),
+    Find(FindStmt),
+    Du(DuStmt),
+    Chmod(ChmodStmt),
+    Watch(WatchStmt),
+    TailFollow(TailFollowStmt),
+    While(WhileStmt),
+    Echo(EchoStmt),
+    Sleep(SleepStmt),
+    Include(IncludeStmt),
+    Def(DefStmt),
+    Call(CallStmt),
+    Try(TryStmt),
+    Symlink(SymlinkStmt),
+    HardLink(HardLinkStmt),
+    ReadLink(ReadLinkStmt),
+    ZipExtract(ZipExtractStmt),
+    Decode(DecodeStmt),
+    TrimLines(TrimLinesStmt),
+    Cd(CdStmt),
+    Pwd(PwdStmt),
+    RestoreBackup(RestoreBackupStmt),
+    TempFile(TempFileStmt),
+    Buffer(BufferStmt),
+    SaveAs(SaveAsStmt),
+    CopyTo(CopyToStmt),
+    AppendFrom(AppendFromStmt),
+    ReadRange(ReadRangeStmt),
+    GetEnv(GetEnvStmt),
+    Pipe(PipeStmt),
+    Let(LetStmt),
+    If(IfStmt),
+    Assert(AssertStmt),
+    Prompt(PromptStmt),
+    Undo(UndoStmt),
+    History(HistoryStmt),
+    SaveSession(SaveSessionStmt),
+    LoadSession(LoadSessionStmt),
+    Breakpoint(BreakpointStmt),
+    Parallel(ParallelStmt),
     Exit(ExitStmt),
 }
 
-/// The `open` statement node.
+/// The `open` statement node:
+/// open "file"|stdin|stdout as var [readonly|writable|appendonly] [nocreate] [buffered]
+///   [encoding "name"] [locked|shared] [nomem]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OpenStmt {
+    /// The file to open (empty for standard input or output).
     pub filename: String,
     pub var_name: String,
+    pub flags: OpenFlags,
+    #[serde(default)]
+    pub target: FileTarget,
+}
+
+/// What a file variable reads from and writes to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileTarget {
+    /// A file on disk (or in the interpreter's filesystem).
+    #[default]
+    Disk,
+    /// Standard input, pulled in by `read`.
+    Stdin,
+    /// Standard output: `write` and `append` print their text.
+    Stdout,
+    /// Nothing: the content only lives in memory (`buffer as var`).
+    Buffer,
+}
+
+impl FileTarget {
+    /// The name scripts use for it, e.g. "stdin" (empty for a file on disk).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileTarget::Disk => "",
+            FileTarget::Stdin => "stdin",
+            FileTarget::Stdout => "stdout",
+            FileTarget::Buffer => "buffer",
+        }
+    }
+}
+
+/// Modifiers given after `open "file" as var`.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct OpenFlags {
+    pub mode: OpenMode,
+    /// Fail if the file doesn't already exist.
+    pub no_create: bool,
+    /// Keep modifications in memory until `save`.
+    pub buffered: bool,
+    /// Text encoding of the file. `None` detects a UTF-16 byte order mark and
+    /// otherwise assumes UTF-8.
+    pub encoding: Option<Encoding>,
+    /// An advisory lock held on the file until it is closed.
+    #[serde(default)]
+    pub lock: Option<LockKind>,
+    /// Write straight to disk without keeping the content in memory.
+    #[serde(default)]
+    pub nomem: bool,
+}
+
+/// The kind of lock `open ... locked` or `open ... shared` takes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockKind {
+    /// No one else may lock the file (`locked`).
+    Exclusive,
+    /// Others may take shared locks too, but not an exclusive one (`shared`).
+    Shared,
+}
+
+/// What a file variable may be used for, chosen when it is opened.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpenMode {
+    /// Only reading; every modification is rejected.
+    ReadOnly,
+    /// Any operation (the default).
+    #[default]
+    Writable,
+    /// Reading and adding to the end; overwriting existing content is rejected.
+    AppendOnly,
+}
+
+impl OpenMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OpenMode::ReadOnly => "readonly",
+            OpenMode::Writable => "writable",
+            OpenMode::AppendOnly => "appendonly",
+        }
+    }
 }
 
 /// The `read` statement node.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ReadStmt {
     pub var_name: String,
 }
 
-/// The `write` statement node.
+/// The `write` statement node: write var "text" [overwrite]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct WriteStmt {
     pub var_name: String,
     pub text: String,
+    /// Write even if the file changed on disk (`set noclobber on`).
+    #[serde(default)]
+    pub overwrite: bool,
 }
 
 /// The `append` statement node.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AppendStmt {
     pub var_name: String,
     pub text: String,
 }
 
 /// The `show` statement node.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ShowStmt {
     pub var_name: String,
 }
 
-/// The `close` statement node.
+/// The `close` statement node: close var [discard] [keep]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CloseStmt {
     pub var_name: String,
+    /// Drop unsaved changes instead of refusing to close.
+    pub discard: bool,
+    /// Keep a file made by `tempfile` instead of deleting it when the script ends.
+    #[serde(default)]
+    pub keep: bool,
 }
 
 /// The `truncate` statement node.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TruncateStmt {
     pub var_name: String,
 }
 
-/// The `search` statement node: search var "pattern"
+/// The `search` statement node:
+/// search var "pattern" [ignorecase] [count] [context "N"]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SearchStmt {
     pub var_name: String,
     pub pattern: String,
+    pub options: SearchOptions,
+}
+
+/// Options given after `search var "pattern"`, in any order.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct SearchOptions {
+    pub ignore_case: bool,
+    /// Print only the number of matching lines.
+    pub count_only: bool,
+    /// Lines to show before and after each match.
+    pub context: usize,
+    /// Read the file from disk line by line instead of the in-memory content.
+    #[serde(default)]
+    pub streaming: bool,
+}
+
+/// The `extract` statement node: extract var "pattern"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExtractStmt {
+    pub var_name: String,
+    pub pattern: String,
+}
+
+/// The `grep` statement node:
+/// grep "pattern" in "path" [ignorecase] [include "glob"] [verbose]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GrepStmt {
+    pub pattern: String,
+    pub path: String,
+    pub ignore_case: bool,
+    /// Only search files whose name matches this glob.
+    pub include: Option<String>,
+    /// Report files skipped because they are binary.
+    pub verbose: bool,
+}
+
+/// The `find` statement node:
+/// find "path" [name "glob"] [larger "bytes"] [olderthan "7d"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FindStmt {
+    pub root: String,
+    /// Every filter must match; no filters lists everything.
+    pub filters: Vec<FindFilter>,
 }
 
-/// The `replace` statement node: replace var "pattern" "replacement"
+/// The `replace` statement node:
+/// replace var "pattern" "replacement" [first] [lines "N" "M"] [literal]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ReplaceStmt {
     pub var_name: String,
     pub pattern: String,
     pub replacement: String,
+    pub options: ReplaceOptions,
 }
 
 /// The `linecount` statement node.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct LineCountStmt {
     pub var_name: String,
+    /// Count the lines on disk without loading the file.
+    #[serde(default)]
+    pub streaming: bool,
 }
 
-/// The `copy` statement node: copy "source" "destination"
+/// The `copy` statement node: copy "source" "destination" [recursive] [overwrite]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CopyStmt {
     pub source: String,
     pub destination: String,
+    pub recursive: bool,
+    /// Replace an existing destination (`set noclobber on`).
+    #[serde(default)]
+    pub overwrite: bool,
 }
 
-/// The `move` statement node: move "source" "destination"
+/// The `move` statement node: move "source" "destination" [overwrite]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct MoveStmt {
     pub source: String,
     pub destination: String,
+    /// Replace an existing destination (`set noclobber on`).
+    #[serde(default)]
+    pub overwrite: bool,
 }
 
-/// The `remove` statement node: remove "filename"
+/// The `remove` statement node: remove "filename" [recursive] [ifany]
+/// The filename may be a glob pattern.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RemoveStmt {
     pub filename: String,
+    pub recursive: bool,
+    /// Don't fail when a glob pattern matches nothing.
+    pub if_any: bool,
 }
 
 /// The `rename` statement node: rename var "newfilename"
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RenameStmt {
     pub var_name: String,
     pub new_filename: String,
 }
 
-/// The `listdir` statement node.
+/// The `listdir` statement node: listdir "path" [recursive] [long]
+/// The path may be a glob pattern.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ListDirStmt {
     pub path: String,
+    pub recursive: bool,
+    /// Show size and modification time for each entry.
+    pub long: bool,
+}
+
+/// The `du` statement node: du "path" [depth "1"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DuStmt {
+    pub path: String,
+    /// Show a breakdown of the entries directly inside the path.
+    pub per_child: bool,
+}
+
+/// The `symlink` statement node: symlink "target" "linkpath" [overwrite]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SymlinkStmt {
+    pub target: String,
+    pub link_path: String,
+    /// Replace an existing link (never a regular file) at `link_path`.
+    pub overwrite: bool,
+}
+
+/// The `hardlink` statement node: hardlink "target" "linkpath" [overwrite]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HardLinkStmt {
+    pub target: String,
+    pub link_path: String,
+    /// Replace an existing symlink (never a regular file) at `link_path`.
+    pub overwrite: bool,
+}
+
+/// The `readlink` statement node: readlink "linkpath"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReadLinkStmt {
+    pub link_path: String,
+}
+
+/// The `mkdir` statement node: mkdir "path"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MkdirStmt {
+    pub path: String,
+}
+
+/// The `rmdir` statement node: rmdir "path"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RmdirStmt {
+    pub path: String,
+}
+
+/// The `head` statement node: head var "N"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HeadStmt {
+    pub var_name: String,
+    pub count: usize,
+}
+
+/// The `tail` statement node: tail var "N"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TailStmt {
+    pub var_name: String,
+    pub count: usize,
+}
+
+/// The `getline` statement node: getline var "N" ["M"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GetLineStmt {
+    pub var_name: String,
+    pub from: usize,
+    /// Last line to print (inclusive). Equal to `from` for the single-line form.
+    pub to: usize,
+}
+
+/// The `insertline` statement node: insertline var "N" "text"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InsertLineStmt {
+    pub var_name: String,
+    pub line: usize,
+    pub text: String,
+}
+
+/// The `deleteline` statement node: deleteline var "N" ["M"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeleteLineStmt {
+    pub var_name: String,
+    pub from: usize,
+    /// Last line to delete (inclusive). Equal to `from` for the single-line form.
+    pub to: usize,
+}
+
+/// The `exists` statement node: exists "path"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExistsStmt {
+    pub path: String,
+}
+
+/// Something a statement can refer to either by variable or by path.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum FileRef {
+    /// An open file variable, e.g. `stat f`.
+    Var(String),
+    /// A literal path, e.g. `stat "notes.txt"`.
+    Path(String),
+}
+
+impl FileRef {
+    /// The literal path, if this refers to one rather than to a variable.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            FileRef::Path(path) => Some(path),
+            FileRef::Var(_) => None,
+        }
+    }
+
+    /// The literal path, for rewriting in place.
+    pub fn path_mut(&mut self) -> Option<&mut String> {
+        match self {
+            FileRef::Path(path) => Some(path),
+            FileRef::Var(_) => None,
+        }
+    }
+}
+
+/// The `stat` statement node: stat var | stat "path"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StatStmt {
+    pub target: FileRef,
+}
+
+/// The `diff` statement node: diff "a" "b" | diff var1 var2
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DiffStmt {
+    pub left: FileRef,
+    pub right: FileRef,
+}
+
+/// The `hash` statement node: hash "path" "algorithm" | hash var "algorithm"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HashStmt {
+    pub target: FileRef,
+    pub algorithm: String,
+}
+
+/// The `checkhash` statement node: checkhash "path" "algorithm" "digest"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CheckHashStmt {
+    pub target: FileRef,
+    pub algorithm: String,
+    pub expected: String,
+}
+
+/// The `concat` statement node: concat "out" from "a" "b" ...
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ConcatStmt {
+    pub destination: String,
+    pub sources: Vec<String>,
+}
+
+/// The `split` statement node: split var lines|bytes "N" into "prefix"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SplitStmt {
+    pub var_name: String,
+    pub mode: SplitMode,
+    /// Maximum lines or bytes per chunk (never zero).
+    pub size: usize,
+    /// Chunk files are named prefix000, prefix001, ...
+    pub prefix: String,
+}
+
+/// The `zipcreate` statement node: zipcreate "archive.zip" "dir_or_file"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ZipCreateStmt {
+    pub archive: String,
+    pub source: String,
+}
+
+/// The `zipextract` statement node: zipextract "archive.zip" "destdir"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ZipExtractStmt {
+    pub archive: String,
+    pub destination: String,
+}
+
+/// The `chmod` statement node: chmod "path" "644" | chmod "path" "u+x"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChmodStmt {
+    pub path: String,
+    /// Octal or symbolic mode, resolved against the current mode when run.
+    pub mode: String,
+}
+
+/// The `touch` statement node: touch "filename"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TouchStmt {
+    pub filename: String,
+}
+
+/// The `save` statement node: save var
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveStmt {
+    pub var_name: String,
+}
+
+/// The `revert` statement node: revert var
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RevertStmt {
+    pub var_name: String,
+}
+
+/// The `set` statement node: set option value
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SetStmt {
+    pub option: String,
+    /// The value as written, e.g. "on", "off", or the content of a string literal.
+    pub value: String,
+}
+
+/// The `closeall` statement node.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CloseAllStmt;
+
+/// The `unset` statement node: unset var [force]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UnsetStmt {
+    pub var_name: String,
+    /// Drop the variable even if it has unsaved changes.
+    pub force: bool,
+}
+
+/// The `hexdump` statement node: hexdump var
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HexDumpStmt {
+    pub var_name: String,
+}
+
+/// The `size` statement node: size var
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SizeStmt {
+    pub var_name: String,
+}
+
+/// The `setlineend` statement node: setlineend var "lf"|"crlf"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SetLineEndStmt {
+    pub var_name: String,
+    pub line_ending: LineEnding,
 }
 
-/// The `dumpenv` statement node.
-pub struct DumpEnvStmt;
+/// The `sortlines` statement node: sortlines var [numeric]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SortLinesStmt {
+    pub var_name: String,
+    /// Sort by the integer each line starts with.
+    pub numeric: bool,
+}
 
-/// The `help` statement node.
-pub struct HelpStmt;
+/// The `uniqlines` statement node: uniqlines var [all]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UniqLinesStmt {
+    pub var_name: String,
+    /// Remove every repeated line, not just adjacent ones.
+    pub all: bool,
+}
 
-/// The `exit` statement node.
-pub struct ExitStmt {}
+/// The `reverselines` statement node: reverselines var
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReverseLinesStmt {
+    pub var_name: String,
+}
+
+/// The `numberlines` statement node: numberlines var
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NumberLinesStmt {
+    pub var_name: String,
+}
+
+/// The `trimlines` statement node: trimlines var [leading]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TrimLinesStmt {
+    pub var_name: String,
+    /// Strip leading whitespace as well as trailing.
+    pub leading: bool,
+}
+
+/// The `encode` statement node: encode var "base64"|"hex"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncodeStmt {
+    pub var_name: String,
+    pub codec: ContentCodec,
+}
+
+/// The `decode` statement node: decode var "base64"|"hex"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DecodeStmt {
+    pub var_name: String,
+    pub codec: ContentCodec,
+}
+
+/// The `watch` statement node:
+/// watch "path" [every "ms"] [maxruns "N"] do ... end
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WatchStmt {
+    pub path: String,
+    /// How often to poll the file, in milliseconds.
+    pub interval_ms: u64,
+    /// Stop after the block has run this many times.
+    pub max_runs: Option<usize>,
+    /// Statements run each time the file changes.
+    pub body: Vec<Located>,
+}
+
+/// The `tailfollow` statement node:
+/// tailfollow "path" [every "ms"] [maxseconds "N"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TailFollowStmt {
+    pub path: String,
+    /// How often to poll the file, in milliseconds.
+    pub interval_ms: u64,
+    /// Stop following after this many seconds.
+    pub max_seconds: Option<u64>,
+}
+
+/// A condition tested by `while` and `if`.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Condition {
+    /// contains var "pattern": the variable's content matches the pattern.
+    Contains { var_name: String, pattern: String },
+    /// exists "path": something exists at the path.
+    Exists { path: String },
+    /// not condition
+    Not(Box<Condition>),
+    /// left op right, e.g. linecount f > 100
+    Compare {
+        left: Operand,
+        op: CompareOp,
+        right: Operand,
+    },
+}
+
+/// One side of a comparison.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Operand {
+    /// A string, with `${name}` filled in each time the condition is tested.
+    Text(String),
+    Number(i64),
+    /// linecount var: the number of lines in the variable's content.
+    LineCount(String),
+    /// bytecount var: the size of the variable's content in bytes.
+    ByteCount(String),
+}
+
+/// A comparison operator. Values compare as integers when both sides are
+/// integers, and as strings otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        }
+    }
+
+    /// Whether the comparison holds for values that order as `ordering`.
+    pub fn holds(&self, ordering: std::cmp::Ordering) -> bool {
+        match self {
+            CompareOp::Eq => ordering.is_eq(),
+            CompareOp::Ne => ordering.is_ne(),
+            CompareOp::Lt => ordering.is_lt(),
+            CompareOp::Le => ordering.is_le(),
+            CompareOp::Gt => ordering.is_gt(),
+            CompareOp::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+/// The `prompt` statement node: prompt "text" as var [default "text"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PromptStmt {
+    /// What to print before reading the answer.
+    pub text: String,
+    /// The string variable to bind the answer to.
+    pub var_name: String,
+    /// The answer to use when the line read is empty or input has ended.
+    pub default: Option<String>,
+}
+
+/// The `assert` statement node: assert condition
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AssertStmt {
+    pub condition: Condition,
+    /// The source line of the `assert`, for the failure message.
+    pub line: usize,
+}
+
+/// The `if` statement node: if condition do ... end
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IfStmt {
+    pub condition: Condition,
+    pub body: Vec<Located>,
+}
+
+/// The `while` statement node: while condition do ... end
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WhileStmt {
+    pub condition: Condition,
+    pub body: Vec<Located>,
+    /// The source line of the `while`, for error messages.
+    pub line: usize,
+}
+
+/// The `echo` statement node: echo "text"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EchoStmt {
+    pub text: String,
+}
+
+/// The `sleep` statement node: sleep "ms"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SleepStmt {
+    pub millis: u64,
+}
+
+/// The `include` statement node: include "script.fl"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IncludeStmt {
+    /// Relative paths are resolved against the including script's directory.
+    pub path: String,
+}
+
+/// The `def` statement node: def name(param, ...) ... end
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DefStmt {
+    pub name: String,
+    pub params: Vec<String>,
+    /// The body's tokens. They are re-parsed on every call, after `${param}` in
+    /// string literals has been replaced by the argument.
+    pub body: Vec<Token>,
+}
+
+/// The `call` statement node: call name("arg", ...)
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CallStmt {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// The `try` statement node: try ... onerror ... end
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TryStmt {
+    pub body: Vec<Located>,
+    /// The handler's tokens, re-parsed after `${error}` in its string literals
+    /// has been replaced by the error message.
+    pub handler: Vec<Token>,
+}
+
+/// The `dumpenv` statement node: dumpenv [var]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DumpEnvStmt {
+    /// The variable to describe in detail (every variable, briefly, when None).
+    pub var_name: Option<String>,
+}
+
+/// The `savesession` statement node: savesession "path" [full]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveSessionStmt {
+    pub path: String,
+    /// Keep the content of each variable in the session file too.
+    pub full: bool,
+}
+
+/// The `loadsession` statement node: loadsession "path" [merge]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LoadSessionStmt {
+    pub path: String,
+    /// Load alongside the variables already defined.
+    pub merge: bool,
+}
+
+/// The `help` statement node: help ["command"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HelpStmt {
+    /// The command to show help for (every command when None).
+    pub topic: Option<String>,
+}
+
+/// The `cd` statement node: cd "path"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CdStmt {
+    pub path: String,
+}
+
+/// The `pwd` statement node.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PwdStmt;
+
+/// The `undo` statement node: undo ["count"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UndoStmt {
+    /// How many journaled operations to reverse, newest first.
+    pub count: usize,
+}
+
+/// The `history` statement node.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryStmt;
+
+/// The `breakpoint` statement node.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BreakpointStmt;
+
+/// The `parallel` statement node: parallel ... end
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ParallelStmt {
+    /// Statements run at the same time, each one `runs_in_parallel` allows.
+    pub body: Vec<Located>,
+}
+
+/// The `restorebackup` statement node: restorebackup var
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RestoreBackupStmt {
+    pub var_name: String,
+}
+
+/// The `tempfile` statement node: tempfile as var
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TempFileStmt {
+    pub var_name: String,
+}
+
+/// The `buffer` statement node: buffer as var
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BufferStmt {
+    pub var_name: String,
+}
+
+/// The `saveas` statement node: saveas var "path"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveAsStmt {
+    pub var_name: String,
+    pub path: String,
+}
+
+/// The `copyto` statement node: copyto source destination
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CopyToStmt {
+    pub source: String,
+    pub destination: String,
+}
+
+/// The `appendfrom` statement node: appendfrom destination source
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AppendFromStmt {
+    pub destination: String,
+    pub source: String,
+}
+
+/// The `readrange` statement node: readrange var "offset" "length"
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReadRangeStmt {
+    pub var_name: String,
+    /// Byte offset in the file to start reading at.
+    pub offset: usize,
+    /// Number of bytes to read (fewer if the file ends first).
+    pub length: usize,
+}
+
+/// The `getenv` statement node: getenv "NAME" as var [default "text"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GetEnvStmt {
+    /// The process environment variable to read.
+    pub name: String,
+    /// The string variable to bind its value to.
+    pub var_name: String,
+    /// The value to use when the environment variable is unset.
+    pub default: Option<String>,
+}
+
+/// A pipeline: producer | sink, e.g. search f "ERROR" | append log
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PipeStmt {
+    /// The statement whose results are piped (search, listdir, getline, head,
+    /// tail, or extract).
+    pub producer: Box<Statement>,
+    pub sink: PipeSink,
+}
+
+/// Where a pipeline sends its producer's output lines.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum PipeSink {
+    /// Append them to a variable: `| append var`.
+    Append(String),
+    /// Replace a variable's content with them: `| write var`.
+    Write(String),
+    /// Print them: `| show`.
+    Show,
+}
+
+/// The `let` statement node: let var = statement, e.g. let n = linecount f
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LetStmt {
+    /// The string variable to bind the result to.
+    pub var_name: String,
+    /// A statement that produces a result (see `Statement::produces_result`).
+    pub value: Box<Statement>,
+}
+
+/// The `exit` statement node: exit ["code"]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExitStmt {
+    /// The process exit status (0 when not given).
+    pub code: i32,
+}
+
+impl Statement {
+    /// The keyword the statement starts with, e.g. "open".
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Statement::Open(_) => "open",
+            Statement::Read(_) => "read",
+            Statement::Write(_) => "write",
+            Statement::Append(_) => "append",
+            Statement::Show(_) => "show",
+            Statement::Close(_) => "close",
+            Statement::Truncate(_) => "truncate",
+            Statement::Search(_) => "search",
+            Statement::Replace(_) => "replace",
+            Statement::LineCount(_) => "linecount",
+            Statement::Copy(_) => "copy",
+            Statement::Move(_) => "move",
+            Statement::Remove(_) => "remove",
+            Statement::Rename(_) => "rename",
+            Statement::ListDir(_) => "listdir",
+            Statement::DumpEnv(_) => "dumpenv",
+            Statement::Help(_) => "help",
+            Statement::Mkdir(_) => "mkdir",
+            Statement::Rmdir(_) => "rmdir",
+            Statement::Head(_) => "head",
+            Statement::Tail(_) => "tail",
+            Statement::InsertLine(_) => "insertline",
+            Statement::DeleteLine(_) => "deleteline",
+            Statement::Exists(_) => "exists",
+            Statement::Stat(_) => "stat",
+            Statement::Touch(_) => "touch",
+            Statement::Save(_) => "save",
+            Statement::Revert(_) => "revert",
+            Statement::Set(_) => "set",
+            Statement::CloseAll(_) => "closeall",
+            Statement::Unset(_) => "unset",
+            Statement::HexDump(_) => "hexdump",
+            Statement::Size(_) => "size",
+            Statement::SetLineEnd(_) => "setlineend",
+            Statement::Extract(_) => "extract",
+            Statement::SortLines(_) => "sortlines",
+            Statement::UniqLines(_) => "uniqlines",
+            Statement::ReverseLines(_) => "reverselines",
+            Statement::NumberLines(_) => "numberlines",
+            Statement::GetLine(_) => "getline",
+            Statement::Diff(_) => "diff",
+            Statement::Hash(_) => "hash",
+            Statement::CheckHash(_) => "checkhash",
+            Statement::Encode(_) => "encode",
+            Statement::ZipCreate(_) => "zipcreate",
+            Statement::Concat(_) => "concat",
+            Statement::Split(_) => "split",
+            Statement::Grep(_) => "grep",
+            Statement::Find(_) => "find",
+            Statement::Du(_) => "du",
+            Statement::Chmod(_) => "chmod",
+            Statement::Watch(_) => "watch",
+            Statement::TailFollow(_) => "tailfollow",
+            Statement::While(_) => "while",
+            Statement::Echo(_) => "echo",
+            Statement::Sleep(_) => "sleep",
+            Statement::Include(_) => "include",
+            Statement::Def(_) => "def",
+            Statement::Call(_) => "call",
+            Statement::Try(_) => "try",
+            Statement::Symlink(_) => "symlink",
+            Statement::HardLink(_) => "hardlink",
+            Statement::ReadLink(_) => "readlink",
+            Statement::ZipExtract(_) => "zipextract",
+            Statement::Decode(_) => "decode",
+            Statement::TrimLines(_) => "trimlines",
+            Statement::Cd(_) => "cd",
+            Statement::Pwd(_) => "pwd",
+            Statement::RestoreBackup(_) => "restorebackup",
+            Statement::TempFile(_) => "tempfile",
+            Statement::Buffer(_) => "buffer",
+            Statement::SaveAs(_) => "saveas",
+            Statement::CopyTo(_) => "copyto",
+            Statement::AppendFrom(_) => "appendfrom",
+            Statement::ReadRange(_) => "readrange",
+            Statement::GetEnv(_) => "getenv",
+            Statement::Pipe(s) => s.producer.keyword(),
+            Statement::Let(_) => "let",
+            Statement::If(_) => "if",
+            Statement::Assert(_) => "assert",
+            Statement::Prompt(_) => "prompt",
+            Statement::Undo(_) => "undo",
+            Statement::History(_) => "history",
+            Statement::SaveSession(_) => "savesession",
+            Statement::LoadSession(_) => "loadsession",
+            Statement::Breakpoint(_) => "breakpoint",
+            Statement::Parallel(_) => "parallel",
+            Statement::Exit(_) => "exit",
+        }
+    }
+}
+
+impl Statement {
+    /// The filesystem paths the statement names directly (not patterns or text).
+    /// A symlink's target is left out: it is relative to the link, not the
+    /// working directory.
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            Statement::Open(s) if s.target != FileTarget::Disk => Vec::new(),
+            Statement::Open(s) => vec![&s.filename],
+            Statement::Copy(s) => vec![&s.source, &s.destination],
+            Statement::Move(s) => vec![&s.source, &s.destination],
+            Statement::Remove(s) => vec![&s.filename],
+            Statement::Rename(s) => vec![&s.new_filename],
+            Statement::ListDir(s) => vec![&s.path],
+            Statement::Mkdir(s) => vec![&s.path],
+            Statement::Rmdir(s) => vec![&s.path],
+            Statement::Exists(s) => vec![&s.path],
+            Statement::Stat(s) => s.target.path().into_iter().collect(),
+            Statement::Touch(s) => vec![&s.filename],
+            Statement::Diff(s) => [s.left.path(), s.right.path()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            Statement::Hash(s) => s.target.path().into_iter().collect(),
+            Statement::CheckHash(s) => s.target.path().into_iter().collect(),
+            Statement::ZipCreate(s) => vec![&s.archive, &s.source],
+            Statement::ZipExtract(s) => vec![&s.archive, &s.destination],
+            Statement::Concat(s) => std::iter::once(&s.destination)
+                .chain(&s.sources)
+                .map(String::as_str)
+                .collect(),
+            Statement::Split(s) => vec![&s.prefix],
+            Statement::Grep(s) => vec![&s.path],
+            Statement::Find(s) => vec![&s.root],
+            Statement::Du(s) => vec![&s.path],
+            Statement::Symlink(s) => vec![&s.link_path],
+            Statement::HardLink(s) => vec![&s.target, &s.link_path],
+            Statement::ReadLink(s) => vec![&s.link_path],
+            Statement::Chmod(s) => vec![&s.path],
+            Statement::Watch(s) => vec![&s.path],
+            Statement::TailFollow(s) => vec![&s.path],
+            Statement::Cd(s) => vec![&s.path],
+            Statement::SaveAs(s) => vec![&s.path],
+            Statement::SaveSession(s) => vec![&s.path],
+            Statement::LoadSession(s) => vec![&s.path],
+            Statement::Pipe(s) => s.producer.paths(),
+            Statement::Let(s) => s.value.paths(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The same paths as `paths`, for rewriting in place.
+    pub fn paths_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Statement::Open(s) if s.target != FileTarget::Disk => Vec::new(),
+            Statement::Open(s) => vec![&mut s.filename],
+            Statement::Copy(s) => vec![&mut s.source, &mut s.destination],
+            Statement::Move(s) => vec![&mut s.source, &mut s.destination],
+            Statement::Remove(s) => vec![&mut s.filename],
+            Statement::Rename(s) => vec![&mut s.new_filename],
+            Statement::ListDir(s) => vec![&mut s.path],
+            Statement::Mkdir(s) => vec![&mut s.path],
+            Statement::Rmdir(s) => vec![&mut s.path],
+            Statement::Exists(s) => vec![&mut s.path],
+            Statement::Stat(s) => s.target.path_mut().into_iter().collect(),
+            Statement::Touch(s) => vec![&mut s.filename],
+            Statement::Diff(s) => [s.left.path_mut(), s.right.path_mut()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            Statement::Hash(s) => s.target.path_mut().into_iter().collect(),
+            Statement::CheckHash(s) => s.target.path_mut().into_iter().collect(),
+            Statement::ZipCreate(s) => vec![&mut s.archive, &mut s.source],
+            Statement::ZipExtract(s) => vec![&mut s.archive, &mut s.destination],
+            Statement::Concat(s) => std::iter::once(&mut s.destination)
+                .chain(&mut s.sources)
+                .collect(),
+            Statement::Split(s) => vec![&mut s.prefix],
+            Statement::Grep(s) => vec![&mut s.path],
+            Statement::Find(s) => vec![&mut s.root],
+            Statement::Du(s) => vec![&mut s.path],
+            Statement::Symlink(s) => vec![&mut s.link_path],
+            Statement::HardLink(s) => vec![&mut s.target, &mut s.link_path],
+            Statement::ReadLink(s) => vec![&mut s.link_path],
+            Statement::Chmod(s) => vec![&mut s.path],
+            Statement::Watch(s) => vec![&mut s.path],
+            Statement::TailFollow(s) => vec![&mut s.path],
+            Statement::Cd(s) => vec![&mut s.path],
+            Statement::SaveAs(s) => vec![&mut s.path],
+            Statement::SaveSession(s) => vec![&mut s.path],
+            Statement::LoadSession(s) => vec![&mut s.path],
+            Statement::Pipe(s) => s.producer.paths_mut(),
+            Statement::Let(s) => s.value.paths_mut(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Every string the statement takes from the script that `${name}` may
+    /// appear in: its paths, plus text, patterns, and arguments.
+    pub fn strings_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Statement::Write(s) => vec![&mut s.text],
+            Statement::Append(s) => vec![&mut s.text],
+            Statement::InsertLine(s) => vec![&mut s.text],
+            Statement::Echo(s) => vec![&mut s.text],
+            Statement::Search(s) => vec![&mut s.pattern],
+            Statement::Extract(s) => vec![&mut s.pattern],
+            Statement::Replace(s) => vec![&mut s.pattern, &mut s.replacement],
+            Statement::Grep(s) => [Some(&mut s.pattern), Some(&mut s.path), s.include.as_mut()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            Statement::Symlink(s) => vec![&mut s.target, &mut s.link_path],
+            Statement::Hash(s) => s
+                .target
+                .path_mut()
+                .into_iter()
+                .chain(std::iter::once(&mut s.algorithm))
+                .collect(),
+            Statement::CheckHash(s) => s
+                .target
+                .path_mut()
+                .into_iter()
+                .chain([&mut s.algorithm, &mut s.expected])
+                .collect(),
+            Statement::Chmod(s) => vec![&mut s.path, &mut s.mode],
+            Statement::Find(s) => std::iter::once(&mut s.root)
+                .chain(s.filters.iter_mut().filter_map(|filter| match filter {
+                    FindFilter::Name(pattern) => Some(pattern),
+                    _ => None,
+                }))
+                .collect(),
+            Statement::Set(s) => vec![&mut s.value],
+            Statement::Include(s) => vec![&mut s.path],
+            Statement::Call(s) => s.args.iter_mut().collect(),
+            Statement::GetEnv(s) => s.default.iter_mut().collect(),
+            Statement::Prompt(s) => std::iter::once(&mut s.text)
+                .chain(s.default.as_mut())
+                .collect(),
+            Statement::Pipe(s) => s.producer.strings_mut(),
+            Statement::Let(s) => s.value.strings_mut(),
+            _ => self.paths_mut(),
+        }
+    }
+
+    /// Whether the statement produces results that can be piped with `|`.
+    pub fn can_pipe(&self) -> bool {
+        matches!(
+            self,
+            Statement::Search(_)
+                | Statement::ListDir(_)
+                | Statement::GetLine(_)
+                | Statement::Head(_)
+                | Statement::Tail(_)
+                | Statement::Extract(_)
+        )
+    }
+
+    /// Whether the statement returns a result `let` can bind to a variable.
+    pub fn produces_result(&self) -> bool {
+        self.can_pipe()
+            || matches!(
+                self,
+                Statement::LineCount(_)
+                    | Statement::Show(_)
+                    | Statement::ReadLink(_)
+                    | Statement::Exists(_)
+                    | Statement::Pwd(_)
+            )
+    }
+
+    /// Whether the statement goes straight to the operating system's filesystem
+    /// (links, permissions, archives, directory walks, polling), so it cannot run
+    /// against an in-memory one.
+    pub fn requires_native_fs(&self) -> bool {
+        match self {
+            Statement::Open(s) => s.flags.lock.is_some(),
+            Statement::Search(s) => s.options.streaming,
+            Statement::LineCount(s) => s.streaming,
+            Statement::Pipe(s) => s.producer.requires_native_fs(),
+            Statement::Let(s) => s.value.requires_native_fs(),
+            Statement::Copy(s) => s.recursive,
+            Statement::Remove(s) => is_glob_pattern(&s.filename),
+            Statement::ListDir(s) => s.recursive || s.long || is_glob_pattern(&s.path),
+            Statement::Stat(_)
+            | Statement::Hash(_)
+            | Statement::CheckHash(_)
+            | Statement::Grep(_)
+            | Statement::Find(_)
+            | Statement::Du(_)
+            | Statement::Symlink(_)
+            | Statement::HardLink(_)
+            | Statement::ReadLink(_)
+            | Statement::Chmod(_)
+            | Statement::Concat(_)
+            | Statement::ZipCreate(_)
+            | Statement::ZipExtract(_)
+            | Statement::Watch(_)
+            | Statement::TailFollow(_)
+            | Statement::Parallel(_)
+            | Statement::ReadRange(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the statement may go in a `parallel` block: it works on literal
+    /// paths only, touching no variables, so it can run on a thread of its own.
+    pub fn runs_in_parallel(&self) -> bool {
+        match self {
+            Statement::Copy(_)
+            | Statement::Move(_)
+            | Statement::Remove(_)
+            | Statement::Mkdir(_)
+            | Statement::Touch(_)
+            | Statement::Concat(_)
+            | Statement::ZipCreate(_)
+            | Statement::ZipExtract(_) => true,
+            Statement::Hash(s) => s.target.path().is_some(),
+            Statement::CheckHash(s) => s.target.path().is_some(),
+            _ => false,
+        }
+    }
+}
+
+/// Statements print as the source that parses back to them, on one line.
+/// Blocks print only their opening line (e.g. `while exists "lock" do`).
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Open(s) => {
+                match s.target {
+                    FileTarget::Disk => {
+                        write!(f, "open {} as {}", Quoted(&s.filename), s.var_name)?
+                    }
+                    target => write!(f, "open {} as {}", target.as_str(), s.var_name)?,
+                }
+                if s.flags.mode != OpenMode::Writable {
+                    write!(f, " {}", s.flags.mode.as_str())?;
+                }
+                if s.flags.no_create {
+                    write!(f, " nocreate")?;
+                }
+                if s.flags.buffered {
+                    write!(f, " buffered")?;
+                }
+                if let Some(encoding) = s.flags.encoding {
+                    write!(f, " encoding {}", Quoted(encoding.as_str()))?;
+                }
+                match s.flags.lock {
+                    Some(LockKind::Exclusive) => write!(f, " locked")?,
+                    Some(LockKind::Shared) => write!(f, " shared")?,
+                    None => {}
+                }
+                flag(f, s.flags.nomem, "nomem")
+            }
+            Statement::Read(s) => write!(f, "read {}", s.var_name),
+            Statement::Write(s) => {
+                write!(f, "write {} {}", s.var_name, Quoted(&s.text))?;
+                flag(f, s.overwrite, "overwrite")
+            }
+            Statement::Append(s) => write!(f, "append {} {}", s.var_name, Quoted(&s.text)),
+            Statement::Show(s) => write!(f, "show {}", s.var_name),
+            Statement::Close(s) => {
+                write!(f, "close {}", s.var_name)?;
+                flag(f, s.discard, "discard")?;
+                flag(f, s.keep, "keep")
+            }
+            Statement::Truncate(s) => write!(f, "truncate {}", s.var_name),
+            Statement::Search(s) => {
+                write!(f, "search {} {}", s.var_name, Quoted(&s.pattern))?;
+                flag(f, s.options.ignore_case, "ignorecase")?;
+                flag(f, s.options.count_only, "count")?;
+                if s.options.context > 0 {
+                    write!(f, " context {}", s.options.context)?;
+                }
+                flag(f, s.options.streaming, "streaming")
+            }
+            Statement::Replace(s) => {
+                write!(
+                    f,
+                    "replace {} {} {}",
+                    s.var_name,
+                    Quoted(&s.pattern),
+                    Quoted(&s.replacement)
+                )?;
+                flag(f, s.options.first_only, "first")?;
+                if let Some((from, to)) = s.options.lines {
+                    write!(f, " lines {} {}", from, to)?;
+                }
+                flag(f, s.options.literal, "literal")
+            }
+            Statement::LineCount(s) => {
+                write!(f, "linecount {}", s.var_name)?;
+                flag(f, s.streaming, "streaming")
+            }
+            Statement::Copy(s) => {
+                write!(f, "copy {} {}", Quoted(&s.source), Quoted(&s.destination))?;
+                flag(f, s.recursive, "recursive")?;
+                flag(f, s.overwrite, "overwrite")
+            }
+            Statement::Move(s) => {
+                write!(f, "move {} {}", Quoted(&s.source), Quoted(&s.destination))?;
+                flag(f, s.overwrite, "overwrite")
+            }
+            Statement::Remove(s) => {
+                write!(f, "remove {}", Quoted(&s.filename))?;
+                flag(f, s.recursive, "recursive")?;
+                flag(f, s.if_any, "ifany")
+            }
+            Statement::Rename(s) => write!(f, "rename {} {}", s.var_name, Quoted(&s.new_filename)),
+            Statement::ListDir(s) => {
+                write!(f, "listdir {}", Quoted(&s.path))?;
+                flag(f, s.recursive, "recursive")?;
+                flag(f, s.long, "long")
+            }
+            Statement::DumpEnv(s) => {
+                write!(f, "dumpenv")?;
+                if let Some(var) = &s.var_name {
+                    write!(f, " {}", var)?;
+                }
+                Ok(())
+            }
+            Statement::Help(s) => {
+                write!(f, "help")?;
+                if let Some(topic) = &s.topic {
+                    write!(f, " {}", Quoted(topic))?;
+                }
+                Ok(())
+            }
+            Statement::Mkdir(s) => write!(f, "mkdir {}", Quoted(&s.path)),
+            Statement::Rmdir(s) => write!(f, "rmdir {}", Quoted(&s.path)),
+            Statement::Head(s) => write!(f, "head {} {}", s.var_name, s.count),
+            Statement::Tail(s) => write!(f, "tail {} {}", s.var_name, s.count),
+            Statement::InsertLine(s) => {
+                write!(
+                    f,
+                    "insertline {} {} {}",
+                    s.var_name,
+                    s.line,
+                    Quoted(&s.text)
+                )
+            }
+            Statement::DeleteLine(s) => {
+                write!(f, "deleteline {} {}", s.var_name, s.from)?;
+                if s.to != s.from {
+                    write!(f, " {}", s.to)?;
+                }
+                Ok(())
+            }
+            Statement::Exists(s) => write!(f, "exists {}", Quoted(&s.path)),
+            Statement::Stat(s) => write!(f, "stat {}", s.target),
+            Statement::Touch(s) => write!(f, "touch {}", Quoted(&s.filename)),
+            Statement::Save(s) => write!(f, "save {}", s.var_name),
+            Statement::Revert(s) => write!(f, "revert {}", s.var_name),
+            Statement::Set(s) => {
+                let bare = s.value.starts_with(is_identifier_start)
+                    && s.value.chars().all(is_identifier_char);
+                if bare {
+                    write!(f, "set {} {}", s.option, s.value)
+                } else {
+                    write!(f, "set {} {}", s.option, Quoted(&s.value))
+                }
+            }
+            Statement::CloseAll(_) => write!(f, "closeall"),
+            Statement::Unset(s) => {
+                write!(f, "unset {}", s.var_name)?;
+                flag(f, s.force, "force")
+            }
+            Statement::HexDump(s) => write!(f, "hexdump {}", s.var_name),
+            Statement::Size(s) => write!(f, "size {}", s.var_name),
+            Statement::SetLineEnd(s) => {
+                write!(
+                    f,
+                    "setlineend {} {}",
+                    s.var_name,
+                    Quoted(s.line_ending.as_str())
+                )
+            }
+            Statement::Extract(s) => write!(f, "extract {} {}", s.var_name, Quoted(&s.pattern)),
+            Statement::SortLines(s) => {
+                write!(f, "sortlines {}", s.var_name)?;
+                flag(f, s.numeric, "numeric")
+            }
+            Statement::UniqLines(s) => {
+                write!(f, "uniqlines {}", s.var_name)?;
+                flag(f, s.all, "all")
+            }
+            Statement::ReverseLines(s) => write!(f, "reverselines {}", s.var_name),
+            Statement::NumberLines(s) => write!(f, "numberlines {}", s.var_name),
+            Statement::TrimLines(s) => {
+                write!(f, "trimlines {}", s.var_name)?;
+                flag(f, s.leading, "leading")
+            }
+            Statement::GetLine(s) => {
+                write!(f, "getline {} {}", s.var_name, s.from)?;
+                if s.to != s.from {
+                    write!(f, " {}", s.to)?;
+                }
+                Ok(())
+            }
+            Statement::Diff(s) => write!(f, "diff {} {}", s.left, s.right),
+            Statement::Hash(s) => write!(f, "hash {} {}", s.target, Quoted(&s.algorithm)),
+            Statement::CheckHash(s) => write!(
+                f,
+                "checkhash {} {} {}",
+                s.target,
+                Quoted(&s.algorithm),
+                Quoted(&s.expected)
+            ),
+            Statement::Encode(s) => {
+                write!(f, "encode {} {}", s.var_name, Quoted(s.codec.as_str()))
+            }
+            Statement::Decode(s) => {
+                write!(f, "decode {} {}", s.var_name, Quoted(s.codec.as_str()))
+            }
+            Statement::ZipCreate(s) => {
+                write!(f, "zipcreate {} {}", Quoted(&s.archive), Quoted(&s.source))
+            }
+            Statement::ZipExtract(s) => {
+                write!(
+                    f,
+                    "zipextract {} {}",
+                    Quoted(&s.archive),
+                    Quoted(&s.destination)
+                )
+            }
+            Statement::Concat(s) => {
+                write!(f, "concat {} from", Quoted(&s.destination))?;
+                for source in &s.sources {
+                    write!(f, " {}", Quoted(source))?;
+                }
+                Ok(())
+            }
+            Statement::Split(s) => {
+                let mode = match s.mode {
+                    SplitMode::Lines => "lines",
+                    SplitMode::Bytes => "bytes",
+                };
+                write!(
+                    f,
+                    "split {} {} {} into {}",
+                    s.var_name,
+                    mode,
+                    s.size,
+                    Quoted(&s.prefix)
+                )
+            }
+            Statement::Grep(s) => {
+                write!(f, "grep {} in {}", Quoted(&s.pattern), Quoted(&s.path))?;
+                flag(f, s.ignore_case, "ignorecase")?;
+                if let Some(include) = &s.include {
+                    write!(f, " include {}", Quoted(include))?;
+                }
+                flag(f, s.verbose, "verbose")
+            }
+            Statement::Find(s) => {
+                write!(f, "find {}", Quoted(&s.root))?;
+                for filter in &s.filters {
+                    match filter {
+                        FindFilter::Name(glob) => write!(f, " name {}", Quoted(glob))?,
+                        FindFilter::Larger(bytes) => write!(f, " larger {}", bytes)?,
+                        FindFilter::OlderThan(age) => {
+                            write!(f, " olderthan \"{}s\"", age.as_secs())?
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Statement::Du(s) => {
+                write!(f, "du {}", Quoted(&s.path))?;
+                if s.per_child {
+                    write!(f, " depth 1")?;
+                }
+                Ok(())
+            }
+            Statement::Symlink(s) => {
+                write!(f, "symlink {} {}", Quoted(&s.target), Quoted(&s.link_path))?;
+                flag(f, s.overwrite, "overwrite")
+            }
+            Statement::HardLink(s) => {
+                write!(f, "hardlink {} {}", Quoted(&s.target), Quoted(&s.link_path))?;
+                flag(f, s.overwrite, "overwrite")
+            }
+            Statement::ReadLink(s) => write!(f, "readlink {}", Quoted(&s.link_path)),
+            Statement::Chmod(s) => write!(f, "chmod {} {}", Quoted(&s.path), Quoted(&s.mode)),
+            Statement::Watch(s) => {
+                write!(f, "watch {} every {}", Quoted(&s.path), s.interval_ms)?;
+                if let Some(max_runs) = s.max_runs {
+                    write!(f, " maxruns {}", max_runs)?;
+                }
+                write!(f, " do")
+            }
+            Statement::TailFollow(s) => {
+                write!(f, "tailfollow {} every {}", Quoted(&s.path), s.interval_ms)?;
+                if let Some(max_seconds) = s.max_seconds {
+                    write!(f, " maxseconds {}", max_seconds)?;
+                }
+                Ok(())
+            }
+            Statement::While(s) => write!(f, "while {} do", s.condition),
+            Statement::If(s) => write!(f, "if {} do", s.condition),
+            Statement::Assert(s) => write!(f, "assert {}", s.condition),
+            Statement::Prompt(s) => {
+                write!(f, "prompt {} as {}", Quoted(&s.text), s.var_name)?;
+                if let Some(default) = &s.default {
+                    write!(f, " default {}", Quoted(default))?;
+                }
+                Ok(())
+            }
+            Statement::Echo(s) => write!(f, "echo {}", Quoted(&s.text)),
+            Statement::Sleep(s) => write!(f, "sleep {}", s.millis),
+            Statement::Include(s) => write!(f, "include {}", Quoted(&s.path)),
+            Statement::Def(s) => write!(f, "def {}({})", s.name, s.params.join(", ")),
+            Statement::Call(s) => {
+                let args: Vec<String> = s.args.iter().map(|a| format!("{}", Quoted(a))).collect();
+                write!(f, "call {}({})", s.name, args.join(", "))
+            }
+            Statement::Try(_) => write!(f, "try"),
+            Statement::Cd(s) => write!(f, "cd {}", Quoted(&s.path)),
+            Statement::Pwd(_) => write!(f, "pwd"),
+            Statement::Undo(s) if s.count == 1 => write!(f, "undo"),
+            Statement::Undo(s) => write!(f, "undo \"{}\"", s.count),
+            Statement::History(_) => write!(f, "history"),
+            Statement::Breakpoint(_) => write!(f, "breakpoint"),
+            Statement::Parallel(_) => write!(f, "parallel"),
+            Statement::SaveSession(s) => {
+                write!(f, "savesession {}", Quoted(&s.path))?;
+                if s.full {
+                    write!(f, " full")?;
+                }
+                Ok(())
+            }
+            Statement::LoadSession(s) => {
+                write!(f, "loadsession {}", Quoted(&s.path))?;
+                if s.merge {
+                    write!(f, " merge")?;
+                }
+                Ok(())
+            }
+            Statement::RestoreBackup(s) => write!(f, "restorebackup {}", s.var_name),
+            Statement::TempFile(s) => write!(f, "tempfile as {}", s.var_name),
+            Statement::Buffer(s) => write!(f, "buffer as {}", s.var_name),
+            Statement::SaveAs(s) => write!(f, "saveas {} {}", s.var_name, Quoted(&s.path)),
+            Statement::CopyTo(s) => write!(f, "copyto {} {}", s.source, s.destination),
+            Statement::AppendFrom(s) => write!(f, "appendfrom {} {}", s.destination, s.source),
+            Statement::ReadRange(s) => {
+                write!(f, "readrange {} {} {}", s.var_name, s.offset, s.length)
+            }
+            Statement::Let(s) => write!(f, "let {} = {}", s.var_name, s.value),
+            Statement::Pipe(s) => {
+                write!(f, "{} | ", s.producer)?;
+                match &s.sink {
+                    PipeSink::Append(var) => write!(f, "append {}", var),
+                    PipeSink::Write(var) => write!(f, "write {}", var),
+                    PipeSink::Show => write!(f, "show"),
+                }
+            }
+            Statement::GetEnv(s) => {
+                write!(f, "getenv {} as {}", Quoted(&s.name), s.var_name)?;
+                if let Some(default) = &s.default {
+                    write!(f, " default {}", Quoted(default))?;
+                }
+                Ok(())
+            }
+            Statement::Exit(s) => {
+                write!(f, "exit")?;
+                if s.code != 0 {
+                    write!(f, " {}", s.code)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Write ` word` if `set` (for statement modifiers like `recursive`).
+fn flag(f: &mut fmt::Formatter<'_>, set: bool, word: &str) -> fmt::Result {
+    if set {
+        write!(f, " {}", word)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for FileRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileRef::Var(var_name) => write!(f, "{}", var_name),
+            FileRef::Path(path) => write!(f, "{}", Quoted(path)),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Contains { var_name, pattern } => {
+                write!(f, "contains {} {}", var_name, Quoted(pattern))
+            }
+            Condition::Exists { path } => write!(f, "exists {}", Quoted(path)),
+            Condition::Not(inner) => write!(f, "not {}", inner),
+            Condition::Compare { left, op, right } => {
+                write!(f, "{} {} {}", left, op.as_str(), right)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Text(text) => write!(f, "{}", Quoted(text)),
+            Operand::Number(n) => write!(f, "{}", n),
+            Operand::LineCount(var) => write!(f, "linecount {}", var),
+            Operand::ByteCount(var) => write!(f, "bytecount {}", var),
+        }
+    }
+}
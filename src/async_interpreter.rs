@@ -0,0 +1,178 @@
+/*!
+ * async_interpreter.rs
+ *
+ * An AsyncInterpreter runs scripts for async hosts (built with the `async`
+ * feature). It owns an Interpreter on a worker thread of its own, so file
+ * operations, regex searches, and `sleep` never block the host's runtime;
+ * `run` hands the worker the script and returns a future that completes when
+ * it finishes. The futures need no particular runtime, so they work under
+ * tokio as under any other executor.
+ *
+ * Scripts run exactly as through `Interpreter::run`, with the same
+ * interpreter settings (given to `with_setup`) and the same output.
+ *
+ * `cancel` stops the running script with an "interrupted" error, as Ctrl-C
+ * does; dropping the future of an unfinished `run` cancels it too.
+ */
+
+use crate::ast::AST;
+use crate::errors::RuntimeError;
+use crate::interpreter::Interpreter;
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+/// What a finished run reports back: its result and the status passed to `exit`.
+type Outcome = (Result<(), RuntimeError>, Option<i32>);
+
+/// Where the worker leaves a run's outcome for the future waiting on it.
+#[derive(Default)]
+struct Slot {
+    outcome: Option<Outcome>,
+    waker: Option<Waker>,
+    /// Whether the future was dropped before the run finished.
+    abandoned: bool,
+}
+
+/// A script for the worker to run, and where to report how it went.
+struct Job {
+    ast: AST,
+    slot: Arc<Mutex<Slot>>,
+}
+
+/// An interpreter running on its own thread, driven through futures.
+pub struct AsyncInterpreter {
+    /// Hands scripts to the worker; None once it is shutting down.
+    jobs: Option<mpsc::Sender<Job>>,
+    worker: Option<JoinHandle<()>>,
+    interrupt: Arc<AtomicBool>,
+    exit_code: Option<i32>,
+}
+
+impl AsyncInterpreter {
+    /// Create an interpreter that prints to the given writer.
+    pub fn with_output(out: Box<dyn Write + Send>) -> Self {
+        Self::with_setup(out, |_| {})
+    }
+
+    /// Create an interpreter that prints to the given writer, with `setup`
+    /// applying its settings (sandbox, dry run, limits, ...) on the worker.
+    pub fn with_setup(
+        out: Box<dyn Write + Send>,
+        setup: impl FnOnce(&mut Interpreter) + Send + 'static,
+    ) -> Self {
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&interrupt);
+        let (jobs, received) = mpsc::channel::<Job>();
+        let worker = thread::spawn(move || {
+            let mut interpreter = Interpreter::with_output(out);
+            setup(&mut interpreter);
+            interpreter.set_interrupt_flag(flag);
+            for job in received {
+                if job.slot.lock().unwrap_or_else(|e| e.into_inner()).abandoned {
+                    continue;
+                }
+                let result = interpreter.run(&job.ast);
+                let mut slot = job.slot.lock().unwrap_or_else(|e| e.into_inner());
+                slot.outcome = Some((result, interpreter.exit_code()));
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+        Self {
+            jobs: Some(jobs),
+            worker: Some(worker),
+            interrupt,
+            exit_code: None,
+        }
+    }
+
+    /// Run the given AST on the worker, as `Interpreter::run` does.
+    pub async fn run(&mut self, ast: &AST) -> Result<(), RuntimeError> {
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        let job = Job {
+            ast: ast.clone(),
+            slot: Arc::clone(&slot),
+        };
+        let sent = (self.jobs.as_ref()).is_some_and(|jobs| jobs.send(job).is_ok());
+        if !sent {
+            return Err(RuntimeError::new(
+                "The interpreter's worker thread has stopped".to_string(),
+            ));
+        }
+        let (result, exit_code) = Finished {
+            slot,
+            interrupt: &self.interrupt,
+            done: false,
+        }
+        .await;
+        self.exit_code = exit_code;
+        result
+    }
+
+    /// Stop the running script with an "interrupted" error at its next
+    /// statement, loop iteration, sleep slice, or directory entry.
+    pub fn cancel(&self) {
+        self.interrupt.store(true, Ordering::Relaxed);
+    }
+
+    /// The status passed to `exit`, if the last script run exited explicitly.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+}
+
+impl Drop for AsyncInterpreter {
+    fn drop(&mut self) {
+        self.cancel();
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The future of a run: ready once the worker reports its outcome. Dropped
+/// before then, it cancels the run.
+struct Finished<'a> {
+    slot: Arc<Mutex<Slot>>,
+    interrupt: &'a AtomicBool,
+    done: bool,
+}
+
+impl Future for Finished<'_> {
+    type Output = Outcome;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Outcome> {
+        let this = self.get_mut();
+        let mut slot = this.slot.lock().unwrap_or_else(|e| e.into_inner());
+        match slot.outcome.take() {
+            Some(outcome) => {
+                this.done = true;
+                Poll::Ready(outcome)
+            }
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Finished<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut slot = self.slot.lock().unwrap_or_else(|e| e.into_inner());
+        if slot.outcome.is_none() {
+            slot.abandoned = true;
+            self.interrupt.store(true, Ordering::Relaxed);
+        }
+    }
+}
@@ -1,43 +1,1017 @@
-/// Return a help string listing all commands and their usage.
-pub fn help_text() -> String {
-    let text = r#"
-Available commands:
-
-Basic File Operations:
-  open "filename" as var      - Open a file and assign it to a variable
-  read var                    - Read the file content from disk into memory
-  write var "text"            - Overwrite the file with the given text
-  append var "text"           - Append text to the end of the file
-  show var                    - Print the in-memory content of the file
-  close var                   - Close the file associated with the variable
-  truncate var                - Clear the file content (both in memory and on disk)
-
-Advanced File Operations:
-  search var "pattern"        - Search for regex pattern in the file content
-  replace var "pattern" "replacement"
-                             - Replace all occurrences of the pattern with the replacement
-  linecount var               - Show the number of lines in the file
-  rename var "newfilename"    - Rename the file associated with var
-
-File System Operations:
-  copy "source" "destination" - Copy a file on disk
-  move "source" "destination" - Move/rename a file on disk
-  remove "filename"           - Remove a file from disk
-
-Directory and Environment:
-  listdir "path"              - List files in a directory
-  dumpenv                     - Show all variables, their files, and open/closed state
-
-Miscellaneous:
-  help                        - Show this help message
-  exit                        - Exit the interpreter
+/*!
+ * commands.rs
+ *
+ * The registry of commands shown by `help`. Every statement keyword has at
+ * least one `CommandSpec` here; the full help text and `help "name"` are both
+ * generated from the table.
+ */
+
+use crate::utils::closest_match;
+
+/// Column where usage ends and the description starts, after the two-space indent.
+const USAGE_WIDTH: usize = 28;
+
+/// Column width for an option, after its six-space indent.
+const OPTION_WIDTH: usize = 24;
+
+/// Suggest a command for `help "name"` only when it is this close.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// The group a command is listed under in the help.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    Basic,
+    Advanced,
+    FileSystem,
+    Directory,
+    Blocks,
+    Conditions,
+    Miscellaneous,
+}
+
+impl Category {
+    /// Every category, in the order the help lists them.
+    pub const ALL: [Category; 7] = [
+        Category::Basic,
+        Category::Advanced,
+        Category::FileSystem,
+        Category::Directory,
+        Category::Blocks,
+        Category::Conditions,
+        Category::Miscellaneous,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Category::Basic => "Basic File Operations",
+            Category::Advanced => "Advanced File Operations",
+            Category::FileSystem => "File System Operations",
+            Category::Directory => "Directory and Environment",
+            Category::Blocks => "Blocks",
+            Category::Conditions => "Conditions",
+            Category::Miscellaneous => "Miscellaneous",
+        }
+    }
+}
+
+/// One form of a command, as listed in the help.
+pub struct CommandSpec {
+    /// The keyword the statement starts with, which `help "name"` looks up.
+    /// Several specs may share a name (e.g. each `set` option).
+    pub name: &'static str,
+    /// How the statement is written; a block spans several lines.
+    pub usage: &'static str,
+    /// What it does. Further lines continue the description.
+    pub summary: &'static str,
+    pub category: Category,
+    /// Optional modifiers as (syntax, description) pairs.
+    pub options: &'static [(&'static str, &'static str)],
+}
+
+const fn spec(
+    name: &'static str,
+    usage: &'static str,
+    summary: &'static str,
+    category: Category,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        usage,
+        summary,
+        category,
+        options: &[],
+    }
+}
+
+const fn spec_with_options(
+    name: &'static str,
+    usage: &'static str,
+    summary: &'static str,
+    category: Category,
+    options: &'static [(&'static str, &'static str)],
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        usage,
+        summary,
+        category,
+        options,
+    }
+}
+
+use Category::*;
+
+/// Every command, in the order the help lists them within each category.
+pub static COMMANDS: &[CommandSpec] = &[
+    // Basic file operations
+    spec_with_options(
+        "open",
+        r#"open "filename" as var"#,
+        "Open a file, load its content, and assign it to a variable",
+        Basic,
+        &[
+            (
+                "[readonly|writable|appendonly]",
+                "Restrict what may be done with it (default writable)",
+            ),
+            ("[nocreate]", "Fail if the file does not already exist"),
+            ("[buffered]", "Keep changes in memory until 'save'"),
+            (
+                r#"[encoding "name"]"#,
+                "utf-8 (default), latin-1, utf-16le, or utf-16be",
+            ),
+            (
+                "[locked]",
+                "Hold an exclusive lock on the file until 'close'",
+            ),
+            (
+                "[shared]",
+                "Hold a shared lock instead, opening it readonly\n\
+                 (both wait up to 'set locktimeout' for the lock)",
+            ),
+            (
+                "[nomem]",
+                "Don't load or keep the content: write and append go\n\
+                 straight to disk, search and linecount read it from\n\
+                 disk, and other reads need 'read var' first",
+            ),
+        ],
+    ),
+    spec(
+        "open",
+        "open stdin as var",
+        "'read var' pulls in standard input (once)",
+        Basic,
+    ),
+    spec(
+        "open",
+        "open stdout as var",
+        "'write' and 'append' on var print their text",
+        Basic,
+    ),
+    spec(
+        "read",
+        "read var",
+        "Re-read the file content from disk into memory",
+        Basic,
+    ),
+    spec(
+        "readrange",
+        r#"readrange var "OFFSET" "LEN""#,
+        "Replace the content with LEN bytes of the file starting\n\
+         at byte OFFSET, without reading the rest (a later\n\
+         unbuffered write stores just that content)",
+        Basic,
+    ),
+    spec(
+        "write",
+        r#"write var "text" [overwrite]"#,
+        "Overwrite the file with the given text (overwrite: even\n\
+         if it changed on disk, see 'set noclobber')",
+        Basic,
+    ),
+    spec(
+        "append",
+        r#"append var "text""#,
+        "Append text to the end of the file",
+        Basic,
+    ),
+    spec(
+        "copyto",
+        "copyto src dst",
+        "Replace dst's content with src's",
+        Basic,
+    ),
+    spec(
+        "appendfrom",
+        "appendfrom dst src",
+        "Append src's content to the end of dst",
+        Basic,
+    ),
+    spec(
+        "show",
+        "show var",
+        "Print the in-memory content of the file",
+        Basic,
+    ),
+    spec(
+        "close",
+        "close var [discard] [keep]",
+        "Close the file (discard drops unsaved changes; keep\n\
+         keeps a tempfile instead of deleting it at the end)",
+        Basic,
+    ),
+    spec(
+        "buffer",
+        "buffer as var",
+        "Make a variable with no file behind it: its content\n\
+         lives only in memory (read and rename don't apply)",
+        Basic,
+    ),
+    spec(
+        "saveas",
+        r#"saveas var "filename""#,
+        "Write the content to filename and use that file from\n\
+         now on (turns a buffer into a file)",
+        Basic,
+    ),
+    spec(
+        "tempfile",
+        "tempfile as var",
+        "Create an empty file with a unique name in the temp\n\
+         directory (see 'set tempdir') and open it; it is\n\
+         deleted when the script finishes",
+        Basic,
+    ),
+    spec("closeall", "closeall", "Close every open file", Basic),
+    spec(
+        "save",
+        "save var",
+        "Write a buffered file's in-memory content to disk",
+        Basic,
+    ),
+    spec(
+        "revert",
+        "revert var",
+        "Reload a file from disk, discarding unsaved changes",
+        Basic,
+    ),
+    spec(
+        "restorebackup",
+        "restorebackup var",
+        "Copy the file's .bak (see 'set backup') back over it\n\
+         and reload it",
+        Basic,
+    ),
+    spec(
+        "truncate",
+        "truncate var",
+        "Clear the file content (both in memory and on disk)",
+        Basic,
+    ),
+    // Advanced file operations
+    spec_with_options(
+        "search",
+        r#"search var "pattern""#,
+        "Search for regex pattern in the file content",
+        Advanced,
+        &[
+            ("[ignorecase]", "Match regardless of case"),
+            ("[count]", "Print only the number of matching lines"),
+            (
+                r#"[context "N"]"#,
+                "Also show N lines before and after each match",
+            ),
+            (
+                "[streaming]",
+                "Read the file from disk line by line instead of memory\n\
+                 (automatic for files over 64 MiB with no content loaded)",
+            ),
+        ],
+    ),
+    spec_with_options(
+        "grep",
+        r#"grep "pattern" in "dir""#,
+        "Search every text file below dir, printing path:line: text",
+        Advanced,
+        &[
+            ("[ignorecase]", "Match regardless of case"),
+            (
+                r#"[include "glob"]"#,
+                r#"Only search files whose name matches the glob (e.g. "*.rs")"#,
+            ),
+            ("[verbose]", "Also report binary files that were skipped"),
+        ],
+    ),
+    spec(
+        "extract",
+        r#"extract var "pattern""#,
+        "Print every match as line:col: text (group 1 if the\n\
+         pattern has captures; (?s) lets matches span lines)",
+        Advanced,
+    ),
+    spec_with_options(
+        "replace",
+        r#"replace var "pattern" "replacement""#,
+        "Replace all occurrences of the pattern with the replacement\n\
+         ($1 or ${name} inserts a capture group, $$ a literal '$')",
+        Advanced,
+        &[
+            ("[first]", "Replace only the first occurrence"),
+            (
+                r#"[lines "N" "M"]"#,
+                "Only replace within lines N through M",
+            ),
+            ("[literal]", "Match the pattern as plain text, not a regex"),
+        ],
+    ),
+    spec(
+        "pipe",
+        "command | append var",
+        "Append the lines search, listdir, getline, head, tail,\n\
+         or extract would print to var instead of printing them",
+        Advanced,
+    ),
+    spec(
+        "pipe",
+        "command | write var",
+        "Replace var's content with those lines",
+        Advanced,
+    ),
+    spec("pipe", "command | show", "Print them as usual", Advanced),
+    spec(
+        "let",
+        "let var = command",
+        "Store what search, listdir, getline, head, tail, extract,\n\
+         linecount, show, readlink, exists, or pwd produces in\n\
+         \"${var}\" instead of printing it (a count as a number,\n\
+         lines joined with newlines)",
+        Advanced,
+    ),
+    spec(
+        "linecount",
+        "linecount var [streaming]",
+        "Show the number of lines in the file (streaming: count\n\
+         on disk without loading it, like search)",
+        Advanced,
+    ),
+    spec(
+        "size",
+        "size var",
+        "Show the size of the content in bytes",
+        Advanced,
+    ),
+    spec(
+        "hexdump",
+        "hexdump var",
+        "Show the content as a hex+ASCII dump (works on binary files)",
+        Advanced,
+    ),
+    spec(
+        "head",
+        r#"head var "N""#,
+        "Show the first N lines of the file",
+        Advanced,
+    ),
+    spec(
+        "tail",
+        r#"tail var "N""#,
+        "Show the last N lines of the file",
+        Advanced,
+    ),
+    spec(
+        "rename",
+        r#"rename var "newfilename""#,
+        "Rename the file associated with var",
+        Advanced,
+    ),
+    spec(
+        "getline",
+        r#"getline var "N" ["M"]"#,
+        "Show line N, or lines N through M",
+        Advanced,
+    ),
+    spec(
+        "insertline",
+        r#"insertline var "N" "text""#,
+        "Insert text as a new line before line N",
+        Advanced,
+    ),
+    spec(
+        "deleteline",
+        r#"deleteline var "N" ["M"]"#,
+        "Delete line N, or lines N through M",
+        Advanced,
+    ),
+    spec(
+        "sortlines",
+        "sortlines var [numeric]",
+        "Sort the lines (numeric: by leading integer)",
+        Advanced,
+    ),
+    spec(
+        "uniqlines",
+        "uniqlines var [all]",
+        "Remove adjacent duplicate lines (all: every repeat)",
+        Advanced,
+    ),
+    spec(
+        "reverselines",
+        "reverselines var",
+        "Reverse the order of the lines",
+        Advanced,
+    ),
+    spec(
+        "numberlines",
+        "numberlines var",
+        "Prefix every line with its line number",
+        Advanced,
+    ),
+    spec(
+        "trimlines",
+        "trimlines var [leading]",
+        "Strip trailing (and with leading, leading) whitespace",
+        Advanced,
+    ),
+    spec(
+        "encode",
+        r#"encode var "base64"|"hex""#,
+        "Replace the content with its base64 or hex encoding",
+        Advanced,
+    ),
+    spec(
+        "decode",
+        r#"decode var "base64"|"hex""#,
+        "Replace base64 or hex content with the decoded bytes",
+        Advanced,
+    ),
+    spec(
+        "setlineend",
+        r#"setlineend var "lf"|"crlf""#,
+        "Convert the file to LF or CRLF line endings",
+        Advanced,
+    ),
+    // File system operations
+    spec(
+        "copy",
+        r#"copy "source" "destination""#,
+        "Copy a file on disk",
+        FileSystem,
+    ),
+    spec(
+        "copy",
+        r#"copy "srcdir" "dstdir" recursive"#,
+        "Copy a whole directory tree",
+        FileSystem,
+    ),
+    spec_with_options(
+        "move",
+        r#"move "source" "destination""#,
+        "Move/rename a file or directory on disk",
+        FileSystem,
+        &[(
+            "[overwrite]",
+            "(copy and move) Replace an existing destination\n\
+             under 'set noclobber on'",
+        )],
+    ),
+    spec(
+        "remove",
+        r#"remove "filename""#,
+        "Remove a file from disk",
+        FileSystem,
+    ),
+    spec(
+        "touch",
+        r#"touch "filename""#,
+        "Create an empty file or update its modification time",
+        FileSystem,
+    ),
+    spec(
+        "remove",
+        r#"remove "path" recursive"#,
+        "Remove a directory and everything inside it",
+        FileSystem,
+    ),
+    spec(
+        "remove",
+        r#"remove "pattern" [ifany]"#,
+        "Remove every file matching a glob pattern",
+        FileSystem,
+    ),
+    spec(
+        "diff",
+        r#"diff "a" "b" | diff f g"#,
+        "Show a unified diff of two files (paths or open variables)",
+        FileSystem,
+    ),
+    spec(
+        "hash",
+        r#"hash "path" "algorithm""#,
+        "Print the sha256, sha1, or md5 digest of a file (or var)",
+        FileSystem,
+    ),
+    spec(
+        "checkhash",
+        r#"checkhash "path" "algorithm" "digest""#,
+        "Print ok if the file's digest matches, else error",
+        FileSystem,
+    ),
+    spec(
+        "concat",
+        r#"concat "out" from "a" "b" ..."#,
+        "Write the listed files, in order, into out",
+        FileSystem,
+    ),
+    spec(
+        "split",
+        r#"split var lines|bytes "N" into "prefix""#,
+        "Write the content as prefix000, prefix001, ... of N lines/bytes",
+        FileSystem,
+    ),
+    spec(
+        "zipcreate",
+        r#"zipcreate "archive.zip" "path""#,
+        "Create a zip archive of a file or directory tree",
+        FileSystem,
+    ),
+    spec(
+        "zipextract",
+        r#"zipextract "archive.zip" "destdir""#,
+        "Extract a zip archive (entries escaping destdir are refused)",
+        FileSystem,
+    ),
+    spec(
+        "symlink",
+        r#"symlink "target" "link" [overwrite]"#,
+        "Create a symbolic link (overwrite replaces an existing link)",
+        FileSystem,
+    ),
+    spec(
+        "hardlink",
+        r#"hardlink "target" "link" [overwrite]"#,
+        "Create a hard link to an existing file",
+        FileSystem,
+    ),
+    spec(
+        "readlink",
+        r#"readlink "link""#,
+        "Print the target of a symbolic link",
+        FileSystem,
+    ),
+    spec_with_options(
+        "tailfollow",
+        r#"tailfollow "path""#,
+        "Print lines as they are appended to a file (like tail -f)",
+        FileSystem,
+        &[
+            (r#"[every "ms"]"#, "Poll interval (default 200 ms)"),
+            (r#"[maxseconds "N"]"#, "Stop following after N seconds"),
+        ],
+    ),
+    spec(
+        "exists",
+        r#"exists "path""#,
+        "Print whether path is a file, directory, or missing",
+        FileSystem,
+    ),
+    spec(
+        "chmod",
+        r#"chmod "path" "mode""#,
+        r#"Set permissions from octal ("644") or symbolic ("u+x", "go-w")"#,
+        FileSystem,
+    ),
+    spec(
+        "stat",
+        r#"stat var | stat "path""#,
+        "Show size, type, modification time, and permissions",
+        FileSystem,
+    ),
+    // Directory and environment
+    spec(
+        "listdir",
+        r#"listdir "path""#,
+        "List files in a directory",
+        Directory,
+    ),
+    spec(
+        "listdir",
+        r#"listdir "path" recursive"#,
+        "List the whole tree below a directory",
+        Directory,
+    ),
+    spec(
+        "listdir",
+        r#"listdir "path" long"#,
+        "List with size and modification time (combines with\n\
+         recursive, or with a pattern)",
+        Directory,
+    ),
+    spec(
+        "listdir",
+        r#"listdir "pattern""#,
+        r#"List paths matching a glob pattern (e.g. "logs/**/*.txt")"#,
+        Directory,
+    ),
+    spec_with_options(
+        "find",
+        r#"find "path""#,
+        "List entries below path matching all given filters:",
+        Directory,
+        &[
+            (r#"[name "glob"]"#, r#"Name matches a glob (e.g. "*.log")"#),
+            (r#"[larger "bytes"]"#, "File is larger than the given size"),
+            (
+                r#"[olderthan "7d"]"#,
+                "Modified longer ago than an age (s, m, h, or d)",
+            ),
+        ],
+    ),
+    spec(
+        "du",
+        r#"du "path" [depth "1"]"#,
+        "Show the total size under path (depth 1: per entry, largest first)",
+        Directory,
+    ),
+    spec(
+        "mkdir",
+        r#"mkdir "path""#,
+        "Create a directory (and any missing parents)",
+        Directory,
+    ),
+    spec(
+        "rmdir",
+        r#"rmdir "path""#,
+        "Remove an empty directory",
+        Directory,
+    ),
+    spec(
+        "cd",
+        r#"cd "path""#,
+        "Change the directory relative paths are resolved from",
+        Directory,
+    ),
+    spec(
+        "pwd",
+        "pwd",
+        "Print the absolute path of that directory",
+        Directory,
+    ),
+    spec(
+        "dumpenv",
+        "dumpenv",
+        "Show the working directory and all variables, sorted\n\
+         by name, with their files, open/closed state, and\n\
+         content size (* marks unsaved changes)",
+        Directory,
+    ),
+    spec(
+        "dumpenv",
+        "dumpenv var",
+        "Show one variable in detail: file, state, size, line\n\
+         count, encoding, and mode",
+        Directory,
+    ),
+    spec_with_options(
+        "savesession",
+        r#"savesession "file.json""#,
+        "Save the variables (files, open state, modes), string\n\
+         variables, and working directory as JSON",
+        Directory,
+        &[("[full]", "Keep file content too, unsaved changes included")],
+    ),
+    spec_with_options(
+        "loadsession",
+        r#"loadsession "file.json""#,
+        "Restore a saved session, reading content from disk\n\
+         unless it was saved full; variables whose file is gone\n\
+         are closed",
+        Directory,
+        &[(
+            "[merge]",
+            "Load alongside existing variables (otherwise an error)",
+        )],
+    ),
+    spec(
+        "unset",
+        "unset var [force]",
+        "Forget a variable (force drops unsaved changes)",
+        Directory,
+    ),
+    spec(
+        "set",
+        "set buffered on|off",
+        "Open every following file in buffered mode",
+        Directory,
+    ),
+    spec(
+        "set",
+        r#"set tempdir "path""#,
+        "Create tempfiles in path instead of the system temp\n\
+         directory (the sandbox root under --sandbox)",
+        Directory,
+    ),
+    spec(
+        "set",
+        "set keepcontent on|off",
+        "Off: open every following unbuffered file as nomem",
+        Directory,
+    ),
+    spec(
+        "set",
+        r#"set locktimeout "ms""#,
+        "How long 'open ... locked|shared' waits for a lock held\n\
+         elsewhere (default 5000)",
+        Directory,
+    ),
+    spec(
+        "set",
+        r#"set maxloop "N""#,
+        "Limit how many times a while loop may run",
+        Directory,
+    ),
+    spec(
+        "set",
+        "set noclobber on|off",
+        "Refuse to write a file that changed on disk since it was\n\
+         read, or to copy or move onto an existing path, unless\n\
+         the statement ends with 'overwrite'",
+        Directory,
+    ),
+    spec(
+        "set",
+        "set backup on|off",
+        "Before the first write to each existing file, copy it\n\
+         to \"<filename>.bak\" (like --backup)",
+        Directory,
+    ),
+    spec(
+        "set",
+        "set dryrun on|off",
+        "Print the filesystem changes statements would make\n\
+         instead of making them (like --dry-run)",
+        Directory,
+    ),
+    spec(
+        "set",
+        "set trace on|off",
+        "Print each statement and its line to stderr before\n\
+         running it (like --trace)",
+        Directory,
+    ),
+    spec(
+        "set",
+        "set confirm on|off",
+        "When run from a terminal, ask before each remove and\n\
+         truncate, going ahead only on y or yes",
+        Directory,
+    ),
+    spec(
+        "set",
+        "set journal on|off",
+        "Record each change to the disk so undo can reverse it\n\
+         (switching it clears the journal)",
+        Directory,
+    ),
+    spec(
+        "undo",
+        r#"undo ["count"]"#,
+        "Reverse the last journaled change, or the last count\n\
+         of them, newest first",
+        Directory,
+    ),
+    spec(
+        "history",
+        "history",
+        "List the journaled changes, marking those undo can't\n\
+         reverse (recursive removes, links, chmod, archives)",
+        Directory,
+    ),
+    spec(
+        "set",
+        "set onerror continue|abort",
+        "Report failing statements and carry on (like\n\
+         --keep-going), or stop at the first (default)",
+        Directory,
+    ),
+    // Blocks
+    spec(
+        "watch",
+        "watch \"path\" [every \"ms\"] [maxruns \"N\"] do\n  ...\nend",
+        "Run the statements between do and end each time\n\
+         the file changes (polls every 500 ms by default)",
+        Blocks,
+    ),
+    spec(
+        "while",
+        "while condition do\n  ...\nend",
+        "Run the statements for as long as the condition holds\n\
+         (at most 100000 times; change with 'set maxloop \"N\"')",
+        Blocks,
+    ),
+    spec(
+        "if",
+        "if condition do\n  ...\nend",
+        "Run the statements once if the condition holds",
+        Blocks,
+    ),
+    spec(
+        "def",
+        "def name(param, ...) [do]\n  ...\nend",
+        "Define a function; inside it, \"${param}\" in a string\n\
+         is replaced by the argument",
+        Blocks,
+    ),
+    spec(
+        "call",
+        r#"call name("arg", ...)"#,
+        "Run a function (calls may nest up to 64 deep)",
+        Blocks,
+    ),
+    spec(
+        "try",
+        "try\n  ...\nonerror\n  ...\nend",
+        "Run the first block; if a statement in it fails, run\n\
+         the second instead (\"${error}\" holds the message)",
+        Blocks,
+    ),
+    spec(
+        "parallel",
+        "parallel\n  ...\nend",
+        "Run copy, move, remove, mkdir, touch, concat, zip, and\n\
+         hash statements on paths at the same time; failures are\n\
+         reported together once all are done",
+        Blocks,
+    ),
+    // Conditions
+    spec(
+        "contains",
+        r#"contains var "pattern""#,
+        "The variable's content matches the pattern",
+        Conditions,
+    ),
+    spec(
+        "exists",
+        r#"exists "path""#,
+        "A file or directory exists at path",
+        Conditions,
+    ),
+    spec(
+        "not",
+        "not condition",
+        "The condition does not hold",
+        Conditions,
+    ),
+    spec(
+        "compare",
+        "value == value",
+        "Compare two values with ==, !=, <, <=, >, or >=: as\n\
+         integers if both are, otherwise as strings. A value is\n\
+         a string (\"${n}\" works), a number, linecount var,\n\
+         or bytecount var",
+        Conditions,
+    ),
+    // Miscellaneous
+    spec("echo", r#"echo "text""#, "Print the text", Miscellaneous),
+    spec_with_options(
+        "prompt",
+        r#"prompt "text" as var"#,
+        "Print text, read a line of input, and store it, trimmed,\n\
+         in \"${var}\"",
+        Miscellaneous,
+        &[(
+            r#"[default "text"]"#,
+            "Use text if the line is empty or input has ended",
+        )],
+    ),
+    spec_with_options(
+        "getenv",
+        r#"getenv "NAME" as var"#,
+        "Read a process environment variable into \"${var}\"",
+        Miscellaneous,
+        &[(
+            r#"[default "text"]"#,
+            "Use text if it is unset (otherwise that is an error)",
+        )],
+    ),
+    spec(
+        "assert",
+        "assert condition",
+        "Fail with the condition, its actual values, and the\n\
+         line number unless the condition holds (see Conditions)",
+        Miscellaneous,
+    ),
+    spec(
+        "include",
+        r#"include "script.fl""#,
+        "Run another script here, sharing variables with it\n\
+         (see Paths below for where it is looked for)",
+        Miscellaneous,
+    ),
+    spec(
+        "sleep",
+        r#"sleep "ms""#,
+        "Pause for the given number of milliseconds",
+        Miscellaneous,
+    ),
+    spec("help", "help", "Show this help message", Miscellaneous),
+    spec(
+        "help",
+        r#"help "command""#,
+        "Show only the entries for one command",
+        Miscellaneous,
+    ),
+    spec(
+        "breakpoint",
+        "breakpoint",
+        "Pause here; at a terminal, take 'show var', 'dumpenv [var]',\n\
+         'continue', and 'quit' commands before going on",
+        Miscellaneous,
+    ),
+    spec(
+        "exit",
+        r#"exit ["code"]"#,
+        "Exit the interpreter with the given status (default 0)",
+        Miscellaneous,
+    ),
+];
+
+/// The sections of the help after the command listing.
+const GUIDE: &str = r##"Paths:
+  Relative paths start from the working directory, which is, in order:
+    1. the directory set with 'cd' (itself relative to the next two);
+    2. with --paths script-relative, the directory of the running script;
+    3. otherwise, the directory the interpreter was started in.
+  'include' looks for a relative script:
+    1. next to the including script (in the working directory at the top level);
+    2. in each directory of FILE_LANG_PATH (separated by ':'), in order.
 
 End of Statement:
   Statements can end with a newline or a semicolon.
 
 Note:
+  'open' loads the current content of an existing file, so 'show' works right
+  away; a file that doesn't exist yet starts out empty and is created on first write.
   Patterns are regular expressions (using Rust's 'regex' crate syntax).
-  Filenames and text must be in double quotes.
-"#;
-    text.to_string()
+  Paths for listdir and remove may use glob wildcards: *, ?, [abc], and **.
+  Filenames and text must be in double quotes. Numbers may be written bare
+  (head f 10) or quoted (head f "10").
+  "${env:NAME}" in a string is replaced by the process environment variable when
+  the statement runs (left as written if unset); dumpenv never shows those values.
+  """...""" strings may span lines and contain '"' (a newline right after the
+  opening """ is dropped). Raw strings r"..." take backslashes literally, which
+  suits patterns like r"\d{3}-\d{4}"; r#"..."# may also contain '"'.
+  Line endings are normalized to LF in memory (so patterns anchored with '$' work)
+  and the file's original ending is restored whenever it is written.
+  Files that aren't valid UTF-8 are loaded as binary: hexdump and size work on
+  them, but text operations like show, search, and replace report an error.
+"##;
+
+/// Return a help string listing all commands and their usage.
+pub fn help_text() -> String {
+    let mut lines = vec![String::new(), "Available commands:".to_string()];
+    for category in Category::ALL {
+        lines.push(String::new());
+        lines.push(format!("{}:", category.title()));
+        for spec in COMMANDS.iter().filter(|spec| spec.category == category) {
+            push_spec(&mut lines, spec);
+        }
+    }
+    lines.push(String::new());
+    lines.push(GUIDE.to_string());
+    lines.join("\n")
+}
+
+/// Return the help for one command: the usage and description of every form
+/// of it. Unknown names get an error suggesting the closest command.
+pub fn command_help(name: &str) -> Result<String, String> {
+    let name = name.to_lowercase();
+    let mut lines = Vec::new();
+    for spec in COMMANDS.iter().filter(|spec| spec.name == name) {
+        push_spec(&mut lines, spec);
+    }
+    if !lines.is_empty() {
+        return Ok(lines.join("\n"));
+    }
+    let names = COMMANDS.iter().map(|spec| spec.name);
+    Err(match closest_match(&name, names, MAX_SUGGESTION_DISTANCE) {
+        Some(suggestion) => format!("No help for '{}'; did you mean '{}'?", name, suggestion),
+        None => format!("No help for '{}' (see 'help' for every command)", name),
+    })
+}
+
+/// Append a command's help lines: its usage, description, and options.
+fn push_spec(lines: &mut Vec<String>, spec: &CommandSpec) {
+    push_entry(lines, "  ", USAGE_WIDTH, spec.usage, spec.summary);
+    for (option, description) in spec.options {
+        push_entry(lines, "      ", OPTION_WIDTH, option, description);
+    }
+}
+
+/// Append `syntax` with `description` beside it in the description column. A
+/// syntax too long for its column goes on a line of its own.
+fn push_entry(
+    lines: &mut Vec<String>,
+    indent: &str,
+    width: usize,
+    syntax: &str,
+    description: &str,
+) {
+    let mut syntax_lines: Vec<&str> = syntax.lines().collect();
+    let last = syntax_lines.pop().unwrap_or_default();
+    for line in syntax_lines {
+        lines.push(format!("{}{}", indent, line));
+    }
+    let column = indent.len() + width;
+    let mut description_lines = description.lines();
+    let first = description_lines.next().unwrap_or_default();
+    if last.len() < width {
+        lines.push(format!("{}{:<width$}- {}", indent, last, first));
+    } else {
+        lines.push(format!("{}{}", indent, last));
+        lines.push(format!("{:column$}- {}", "", first));
+    }
+    for line in description_lines {
+        lines.push(format!("{:column$}  {}", "", line));
+    }
 }
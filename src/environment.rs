@@ -3,23 +3,110 @@
  *
  * The Environment manages the runtime state:
  * - A mapping from variable names to FileEntry (filename, content, and open state)
+ * - A stack of scopes holding the string parameters of the functions being called
+ * - Global string variables, such as the script's command-line arguments
+ *
+ * File access goes through a FileSystem (the real disk unless replaced).
  *
  * The environment stores the in-memory content of opened files. Operations like `read`, `write`,
  * `append`, `search`, `replace`, `truncate`, `linecount`, `rename`, and `close` all act on
  * these environment entries.
  *
  * If a file operation requires disk access, the environment methods handle it.
+ * Content that isn't valid UTF-8 is kept as raw bytes; text operations on such
+ * entries fail with an error saying the file is binary.
+ * Text is kept with `\n` line endings in memory; the file's original line ending
+ * (LF or CRLF) is restored on every write to disk.
+ * Entries opened in buffered mode only change in memory (and are marked dirty)
+ * until `save` writes them to disk.
+ * In dry-run mode nothing is written: content still changes in memory, and each
+ * write that would have happened is recorded for the interpreter to report.
+ * Optional limits cap the size of a file read into memory and the total bytes
+ * written to files, for running untrusted scripts.
+ * With the journal on, each write to disk is recorded with the content it replaced
+ * so `undo` can put it back.
  */
 
+use crate::ast::{FileTarget, LockKind, OpenFlags, OpenMode};
 use crate::errors::RuntimeError;
+use crate::filesystem::{FileSystem, RealFs};
+use crate::journal::{Journal, JournalEntry, UndoAction};
+use crate::session::{Session, SessionEntry};
 use crate::utils::{
-    append_to_file, read_file_content, replace_in_text, search_in_text, write_to_file,
+    apply_line_ending, closest_match, decode_content, decode_text, detect_bom, detect_line_ending,
+    encode_content, encode_text, extract_matches, join_lines, lock_file, normalize_line_endings,
+    number_lines, read_file_range, replace_in_text, search_in_text, sort_lines, split_chunks,
+    split_lines, stream_line_count, stream_search, trim_lines, uniq_lines, ContentCodec, Encoding,
+    LineEnding, LineMatch, MatchInfo, PathKind, ReplaceOptions, SplitMode,
 };
-use std::collections::HashMap;
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// How long `open ... locked|shared` waits for a lock unless `set locktimeout` says otherwise.
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_millis(5000);
+
+/// Files larger than this are searched and counted from disk, line by line,
+/// when the variable holds no content in memory.
+pub const STREAMING_THRESHOLD: u64 = 64 * 1024 * 1024;
 
 pub struct Environment {
     /// Map of variable names to file entries.
     pub files: HashMap<String, FileEntry>,
+    /// Open every file in buffered mode (`set buffered on`).
+    pub buffered_default: bool,
+    /// Parameter values of each active function call, innermost last.
+    scopes: Vec<HashMap<String, String>>,
+    /// String variables visible to the whole script, like `arg1` and `argcount`.
+    globals: HashMap<String, String>,
+    /// Globals bound by `getenv`, whose values `dump` keeps out of its output.
+    env_globals: HashSet<String>,
+    /// Record disk writes instead of performing them (`set dryrun on`).
+    pub dry_run: bool,
+    /// Writes skipped in dry-run mode, e.g. `write to file "a.txt"`.
+    planned: Vec<String>,
+    /// Refuse to write over changes made on disk since a file was read
+    /// (`set noclobber on`).
+    pub no_clobber: bool,
+    /// Keep file content in memory; when off, files open as if with `nomem`
+    /// (`set keepcontent off`).
+    pub keep_content: bool,
+    /// Where `tempfile` creates files (`set tempdir`); if unset, the system temp
+    /// directory, or the sandbox root under a sandbox.
+    pub temp_dir: Option<PathBuf>,
+    /// Files made by `tempfile`, deleted by `remove_temp_files`.
+    temp_files: Vec<String>,
+    /// How long `open ... locked|shared` waits for a lock (`set locktimeout`).
+    pub lock_timeout: Duration,
+    /// Copy each file to `<filename>.bak` before first writing it (`set backup on`).
+    pub backup: bool,
+    /// Files already backed up (or found missing) this run, so later writes
+    /// leave their `.bak` alone.
+    backed_up: HashSet<String>,
+    /// Refuse to read a file larger than this into memory.
+    pub max_read_bytes: Option<u64>,
+    /// Refuse writes that would bring the bytes written to files past this.
+    pub max_write_bytes: Option<u64>,
+    /// Bytes written to files so far, counted against `max_write_bytes`.
+    written_bytes: u64,
+    /// Files written since the last `take_written`, with the bytes written.
+    written: Vec<(String, usize)>,
+    /// Disk changes `undo` can reverse (`set journal on`).
+    pub journal: Journal,
+    /// Where files are read from and written to.
+    pub fs: Box<dyn FileSystem>,
+    /// What `open stdin` variables and `prompt` read.
+    pub stdin: Box<dyn Read>,
+    /// Text written to `open stdout` variables, waiting to be printed.
+    stdout: Vec<String>,
+    /// The directory relative paths are resolved from (`cd`), itself relative to
+    /// the process working directory. Empty until the script changes it.
+    pub current_dir: PathBuf,
 }
 
 /// A file entry holds the state of an opened file.
@@ -27,9 +114,75 @@ pub struct FileEntry {
     /// The filename on disk.
     pub filename: String,
     /// The in-memory content of the file.
-    pub content: String,
+    pub content: Content,
     /// Whether the file is currently open.
     pub is_open: bool,
+    /// Which operations the variable allows.
+    pub mode: OpenMode,
+    /// Whether modifications stay in memory until `save`.
+    pub buffered: bool,
+    /// Whether the in-memory content has changes not yet saved to disk.
+    pub dirty: bool,
+    /// Text encoding used to decode and re-encode the file.
+    pub encoding: Encoding,
+    /// Whether the file starts with a byte order mark, which is written back on save.
+    pub bom: bool,
+    /// Line ending used when writing text back to disk.
+    pub line_ending: LineEnding,
+    /// The file's modification time and size when it was last read or written
+    /// (None if it didn't exist), to notice changes made by someone else.
+    pub disk_stamp: Option<(SystemTime, u64)>,
+    /// The lock taken with `open ... locked|shared`, and the handle keeping it,
+    /// released on close.
+    pub lock: Option<(LockKind, File)>,
+    /// Whether this is a file or standard input or output.
+    pub target: FileTarget,
+    /// Whether this reads standard input and `read` hasn't pulled it in yet.
+    pub unread: bool,
+    /// Whether writes go straight to disk and `content` stays empty
+    /// (`open ... nomem` or `set keepcontent off`) until `read`.
+    pub nomem: bool,
+}
+
+/// The in-memory content of a file.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Content {
+    Text(String),
+    /// Content that is not valid UTF-8.
+    Binary(Vec<u8>),
+}
+
+impl Content {
+    /// Decode bytes read from disk, keeping them as binary if they aren't UTF-8.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Content::Text(text),
+            Err(e) => Content::Binary(e.into_bytes()),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Content::Text(text) => text.as_bytes(),
+            Content::Binary(bytes) => bytes,
+        }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Content::Binary(_))
+    }
+}
+
+impl From<String> for Content {
+    fn from(text: String) -> Self {
+        Content::Text(text)
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Environment {
@@ -37,78 +190,633 @@ impl Environment {
     pub fn new() -> Self {
         Self {
             files: HashMap::new(),
+            buffered_default: false,
+            scopes: Vec::new(),
+            globals: HashMap::new(),
+            env_globals: HashSet::new(),
+            dry_run: false,
+            planned: Vec::new(),
+            no_clobber: false,
+            keep_content: true,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            temp_dir: None,
+            temp_files: Vec::new(),
+            backup: false,
+            backed_up: HashSet::new(),
+            max_read_bytes: None,
+            max_write_bytes: None,
+            written_bytes: 0,
+            written: Vec::new(),
+            journal: Journal::default(),
+            fs: Box::new(RealFs),
+            stdin: Box::new(io::stdin()),
+            stdout: Vec::new(),
+            current_dir: PathBuf::new(),
         }
     }
 
-    /// Open a file and assign it to a variable.
+    /// Resolve a path the script names against the working directory.
+    pub fn resolve_path(&self, path: &str) -> String {
+        self.current_dir.join(path).to_string_lossy().into_owned()
+    }
+
+    /// The absolute path of the working directory.
+    pub fn working_dir(&self) -> Result<PathBuf, RuntimeError> {
+        let dir = self.current_dir.to_string_lossy();
+        let dir = if dir.is_empty() { "." } else { &dir };
+        self.fs.canonicalize(dir).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to resolve working directory '{}'", dir), e)
+        })
+    }
+
+    /// Take the writes skipped in dry-run mode since the last call.
+    pub fn take_planned(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.planned)
+    }
+
+    /// Count `len` bytes about to be written to `path` towards the write limit,
+    /// refusing the write if that would pass it.
+    pub fn count_write(&mut self, path: &str, len: u64) -> Result<(), RuntimeError> {
+        count_written(
+            &mut self.written_bytes,
+            self.max_write_bytes,
+            path,
+            len as usize,
+        )
+    }
+
+    /// Note that `len` bytes were written to `path`.
+    pub fn record_write(&mut self, path: &str, len: usize) {
+        self.written.push((path.to_string(), len));
+    }
+
+    /// Take the files written since the last call, with the bytes written to each.
+    pub fn take_written(&mut self) -> Vec<(String, usize)> {
+        std::mem::take(&mut self.written)
+    }
+
+    /// Enter a function call, making its parameters visible.
+    pub fn push_scope(&mut self, params: HashMap<String, String>) {
+        self.scopes.push(params);
+    }
+
+    /// Leave the innermost function call.
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// The parameters of the innermost function call (or `try` handler).
+    pub fn innermost_scope(&self) -> Option<&HashMap<String, String>> {
+        self.scopes.last()
+    }
+
+    /// Set a string variable visible to the whole script.
+    pub fn set_global(&mut self, name: &str, value: String) {
+        self.env_globals.remove(name);
+        self.globals.insert(name.to_string(), value);
+    }
+
+    /// Set a string variable to a value taken from the process environment.
+    /// It works like any other global, but `dump` doesn't print its value.
+    pub fn set_global_from_env(&mut self, name: &str, value: String) {
+        self.set_global(name, value);
+        self.env_globals.insert(name.to_string());
+    }
+
+    /// Look up a string variable visible to the whole script.
+    pub fn global_value(&self, name: &str) -> Option<&str> {
+        self.globals.get(name).map(String::as_str)
+    }
+
+    /// Look up a parameter of the innermost function call.
+    pub fn param_value(&self, name: &str) -> Option<&str> {
+        self.scopes.last()?.get(name).map(String::as_str)
+    }
+
+    /// Open a file and assign it to a variable, loading its current content from
+    /// disk if it exists (a missing file starts out empty).
     /// If already open, error unless it was closed previously.
-    pub fn open_file(&mut self, var_name: String, filename: String) -> Result<(), RuntimeError> {
-        if self.files.contains_key(&var_name) {
-            let entry = self.files.get_mut(&var_name).unwrap();
-            if entry.is_open {
-                return Err(RuntimeError::new(format!(
-                    "Variable '{}' already has an open file.",
-                    var_name
-                )));
-            } else {
-                // If the file is closed, we can reuse and update filename
-                entry.filename = filename;
-                entry.content.clear();
-                entry.is_open = true;
+    /// With `no_create`, the file must already exist.
+    /// Returns a warning to show if the file had mixed line endings.
+    pub fn open_file(
+        &mut self,
+        var_name: String,
+        filename: String,
+        flags: &OpenFlags,
+    ) -> Result<Option<String>, RuntimeError> {
+        if flags.no_create && self.fs.path_kind(&filename) == PathKind::Missing {
+            return Err(RuntimeError::new(format!(
+                "Cannot open '{}': file does not exist (opened with nocreate)",
+                filename
+            )));
+        }
+        if self.files.get(&var_name).is_some_and(|entry| entry.is_open) {
+            return Err(RuntimeError::new(format!(
+                "Variable '{}' already has an open file.",
+                var_name
+            )));
+        }
+        let buffered = flags.buffered || self.buffered_default;
+        if flags.nomem && buffered {
+            return Err(RuntimeError::new(format!(
+                "Cannot open '{}' both buffered and nomem: buffered changes live in memory",
+                filename
+            )));
+        }
+        // Buffered entries need their content, so `set keepcontent off` skips them.
+        let nomem = flags.nomem || (!self.keep_content && !buffered);
+        let lock = match flags.lock {
+            // Taking a lock opens the file on disk (creating it if missing), so a dry
+            // run only reports it.
+            Some(kind) if self.dry_run => {
+                let how = if kind == LockKind::Shared {
+                    "shared"
+                } else {
+                    "locked"
+                };
+                self.planned.push(format!("open \"{}\" {}", filename, how));
+                None
             }
+            Some(kind) => {
+                let file = lock_file(&filename, kind == LockKind::Shared, self.lock_timeout)
+                    .map_err(|e| {
+                        if e.kind() == io::ErrorKind::TimedOut {
+                            RuntimeError::new(format!(
+                                "Timed out after {} ms waiting for a lock on '{}'",
+                                self.lock_timeout.as_millis(),
+                                filename
+                            ))
+                        } else {
+                            RuntimeError::with_source(format!("Failed to lock '{}'", filename), e)
+                        }
+                    })?;
+                Some((kind, file))
+            }
+            None => None,
+        };
+        let loaded = if self.fs.path_kind(&filename) == PathKind::File && !nomem {
+            load_content(&*self.fs, &filename, flags.encoding, self.max_read_bytes)?
         } else {
-            self.files.insert(
-                var_name,
-                FileEntry {
-                    filename,
-                    content: String::new(),
-                    is_open: true,
+            Loaded {
+                content: Content::Text(String::new()),
+                encoding: flags.encoding.unwrap_or(Encoding::Utf8),
+                bom: false,
+                line_ending: LineEnding::Lf,
+                mixed: false,
+            }
+        };
+        let warning = mixed_warning(&filename, &loaded);
+        let disk_stamp = disk_stamp(&*self.fs, &filename);
+        // A closed entry for the same variable is simply replaced.
+        self.files.insert(
+            var_name,
+            FileEntry {
+                filename,
+                content: loaded.content,
+                is_open: true,
+                mode: flags.mode,
+                buffered,
+                dirty: false,
+                encoding: loaded.encoding,
+                bom: loaded.bom,
+                line_ending: loaded.line_ending,
+                disk_stamp,
+                lock,
+                target: FileTarget::Disk,
+                unread: false,
+                nomem,
+            },
+        );
+        Ok(warning)
+    }
+
+    /// Make a variable whose content lives only in memory.
+    pub fn open_buffer(&mut self, var_name: String) -> Result<(), RuntimeError> {
+        self.open_stream(var_name, FileTarget::Buffer, &OpenFlags::default())
+    }
+
+    /// Open standard input (pulled in by `read`, then edited only in memory) or
+    /// standard output (written text is printed) as a variable.
+    pub fn open_stream(
+        &mut self,
+        var_name: String,
+        target: FileTarget,
+        flags: &OpenFlags,
+    ) -> Result<(), RuntimeError> {
+        if flags.buffered || flags.lock.is_some() {
+            return Err(RuntimeError::new(format!(
+                "Cannot open {} buffered or locked",
+                target.as_str()
+            )));
+        }
+        if self.files.get(&var_name).is_some_and(|entry| entry.is_open) {
+            return Err(RuntimeError::new(format!(
+                "Variable '{}' already has an open file.",
+                var_name
+            )));
+        }
+        self.files.insert(
+            var_name,
+            FileEntry {
+                filename: match target {
+                    FileTarget::Buffer => "(buffer)".to_string(),
+                    _ => format!("<{}>", target.as_str()),
                 },
-            );
+                content: Content::Text(String::new()),
+                is_open: true,
+                mode: flags.mode,
+                buffered: false,
+                dirty: false,
+                encoding: Encoding::Utf8,
+                bom: false,
+                line_ending: LineEnding::Lf,
+                disk_stamp: None,
+                lock: None,
+                target,
+                unread: target == FileTarget::Stdin,
+                nomem: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// A name for a new file in the temp directory, `default_dir` unless set with
+    /// `set tempdir`, that no existing file has.
+    pub fn temp_file_path(&self, default_dir: PathBuf) -> String {
+        let dir = self.temp_dir.clone().unwrap_or(default_dir);
+        (self.temp_files.len()..)
+            .map(|n| {
+                dir.join(format!("file_lang-{}-{}.tmp", std::process::id(), n))
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .find(|path| self.fs.path_kind(path) == PathKind::Missing)
+            .expect("the range of names is unbounded")
+    }
+
+    /// Create an empty file at a path from `temp_file_path`, and remember it for
+    /// `remove_temp_files`. In dry-run mode it is only remembered.
+    pub fn create_temp_file(&mut self, path: &str) -> Result<(), RuntimeError> {
+        if !self.dry_run {
+            self.fs.write(path, b"").map_err(|e| {
+                RuntimeError::with_source(format!("Failed to create temporary file '{}'", path), e)
+            })?;
         }
+        self.temp_files.push(path.to_string());
         Ok(())
     }
 
-    /// Read file content from disk into the environment.
-    pub fn read_file_content(&mut self, var_name: &str) -> Result<(), RuntimeError> {
-        let entry = self.get_entry_mut(var_name)?;
-        let filename = &entry.filename;
-        let buffer = read_file_content(filename)
-            .map_err(|e| RuntimeError::new(format!("Failed to read file '{}': {}", filename, e)))?;
-        entry.content = buffer;
+    /// Keep a file made by `tempfile` instead of deleting it at the end.
+    pub fn keep_temp_file(&mut self, filename: &str) {
+        self.temp_files.retain(|path| path != filename);
+    }
+
+    /// Delete every file made by `tempfile` (and not kept) that still exists.
+    pub fn remove_temp_files(&mut self) -> Result<(), RuntimeError> {
+        for path in std::mem::take(&mut self.temp_files) {
+            if self.fs.path_kind(&path) == PathKind::File {
+                self.fs.remove_file(&path).map_err(|e| {
+                    RuntimeError::with_source(
+                        format!("Failed to remove temporary file '{}'", path),
+                        e,
+                    )
+                })?;
+            }
+        }
         Ok(())
     }
 
-    /// Write new content to the file (overwrite) and memory.
-    pub fn write_file_content(&mut self, var_name: &str, text: &str) -> Result<(), RuntimeError> {
-        let entry = self.get_entry_mut(var_name)?;
-        let filename = &entry.filename;
-        write_to_file(filename, text).map_err(|e| {
-            RuntimeError::new(format!("Failed to write to file '{}': {}", filename, e))
+    /// Take the text written to `stdout` variables since the last call.
+    pub fn take_stdout(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.stdout)
+    }
+
+    /// Read one line of input for `prompt`, without its newline, or None at the
+    /// end of input. Bytes are read one at a time so the rest is left for later
+    /// prompts and `open stdin`.
+    pub fn read_input_line(&mut self) -> Result<Option<String>, RuntimeError> {
+        let mut bytes = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            match self.stdin.read(&mut byte) {
+                Ok(0) if bytes.is_empty() => return Ok(None),
+                Ok(0) => break,
+                Ok(_) if byte[0] == b'\n' => break,
+                Ok(_) => bytes.push(byte[0]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    return Err(RuntimeError::with_source(
+                        "Failed to read standard input".to_string(),
+                        e,
+                    ))
+                }
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+    }
+
+    /// Read file content from disk into the environment, discarding any
+    /// unsaved changes. Returns a warning to show if the file had mixed line endings.
+    pub fn read_file_content(&mut self, var_name: &str) -> Result<Option<String>, RuntimeError> {
+        let entry = open_entry_mut(&mut self.files, var_name)?;
+        match entry.target {
+            FileTarget::Disk => {}
+            FileTarget::Stdin if entry.unread => {
+                let mut bytes = Vec::new();
+                self.stdin.read_to_end(&mut bytes).map_err(|e| {
+                    RuntimeError::with_source("Failed to read standard input".to_string(), e)
+                })?;
+                entry.content = match Content::from_bytes(bytes) {
+                    Content::Text(text) => Content::Text(normalize_line_endings(&text)),
+                    binary => binary,
+                };
+                entry.unread = false;
+                return Ok(None);
+            }
+            FileTarget::Stdin => {
+                return Err(RuntimeError::new(format!(
+                    "Cannot read '{}' again: standard input can only be read once",
+                    var_name
+                )));
+            }
+            FileTarget::Stdout => {
+                return Err(RuntimeError::new(format!(
+                    "Cannot read '{}': it is standard output",
+                    var_name
+                )));
+            }
+            FileTarget::Buffer => {
+                return Err(RuntimeError::new(format!(
+                    "Cannot read '{}': it is a buffer, with no file behind it",
+                    var_name
+                )));
+            }
+        }
+        let loaded = load_content(
+            &*self.fs,
+            &entry.filename,
+            Some(entry.encoding),
+            self.max_read_bytes,
+        )?;
+        let warning = mixed_warning(&entry.filename, &loaded);
+        entry.content = loaded.content;
+        entry.nomem = false;
+        entry.bom = loaded.bom;
+        entry.line_ending = loaded.line_ending;
+        entry.dirty = false;
+        entry.disk_stamp = disk_stamp(&*self.fs, &entry.filename);
+        Ok(warning)
+    }
+
+    /// Replace a file's in-memory content with `len` bytes read from disk at
+    /// `offset`. Returns a warning if the offset is past the end of the file, or
+    /// if the bytes aren't valid UTF-8 and so are kept as binary.
+    pub fn read_file_range(
+        &mut self,
+        var_name: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<String>, RuntimeError> {
+        let filename = self.filename_of(var_name)?;
+        let size = self
+            .fs
+            .metadata(&filename)
+            .map_err(|e| {
+                RuntimeError::with_source(format!("Failed to read file '{}'", filename), e)
+            })?
+            .len;
+        if let Some(max) = self.max_read_bytes {
+            check_read_limit(&filename, len.min(size.saturating_sub(offset)), max)?;
+        }
+        let bytes = read_file_range(&filename, offset, len).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to read file '{}'", filename), e)
         })?;
-        entry.content = text.to_string();
-        Ok(())
+        let mut warning = None;
+        if offset > size {
+            warning = Some(format!(
+                "offset {} is past the end of '{}' ({} bytes); the content is empty",
+                offset, filename, size
+            ));
+        }
+        let encoding = self.get_entry(var_name)?.encoding;
+        let content = if encoding == Encoding::Utf8 {
+            match String::from_utf8(bytes) {
+                Ok(text) => Content::Text(normalize_line_endings(&text)),
+                Err(e) => {
+                    warning = Some(format!(
+                        "bytes {}..{} of '{}' aren't valid UTF-8 (first invalid byte at offset {}); kept as binary",
+                        offset,
+                        offset + e.as_bytes().len() as u64,
+                        filename,
+                        offset + e.utf8_error().valid_up_to() as u64
+                    ));
+                    Content::Binary(e.into_bytes())
+                }
+            }
+        } else {
+            let text = decode_text(&bytes, encoding).map_err(|e| {
+                RuntimeError::with_source(
+                    format!(
+                        "Failed to decode bytes {}..{} of '{}' as {}",
+                        offset,
+                        offset + bytes.len() as u64,
+                        filename,
+                        encoding.as_str()
+                    ),
+                    e,
+                )
+            })?;
+            Content::Text(normalize_line_endings(&text))
+        };
+        let entry = open_entry_mut(&mut self.files, var_name)?;
+        entry.content = content;
+        entry.nomem = false;
+        entry.dirty = false;
+        Ok(warning)
+    }
+
+    /// Write new content to the file (overwrite) and memory. Under `no_clobber`,
+    /// the file must not have changed on disk since it was read, unless `overwrite`.
+    pub fn write_file_content(
+        &mut self,
+        var_name: &str,
+        text: &str,
+        overwrite: bool,
+    ) -> Result<(), RuntimeError> {
+        self.check_mode(var_name, "write to", false)?;
+        if self.no_clobber && !overwrite {
+            let entry = self.get_entry(var_name)?;
+            if entry.target == FileTarget::Disk
+                && disk_stamp(&*self.fs, &entry.filename) != entry.disk_stamp
+            {
+                return Err(RuntimeError::new(format!(
+                    "Cannot write to '{}': it changed on disk since it was read (add 'overwrite' to write anyway)",
+                    entry.filename
+                )));
+            }
+        }
+        self.update_content(
+            var_name,
+            normalize_line_endings(text).into(),
+            "write to file",
+        )
     }
 
     /// Append text to the file content in memory and on disk.
     pub fn append_file_content(&mut self, var_name: &str, text: &str) -> Result<(), RuntimeError> {
-        let entry = self.get_entry_mut(var_name)?;
+        self.check_mode(var_name, "append to", true)?;
+        if !self.get_entry(var_name)?.nomem {
+            self.get_text(var_name)?;
+        }
+        self.back_up(var_name, false)?;
+        let journaled = self.journal_write(var_name, false, "append to file")?;
+        let text = &normalize_line_endings(text);
+        let dry_run = self.dry_run;
+        let entry = open_entry_mut(&mut self.files, var_name)?;
+        let mut planned = None;
+        if entry.target == FileTarget::Stdout {
+            self.stdout.push(text.to_string());
+        } else if matches!(entry.target, FileTarget::Stdin | FileTarget::Buffer) {
+            // Standard input and buffers are edited in memory only.
+        } else if entry.buffered {
+            entry.dirty = true;
+        } else if dry_run {
+            planned = Some(format!("append to file \"{}\"", entry.filename));
+        } else {
+            let filename = &entry.filename;
+            let on_disk = apply_line_ending(text, entry.line_ending);
+            let bytes = encode_for(filename, &on_disk, entry.encoding, false)?;
+            count_written(
+                &mut self.written_bytes,
+                self.max_write_bytes,
+                filename,
+                bytes.len(),
+            )?;
+            self.fs.append(filename, &bytes).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to append to file '{}'", filename), e)
+            })?;
+            self.written.push((filename.clone(), bytes.len()));
+            entry.disk_stamp = disk_stamp(&*self.fs, filename);
+        }
+        if let (Content::Text(content), false) = (&mut entry.content, entry.nomem) {
+            content.push_str(text);
+        }
+        self.planned.extend(planned);
+        self.journal.extend(journaled);
+        Ok(())
+    }
+
+    /// Replace one variable's content with another's, in memory and (unless the
+    /// destination is buffered) on disk.
+    pub fn copy_content(&mut self, source: &str, destination: &str) -> Result<(), RuntimeError> {
+        let content = self.loaded_entry(source)?.content.clone();
+        self.check_mode(destination, "write to", false)?;
+        self.update_content(destination, content, "write to file")
+    }
+
+    /// Append one variable's text to another's. The source is copied first, so a
+    /// variable appended to itself ends up with its content twice.
+    pub fn append_content_from(
+        &mut self,
+        destination: &str,
+        source: &str,
+    ) -> Result<(), RuntimeError> {
+        let text = self.get_text(source)?.to_string();
+        self.append_file_content(destination, &text)
+    }
+
+    /// Write the in-memory content of a file to disk.
+    pub fn save_file(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        // An appendonly variable's changes can only be appends, so saving it is too.
+        self.check_mode(var_name, "save", true)?;
+        self.loaded_entry(var_name)?;
+        if self.dry_run {
+            let filename = self.filename_of(var_name)?;
+            self.planned.push(format!("save file \"{}\"", filename));
+            self.get_entry_mut(var_name)?.dirty = false;
+            return Ok(());
+        }
+        self.back_up(var_name, true)?;
+        let journaled = self.journal_write(var_name, true, "save file")?;
+        let entry = open_entry_mut(&mut self.files, var_name)?;
         let filename = &entry.filename;
-        append_to_file(filename, text).map_err(|e| {
-            RuntimeError::new(format!("Failed to append to file '{}': {}", filename, e))
+        let bytes = entry_bytes(entry, &entry.content)?;
+        count_written(
+            &mut self.written_bytes,
+            self.max_write_bytes,
+            filename,
+            bytes.len(),
+        )?;
+        self.fs.write(filename, &bytes).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to save file '{}'", filename), e)
         })?;
-        entry.content.push_str(text);
+        self.written.push((filename.clone(), bytes.len()));
+        entry.disk_stamp = disk_stamp(&*self.fs, filename);
+        entry.dirty = false;
+        self.journal.extend(journaled);
         Ok(())
     }
 
-    /// Get the content of a file in memory.
-    pub fn get_file_content(&self, var_name: &str) -> Result<String, RuntimeError> {
-        let entry = self.get_entry(var_name)?;
-        Ok(entry.content.clone())
+    /// Get the text content of a file in memory.
+    pub fn get_file_content(&self, var_name: &str) -> Result<&str, RuntimeError> {
+        self.get_text(var_name)
     }
 
-    /// Close a file.
-    pub fn close_file(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+    /// Get the raw bytes of a file in memory, text or binary.
+    pub fn get_file_bytes(&self, var_name: &str) -> Result<Vec<u8>, RuntimeError> {
+        let entry = self.loaded_entry(var_name)?;
+        Ok(entry.content.as_bytes().to_vec())
+    }
+
+    /// Size in bytes of a file's in-memory content.
+    pub fn byte_size(&self, var_name: &str) -> Result<usize, RuntimeError> {
+        let entry = self.loaded_entry(var_name)?;
+        Ok(entry.content.as_bytes().len())
+    }
+
+    /// Replace a file's content with its base64 or hex encoding.
+    pub fn encode_file_content(
+        &mut self,
+        var_name: &str,
+        codec: ContentCodec,
+    ) -> Result<(), RuntimeError> {
+        self.check_mode(var_name, "encode", false)?;
+        let encoded = encode_content(self.loaded_entry(var_name)?.content.as_bytes(), codec);
+        self.update_content(var_name, encoded.into(), "write to file")
+    }
+
+    /// Replace a file's base64 or hex content with the decoded bytes, which are
+    /// kept as binary if they aren't valid UTF-8.
+    pub fn decode_file_content(
+        &mut self,
+        var_name: &str,
+        codec: ContentCodec,
+    ) -> Result<(), RuntimeError> {
+        self.check_mode(var_name, "decode", false)?;
+        let decoded = decode_content(self.get_text(var_name)?, codec).map_err(|e| {
+            RuntimeError::with_source(
+                format!("Failed to decode '{}' as {}", var_name, codec.as_str()),
+                e,
+            )
+        })?;
+        self.update_content(var_name, Content::from_bytes(decoded), "write to file")
+    }
+
+    /// Split a file's content into chunks (see `utils::split_chunks`).
+    pub fn split_file(
+        &self,
+        var_name: &str,
+        mode: SplitMode,
+        size: usize,
+    ) -> Result<Vec<Vec<u8>>, RuntimeError> {
+        let content = &self.loaded_entry(var_name)?.content;
+        let chunks = split_chunks(content.as_bytes(), mode, size, !content.is_binary());
+        Ok(chunks.into_iter().map(|c| c.to_vec()).collect())
+    }
+
+    /// Close a file. A file with unsaved changes can only be closed with
+    /// `discard`, which drops the changes.
+    pub fn close_file(&mut self, var_name: &str, discard: bool) -> Result<(), RuntimeError> {
         let entry = self.get_entry_mut(var_name)?;
         if !entry.is_open {
             return Err(RuntimeError::new(format!(
@@ -116,81 +824,682 @@ impl Environment {
                 var_name
             )));
         }
+        if entry.dirty && !discard {
+            return Err(RuntimeError::new(format!(
+                "Variable '{}' has unsaved changes; use 'save {}' or 'close {} discard'",
+                var_name, var_name, var_name
+            )));
+        }
         entry.is_open = false;
+        entry.dirty = false;
+        entry.lock = None;
+        Ok(())
+    }
+
+    /// Close every open file, returning how many were closed. Nothing is closed
+    /// if any open file has unsaved changes.
+    pub fn close_all(&mut self) -> Result<usize, RuntimeError> {
+        let mut dirty: Vec<&String> = self
+            .files
+            .iter()
+            .filter(|(_, entry)| entry.is_open && entry.dirty)
+            .map(|(var, _)| var)
+            .collect();
+        if !dirty.is_empty() {
+            dirty.sort();
+            let names: Vec<&str> = dirty.iter().map(|s| s.as_str()).collect();
+            return Err(RuntimeError::new(format!(
+                "Cannot close all files: unsaved changes in {}",
+                names.join(", ")
+            )));
+        }
+        let mut closed = 0;
+        for entry in self.files.values_mut() {
+            if entry.is_open {
+                entry.is_open = false;
+                entry.lock = None;
+                closed += 1;
+            }
+        }
+        Ok(closed)
+    }
+
+    /// Remove a variable from the environment entirely. A variable with unsaved
+    /// changes is only removed with `force`.
+    pub fn unset(&mut self, var_name: &str, force: bool) -> Result<(), RuntimeError> {
+        let entry = self
+            .files
+            .get(var_name)
+            .ok_or_else(|| no_such_variable(&self.files, var_name))?;
+        if entry.is_open && entry.dirty && !force {
+            return Err(RuntimeError::new(format!(
+                "Variable '{}' has unsaved changes; use 'unset {} force' to drop it",
+                var_name, var_name
+            )));
+        }
+        self.files.remove(var_name);
         Ok(())
     }
 
     /// Truncate a file: clear its content both in memory and on disk.
     pub fn truncate_file(&mut self, var_name: &str) -> Result<(), RuntimeError> {
-        let entry = self.get_entry_mut(var_name)?;
-        let filename = &entry.filename;
-        write_to_file(filename, "").map_err(|e| {
-            RuntimeError::new(format!("Failed to truncate file '{}': {}", filename, e))
-        })?;
-        entry.content.clear();
-        Ok(())
+        self.check_mode(var_name, "truncate", false)?;
+        self.update_content(var_name, String::new().into(), "truncate file")
     }
 
     /// Search for a regex pattern in the file content and return matches.
+    /// With `streaming` (or see `streams_from_disk`) the file is read from disk
+    /// line by line instead.
     pub fn search_file(
         &self,
         var_name: &str,
         pattern: &str,
-    ) -> Result<Vec<(usize, String)>, RuntimeError> {
-        let entry = self.get_entry(var_name)?;
-        let content = &entry.content;
-        let matches = search_in_text(content, pattern)
-            .map_err(|e| RuntimeError::new(format!("Invalid regex '{}': {}", pattern, e)))?;
+        ignore_case: bool,
+        streaming: bool,
+    ) -> Result<Vec<LineMatch>, RuntimeError> {
+        if self.streams_from_disk(var_name, streaming)? {
+            let re = RegexBuilder::new(pattern)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| {
+                    RuntimeError::with_source(format!("Invalid regex '{}'", pattern), e)
+                })?;
+            let filename = &self.get_entry(var_name)?.filename;
+            return stream_search(filename, &re).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to read file '{}'", filename), e)
+            });
+        }
+        let content = self.get_text(var_name)?;
+        let matches = search_in_text(content, pattern, ignore_case)
+            .map_err(|e| RuntimeError::with_source(format!("Invalid regex '{}'", pattern), e))?;
         Ok(matches)
     }
 
+    /// Find every match of a regex pattern in the whole file content.
+    pub fn extract_from_file(
+        &self,
+        var_name: &str,
+        pattern: &str,
+    ) -> Result<Vec<MatchInfo>, RuntimeError> {
+        let content = self.get_text(var_name)?;
+        extract_matches(content, pattern)
+            .map_err(|e| RuntimeError::with_source(format!("Invalid regex '{}'", pattern), e))
+    }
+
     /// Replace occurrences of a pattern with replacement in the file.
+    /// Returns the number of replacements made; when nothing matched the file is
+    /// left untouched, in memory and on disk.
     pub fn replace_file(
         &mut self,
         var_name: &str,
         pattern: &str,
         replacement: &str,
+        options: &ReplaceOptions,
+    ) -> Result<usize, RuntimeError> {
+        if let Some((from, to)) = options.lines {
+            if from == 0 || to < from {
+                return Err(RuntimeError::new(format!(
+                    "Invalid line range {}-{} in 'replace' (lines start at 1)",
+                    from, to
+                )));
+            }
+        }
+        self.check_mode(var_name, "replace in", false)?;
+        let content = self.get_text(var_name)?;
+        let (new_content, count) = replace_in_text(content, pattern, replacement, options)
+            .map_err(|e| RuntimeError::with_source(format!("Invalid regex '{}'", pattern), e))?;
+        if count == 0 {
+            return Ok(0);
+        }
+        self.update_content(
+            var_name,
+            new_content.into(),
+            "write replaced content to file",
+        )?;
+        Ok(count)
+    }
+
+    /// Count lines in a file's content.
+    pub fn line_count(&self, var_name: &str, streaming: bool) -> Result<usize, RuntimeError> {
+        if self.streams_from_disk(var_name, streaming)? {
+            let filename = &self.get_entry(var_name)?.filename;
+            return stream_line_count(filename).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to read file '{}'", filename), e)
+            });
+        }
+        Ok(self.get_text(var_name)?.lines().count())
+    }
+
+    /// Whether `search` and `linecount` should read the file from disk line by
+    /// line: always when asked to, otherwise when nothing is held in memory and
+    /// the file is larger than `STREAMING_THRESHOLD`.
+    pub fn streams_from_disk(&self, var_name: &str, requested: bool) -> Result<bool, RuntimeError> {
+        let entry = self.get_entry(var_name)?;
+        let streamable = entry.target == FileTarget::Disk
+            && !entry.dirty
+            && entry.encoding == Encoding::Utf8
+            && !entry.bom
+            && self.fs.is_native();
+        if requested {
+            if !streamable {
+                return Err(RuntimeError::new(format!(
+                    "Variable '{}' can't be streamed; 'streaming' needs a saved UTF-8 file on disk",
+                    var_name
+                )));
+            }
+            return Ok(true);
+        }
+        if !streamable || !entry.content.as_bytes().is_empty() {
+            return Ok(false);
+        }
+        if entry.nomem {
+            return Ok(true);
+        }
+        Ok(self
+            .fs
+            .metadata(&entry.filename)
+            .is_ok_and(|meta| meta.len > STREAMING_THRESHOLD))
+    }
+
+    /// Return the first `count` lines of a file's content.
+    pub fn head_lines(&self, var_name: &str, count: usize) -> Result<Vec<String>, RuntimeError> {
+        Ok(self
+            .get_text(var_name)?
+            .lines()
+            .take(count)
+            .map(|l| l.to_string())
+            .collect())
+    }
+
+    /// Return the last `count` lines of a file's content.
+    pub fn tail_lines(&self, var_name: &str, count: usize) -> Result<Vec<String>, RuntimeError> {
+        let lines: Vec<&str> = self.get_text(var_name)?.lines().collect();
+        let start = lines.len().saturating_sub(count);
+        Ok(lines[start..].iter().map(|l| l.to_string()).collect())
+    }
+
+    /// Return lines `from` through `to` (1-based, inclusive).
+    pub fn get_lines(
+        &self,
+        var_name: &str,
+        from: usize,
+        to: usize,
+    ) -> Result<Vec<String>, RuntimeError> {
+        let lines: Vec<&str> = self.get_text(var_name)?.lines().collect();
+        if to < from {
+            return Err(RuntimeError::new(format!(
+                "Line range {}-{} is reversed",
+                from, to
+            )));
+        }
+        if from == 0 || to > lines.len() {
+            let range = if from == to {
+                format!("Line {}", from)
+            } else {
+                format!("Line range {}-{}", from, to)
+            };
+            return Err(RuntimeError::new(format!(
+                "{} is out of range for '{}' ({} lines)",
+                range,
+                var_name,
+                lines.len()
+            )));
+        }
+        Ok(lines[from - 1..to].iter().map(|l| l.to_string()).collect())
+    }
+
+    /// Insert `text` as a new line before line `line` (1-based). A line number past
+    /// the end appends the text as the last line.
+    pub fn insert_line(
+        &mut self,
+        var_name: &str,
+        line: usize,
+        text: &str,
     ) -> Result<(), RuntimeError> {
-        let entry = self.get_entry_mut(var_name)?;
-        let new_content = replace_in_text(&entry.content, pattern, replacement)
-            .map_err(|e| RuntimeError::new(format!("Invalid regex '{}': {}", pattern, e)))?;
-        let filename = &entry.filename;
-        write_to_file(filename, &new_content).map_err(|e| {
-            RuntimeError::new(format!(
-                "Failed to write replaced content to file '{}': {}",
-                filename, e
-            ))
-        })?;
-        entry.content = new_content;
+        if line == 0 {
+            return Err(RuntimeError::new(
+                "Line numbers start at 1 in 'insertline'".to_string(),
+            ));
+        }
+        self.check_mode(var_name, "insert into", false)?;
+        let (mut lines, trailing_newline) = split_lines(self.get_text(var_name)?);
+        let index = (line - 1).min(lines.len());
+        lines.insert(index, normalize_line_endings(text));
+        let new_content = join_lines(&lines, trailing_newline);
+        self.update_content(var_name, new_content.into(), "write to file")
+    }
+
+    /// Delete lines `from` through `to` (1-based, inclusive).
+    pub fn delete_lines(
+        &mut self,
+        var_name: &str,
+        from: usize,
+        to: usize,
+    ) -> Result<(), RuntimeError> {
+        self.check_mode(var_name, "delete lines from", false)?;
+        let (mut lines, trailing_newline) = split_lines(self.get_text(var_name)?);
+        if from == 0 || to < from || to > lines.len() {
+            return Err(RuntimeError::new(format!(
+                "Line range {}-{} is out of range for '{}' ({} lines)",
+                from,
+                to,
+                var_name,
+                lines.len()
+            )));
+        }
+        lines.drain(from - 1..to);
+        let new_content = join_lines(&lines, trailing_newline);
+        self.update_content(var_name, new_content.into(), "write to file")
+    }
+
+    /// Sort the lines of a file (see `utils::sort_lines`).
+    pub fn sort_file_lines(&mut self, var_name: &str, numeric: bool) -> Result<(), RuntimeError> {
+        self.transform_lines(var_name, "sort lines of", |mut lines| {
+            sort_lines(&mut lines, numeric);
+            lines
+        })
+    }
+
+    /// Remove duplicate lines from a file (see `utils::uniq_lines`).
+    pub fn uniq_file_lines(&mut self, var_name: &str, all: bool) -> Result<(), RuntimeError> {
+        self.transform_lines(var_name, "deduplicate lines of", |lines| {
+            uniq_lines(lines, all)
+        })
+    }
+
+    /// Reverse the order of a file's lines.
+    pub fn reverse_file_lines(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        self.transform_lines(var_name, "reverse lines of", |mut lines| {
+            lines.reverse();
+            lines
+        })
+    }
+
+    /// Prefix every line of a file with its line number (see `utils::number_lines`).
+    pub fn number_file_lines(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        self.check_mode(var_name, "number lines of", false)?;
+        let new_content = number_lines(self.get_text(var_name)?);
+        self.update_content(var_name, new_content.into(), "write to file")
+    }
+
+    /// Strip whitespace from the lines of a file (see `utils::trim_lines`).
+    pub fn trim_file_lines(&mut self, var_name: &str, leading: bool) -> Result<(), RuntimeError> {
+        self.check_mode(var_name, "trim lines of", false)?;
+        let new_content = trim_lines(self.get_text(var_name)?, leading);
+        self.update_content(var_name, new_content.into(), "write to file")
+    }
+
+    /// Rewrite a file line by line, keeping a trailing newline if it had one.
+    /// `operation` describes the change for mode errors (e.g. "sort lines of").
+    fn transform_lines(
+        &mut self,
+        var_name: &str,
+        operation: &str,
+        transform: impl FnOnce(Vec<String>) -> Vec<String>,
+    ) -> Result<(), RuntimeError> {
+        self.check_mode(var_name, operation, false)?;
+        let (lines, trailing_newline) = split_lines(self.get_text(var_name)?);
+        let new_content = join_lines(&transform(lines), trailing_newline);
+        self.update_content(var_name, new_content.into(), "write to file")
+    }
+
+    /// Change the line ending a file is written with, rewriting it on disk
+    /// unless the entry is buffered.
+    pub fn set_line_ending(
+        &mut self,
+        var_name: &str,
+        line_ending: LineEnding,
+    ) -> Result<(), RuntimeError> {
+        self.check_mode(var_name, "change line endings of", false)?;
+        let content = self.get_text(var_name)?.to_string();
+        self.get_entry_mut(var_name)?.line_ending = line_ending;
+        self.update_content(var_name, content.into(), "write to file")
+    }
+
+    /// Replace a file's content in memory and, unless the entry is buffered, on
+    /// disk. `action` describes the write for error messages (e.g. "truncate file").
+    fn update_content(
+        &mut self,
+        var_name: &str,
+        new_content: Content,
+        action: &str,
+    ) -> Result<(), RuntimeError> {
+        let to_stdout = self.get_entry(var_name)?.target == FileTarget::Stdout;
+        if to_stdout && matches!(new_content, Content::Binary(_)) {
+            return Err(RuntimeError::new(format!(
+                "Cannot {} '{}': binary content can't be written to standard output",
+                action, var_name
+            )));
+        }
+        self.back_up(var_name, false)?;
+        let journaled = self.journal_write(var_name, false, action)?;
+        let dry_run = self.dry_run;
+        let entry = open_entry_mut(&mut self.files, var_name)?;
+        let mut planned = None;
+        if to_stdout {
+            if let Content::Text(text) = &new_content {
+                self.stdout.push(text.clone());
+            }
+        } else if matches!(entry.target, FileTarget::Stdin | FileTarget::Buffer) {
+            // Standard input and buffers are edited in memory only.
+        } else if entry.buffered {
+            entry.dirty = true;
+        } else if dry_run {
+            planned = Some(format!("{} \"{}\"", action, entry.filename));
+        } else {
+            let filename = &entry.filename;
+            let bytes = entry_bytes(entry, &new_content)?;
+            count_written(
+                &mut self.written_bytes,
+                self.max_write_bytes,
+                filename,
+                bytes.len(),
+            )?;
+            self.fs.write(filename, &bytes).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to {} '{}'", action, filename), e)
+            })?;
+            self.written.push((filename.clone(), bytes.len()));
+            entry.disk_stamp = disk_stamp(&*self.fs, filename);
+        }
+        if !entry.nomem {
+            entry.content = new_content;
+        }
+        self.planned.extend(planned);
+        self.journal.extend(journaled);
         Ok(())
     }
 
-    /// Count lines in a file's content.
-    pub fn line_count(&self, var_name: &str) -> Result<usize, RuntimeError> {
+    /// With `backup` on, copy a file's on-disk content to `<filename>.bak` before
+    /// the first write to it this run. Buffered entries only write when `saving`.
+    fn back_up(&mut self, var_name: &str, saving: bool) -> Result<(), RuntimeError> {
+        let entry = self.get_entry(var_name)?;
+        if !self.backup
+            || entry.target != FileTarget::Disk
+            || self.dry_run
+            || (entry.buffered && !saving)
+            || self.backed_up.contains(&entry.filename)
+        {
+            return Ok(());
+        }
+        let filename = entry.filename.clone();
+        if self.fs.path_kind(&filename) == PathKind::File {
+            let backup = backup_filename(&filename);
+            self.fs.copy(&filename, &backup).map_err(|e| {
+                RuntimeError::with_source(
+                    format!("Failed to back up '{}' to '{}'", filename, backup),
+                    e,
+                )
+            })?;
+        }
+        self.backed_up.insert(filename);
+        Ok(())
+    }
+
+    /// The journal entry undoing a write to a variable's file, taken before the
+    /// write. Like backups, buffered entries only write when `saving`.
+    fn journal_write(
+        &self,
+        var_name: &str,
+        saving: bool,
+        action: &str,
+    ) -> Result<Option<JournalEntry>, RuntimeError> {
         let entry = self.get_entry(var_name)?;
-        Ok(entry.content.lines().count())
+        if entry.target != FileTarget::Disk || self.dry_run || (entry.buffered && !saving) {
+            return Ok(None);
+        }
+        let description = format!("{} \"{}\"", action, entry.filename);
+        Ok(self
+            .journal
+            .snapshot(&*self.fs, description, &entry.filename))
+    }
+
+    /// Reverse the most recent journaled operation and reload the variables open
+    /// on the file it restored. Returns what was undone, or None if the journal is empty.
+    pub fn undo_last(&mut self) -> Result<Option<String>, RuntimeError> {
+        let Some(entry) = self.journal.pop() else {
+            return Ok(None);
+        };
+        let Some(action) = &entry.undo else {
+            return Err(RuntimeError::new(format!(
+                "Cannot undo '{}': it can't be reversed, so it was dropped from the journal",
+                entry.description
+            )));
+        };
+        action.apply(&*self.fs).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to undo '{}'", entry.description), e)
+        })?;
+        if let UndoAction::MoveBack { from, to, .. } = action {
+            for file in self.files.values_mut().filter(|f| &f.filename == from) {
+                file.filename = to.clone();
+            }
+        }
+        if let Some(path) = action.restored_path() {
+            self.reload_restored(path)?;
+        }
+        Ok(Some(entry.description))
+    }
+
+    /// Bring the variables open on a file `undo` changed back in line with the
+    /// disk, leaving unsaved buffered changes alone.
+    fn reload_restored(&mut self, path: &str) -> Result<(), RuntimeError> {
+        let exists = self.fs.path_kind(path) == PathKind::File;
+        let mut stale = Vec::new();
+        for (var_name, entry) in &mut self.files {
+            if entry.filename != path || entry.target != FileTarget::Disk || entry.dirty {
+                continue;
+            }
+            entry.disk_stamp = disk_stamp(&*self.fs, path);
+            if entry.is_open && !entry.nomem && exists {
+                stale.push(var_name.clone());
+            }
+        }
+        for var_name in stale {
+            self.read_file_content(&var_name)?;
+        }
+        Ok(())
+    }
+
+    /// Copy a file's `.bak` back over it and reload its content.
+    /// Returns a warning to show if the file had mixed line endings.
+    pub fn restore_backup(&mut self, var_name: &str) -> Result<Option<String>, RuntimeError> {
+        self.check_mode(var_name, "restore", false)?;
+        let filename = self.filename_of(var_name)?;
+        let backup = backup_filename(&filename);
+        if self.fs.path_kind(&backup) != PathKind::File {
+            return Err(RuntimeError::new(format!(
+                "No backup '{}' to restore '{}' from",
+                backup, filename
+            )));
+        }
+        if self.dry_run {
+            self.planned
+                .push(format!("restore \"{}\" from \"{}\"", filename, backup));
+            return Ok(None);
+        }
+        let description = format!("restore \"{}\" from \"{}\"", filename, backup);
+        let journaled = self.journal.snapshot(&*self.fs, description, &filename);
+        self.fs.copy(&backup, &filename).map_err(|e| {
+            RuntimeError::with_source(
+                format!("Failed to restore '{}' from '{}'", filename, backup),
+                e,
+            )
+        })?;
+        self.journal.extend(journaled);
+        if self.get_entry(var_name)?.nomem {
+            return Ok(None);
+        }
+        self.read_file_content(var_name)
+    }
+
+    /// Write a variable's content to a file, which the variable refers to from
+    /// then on (so a buffer becomes file-backed).
+    pub fn save_as(&mut self, var_name: &str, filename: &str) -> Result<(), RuntimeError> {
+        let entry = self.loaded_entry(var_name)?;
+        if matches!(entry.target, FileTarget::Stdin | FileTarget::Stdout) {
+            return Err(RuntimeError::new(format!(
+                "Cannot save '{}' as a file: it is {}",
+                var_name,
+                describe_target(entry.target)
+            )));
+        }
+        let dry_run = self.dry_run;
+        let entry = open_entry_mut(&mut self.files, var_name)?;
+        if dry_run {
+            self.planned
+                .push(format!("save \"{}\" as \"{}\"", var_name, filename));
+        } else {
+            let description = format!("save \"{}\" as \"{}\"", var_name, filename);
+            let journaled = self.journal.snapshot(&*self.fs, description, filename);
+            let bytes = entry_bytes(entry, &entry.content)?;
+            count_written(
+                &mut self.written_bytes,
+                self.max_write_bytes,
+                filename,
+                bytes.len(),
+            )?;
+            self.fs.write(filename, &bytes).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to save file '{}'", filename), e)
+            })?;
+            self.written.push((filename.to_string(), bytes.len()));
+            entry.disk_stamp = disk_stamp(&*self.fs, filename);
+            self.journal.extend(journaled);
+        }
+        entry.filename = filename.to_string();
+        entry.target = FileTarget::Disk;
+        entry.dirty = false;
+        Ok(())
     }
 
     /// Rename the file associated with a variable and update the environment.
     pub fn rename_file(&mut self, var_name: &str, new_filename: &str) -> Result<(), RuntimeError> {
-        let entry = self.get_entry_mut(var_name)?;
+        self.check_mode(var_name, "rename", true)?;
+        if self.dry_run {
+            let old_filename = self.filename_of(var_name)?;
+            self.planned.push(format!(
+                "rename \"{}\" to \"{}\"",
+                old_filename, new_filename
+            ));
+            self.get_entry_mut(var_name)?.filename = new_filename.to_string();
+            return Ok(());
+        }
+        let entry = open_entry_mut(&mut self.files, var_name)?;
         let old_filename = &entry.filename;
-        std::fs::rename(old_filename, new_filename).map_err(|e| {
-            RuntimeError::new(format!(
-                "Failed to rename file '{}' to '{}': {}",
-                old_filename, new_filename, e
-            ))
+        let description = format!("rename \"{}\" to \"{}\"", old_filename, new_filename);
+        let journaled = self
+            .journal
+            .moved(&*self.fs, description, old_filename, new_filename);
+        self.fs.rename(old_filename, new_filename).map_err(|e| {
+            RuntimeError::with_source(
+                format!(
+                    "Failed to rename file '{}' to '{}'",
+                    old_filename, new_filename
+                ),
+                e,
+            )
         })?;
         entry.filename = new_filename.to_string();
+        self.journal.extend(journaled);
+        Ok(())
+    }
+
+    /// Get the on-disk filename behind an open variable.
+    pub fn filename_of(&self, var_name: &str) -> Result<String, RuntimeError> {
+        let entry = self.get_entry(var_name)?;
+        if entry.target != FileTarget::Disk {
+            return Err(RuntimeError::new(format!(
+                "Variable '{}' is {}, not a file",
+                var_name,
+                describe_target(entry.target)
+            )));
+        }
+        Ok(entry.filename.clone())
+    }
+
+    /// Check that the variable's open mode allows an operation. Operations that
+    /// only add to the end of the file (`appends`) are allowed in appendonly mode.
+    fn check_mode(
+        &self,
+        var_name: &str,
+        operation: &str,
+        appends: bool,
+    ) -> Result<(), RuntimeError> {
+        let entry = self.get_entry(var_name)?;
+        match entry.target {
+            FileTarget::Disk => {}
+            FileTarget::Stdin if matches!(operation, "save" | "rename" | "restore") => {
+                return Err(RuntimeError::new(format!(
+                    "Cannot {} '{}': it is standard input",
+                    operation, var_name
+                )));
+            }
+            FileTarget::Stdin => {}
+            FileTarget::Buffer if matches!(operation, "save" | "rename" | "restore") => {
+                return Err(RuntimeError::new(format!(
+                    "Cannot {} '{}': it is a buffer (use 'saveas {} \"filename\"')",
+                    operation, var_name, var_name
+                )));
+            }
+            FileTarget::Buffer => {}
+            FileTarget::Stdout if operation == "write to" || operation == "append to" => {}
+            FileTarget::Stdout => {
+                return Err(RuntimeError::new(format!(
+                    "Cannot {} '{}': it is standard output (only write and append work on it)",
+                    operation, var_name
+                )));
+            }
+        }
+        let allowed = match entry.mode {
+            OpenMode::Writable => true,
+            OpenMode::AppendOnly => appends,
+            OpenMode::ReadOnly => false,
+        };
+        if !allowed {
+            return Err(RuntimeError::new(format!(
+                "Cannot {} '{}': file is open {}",
+                operation,
+                var_name,
+                entry.mode.as_str()
+            )));
+        }
         Ok(())
     }
 
+    /// Get the text content of an open file, failing if the content is binary.
+    fn get_text(&self, var_name: &str) -> Result<&str, RuntimeError> {
+        let entry = self.loaded_entry(var_name)?;
+        if entry.unread {
+            return Err(RuntimeError::new(format!(
+                "Variable '{}' is standard input; use 'read {}' first",
+                var_name, var_name
+            )));
+        }
+        match &entry.content {
+            Content::Text(text) => Ok(text),
+            Content::Binary(_) => Err(RuntimeError::new(format!(
+                "Variable '{}' refers to binary file '{}'; this operation needs text content",
+                var_name, entry.filename
+            ))),
+        }
+    }
+
+    /// Get a file entry whose content is held in memory, i.e. not `nomem`.
+    fn loaded_entry(&self, var_name: &str) -> Result<&FileEntry, RuntimeError> {
+        let entry = self.get_entry(var_name)?;
+        if entry.nomem {
+            return Err(RuntimeError::new(format!(
+                "Variable '{}' doesn't keep its content in memory (nomem); use 'read {}' first",
+                var_name, var_name
+            )));
+        }
+        Ok(entry)
+    }
+
     /// Get read-only reference to a file entry.
     fn get_entry(&self, var_name: &str) -> Result<&FileEntry, RuntimeError> {
         let entry = self
             .files
             .get(var_name)
-            .ok_or_else(|| RuntimeError::new(format!("No such variable '{}'", var_name)))?;
+            .ok_or_else(|| no_such_variable(&self.files, var_name))?;
         if !entry.is_open {
             return Err(RuntimeError::new(format!(
                 "Variable '{}' file is not open.",
@@ -202,28 +1511,465 @@ impl Environment {
 
     /// Get mutable reference to a file entry.
     fn get_entry_mut(&mut self, var_name: &str) -> Result<&mut FileEntry, RuntimeError> {
-        let entry = self
+        open_entry_mut(&mut self.files, var_name)
+    }
+
+    /// The names of the file variables, sorted.
+    pub fn variable_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.files.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// Snapshot the file variables, string variables, and working directory for
+    /// `savesession`, with file content only when `full`.
+    pub fn to_session(&self, full: bool) -> Session {
+        let files = self
             .files
-            .get_mut(var_name)
-            .ok_or_else(|| RuntimeError::new(format!("No such variable '{}'", var_name)))?;
-        if !entry.is_open {
+            .iter()
+            .map(|(var_name, entry)| {
+                let saved = SessionEntry {
+                    filename: entry.filename.clone(),
+                    is_open: entry.is_open,
+                    mode: entry.mode,
+                    buffered: entry.buffered,
+                    nomem: entry.nomem,
+                    target: entry.target,
+                    encoding: entry.encoding,
+                    bom: entry.bom,
+                    line_ending: entry.line_ending,
+                    dirty: entry.dirty,
+                    content: (full && !entry.nomem && !entry.unread).then(|| entry.content.clone()),
+                };
+                (var_name.clone(), saved)
+            })
+            .collect();
+        let globals = self
+            .globals
+            .iter()
+            .filter(|(name, _)| !self.env_globals.contains(*name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        Session {
+            current_dir: self.current_dir.clone(),
+            globals,
+            files,
+        }
+    }
+
+    /// Restore a session saved by `to_session`, reading file content from disk
+    /// where the session doesn't hold it. An environment that already has file
+    /// variables only takes the session with `merge`, which keeps its working
+    /// directory and refuses to replace variables that are open. String variables
+    /// already set (like `argcount`) keep their values. Entries whose file no
+    /// longer exists are closed. Returns warnings to show.
+    pub fn from_session(
+        &mut self,
+        session: Session,
+        merge: bool,
+    ) -> Result<Vec<String>, RuntimeError> {
+        if !merge && !self.files.is_empty() {
             return Err(RuntimeError::new(format!(
-                "Variable '{}' file is not open.",
+                "Cannot load session: variables are already defined ({}); add 'merge' to load it alongside them",
+                self.variable_names().join(", ")
+            )));
+        }
+        let open_conflict = session
+            .files
+            .keys()
+            .find(|name| self.files.get(*name).is_some_and(|entry| entry.is_open));
+        if let Some(var_name) = open_conflict {
+            return Err(RuntimeError::new(format!(
+                "Cannot merge session: variable '{}' is already open",
                 var_name
             )));
         }
-        Ok(entry)
+        let mut warnings = Vec::new();
+        for (var_name, saved) in session.files {
+            let mut entry = FileEntry {
+                filename: saved.filename,
+                unread: saved.target == FileTarget::Stdin && saved.content.is_none(),
+                dirty: saved.dirty && saved.content.is_some(),
+                content: saved.content.unwrap_or(Content::Text(String::new())),
+                is_open: saved.is_open,
+                mode: saved.mode,
+                buffered: saved.buffered,
+                encoding: saved.encoding,
+                bom: saved.bom,
+                line_ending: saved.line_ending,
+                disk_stamp: None,
+                lock: None,
+                target: saved.target,
+                nomem: saved.nomem,
+            };
+            if entry.target == FileTarget::Disk && entry.is_open {
+                if self.fs.path_kind(&entry.filename) != PathKind::File {
+                    warnings.push(format!(
+                        "'{}' no longer exists, so '{}' was closed",
+                        entry.filename, var_name
+                    ));
+                    entry.is_open = false;
+                } else if !entry.nomem && !entry.dirty {
+                    let loaded = load_content(
+                        &*self.fs,
+                        &entry.filename,
+                        Some(entry.encoding),
+                        self.max_read_bytes,
+                    )?;
+                    warnings.extend(mixed_warning(&entry.filename, &loaded));
+                    entry.content = loaded.content;
+                    entry.bom = loaded.bom;
+                    entry.line_ending = loaded.line_ending;
+                }
+                entry.disk_stamp = disk_stamp(&*self.fs, &entry.filename);
+            }
+            self.files.insert(var_name, entry);
+        }
+        for (name, value) in session.globals {
+            if !self.globals.contains_key(&name) {
+                self.set_global(&name, value);
+            }
+        }
+        if !merge {
+            self.current_dir = session.current_dir;
+        }
+        Ok(warnings)
     }
 
-    /// Dump the environment: list all variables and their files, open state.
-    pub fn dump(&self) {
-        println!("Environment Variables:");
+    /// Dump the environment: list all variables, sorted by name, with their
+    /// files, open state, and content size.
+    pub fn dump(&self) -> String {
+        let working_dir = match self.working_dir() {
+            Ok(dir) => dir.display().to_string(),
+            Err(e) => e.to_string(),
+        };
+        let mut lines = vec![
+            format!("Working directory: {}", working_dir),
+            "Environment Variables:".to_string(),
+        ];
         if self.files.is_empty() {
-            println!("  (none)");
+            lines.push("  (none)".to_string());
+        }
+        let mut vars: Vec<&String> = self.files.keys().collect();
+        vars.sort();
+        for var in vars {
+            let entry = &self.files[var];
+            let dirty = if entry.dirty { "*" } else { "" };
+            lines.push(format!(
+                "  {}{} -> {} [{}] {}",
+                var,
+                dirty,
+                entry.filename,
+                entry_state(entry).join(", "),
+                content_summary(entry)
+            ));
+        }
+        if !self.globals.is_empty() {
+            lines.push("String Variables:".to_string());
+            let mut names: Vec<&String> = self.globals.keys().collect();
+            names.sort();
+            for name in names {
+                if self.env_globals.contains(name) {
+                    lines.push(format!("  {} (from the process environment)", name));
+                } else {
+                    lines.push(format!("  {} = {:?}", name, self.globals[name]));
+                }
+            }
         }
-        for (var, entry) in &self.files {
-            let state = if entry.is_open { "open" } else { "closed" };
-            println!("  {} -> {} [{}]", var, entry.filename, state);
+        lines.join("\n")
+    }
+
+    /// Describe one variable in detail: its file, state, size, line count,
+    /// encoding, and mode (or the value of a string variable).
+    pub fn dump_variable(&self, var_name: &str) -> Result<String, RuntimeError> {
+        let Some(entry) = self.files.get(var_name) else {
+            return match self.globals.get(var_name) {
+                Some(_) if self.env_globals.contains(var_name) => Ok(format!(
+                    "{}: string variable (from the process environment)",
+                    var_name
+                )),
+                Some(value) => Ok(format!("{}: string variable = {:?}", var_name, value)),
+                None => Err(no_such_variable(&self.files, var_name)),
+            };
+        };
+        let mut state = entry_state(entry);
+        if entry.dirty {
+            state.push("unsaved changes");
         }
+        let (size, lines) = if entry.nomem {
+            let held = "not held in memory (nomem)".to_string();
+            (held.clone(), held)
+        } else {
+            let lines = match &entry.content {
+                Content::Text(text) => text.lines().count().to_string(),
+                Content::Binary(_) => "binary content".to_string(),
+            };
+            (format!("{} bytes", entry.content.as_bytes().len()), lines)
+        };
+        let bom = if entry.bom { " (with BOM)" } else { "" };
+        Ok([
+            format!("{}:", var_name),
+            format!("  File:        {}", entry.filename),
+            format!("  State:       {}", state.join(", ")),
+            format!("  Size:        {}", size),
+            format!("  Lines:       {}", lines),
+            format!("  Encoding:    {}{}", entry.encoding.as_str(), bom),
+            format!("  Line ending: {}", entry.line_ending.as_str()),
+            format!("  Mode:        {}", entry.mode.as_str()),
+        ]
+        .join("\n"))
+    }
+}
+
+/// The open state of a variable and the flags it was opened with, for `dump`.
+fn entry_state(entry: &FileEntry) -> Vec<&'static str> {
+    let mut state = vec![if entry.is_open { "open" } else { "closed" }];
+    if entry.buffered {
+        state.push("buffered");
+    }
+    match entry.lock {
+        Some((LockKind::Exclusive, _)) => state.push("locked"),
+        Some((LockKind::Shared, _)) => state.push("shared lock"),
+        None => {}
+    }
+    if entry.nomem {
+        state.push("nomem");
+    }
+    state
+}
+
+/// The size and line count of a variable's in-memory content, for `dump`.
+fn content_summary(entry: &FileEntry) -> String {
+    if entry.nomem {
+        return "(content not in memory)".to_string();
     }
+    let bytes = entry.content.as_bytes().len();
+    match &entry.content {
+        Content::Text(text) => format!("{} bytes, {} lines", bytes, text.lines().count()),
+        Content::Binary(_) => format!("{} bytes, binary", bytes),
+    }
+}
+
+/// What a variable that isn't a file refers to, for error messages.
+fn describe_target(target: FileTarget) -> &'static str {
+    match target {
+        FileTarget::Disk => "a file",
+        FileTarget::Stdin => "standard input",
+        FileTarget::Stdout => "standard output",
+        FileTarget::Buffer => "a buffer",
+    }
+}
+
+/// A file's modification time and size, or None if it can't be read.
+fn disk_stamp(fs: &dyn FileSystem, filename: &str) -> Option<(SystemTime, u64)> {
+    fs.metadata(filename)
+        .ok()
+        .map(|meta| (meta.modified, meta.len))
+}
+
+/// Where `set backup on` keeps a file's original content.
+fn backup_filename(filename: &str) -> String {
+    format!("{}.bak", filename)
+}
+
+/// A file's content as loaded from disk, with what is needed to write it back.
+struct Loaded {
+    content: Content,
+    encoding: Encoding,
+    bom: bool,
+    line_ending: LineEnding,
+    /// Whether the text used both line endings before being normalized.
+    mixed: bool,
+}
+
+/// How many edits away a misspelled variable may be for its name to be suggested.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// The error for an unknown variable, suggesting the closest existing name.
+fn no_such_variable(files: &HashMap<String, FileEntry>, var_name: &str) -> RuntimeError {
+    let mut names: Vec<&str> = files.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    match closest_match(var_name, names, MAX_SUGGESTION_DISTANCE) {
+        Some(name) => RuntimeError::new(format!(
+            "No such variable '{}'; did you mean '{}'?",
+            var_name, name
+        )),
+        None => RuntimeError::new(format!("No such variable '{}'", var_name)),
+    }
+}
+
+/// Look up an open entry; a free function so callers can borrow `fs` alongside it.
+fn open_entry_mut<'a>(
+    files: &'a mut HashMap<String, FileEntry>,
+    var_name: &str,
+) -> Result<&'a mut FileEntry, RuntimeError> {
+    if !files.contains_key(var_name) {
+        return Err(no_such_variable(files, var_name));
+    }
+    let entry = files
+        .get_mut(var_name)
+        .expect("the variable was just checked");
+    if !entry.is_open {
+        return Err(RuntimeError::new(format!(
+            "Variable '{}' file is not open.",
+            var_name
+        )));
+    }
+    Ok(entry)
+}
+
+/// Refuse to read `len` bytes of a file when that is over the read limit.
+fn check_read_limit(filename: &str, len: u64, max: u64) -> Result<(), RuntimeError> {
+    if len > max {
+        return Err(RuntimeError::new(format!(
+            "Read limit exceeded: reading '{}' takes {} bytes, over the limit of {} bytes",
+            filename, len, max
+        )));
+    }
+    Ok(())
+}
+
+/// Count `len` more bytes written to `filename` towards `written`, refusing
+/// the write if that would pass the write limit.
+fn count_written(
+    written: &mut u64,
+    max: Option<u64>,
+    filename: &str,
+    len: usize,
+) -> Result<(), RuntimeError> {
+    let total = *written + len as u64;
+    if let Some(max) = max.filter(|&max| total > max) {
+        return Err(RuntimeError::new(format!(
+            "Write limit exceeded: writing {} bytes to '{}' brings the total written to {} bytes, over the limit of {} bytes",
+            len, filename, total, max
+        )));
+    }
+    *written = total;
+    Ok(())
+}
+
+/// Read a file from disk and decode it. With no explicit encoding, a UTF-16 byte
+/// order mark selects UTF-16; otherwise UTF-8 is assumed and invalid UTF-8 is kept
+/// as binary. Text is normalized to `\n` line endings.
+fn load_content(
+    fs: &dyn FileSystem,
+    filename: &str,
+    encoding: Option<Encoding>,
+    max_bytes: Option<u64>,
+) -> Result<Loaded, RuntimeError> {
+    if let (Some(max), Ok(meta)) = (max_bytes, fs.metadata(filename)) {
+        check_read_limit(filename, meta.len, max)?;
+    }
+    let bytes = fs
+        .read(filename)
+        .map_err(|e| RuntimeError::with_source(format!("Failed to read file '{}'", filename), e))?;
+    let (content, encoding, bom) = match encoding.or_else(|| detect_bom(&bytes)) {
+        Some(encoding) => {
+            let (text, bom) = decode_file(filename, &bytes, encoding)?;
+            (Content::Text(text), encoding, bom)
+        }
+        None => (Content::from_bytes(bytes), Encoding::Utf8, false),
+    };
+    let (content, line_ending, mixed) = match content {
+        Content::Text(text) => {
+            let (line_ending, mixed) = detect_line_ending(&text);
+            (
+                Content::Text(normalize_line_endings(&text)),
+                line_ending,
+                mixed,
+            )
+        }
+        binary => (binary, LineEnding::Lf, false),
+    };
+    Ok(Loaded {
+        content,
+        encoding,
+        bom,
+        line_ending,
+        mixed,
+    })
+}
+
+/// The warning to show for a file loaded with mixed line endings.
+fn mixed_warning(filename: &str, loaded: &Loaded) -> Option<String> {
+    loaded.mixed.then(|| {
+        format!(
+            "'{}' has mixed line endings; normalized to {}",
+            filename,
+            loaded.line_ending.as_str()
+        )
+    })
+}
+
+/// Decode a file's bytes with an explicit encoding, stripping a leading byte
+/// order mark. Returns the text and whether a BOM was present.
+fn decode_file(
+    filename: &str,
+    bytes: &[u8],
+    encoding: Encoding,
+) -> Result<(String, bool), RuntimeError> {
+    let bom = !encoding.bom().is_empty() && bytes.starts_with(encoding.bom());
+    let body = if bom {
+        &bytes[encoding.bom().len()..]
+    } else {
+        bytes
+    };
+    let text = decode_text(body, encoding).map_err(|e| {
+        RuntimeError::with_source(
+            format!(
+                "Failed to decode file '{}' as {}",
+                filename,
+                encoding.as_str()
+            ),
+            e,
+        )
+    })?;
+    Ok((text, bom))
+}
+
+/// The bytes to write to disk for an entry's content, with the entry's line
+/// ending, encoding, and byte order mark applied.
+fn entry_bytes<'a>(entry: &FileEntry, content: &'a Content) -> Result<Cow<'a, [u8]>, RuntimeError> {
+    match content {
+        Content::Text(text)
+            if entry.encoding != Encoding::Utf8
+                || entry.bom
+                || entry.line_ending != LineEnding::Lf =>
+        {
+            let text = apply_line_ending(text, entry.line_ending);
+            Ok(Cow::Owned(encode_for(
+                &entry.filename,
+                &text,
+                entry.encoding,
+                entry.bom,
+            )?))
+        }
+        _ => Ok(Cow::Borrowed(content.as_bytes())),
+    }
+}
+
+/// Encode text for writing to `filename`, optionally preceded by a byte order mark.
+fn encode_for(
+    filename: &str,
+    text: &str,
+    encoding: Encoding,
+    with_bom: bool,
+) -> Result<Vec<u8>, RuntimeError> {
+    let mut bytes = if with_bom {
+        encoding.bom().to_vec()
+    } else {
+        Vec::new()
+    };
+    let encoded = encode_text(text, encoding).map_err(|e| {
+        RuntimeError::with_source(
+            format!(
+                "Failed to encode text for '{}' as {}",
+                filename,
+                encoding.as_str()
+            ),
+            e,
+        )
+    })?;
+    bytes.extend(encoded);
+    Ok(bytes)
 }
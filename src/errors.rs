@@ -2,76 +2,306 @@
  * errors.rs
  *
  * Defines error types for lexing, parsing, and runtime.
- * Each error type is simple and just carries a string message.
+ * Each error type carries a string message and, optionally, the underlying
+ * error that caused it (an io::Error, a regex::Error, ...). FileLangError
+ * wraps all three so callers can match on the phase that failed.
+ *
+ * A ParseError can be marked incomplete: the input ran out in the middle of a
+ * statement or block, so more lines could still make it parse (the interactive
+ * prompt uses this to keep reading, and editors to tell it from a real mistake).
+ *
+ * `render_diagnostic` shows where in the source a lexing or parsing error is,
+ * with a caret under the offending character.
  */
 
+use std::error::Error;
 use std::fmt;
 
+/// The underlying cause attached to one of the error types.
+pub type ErrorSource = Box<dyn Error + Send + Sync>;
+
 /// Error type for lexing
 pub struct LexError {
     msg: String,
+    source: Option<ErrorSource>,
+    /// Byte offset in the source where the error was found, if known.
+    pos: Option<usize>,
 }
 
 impl LexError {
     pub fn new(msg: String) -> Self {
-        Self { msg }
+        Self {
+            msg,
+            source: None,
+            pos: None,
+        }
+    }
+
+    /// Create an error caused by another error.
+    pub fn with_source(msg: String, source: impl Into<ErrorSource>) -> Self {
+        Self {
+            msg,
+            source: Some(source.into()),
+            pos: None,
+        }
+    }
+
+    /// Record where in the source the error was found.
+    pub fn at(mut self, pos: usize) -> Self {
+        self.pos = Some(pos);
+        self
+    }
+
+    /// The byte offset in the source where the error was found, if known.
+    pub fn position(&self) -> Option<usize> {
+        self.pos
     }
 }
 
 impl fmt::Display for LexError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "LexError: {}", self.msg)
+        write!(f, "LexError: {}", self.msg)?;
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
     }
 }
 
 impl fmt::Debug for LexError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "LexError: {}", self.msg)
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for LexError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn Error + 'static))
     }
 }
 
 /// Error type for parsing
 pub struct ParseError {
     msg: String,
+    source: Option<ErrorSource>,
+    /// Byte offset in the source where the error was found, if known.
+    pos: Option<usize>,
+    /// Whether the input ended mid-construct rather than being wrong.
+    incomplete: bool,
 }
 
 impl ParseError {
     pub fn new(msg: String) -> Self {
-        Self { msg }
+        Self {
+            msg,
+            source: None,
+            pos: None,
+            incomplete: false,
+        }
+    }
+
+    /// Create an error caused by another error.
+    pub fn with_source(msg: String, source: impl Into<ErrorSource>) -> Self {
+        Self {
+            msg,
+            source: Some(source.into()),
+            pos: None,
+            incomplete: false,
+        }
+    }
+
+    /// Create an error for input that ended inside a statement or block.
+    pub fn incomplete(msg: String) -> Self {
+        Self {
+            incomplete: true,
+            ..Self::new(msg)
+        }
+    }
+
+    /// Whether the input ended inside a statement or block, so that more input
+    /// might complete it.
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+
+    /// Record where in the source the error was found.
+    pub fn at(mut self, pos: usize) -> Self {
+        self.pos = Some(pos);
+        self
+    }
+
+    /// The byte offset in the source where the error was found, if known.
+    pub fn position(&self) -> Option<usize> {
+        self.pos
     }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ParseError: {}", self.msg)
+        write!(f, "ParseError: {}", self.msg)?;
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
     }
 }
 
 impl fmt::Debug for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ParseError: {}", self.msg)
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn Error + 'static))
     }
 }
 
 /// Error type for runtime
 pub struct RuntimeError {
     msg: String,
+    source: Option<ErrorSource>,
 }
 
 impl RuntimeError {
     pub fn new(msg: String) -> Self {
-        Self { msg }
+        Self { msg, source: None }
+    }
+
+    /// Create an error caused by another error.
+    pub fn with_source(msg: String, source: impl Into<ErrorSource>) -> Self {
+        Self {
+            msg,
+            source: Some(source.into()),
+        }
+    }
+
+    /// The message and its cause, without the "RuntimeError:" prefix.
+    pub fn message(&self) -> String {
+        match &self.source {
+            Some(source) => format!("{}: {}", self.msg, source),
+            None => self.msg.clone(),
+        }
     }
 }
 
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "RuntimeError: {}", self.msg)
+        write!(f, "RuntimeError: {}", self.msg)?;
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
     }
 }
 
 impl fmt::Debug for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "RuntimeError: {}", self.msg)
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+/// Any error produced while running a script, tagged with the phase that failed.
+#[derive(Debug)]
+pub enum FileLangError {
+    Lex(LexError),
+    Parse(ParseError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for FileLangError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileLangError::Lex(e) => fmt::Display::fmt(e, f),
+            FileLangError::Parse(e) => fmt::Display::fmt(e, f),
+            FileLangError::Runtime(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl FileLangError {
+    /// The byte offset in the source a lexing or parsing error was found at.
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            FileLangError::Lex(e) => e.position(),
+            FileLangError::Parse(e) => e.position(),
+            FileLangError::Runtime(_) => None,
+        }
+    }
+
+    /// Whether this is a parsing error for input that ended too soon.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, FileLangError::Parse(e) if e.is_incomplete())
+    }
+}
+
+impl Error for FileLangError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FileLangError::Lex(e) => Some(e),
+            FileLangError::Parse(e) => Some(e),
+            FileLangError::Runtime(e) => Some(e),
+        }
+    }
+}
+
+impl From<LexError> for FileLangError {
+    fn from(e: LexError) -> Self {
+        FileLangError::Lex(e)
+    }
+}
+
+impl From<ParseError> for FileLangError {
+    fn from(e: ParseError) -> Self {
+        FileLangError::Parse(e)
+    }
+}
+
+impl From<RuntimeError> for FileLangError {
+    fn from(e: RuntimeError) -> Self {
+        FileLangError::Runtime(e)
+    }
+}
+
+/// Columns a tab advances to the next multiple of in `render_diagnostic`.
+const TAB_WIDTH: usize = 4;
+
+/// The source line containing byte offset `pos`, indented by four spaces, and
+/// under it a caret at `pos` followed by `label` (if not empty). Tabs are
+/// expanded in both lines so the caret lines up with the character above it.
+pub fn render_diagnostic(source: &str, pos: usize, label: &str) -> String {
+    let mut pos = pos.min(source.len());
+    while !source.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    let line_start = source[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[pos..].find('\n').map_or(source.len(), |i| pos + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+    let mut shown = String::new();
+    let mut caret_column = None;
+    for (offset, c) in line.char_indices() {
+        if line_start + offset >= pos && caret_column.is_none() {
+            caret_column = Some(shown.chars().count());
+        }
+        if c == '\t' {
+            let width = TAB_WIDTH - shown.chars().count() % TAB_WIDTH;
+            shown.extend(std::iter::repeat_n(' ', width));
+        } else {
+            shown.push(c);
+        }
+    }
+    let column = caret_column.unwrap_or(shown.chars().count());
+    let mut caret = format!("    {}^", " ".repeat(column));
+    if !label.is_empty() {
+        caret.push(' ');
+        caret.push_str(label);
     }
+    format!("    {}\n{}\n", shown.trim_end(), caret)
 }
@@ -0,0 +1,359 @@
+/*!
+ * execution.rs
+ *
+ * An ExecutionSession runs a script one statement at a time, for hosts such as
+ * debuggers; `Interpreter::run` is a session run to the end.
+ * - `step` runs the next statement. A block statement (`while`, `if`, `try`,
+ *   `call`, `include`) only enters its block, whose statements are then stepped
+ *   one by one; the loop condition is re-checked once the body is done.
+ * - `current_statement` is the statement the next step runs, and `environment`
+ *   shows the file variables and globals between steps.
+ *
+ * Errors are handled as when running straight through: a `try` around the
+ * failing statement runs its handler, and `set onerror continue` reports the
+ * error and moves on. An error nothing catches ends the session.
+ *
+ * `run_to_end` pauses, returning control to the caller, before a statement on
+ * a line given to `set_breakpoint`, after a `breakpoint` statement, and after
+ * a statement changes the content of a variable given to `watch_variable`.
+ * Calling it again resumes. Breakpoint lines are counted in the file each
+ * statement comes from, so they also match lines of included scripts.
+ *
+ * `watch` and `parallel` blocks run whole, in a single step.
+ */
+
+use crate::ast::{Condition, Located, Statement};
+use crate::environment::Environment;
+use crate::errors::RuntimeError;
+use crate::interpreter::{ExecResult, Interpreter};
+use crate::tokens::Token;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// What one `step` did.
+#[derive(Clone)]
+pub struct StepOutcome {
+    /// The line of the statement that ran.
+    pub line: usize,
+    pub statement: Statement,
+    /// Whether that was the last statement, so there is nothing left to step.
+    pub finished: bool,
+    /// The watched variables whose content the statement changed.
+    pub changed: Vec<String>,
+}
+
+/// Why `run_to_end` returned.
+#[derive(Clone)]
+pub enum RunOutcome {
+    /// The script ran to the end.
+    Finished,
+    /// Stopped at a breakpoint: before the statement on `line` (one given to
+    /// `set_breakpoint`) or just after the `breakpoint` statement there.
+    Paused { line: usize },
+    /// Stopped after the statement on `line` changed watched variables.
+    Changed {
+        line: usize,
+        statement: Statement,
+        var_names: Vec<String>,
+    },
+}
+
+/// What entering a statement did.
+pub(crate) enum Entered {
+    /// The statement ran, with this result.
+    Ran(ExecResult),
+    /// The statement opened a block, with these statements, to run next.
+    Block(Vec<Located>, Block),
+}
+
+/// The kind of block being stepped through, with what is needed to carry on
+/// or to clean up once it is done.
+pub(crate) enum Block {
+    /// The script itself.
+    Script,
+    If,
+    /// A loop body; `iterations` counts the passes so far.
+    While {
+        condition: Condition,
+        line: usize,
+        iterations: usize,
+    },
+    /// A `try` body, with the handler to run if it fails.
+    Try {
+        handler: Vec<Token>,
+    },
+    /// A `try` handler, with `${error}` bound.
+    Handler,
+    /// A function body, with its parameters bound.
+    Call,
+    /// An included script, with the working directory to go back to.
+    Include {
+        previous_dir: Option<PathBuf>,
+    },
+}
+
+/// A block being run, and where in it the session is.
+struct Frame {
+    statements: Vec<Located>,
+    /// The index of the next statement to run.
+    next: usize,
+    block: Block,
+    /// The statement that opened the block, and when it started (if profiling),
+    /// finished once the block is done.
+    opened_by: Option<(Located, Option<Instant>)>,
+}
+
+/// A script being run step by step, made by `Interpreter::prepare`.
+/// Dropping the session part way leaves the interpreter outside any blocks it
+/// had entered.
+pub struct ExecutionSession<'a> {
+    interpreter: &'a mut Interpreter,
+    /// The blocks being run, outermost (the script) first. Empty once finished.
+    frames: Vec<Frame>,
+    /// Lines `run_to_end` stops before.
+    breakpoints: BTreeSet<usize>,
+    /// Whether `run_to_end` last stopped before a breakpoint line, which it
+    /// passes when resumed.
+    at_breakpoint: bool,
+    /// Watched variables, with their content as of the last step (None when
+    /// they have none, e.g. they aren't open).
+    watched: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+impl<'a> ExecutionSession<'a> {
+    pub(crate) fn new(interpreter: &'a mut Interpreter, statements: Vec<Located>) -> Self {
+        let frame = Frame {
+            statements,
+            next: 0,
+            block: Block::Script,
+            opened_by: None,
+        };
+        let mut session = Self {
+            interpreter,
+            frames: vec![frame],
+            breakpoints: BTreeSet::new(),
+            at_breakpoint: false,
+            watched: BTreeMap::new(),
+        };
+        session
+            .advance()
+            .expect("leaving the script block can't fail");
+        session
+    }
+
+    /// The statement the next `step` runs, or None once the script has finished.
+    pub fn current_statement(&self) -> Option<&Located> {
+        let frame = self.frames.last()?;
+        frame.statements.get(frame.next)
+    }
+
+    /// The file variables and globals as they are between steps.
+    pub fn environment(&self) -> &Environment {
+        self.interpreter.environment()
+    }
+
+    /// Make `run_to_end` stop before running a statement on `line`.
+    pub fn set_breakpoint(&mut self, line: usize) {
+        self.breakpoints.insert(line);
+    }
+
+    pub fn clear_breakpoint(&mut self, line: usize) {
+        self.breakpoints.remove(&line);
+    }
+
+    /// Report each statement that changes the content of the file variable
+    /// `var_name` (which needn't be open yet): in `StepOutcome::changed`, and
+    /// by `run_to_end` stopping after it.
+    pub fn watch_variable(&mut self, var_name: &str) {
+        let content = self.content_of(var_name);
+        self.watched.insert(var_name.to_string(), content);
+    }
+
+    /// Whether the script has finished (or failed), so there is nothing left to step.
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Run the next statement. An error that no `try` or `set onerror continue`
+    /// handles is returned, and finishes the session.
+    pub fn step(&mut self) -> Result<StepOutcome, RuntimeError> {
+        let Some(located) = self.current_statement().cloned() else {
+            return Err(RuntimeError::new(
+                "Nothing left to run: the script has finished".to_string(),
+            ));
+        };
+        if let Some(frame) = self.frames.last_mut() {
+            frame.next += 1;
+        }
+        self.at_breakpoint = false;
+        self.run_statement(&located)?;
+        self.advance()?;
+        Ok(StepOutcome {
+            line: located.line,
+            statement: located.statement,
+            finished: self.frames.is_empty(),
+            changed: self.changed_variables(),
+        })
+    }
+
+    /// Step until the script finishes, or a breakpoint or a change to a watched
+    /// variable stops it.
+    pub fn run_to_end(&mut self) -> Result<RunOutcome, RuntimeError> {
+        while let Some(next) = self.current_statement() {
+            let line = next.line;
+            if self.breakpoints.contains(&line) && !self.at_breakpoint {
+                self.at_breakpoint = true;
+                return Ok(RunOutcome::Paused { line });
+            }
+            let outcome = self.step()?;
+            if let Statement::Breakpoint(_) = outcome.statement {
+                return Ok(RunOutcome::Paused { line: outcome.line });
+            }
+            if !outcome.changed.is_empty() {
+                return Ok(RunOutcome::Changed {
+                    line: outcome.line,
+                    statement: outcome.statement,
+                    var_names: outcome.changed,
+                });
+            }
+        }
+        Ok(RunOutcome::Finished)
+    }
+
+    /// The interpreter, for the caller to deal with a pause.
+    pub(crate) fn interpreter(&mut self) -> &mut Interpreter {
+        self.interpreter
+    }
+
+    /// The watched variables whose content differs from the last time, noting
+    /// their new content.
+    fn changed_variables(&mut self) -> Vec<String> {
+        let mut changed = Vec::new();
+        for (var_name, last) in &self.watched {
+            if self.content_of(var_name) != *last {
+                changed.push(var_name.clone());
+            }
+        }
+        for var_name in &changed {
+            let content = self.content_of(var_name);
+            self.watched.insert(var_name.clone(), content);
+        }
+        changed
+    }
+
+    fn content_of(&self, var_name: &str) -> Option<Vec<u8>> {
+        self.interpreter.environment().get_file_bytes(var_name).ok()
+    }
+
+    /// Run a statement, or enter the block it opens.
+    fn run_statement(&mut self, located: &Located) -> Result<(), RuntimeError> {
+        let started = match self.interpreter.begin_statement(located) {
+            Ok(started) => started,
+            Err(e) => return self.fail(e),
+        };
+        let result = match self.interpreter.enter(&located.statement) {
+            Ok(Entered::Block(statements, block)) => {
+                self.frames.push(Frame {
+                    statements,
+                    next: 0,
+                    block,
+                    opened_by: Some((located.clone(), started)),
+                });
+                return Ok(());
+            }
+            Ok(Entered::Ran(result)) => Ok(result),
+            Err(e) => Err(e),
+        };
+        match self.interpreter.finish_statement(located, started, result) {
+            Ok(()) => Ok(()),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    /// Leave the blocks that are done (all of them after `exit`), going round
+    /// again in loops whose condition still holds, until a statement is next.
+    fn advance(&mut self) -> Result<(), RuntimeError> {
+        let stopped = self.interpreter.stopped();
+        while let Some(frame) = self.frames.last_mut() {
+            if frame.next < frame.statements.len() && !stopped {
+                return Ok(());
+            }
+            if let Block::While {
+                condition,
+                line,
+                iterations,
+            } = &mut frame.block
+            {
+                match self.interpreter.loop_again(condition, *line, iterations) {
+                    Ok(true) => {
+                        frame.next = 0;
+                        continue;
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        self.fail(e)?;
+                        continue;
+                    }
+                }
+            }
+            let Some(frame) = self.frames.pop() else {
+                break;
+            };
+            self.interpreter.leave(frame.block);
+            if let Some((located, started)) = frame.opened_by {
+                let result = Ok(ExecResult::Unit);
+                if let Err(e) = self.interpreter.finish_statement(&located, started, result) {
+                    self.fail(e)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Abandon the innermost block after `error`, which fails the statement that
+    /// opened it, unless that is a `try` whose handler takes over. Returns the
+    /// error if it reaches the script itself.
+    fn fail(&mut self, mut error: RuntimeError) -> Result<(), RuntimeError> {
+        while let Some(frame) = self.frames.pop() {
+            let handler = match &frame.block {
+                Block::Try { handler } => Some(handler.clone()),
+                _ => None,
+            };
+            self.interpreter.leave(frame.block);
+            let Some((located, started)) = frame.opened_by else {
+                continue;
+            };
+            if let Some(handler) = handler.filter(|_| !self.interpreter.stopping()) {
+                match self.interpreter.enter_handler(&handler, &error) {
+                    Ok(ast) => {
+                        self.frames.push(Frame {
+                            statements: ast.statements,
+                            next: 0,
+                            block: Block::Handler,
+                            opened_by: Some((located, started)),
+                        });
+                        return Ok(());
+                    }
+                    Err(e) => error = e,
+                }
+            }
+            match self
+                .interpreter
+                .finish_statement(&located, started, Err(error))
+            {
+                Ok(()) => return Ok(()),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+}
+
+impl Drop for ExecutionSession<'_> {
+    fn drop(&mut self) {
+        while let Some(frame) = self.frames.pop() {
+            self.interpreter.leave(frame.block);
+        }
+    }
+}
@@ -0,0 +1,444 @@
+/*!
+ * filesystem.rs
+ *
+ * The FileSystem trait through which the environment and interpreter reach the disk:
+ * - RealFs forwards every call to the operating system (used by the command line).
+ * - MemoryFs keeps files and directories in memory, for tests and for embedding
+ *   where there is no disk to touch.
+ *
+ * Statements that only make sense on a real disk (links, permissions, archives,
+ * recursive walks, watching) report an error when run against a non-native filesystem.
+ */
+
+use crate::utils::{
+    append_to_file, copy_file, make_directory, move_file, read_file_bytes, remove_directory,
+    remove_directory_all, remove_file, touch_file, write_to_file, MoveStrategy, PathKind,
+};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/// What the interpreter needs to know about a path.
+#[derive(Clone, Copy, Debug)]
+pub struct FsMetadata {
+    pub is_dir: bool,
+    /// Size in bytes (0 for directories).
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+/// The file operations used by the environment and interpreter.
+pub trait FileSystem {
+    /// Read a whole file as bytes.
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+
+    /// Read a whole file as UTF-8 text.
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Create or overwrite a file.
+    fn write(&self, path: &str, contents: &[u8]) -> io::Result<()>;
+
+    /// Append to a file, creating it if missing.
+    fn append(&self, path: &str, contents: &[u8]) -> io::Result<()>;
+
+    /// Copy a file, returning the number of bytes copied.
+    fn copy(&self, src: &str, dst: &str) -> io::Result<u64>;
+
+    /// Move or rename a file or directory, saying how it was moved.
+    fn rename(&self, src: &str, dst: &str) -> io::Result<MoveStrategy>;
+
+    fn remove_file(&self, path: &str) -> io::Result<()>;
+
+    /// Remove an empty directory.
+    fn remove_dir(&self, path: &str) -> io::Result<()>;
+
+    /// Remove a directory and everything inside it.
+    fn remove_dir_all(&self, path: &str) -> io::Result<()>;
+
+    /// Create a directory and any missing parents.
+    fn create_dir_all(&self, path: &str) -> io::Result<()>;
+
+    /// The names of the entries in a directory.
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>>;
+
+    fn metadata(&self, path: &str) -> io::Result<FsMetadata>;
+
+    /// Create a file if it is missing and set its modification time to now.
+    fn touch(&self, path: &str) -> io::Result<()>;
+
+    /// The absolute form of an existing path, used to tell whether two paths
+    /// name the same file.
+    fn canonicalize(&self, path: &str) -> io::Result<PathBuf>;
+
+    /// Whether a path is a file, a directory, or missing.
+    fn path_kind(&self, path: &str) -> PathKind {
+        match self.metadata(path) {
+            Ok(meta) if meta.is_dir => PathKind::Directory,
+            Ok(_) => PathKind::File,
+            Err(_) => PathKind::Missing,
+        }
+    }
+
+    /// Whether this is the operating system's filesystem, so that statements
+    /// going straight to the disk see the same files.
+    fn is_native(&self) -> bool {
+        false
+    }
+}
+
+/// The operating system's filesystem.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        read_file_bytes(path)
+    }
+
+    fn write(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        write_to_file(path, contents)
+    }
+
+    fn append(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        append_to_file(path, contents)
+    }
+
+    fn copy(&self, src: &str, dst: &str) -> io::Result<u64> {
+        copy_file(src, dst)
+    }
+
+    fn rename(&self, src: &str, dst: &str) -> io::Result<MoveStrategy> {
+        move_file(src, dst)
+    }
+
+    fn remove_file(&self, path: &str) -> io::Result<()> {
+        remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> io::Result<()> {
+        remove_directory(path)
+    }
+
+    fn remove_dir_all(&self, path: &str) -> io::Result<()> {
+        remove_directory_all(path)
+    }
+
+    fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        make_directory(path)
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        fs::read_dir(path)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect()
+    }
+
+    fn metadata(&self, path: &str) -> io::Result<FsMetadata> {
+        let meta = fs::metadata(path)?;
+        Ok(FsMetadata {
+            is_dir: meta.is_dir(),
+            len: if meta.is_dir() { 0 } else { meta.len() },
+            modified: meta.modified()?,
+        })
+    }
+
+    fn touch(&self, path: &str) -> io::Result<()> {
+        touch_file(path)
+    }
+
+    fn canonicalize(&self, path: &str) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+/// A filesystem held entirely in memory. Relative paths start from a virtual
+/// root, which always exists. Clones share the same files, so a caller can keep
+/// a handle to inspect what a script wrote.
+#[derive(Clone, Default)]
+pub struct MemoryFs {
+    state: Rc<RefCell<MemoryState>>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    files: HashMap<PathBuf, (Vec<u8>, SystemTime)>,
+    /// Every directory except the root.
+    dirs: HashSet<PathBuf>,
+}
+
+impl MemoryState {
+    fn is_dir(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty() || self.dirs.contains(path)
+    }
+
+    /// Fail unless the directory that would hold `path` exists.
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !self.is_dir(parent) => Err(not_found(parent)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether anything lives directly or indirectly below a directory.
+    fn has_children(&self, dir: &Path) -> bool {
+        let below = |p: &PathBuf| p != dir && p.starts_with(dir);
+        self.files.keys().any(below) || self.dirs.iter().any(below)
+    }
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file with the given content, creating any missing parent directories.
+    pub fn insert_file(&self, path: &str, contents: impl AsRef<[u8]>) {
+        let key = normalize(path);
+        if let Some(parent) = key.parent() {
+            self.create_dir_all(&parent.to_string_lossy()).ok();
+        }
+        self.state
+            .borrow_mut()
+            .files
+            .insert(key, (contents.as_ref().to_vec(), SystemTime::now()));
+    }
+
+    /// The content of a file, if it exists.
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        let state = self.state.borrow();
+        state.files.get(&normalize(path)).map(|f| f.0.clone())
+    }
+
+    /// The content of a file as text, if it exists and is valid UTF-8.
+    pub fn file_string(&self, path: &str) -> Option<String> {
+        self.file(path)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+    }
+}
+
+/// Resolve `.` and `..` lexically, giving a path relative to the virtual root.
+fn normalize(path: &str) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => result.push(part),
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    result
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("'{}' does not exist", path.display()),
+    )
+}
+
+fn is_a_directory(path: &Path) -> io::Error {
+    io::Error::other(format!("'{}' is a directory", path.display()))
+}
+
+impl FileSystem for MemoryFs {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let key = normalize(path);
+        let state = self.state.borrow();
+        match state.files.get(&key) {
+            Some((bytes, _)) => Ok(bytes.clone()),
+            None if state.is_dir(&key) => Err(is_a_directory(&key)),
+            None => Err(not_found(&key)),
+        }
+    }
+
+    fn write(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let key = normalize(path);
+        let mut state = self.state.borrow_mut();
+        if state.is_dir(&key) {
+            return Err(is_a_directory(&key));
+        }
+        state.check_parent(&key)?;
+        state
+            .files
+            .insert(key, (contents.to_vec(), SystemTime::now()));
+        Ok(())
+    }
+
+    fn append(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let key = normalize(path);
+        let mut state = self.state.borrow_mut();
+        if state.is_dir(&key) {
+            return Err(is_a_directory(&key));
+        }
+        state.check_parent(&key)?;
+        let file = state
+            .files
+            .entry(key)
+            .or_insert_with(|| (Vec::new(), SystemTime::now()));
+        file.0.extend_from_slice(contents);
+        file.1 = SystemTime::now();
+        Ok(())
+    }
+
+    fn copy(&self, src: &str, dst: &str) -> io::Result<u64> {
+        let bytes = self.read(src)?;
+        self.write(dst, &bytes)?;
+        Ok(bytes.len() as u64)
+    }
+
+    fn rename(&self, src: &str, dst: &str) -> io::Result<MoveStrategy> {
+        let (from, to) = (normalize(src), normalize(dst));
+        let mut state = self.state.borrow_mut();
+        state.check_parent(&to)?;
+        if let Some(file) = state.files.remove(&from) {
+            state.files.insert(to, file);
+            return Ok(MoveStrategy::Renamed);
+        }
+        if from.as_os_str().is_empty() || !state.dirs.contains(&from) {
+            return Err(not_found(&from));
+        }
+        if to.starts_with(&from) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot move '{}' into itself", from.display()),
+            ));
+        }
+        let moved = |p: &PathBuf| to.join(p.strip_prefix(&from).unwrap_or(p));
+        let files: Vec<PathBuf> = state
+            .files
+            .keys()
+            .filter(|p| p.starts_with(&from))
+            .cloned()
+            .collect();
+        for path in files {
+            if let Some(file) = state.files.remove(&path) {
+                state.files.insert(moved(&path), file);
+            }
+        }
+        let dirs: Vec<PathBuf> = state
+            .dirs
+            .iter()
+            .filter(|p| p.starts_with(&from))
+            .cloned()
+            .collect();
+        for path in dirs {
+            state.dirs.remove(&path);
+            state.dirs.insert(moved(&path));
+        }
+        Ok(MoveStrategy::Renamed)
+    }
+
+    fn remove_file(&self, path: &str) -> io::Result<()> {
+        let key = normalize(path);
+        let mut state = self.state.borrow_mut();
+        match state.files.remove(&key) {
+            Some(_) => Ok(()),
+            None if state.is_dir(&key) => Err(is_a_directory(&key)),
+            None => Err(not_found(&key)),
+        }
+    }
+
+    fn remove_dir(&self, path: &str) -> io::Result<()> {
+        let key = normalize(path);
+        let mut state = self.state.borrow_mut();
+        if !state.dirs.contains(&key) {
+            return Err(not_found(&key));
+        }
+        if state.has_children(&key) {
+            return Err(io::Error::new(
+                io::ErrorKind::DirectoryNotEmpty,
+                format!("Directory '{}' is not empty", key.display()),
+            ));
+        }
+        state.dirs.remove(&key);
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &str) -> io::Result<()> {
+        let key = normalize(path);
+        let mut state = self.state.borrow_mut();
+        if !state.dirs.contains(&key) {
+            return Err(not_found(&key));
+        }
+        state.files.retain(|p, _| !p.starts_with(&key));
+        state.dirs.retain(|p| !p.starts_with(&key));
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        let key = normalize(path);
+        let mut state = self.state.borrow_mut();
+        let mut dir = PathBuf::new();
+        for part in key.iter() {
+            dir.push(part);
+            if state.files.contains_key(&dir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("'{}' is a file", dir.display()),
+                ));
+            }
+            state.dirs.insert(dir.clone());
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let key = normalize(path);
+        let state = self.state.borrow();
+        if !state.is_dir(&key) {
+            return Err(not_found(&key));
+        }
+        let mut names: Vec<String> = state
+            .files
+            .keys()
+            .chain(state.dirs.iter())
+            .filter(|p| p.parent() == Some(key.as_path()))
+            .filter_map(|p| p.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn metadata(&self, path: &str) -> io::Result<FsMetadata> {
+        let key = normalize(path);
+        let state = self.state.borrow();
+        if let Some((bytes, modified)) = state.files.get(&key) {
+            return Ok(FsMetadata {
+                is_dir: false,
+                len: bytes.len() as u64,
+                modified: *modified,
+            });
+        }
+        if state.is_dir(&key) {
+            return Ok(FsMetadata {
+                is_dir: true,
+                len: 0,
+                modified: SystemTime::UNIX_EPOCH,
+            });
+        }
+        Err(not_found(&key))
+    }
+
+    fn touch(&self, path: &str) -> io::Result<()> {
+        self.append(path, &[])
+    }
+
+    fn canonicalize(&self, path: &str) -> io::Result<PathBuf> {
+        self.metadata(path)?;
+        Ok(Path::new("/").join(normalize(path)))
+    }
+}
@@ -0,0 +1,124 @@
+/*!
+ * hooks.rs
+ *
+ * Callbacks through which a host (a GUI, a test) observes the interpreter,
+ * set with `Interpreter::set_hooks`:
+ * - every statement starting and ending, including those inside loops,
+ *   functions, and included scripts;
+ * - files opened, files written (with the number of bytes), and output printed.
+ *
+ * Returning an error from `on_statement_start` stops the script before that
+ * statement runs, whatever its error handling says, which lets the host cancel
+ * a run or make statements wait for approval.
+ *
+ * RecordingHooks keeps every event in a list, for tests and debugging.
+ */
+
+use crate::ast::Statement;
+use crate::errors::RuntimeError;
+use crate::interpreter::ExecResult;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Observers of the interpreter. Every method does nothing by default.
+pub trait InterpreterHooks {
+    /// A statement on `line` is about to run; an error stops the script instead.
+    fn on_statement_start(&mut self, _stmt: &Statement, _line: usize) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+
+    /// A statement has finished, with what it produced or the error it failed with.
+    fn on_statement_end(&mut self, _stmt: &Statement, _result: &Result<ExecResult, RuntimeError>) {}
+
+    /// A variable was opened on a file, or on a stream or buffer, whose path is
+    /// then the name it is shown with: `<stdin>`, `<stdout>`, or `(buffer)`.
+    fn on_file_opened(&mut self, _var_name: &str, _path: &str) {}
+
+    /// Bytes were written (or appended) to a file.
+    fn on_file_written(&mut self, _path: &str, _bytes: usize) {}
+
+    /// Text was printed to the output.
+    fn on_output(&mut self, _text: &str) {}
+}
+
+/// The hooks an interpreter starts with, which do nothing.
+pub struct NoHooks;
+
+impl InterpreterHooks for NoHooks {}
+
+/// Something the interpreter reported to its hooks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HookEvent {
+    /// A statement started, shown as script text.
+    StatementStart {
+        line: usize,
+        statement: String,
+    },
+    /// A statement ended, successfully or not.
+    StatementEnd {
+        statement: String,
+        ok: bool,
+    },
+    FileOpened {
+        var_name: String,
+        path: String,
+    },
+    FileWritten {
+        path: String,
+        bytes: usize,
+    },
+    Output(String),
+}
+
+/// Hooks that record every event. Clones share the same record, so a host can
+/// keep one and hand the other to `Interpreter::set_hooks`.
+#[derive(Clone, Default)]
+pub struct RecordingHooks {
+    events: Rc<RefCell<Vec<HookEvent>>>,
+}
+
+impl RecordingHooks {
+    /// The events recorded so far, oldest first.
+    pub fn events(&self) -> Vec<HookEvent> {
+        self.events.borrow().clone()
+    }
+
+    fn record(&self, event: HookEvent) {
+        self.events.borrow_mut().push(event);
+    }
+}
+
+impl InterpreterHooks for RecordingHooks {
+    fn on_statement_start(&mut self, stmt: &Statement, line: usize) -> Result<(), RuntimeError> {
+        self.record(HookEvent::StatementStart {
+            line,
+            statement: stmt.to_string(),
+        });
+        Ok(())
+    }
+
+    fn on_statement_end(&mut self, stmt: &Statement, result: &Result<ExecResult, RuntimeError>) {
+        self.record(HookEvent::StatementEnd {
+            statement: stmt.to_string(),
+            ok: result.is_ok(),
+        });
+    }
+
+    fn on_file_opened(&mut self, var_name: &str, path: &str) {
+        self.record(HookEvent::FileOpened {
+            var_name: var_name.to_string(),
+            path: path.to_string(),
+        });
+    }
+
+    fn on_file_written(&mut self, path: &str, bytes: usize) {
+        self.record(HookEvent::FileWritten {
+            path: path.to_string(),
+            bytes,
+        });
+    }
+
+    fn on_output(&mut self, text: &str) {
+        self.record(HookEvent::Output(text.to_string()));
+    }
+}
@@ -3,181 +3,2905 @@
  *
  * The Interpreter executes the AST. It uses:
  * - An Environment for file variables.
+ * - A FileSystem (held by the environment) for the files statements touch.
+ * - An output sink that receives everything the script prints (stdout by default).
+ * - InterpreterHooks told about each statement, opened and written file, and
+ *   printed output (none by default).
  *
  * For each statement in the AST, the interpreter performs the corresponding action.
  * Errors at runtime return a RuntimeError.
+ *
+ * Scripts run through an ExecutionSession (see `prepare`), which can also be
+ * stepped one statement at a time.
  */
 
 use crate::ast::*;
-use crate::commands::help_text;
+use crate::commands::{command_help, help_text};
 use crate::environment::Environment;
-use crate::errors::RuntimeError;
-use crate::utils::{copy_file, list_directory, move_file, remove_file};
+use crate::errors::{FileLangError, RuntimeError};
+use crate::execution::{Block, Entered, ExecutionSession, RunOutcome};
+use crate::filesystem::FileSystem;
+use crate::hooks::{InterpreterHooks, NoHooks};
+use crate::journal::UndoAction;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::script::SharedBuffer;
+use crate::session::Session;
+use crate::tokens::Token;
+use crate::utils::{
+    canonicalize_lenient, concat_files, context_windows, copy_dir_recursive, create_hardlink,
+    create_symlink, describe_entries, diff_lines, directory_size, directory_sizes, find_files,
+    format_rfc3339, format_size, glob_paths, grep_directory, hash_file, hex_dump, interpolate,
+    is_glob_pattern, is_symlink, list_directory_detailed, list_directory_recursive,
+    normalize_lexically, normalize_line_endings, parse_mode, read_from_offset, read_link,
+    relative_path, remove_file, set_mode, stat_path, zip_create, zip_extract, zip_extracted_size,
+    ContentCodec, DetailedEntry, FindFilter, HashAlgorithm, LineEnding, MoveStrategy, PathKind,
+};
+use regex::RegexBuilder;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default for `set maxloop`.
+const DEFAULT_MAX_LOOP_ITERATIONS: usize = 100_000;
+
+/// How deeply function calls may nest (including recursion).
+const MAX_CALL_DEPTH: usize = 64;
+
+/// `sleep`, `watch`, and `tailfollow` wait in slices of this many milliseconds.
+const SLEEP_SLICE_MS: u64 = 100;
 
 pub struct Interpreter {
     env: Environment,
     stop: bool,
+    out: Box<dyn Write>,
+    /// How many times a `while` loop may run before it is treated as stuck.
+    max_loop_iterations: usize,
+    /// The scripts being run, outermost first (for resolving includes).
+    script_stack: Vec<PathBuf>,
+    /// Functions defined with `def`.
+    functions: HashMap<String, Rc<Function>>,
+    /// How many function calls are in progress.
+    call_depth: usize,
+    /// What to do when a statement fails.
+    error_policy: ErrorPolicy,
+    /// Errors reported and skipped under `ErrorPolicy::Continue`.
+    errors: Vec<RuntimeError>,
+    /// How many `try` bodies are running; errors in them always propagate.
+    try_depth: usize,
+    /// The status given to `exit`, once it has run.
+    exit_code: Option<i32>,
+    /// Print each statement to `trace_out` before running it.
+    trace: bool,
+    /// Where trace lines go (stderr by default).
+    trace_out: Box<dyn Write>,
+    /// Statement timings, collected when profiling is on.
+    profile: Option<Profile>,
+    /// When set, every path a statement uses must resolve to somewhere below it.
+    sandbox_root: Option<PathBuf>,
+    /// Warnings printed so far (without the `warning: ` prefix).
+    warnings: Vec<String>,
+    /// Ask before removing or truncating (`set confirm on`), when interactive.
+    confirm: bool,
+    /// Whether someone is there to answer, e.g. standard input is a terminal.
+    interactive: bool,
+    /// What relative paths in a script file start from.
+    path_mode: PathMode,
+    /// Set from outside (e.g. on Ctrl-C) to stop the running script.
+    interrupt: Arc<AtomicBool>,
+    /// The statement and output limits (the environment keeps the file ones).
+    limits: Limits,
+    /// Statements run so far, counted against `limits.max_statements`.
+    statements_run: u64,
+    /// Bytes printed so far, counted against `limits.max_output_bytes`.
+    output_bytes: u64,
+    /// Whether a statement or output limit was hit or the hooks refused a
+    /// statement, which stops the script even under `try` or `set onerror continue`.
+    halted: bool,
+    /// Told about each statement, opened and written file, and printed output.
+    hooks: Box<dyn InterpreterHooks>,
+}
+
+/// What a statement produced, for hosts that call `Interpreter::execute`.
+/// `run` prints these with `render`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExecResult {
+    /// Nothing to report (or the statement printed its own output).
+    Unit,
+    /// A number of lines, from `linecount`.
+    Count(usize),
+    /// Lines of output, e.g. from `head` or a `search` with context.
+    Lines(Vec<String>),
+    /// Matching lines as (line number, text), from `search`.
+    Matches(Vec<(usize, String)>),
+    Text(String),
+    /// Directory entries or glob matches, from `listdir`.
+    Paths(Vec<String>),
+}
+
+/// The text `run` prints for a statement's result, one line per entry.
+pub fn render(result: &ExecResult) -> String {
+    let lines = match result {
+        ExecResult::Unit => return String::new(),
+        ExecResult::Count(count) => vec![format!("{} lines", count)],
+        ExecResult::Lines(lines) => lines.clone(),
+        ExecResult::Matches(matches) if matches.is_empty() => {
+            vec!["No matches found.".to_string()]
+        }
+        ExecResult::Matches(matches) => matches
+            .iter()
+            .map(|(line, text)| format!("{}: {}", line, text))
+            .collect(),
+        ExecResult::Text(text) => return format!("{}\n", text),
+        ExecResult::Paths(paths) if paths.is_empty() => vec!["(empty directory)".to_string()],
+        ExecResult::Paths(paths) => paths.clone(),
+    };
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// The string `let` binds for a statement's result: a count as a number, lines
+/// and paths joined with newlines, and text as it is.
+pub fn result_value(result: &ExecResult) -> String {
+    match result {
+        ExecResult::Unit => String::new(),
+        ExecResult::Count(count) => count.to_string(),
+        ExecResult::Lines(lines) | ExecResult::Paths(lines) => lines.join("\n"),
+        ExecResult::Matches(matches) => matches
+            .iter()
+            .map(|(line, text)| format!("{}: {}", line, text))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        ExecResult::Text(text) => text.clone(),
+    }
+}
+
+/// Order two values for a comparison: as integers when both are (ignoring
+/// surrounding whitespace, so "007" equals "7" and "-3" is less than "2"),
+/// otherwise as strings.
+pub fn compare_values(left: &str, right: &str) -> Ordering {
+    match (left.trim().parse::<i128>(), right.trim().parse::<i128>()) {
+        (Ok(left), Ok(right)) => left.cmp(&right),
+        _ => left.cmp(right),
+    }
+}
+
+/// How many of the slowest statements the profile report lists.
+const PROFILE_SLOWEST: usize = 5;
+
+/// Timings of the statements run so far, for `--profile`.
+#[derive(Default)]
+struct Profile {
+    /// Count, total time, and longest time for each statement keyword.
+    by_keyword: BTreeMap<&'static str, (usize, Duration, Duration)>,
+    /// The slowest individual statements (line, source), slowest first.
+    slowest: Vec<(Duration, usize, String)>,
+}
+
+impl Profile {
+    fn record(&mut self, located: &Located, elapsed: Duration) {
+        let stats = self
+            .by_keyword
+            .entry(located.statement.keyword())
+            .or_default();
+        stats.0 += 1;
+        stats.1 += elapsed;
+        stats.2 = stats.2.max(elapsed);
+
+        let fastest_kept = self.slowest.get(PROFILE_SLOWEST - 1).map(|s| s.0);
+        if fastest_kept.is_none_or(|fastest| elapsed > fastest) {
+            let entry = (elapsed, located.line, located.statement.to_string());
+            let index = self.slowest.partition_point(|s| s.0 >= elapsed);
+            self.slowest.insert(index, entry);
+            self.slowest.truncate(PROFILE_SLOWEST);
+        }
+    }
+
+    fn report(&self) -> String {
+        let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+        let mut report = format!(
+            "{:<14} {:>7} {:>12} {:>12}\n",
+            "statement", "count", "total ms", "max ms"
+        );
+        for (keyword, (count, total, max)) in &self.by_keyword {
+            report.push_str(&format!(
+                "{:<14} {:>7} {:>12.3} {:>12.3}\n",
+                keyword,
+                count,
+                ms(total),
+                ms(max)
+            ));
+        }
+        report.push_str("slowest statements:\n");
+        for (elapsed, line, source) in &self.slowest {
+            report.push_str(&format!(
+                "{:>12.3} ms  [line {}] {}\n",
+                ms(elapsed),
+                line,
+                source
+            ));
+        }
+        report
+    }
+}
+
+/// What the interpreter does when a statement fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop the script with the error.
+    #[default]
+    Abort,
+    /// Report the error with its line, skip the statement, and fail at the end.
+    Continue,
+}
+
+/// What relative paths in a script file start from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathMode {
+    /// The directory the interpreter was started in.
+    #[default]
+    Cwd,
+    /// The directory of the script being run.
+    ScriptRelative,
+}
+
+/// Guardrails for running generated or untrusted scripts, set with
+/// `Interpreter::set_limits`. None means unlimited (the default).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Bytes `open`, `read`, or `readrange` may read from a file into memory.
+    pub max_read_bytes: Option<u64>,
+    /// Bytes that may be written to files in total.
+    pub max_write_bytes: Option<u64>,
+    /// Statements that may run, counting each pass through a loop body.
+    pub max_statements: Option<u64>,
+    /// Bytes of output that may be printed; output past it is cut off.
+    pub max_output_bytes: Option<u64>,
+}
+
+/// What one statement of a `parallel` block did on its thread.
+struct TaskReport {
+    /// Everything it printed.
+    output: String,
+    /// The files it wrote, with the bytes written to each.
+    written: Vec<(String, usize)>,
+    /// Its error message, if it failed.
+    error: Option<String>,
+}
+
+impl TaskReport {
+    fn failed(error: String) -> Self {
+        Self {
+            output: String::new(),
+            written: Vec::new(),
+            error: Some(error),
+        }
+    }
+}
+
+/// The settings of a `parallel` block's interpreter that its workers run under.
+#[derive(Clone)]
+struct WorkerSettings {
+    sandbox_root: Option<PathBuf>,
+    /// The block's limits, less what it has already used.
+    limits: Limits,
+    dry_run: bool,
+    no_clobber: bool,
+    backup: bool,
+    confirm: bool,
+    interactive: bool,
+}
+
+impl WorkerSettings {
+    /// Give a fresh worker interpreter these settings.
+    fn apply(&self, worker: &mut Interpreter) {
+        worker.sandbox_root = self.sandbox_root.clone();
+        worker.set_limits(self.limits);
+        worker.env.dry_run = self.dry_run;
+        worker.env.no_clobber = self.no_clobber;
+        worker.env.backup = self.backup;
+        worker.confirm = self.confirm;
+        worker.interactive = self.interactive;
+    }
+}
+
+/// Run a statement from a `parallel` block, already prepared by the block's
+/// interpreter, on an interpreter of its own.
+fn run_parallel_task(statement: &Statement, settings: &WorkerSettings) -> TaskReport {
+    let output = SharedBuffer::default();
+    let mut worker = Interpreter::with_output(Box::new(output.clone()));
+    settings.apply(&mut worker);
+    let result = worker
+        .execute(statement)
+        .and_then(|result| worker.print(&render(&result)));
+    let printed = String::from_utf8_lossy(&output.0.borrow()).into_owned();
+    TaskReport {
+        output: printed,
+        written: worker.env.take_written(),
+        error: result.err().map(|e| e.message()),
+    }
+}
+
+/// A function defined with `def`.
+struct Function {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
-    /// Create a new interpreter.
+    /// Create a new interpreter that prints to stdout.
     pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    /// Create a new interpreter that prints to the given writer.
+    pub fn with_output(out: Box<dyn Write>) -> Self {
         Self {
             env: Environment::new(),
             stop: false,
+            out,
+            max_loop_iterations: DEFAULT_MAX_LOOP_ITERATIONS,
+            script_stack: Vec::new(),
+            functions: HashMap::new(),
+            call_depth: 0,
+            error_policy: ErrorPolicy::Abort,
+            errors: Vec::new(),
+            try_depth: 0,
+            exit_code: None,
+            trace: false,
+            trace_out: Box::new(io::stderr()),
+            profile: None,
+            sandbox_root: None,
+            warnings: Vec::new(),
+            confirm: false,
+            interactive: false,
+            path_mode: PathMode::Cwd,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            limits: Limits::default(),
+            statements_run: 0,
+            output_bytes: 0,
+            halted: false,
+            hooks: Box::new(NoHooks),
         }
     }
 
-    /// Run the given AST in the interpreter.
+    /// Run the given AST in the interpreter. Files made by `tempfile` are deleted
+    /// at the end, even if the script fails. An interrupt requested before the
+    /// run starts is ignored; one requested during it is cleared once it stops.
     pub fn run(&mut self, ast: &AST) -> Result<(), RuntimeError> {
-        for stmt in &ast.statements {
+        self.interrupt.store(false, AtomicOrdering::Relaxed);
+        let result = self.run_statements(ast.statements.clone());
+        self.interrupt.store(false, AtomicOrdering::Relaxed);
+        let cleanup = self.env.remove_temp_files();
+        result?;
+        cleanup?;
+        self.flush()?;
+        self.check_errors()
+    }
+
+    /// Get ready to run the given AST one statement at a time. Unlike `run`, the
+    /// session leaves temporary files, interrupts, and errors collected under
+    /// `set onerror continue` for the caller to deal with.
+    pub fn prepare(&mut self, ast: &AST) -> ExecutionSession<'_> {
+        ExecutionSession::new(self, ast.statements.clone())
+    }
+
+    /// The file variables and globals, for inspection.
+    pub fn environment(&self) -> &Environment {
+        &self.env
+    }
+
+    /// The warnings printed so far, such as mixed line endings found on open.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// The names of the file variables defined so far, sorted.
+    pub fn variable_names(&self) -> Vec<String> {
+        self.env
+            .variable_names()
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The status passed to `exit`, if the script exited explicitly.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Share a flag that, once set (say by a Ctrl-C handler on another thread),
+    /// stops the running script with an "interrupted" error at the next
+    /// statement, loop iteration, sleep slice, or directory entry.
+    /// Neither `try` nor `set onerror continue` catches the error.
+    pub fn set_interrupt_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = flag;
+    }
+
+    /// Cap what scripts may read, write, run, and print. Each limit is counted
+    /// over everything this interpreter runs; going past one is a runtime error
+    /// naming the limit. Past the statement or output limit the script stops,
+    /// even under `try` or `set onerror continue`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.env.max_read_bytes = limits.max_read_bytes;
+        self.env.max_write_bytes = limits.max_write_bytes;
+    }
+
+    /// Report execution to `hooks`: every statement (in loops, functions, and
+    /// included scripts too), opened and written files, and printed output.
+    /// An error from `on_statement_start` stops the script before that statement.
+    pub fn set_hooks(&mut self, hooks: Box<dyn InterpreterHooks>) {
+        self.hooks = hooks;
+    }
+
+    /// Print each statement, with its line number, before running it.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Ask for confirmation on the input source before `remove` and `truncate`
+    /// (like `set confirm on`). Only has an effect when interactive.
+    pub fn set_confirm(&mut self, confirm: bool) {
+        self.confirm = confirm;
+    }
+
+    /// Say whether someone is answering on the input source (a terminal), which
+    /// `set confirm on` needs before it asks anything.
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
+
+    /// Send trace lines to the given writer instead of stderr.
+    pub fn set_trace_output(&mut self, out: Box<dyn Write>) {
+        self.trace_out = out;
+    }
+
+    /// Start or stop timing every statement (see `profile_report`).
+    pub fn set_profile(&mut self, profile: bool) {
+        self.profile = profile.then(Profile::default);
+    }
+
+    /// A table of statement counts and times by keyword, followed by the five
+    /// slowest statements. Empty unless profiling is on.
+    pub fn profile_report(&self) -> String {
+        self.profile
+            .as_ref()
+            .map(Profile::report)
+            .unwrap_or_default()
+    }
+
+    /// Confine the script to a directory: absolute paths, and paths that lead
+    /// outside the directory (through `..` or a symlink), are rejected.
+    pub fn set_sandbox_root(&mut self, root: impl AsRef<Path>) -> Result<(), RuntimeError> {
+        let root = root.as_ref();
+        let canonical = fs::canonicalize(root).map_err(|e| {
+            RuntimeError::with_source(format!("Invalid sandbox root '{}'", root.display()), e)
+        })?;
+        self.sandbox_root = Some(canonical);
+        Ok(())
+    }
+
+    /// Resolve the script's relative paths from `dir`, as after `cd`, leaving the
+    /// process working directory alone.
+    pub fn set_working_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), RuntimeError> {
+        let dir = dir.as_ref().to_string_lossy();
+        let canonical = (self.env.fs.canonicalize(&dir))
+            .ok()
+            .filter(|_| self.env.fs.path_kind(&dir) == PathKind::Directory)
+            .ok_or_else(|| {
+                RuntimeError::new(format!(
+                    "Invalid working directory '{}': not a directory",
+                    dir
+                ))
+            })?;
+        let process_dir = self.env.fs.canonicalize(".").map_err(|e| {
+            RuntimeError::with_source("Failed to read the working directory".to_string(), e)
+        })?;
+        self.env.current_dir = relative_path(&canonical, &process_dir);
+        Ok(())
+    }
+
+    /// Resolve a path the script uses, checking it against the sandbox root (if
+    /// any). Relative paths are resolved from the working directory.
+    fn resolve_path(&self, path: &str) -> Result<PathBuf, RuntimeError> {
+        if self.sandbox_root.is_none() {
+            return Ok(PathBuf::from(path));
+        }
+        if Path::new(path).is_absolute() {
+            return Err(RuntimeError::new(format!(
+                "Sandbox: absolute path '{}' is not allowed",
+                path
+            )));
+        }
+        self.confine(Path::new(path))
+    }
+
+    /// Resolve a path, absolute or not, checking that it is inside the sandbox
+    /// root (if any).
+    fn confine(&self, path: &Path) -> Result<PathBuf, RuntimeError> {
+        let Some(root) = &self.sandbox_root else {
+            return Ok(path.to_path_buf());
+        };
+        let resolved = canonicalize_lenient(path).map_err(|e| {
+            RuntimeError::with_source(format!("Sandbox: cannot resolve '{}'", path.display()), e)
+        })?;
+        if !resolved.starts_with(root) {
+            return Err(RuntimeError::new(format!(
+                "Sandbox: '{}' is outside the sandbox root '{}'",
+                path.display(),
+                root.display()
+            )));
+        }
+        Ok(resolved)
+    }
+
+    /// Read `open stdin` variables and `prompt` answers from this reader instead
+    /// of standard input.
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.env.stdin = input;
+    }
+
+    /// Run against another filesystem, such as a `MemoryFs`, instead of the disk.
+    pub fn set_filesystem(&mut self, fs: impl FileSystem + 'static) {
+        self.env.fs = Box::new(fs);
+    }
+
+    /// In dry-run mode, report filesystem changes instead of making them.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.env.dry_run = dry_run;
+    }
+
+    /// Before the first write to each existing file, copy it to `<filename>.bak`.
+    pub fn set_backup(&mut self, backup: bool) {
+        self.env.backup = backup;
+    }
+
+    /// Choose what relative paths in a script file start from. With
+    /// `PathMode::ScriptRelative`, each script (including ones it includes)
+    /// starts in its own directory, and the previous one is restored after it.
+    pub fn set_path_mode(&mut self, mode: PathMode) {
+        self.path_mode = mode;
+    }
+
+    /// Choose whether a failing statement stops the script or is reported and skipped.
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+
+    /// The errors reported so far under `ErrorPolicy::Continue`.
+    pub fn errors(&self) -> &[RuntimeError] {
+        &self.errors
+    }
+
+    /// Fail if any statement failed under `ErrorPolicy::Continue`.
+    fn check_errors(&self) -> Result<(), RuntimeError> {
+        match self.errors.len() {
+            0 => Ok(()),
+            1 => Err(RuntimeError::new("1 statement failed".to_string())),
+            n => Err(RuntimeError::new(format!("{} statements failed", n))),
+        }
+    }
+
+    /// Make the script's command-line arguments available as `${arg1}`,
+    /// `${arg2}`, ..., with their number in `${argcount}`.
+    pub fn set_script_args(&mut self, args: &[String]) {
+        for (i, arg) in args.iter().enumerate() {
+            self.env.set_global(&format!("arg{}", i + 1), arg.clone());
+        }
+        self.env.set_global("argcount", args.len().to_string());
+    }
+
+    /// Lex, parse, and run a script given as text.
+    pub fn run_source(&mut self, source: &str) -> Result<(), FileLangError> {
+        let ast = self.parse_source(source)?;
+        self.run(&ast)?;
+        Ok(())
+    }
+
+    /// Lex and parse a script. `${name}` in its strings is filled in when each
+    /// statement runs.
+    fn parse_source(&self, source: &str) -> Result<AST, FileLangError> {
+        let tokens = Lexer::new(source).lex()?;
+        Ok(Parser::new(tokens).parse()?)
+    }
+
+    /// Lex, parse, and run a script file. Includes inside it are resolved
+    /// relative to the script's directory. As with `run`, files made by
+    /// `tempfile` are deleted, and an interrupt cleared, when the outermost
+    /// script finishes.
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<(), FileLangError> {
+        let path = path.as_ref();
+        let outermost = self.script_stack.is_empty();
+        let (ast, previous_dir) = self.enter_script(path)?;
+        if outermost {
+            self.interrupt.store(false, AtomicOrdering::Relaxed);
+        }
+        let result = self.run_statements(ast.statements);
+        self.leave_script(previous_dir);
+        if outermost {
+            self.interrupt.store(false, AtomicOrdering::Relaxed);
+        }
+        let cleanup = if outermost {
+            self.env.remove_temp_files()
+        } else {
+            Ok(())
+        };
+        result?;
+        cleanup?;
+        self.flush()?;
+        if outermost {
+            self.check_errors()?;
+        }
+        Ok(())
+    }
+
+    /// Run statements through a session, stopping at `breakpoint` statements
+    /// for the breakpoint prompt.
+    fn run_statements(&mut self, statements: Vec<Located>) -> Result<(), RuntimeError> {
+        let mut session = ExecutionSession::new(self, statements);
+        loop {
+            match session.run_to_end()? {
+                RunOutcome::Finished => return Ok(()),
+                RunOutcome::Paused { line } => session.interpreter().breakpoint_prompt(line)?,
+                RunOutcome::Changed { .. } => {}
+            }
+        }
+    }
+
+    /// At a `breakpoint` on `line`, when interactive, read commands from the
+    /// input source until `continue` (or the end of input): `show var` and
+    /// `dumpenv [var]` print as usual, and `quit` stops the script.
+    fn breakpoint_prompt(&mut self, line: usize) -> Result<(), RuntimeError> {
+        if !self.interactive {
+            return Ok(());
+        }
+        self.emit(&format!(
+            "breakpoint on line {}: show var, dumpenv [var], continue, quit",
+            line
+        ))?;
+        loop {
+            self.print("debug> ")?;
+            self.flush()?;
+            let Some(command) = self.env.read_input_line()? else {
+                return Ok(());
+            };
+            let statement = match command.trim() {
+                "" => continue,
+                "continue" | "c" => return Ok(()),
+                "quit" | "q" => {
+                    return Err(RuntimeError::new(format!(
+                        "Quit at the breakpoint on line {}",
+                        line
+                    )));
+                }
+                command => self
+                    .parse_source(command)
+                    .ok()
+                    .and_then(|ast| ast.statements.into_iter().next())
+                    .map(|located| located.statement),
+            };
+            let result = match statement {
+                Some(statement @ (Statement::Show(_) | Statement::DumpEnv(_))) => {
+                    self.execute(&statement)
+                }
+                _ => {
+                    self.emit("Commands: show var, dumpenv [var], continue, quit")?;
+                    continue;
+                }
+            };
+            match result {
+                Ok(result) => self.print(&render(&result))?,
+                Err(e) => self.emit(&format!("error: {}", e.message()))?,
+            }
+        }
+    }
+
+    /// Read and parse a script file, then make it the script being run (and,
+    /// for script-relative paths, its directory the working directory).
+    /// Returns the script and the working directory to restore with `leave_script`.
+    fn enter_script(&mut self, path: &Path) -> Result<(AST, Option<PathBuf>), FileLangError> {
+        let source = self
+            .env
+            .fs
+            .read_to_string(&path.to_string_lossy())
+            .map_err(|e| {
+                RuntimeError::with_source(format!("Failed to read script '{}'", path.display()), e)
+            })?;
+        let ast = self.parse_source(&source)?;
+        let previous_dir = match self.path_mode {
+            PathMode::Cwd => None,
+            PathMode::ScriptRelative => {
+                let dir = path.parent().unwrap_or(Path::new(""));
+                Some(std::mem::replace(
+                    &mut self.env.current_dir,
+                    dir.to_path_buf(),
+                ))
+            }
+        };
+        self.script_stack.push(path.to_path_buf());
+        Ok((ast, previous_dir))
+    }
+
+    /// Return to the script that was running before `enter_script`.
+    fn leave_script(&mut self, previous_dir: Option<PathBuf>) {
+        self.script_stack.pop();
+        if let Some(dir) = previous_dir {
+            self.env.current_dir = dir;
+        }
+    }
+
+    /// Execute statements in order, stopping early after `exit`.
+    /// With `ErrorPolicy::Continue` (outside a `try` body), a failing statement
+    /// is reported and recorded and the block carries on.
+    fn execute_block(&mut self, statements: &[Located]) -> Result<(), RuntimeError> {
+        for located in statements {
             if self.stop {
                 break;
             }
-            self.execute_statement(stmt)?;
+            let started = self.begin_statement(located)?;
+            let result = self.execute(&located.statement);
+            self.finish_statement(located, started, result)?;
         }
         Ok(())
     }
 
-    /// Execute a single statement.
-    fn execute_statement(&mut self, stmt: &Statement) -> Result<(), RuntimeError> {
-        match stmt {
-            Statement::Open(s) => self.execute_open(&s.filename, &s.var_name),
-            Statement::Read(s) => self.execute_read(&s.var_name),
-            Statement::Write(s) => self.execute_write(&s.var_name, &s.text),
-            Statement::Append(s) => self.execute_append(&s.var_name, &s.text),
-            Statement::Show(s) => self.execute_show(&s.var_name),
-            Statement::Close(s) => self.execute_close(&s.var_name),
-            Statement::Truncate(s) => self.execute_truncate(&s.var_name),
-            Statement::Search(s) => self.execute_search(&s.var_name, &s.pattern),
-            Statement::Replace(s) => self.execute_replace(&s.var_name, &s.pattern, &s.replacement),
-            Statement::LineCount(s) => self.execute_linecount(&s.var_name),
-            Statement::Copy(s) => self.execute_copy(&s.source, &s.destination),
-            Statement::Move(s) => self.execute_move(&s.source, &s.destination),
-            Statement::Remove(s) => self.execute_remove(&s.filename),
-            Statement::Rename(s) => self.execute_rename(&s.var_name, &s.new_filename),
-            Statement::ListDir(s) => self.execute_listdir(&s.path),
-            Statement::DumpEnv(_) => self.execute_dumpenv(),
-            Statement::Help(_) => self.execute_help(),
-            Statement::Exit(_) => self.execute_exit(),
+    /// Get ready to run a statement: check for an interrupt and the statement
+    /// limit, trace it, and ask the hooks. Returns when it started, if profiling.
+    pub(crate) fn begin_statement(
+        &mut self,
+        located: &Located,
+    ) -> Result<Option<Instant>, RuntimeError> {
+        self.check_interrupt()?;
+        self.count_statement()?;
+        if self.trace {
+            writeln!(
+                self.trace_out,
+                "[line {}] {}",
+                located.line, located.statement
+            )
+            .map_err(|e| RuntimeError::with_source("Failed to write trace".to_string(), e))?;
+        }
+        if let Err(e) = self
+            .hooks
+            .on_statement_start(&located.statement, located.line)
+        {
+            self.halted = true;
+            return Err(e);
         }
+        Ok(self.profile.is_some().then(Instant::now))
     }
 
-    fn execute_open(&mut self, filename: &str, var_name: &str) -> Result<(), RuntimeError> {
-        self.env
-            .open_file(var_name.to_string(), filename.to_string())
+    /// Wrap up a statement that has run (for a block, once the block is done):
+    /// record its time, report what it planned and wrote, print its result,
+    /// and tell the hooks. A failure is returned, unless `ErrorPolicy::Continue`
+    /// reports and records it.
+    pub(crate) fn finish_statement(
+        &mut self,
+        located: &Located,
+        started: Option<Instant>,
+        result: Result<ExecResult, RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        if let (Some(profile), Some(started)) = (&mut self.profile, started) {
+            profile.record(located, started.elapsed());
+        }
+        for action in self.env.take_planned() {
+            self.emit(&format!("DRY-RUN: would {}", action))?;
+        }
+        for (path, bytes) in self.env.take_written() {
+            self.hooks.on_file_written(&path, bytes);
+        }
+        let result = result.and_then(|result| self.print(&render(&result)).map(|()| result));
+        self.hooks.on_statement_end(&located.statement, &result);
+        match result {
+            Ok(_) => Ok(()),
+            Err(e)
+                if self.error_policy == ErrorPolicy::Continue
+                    && self.try_depth == 0
+                    && !self.stopping() =>
+            {
+                self.emit(&format!("error: line {}: {}", located.line, e.message()))?;
+                self.errors.push(e);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
     }
 
-    fn execute_read(&mut self, var_name: &str) -> Result<(), RuntimeError> {
-        self.env.read_file_content(var_name)
+    /// Whether the script ran `exit`.
+    pub(crate) fn stopped(&self) -> bool {
+        self.stop
     }
 
-    fn execute_write(&mut self, var_name: &str, text: &str) -> Result<(), RuntimeError> {
-        self.env.write_file_content(var_name, text)
+    /// Whether the interrupt flag has been set.
+    pub(crate) fn interrupted(&self) -> bool {
+        self.interrupt.load(AtomicOrdering::Relaxed)
     }
 
-    fn execute_append(&mut self, var_name: &str, text: &str) -> Result<(), RuntimeError> {
-        self.env.append_file_content(var_name, text)
+    pub(crate) fn clear_interrupt(&self) {
+        self.interrupt.store(false, AtomicOrdering::Relaxed);
     }
 
-    fn execute_show(&mut self, var_name: &str) -> Result<(), RuntimeError> {
-        let content = self.env.get_file_content(var_name)?;
-        println!("{}", content);
+    /// Whether the script must stop, whatever its error handling says: it was
+    /// interrupted, went past a statement or output limit, or the hooks refused
+    /// a statement.
+    pub(crate) fn stopping(&self) -> bool {
+        self.interrupted() || self.halted
+    }
+
+    /// The settings for the workers of a `parallel` block: this interpreter's,
+    /// with the limits cut down to what is left of them.
+    fn worker_settings(&self) -> WorkerSettings {
+        let left = |max: Option<u64>, used: u64| max.map(|max| max.saturating_sub(used));
+        let limits = Limits {
+            max_statements: left(self.limits.max_statements, self.statements_run),
+            max_output_bytes: left(self.limits.max_output_bytes, self.output_bytes),
+            ..self.limits
+        };
+        WorkerSettings {
+            sandbox_root: self.sandbox_root.clone(),
+            limits,
+            dry_run: self.env.dry_run,
+            no_clobber: self.env.no_clobber,
+            backup: self.env.backup,
+            confirm: self.confirm,
+            interactive: self.interactive,
+        }
+    }
+
+    /// Count a statement about to run against the statement limit.
+    fn count_statement(&mut self) -> Result<(), RuntimeError> {
+        self.statements_run += 1;
+        match self.limits.max_statements {
+            Some(max) if self.statements_run > max => {
+                self.halted = true;
+                Err(RuntimeError::new(format!(
+                    "Statement limit exceeded: the script ran more than {} statements",
+                    max
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail with "interrupted" once the interrupt flag has been set.
+    fn check_interrupt(&self) -> Result<(), RuntimeError> {
+        if self.interrupted() {
+            return Err(RuntimeError::new("interrupted".to_string()));
+        }
         Ok(())
     }
 
-    fn execute_close(&mut self, var_name: &str) -> Result<(), RuntimeError> {
-        self.env.close_file(var_name)
+    /// In dry-run mode, report the filesystem change `action` and return true so
+    /// the caller skips it.
+    fn skip_for_dry_run(&mut self, action: &str) -> Result<bool, RuntimeError> {
+        if !self.env.dry_run {
+            return Ok(false);
+        }
+        self.emit(&format!("DRY-RUN: would {}", action))?;
+        Ok(true)
     }
 
-    fn execute_truncate(&mut self, var_name: &str) -> Result<(), RuntimeError> {
-        self.env.truncate_file(var_name)
+    fn flush(&mut self) -> Result<(), RuntimeError> {
+        self.out
+            .flush()
+            .map_err(|e| RuntimeError::with_source("Failed to flush output".to_string(), e))
     }
 
-    fn execute_search(&mut self, var_name: &str, pattern: &str) -> Result<(), RuntimeError> {
-        let matches = self.env.search_file(var_name, pattern)?;
-        if matches.is_empty() {
-            println!("No matches found.");
-        } else {
-            for (line_num, line) in matches {
-                println!("{}: {}", line_num, line);
+    /// Write text as is to the output sink. Past the output limit, only what
+    /// fits is written and the rest is an error.
+    fn print(&mut self, text: &str) -> Result<(), RuntimeError> {
+        let mut bytes = text.as_bytes();
+        let mut over = None;
+        if let Some(max) = self.limits.max_output_bytes {
+            let room = max.saturating_sub(self.output_bytes);
+            if bytes.len() as u64 > room {
+                bytes = &bytes[..room as usize];
+                over = Some(max);
             }
         }
-        Ok(())
+        self.output_bytes += bytes.len() as u64;
+        self.out
+            .write_all(bytes)
+            .map_err(|e| RuntimeError::with_source("Failed to write output".to_string(), e))?;
+        if !bytes.is_empty() {
+            self.hooks.on_output(&String::from_utf8_lossy(bytes));
+        }
+        match over {
+            Some(max) => {
+                self.halted = true;
+                self.flush()?;
+                Err(RuntimeError::new(format!(
+                    "Output limit exceeded: the script printed more than {} bytes",
+                    max
+                )))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Write a line of text to the output sink.
+    fn emit(&mut self, text: &str) -> Result<(), RuntimeError> {
+        self.print(&format!("{}\n", text))
+    }
+
+    /// A copy of the statement with `${...}` in its strings filled in (see
+    /// `fill_in`), and its paths resolved against the working directory; None
+    /// if nothing changes.
+    fn rewrite_strings(&self, stmt: &Statement) -> Option<Statement> {
+        let relocate = !self.env.current_dir.as_os_str().is_empty();
+        let mut copy = stmt.clone();
+        let mut changed = relocate;
+        for text in copy.strings_mut() {
+            if text.contains("${") {
+                let filled = self.fill_in(text);
+                changed |= filled != *text;
+                *text = filled;
+            }
+        }
+        if relocate {
+            for path in copy.paths_mut() {
+                *path = self.env.resolve_path(path);
+            }
+        }
+        changed.then_some(copy)
+    }
+
+    /// Replace `${env:NAME}` with the process environment variable, and `${name}`
+    /// with the parameter of the innermost function call (or `${error}` in a
+    /// handler) or else the global's current value. This is the only pass over
+    /// the text, so values that contain `${...}` are inserted as they are.
+    fn fill_in(&self, text: &str) -> String {
+        interpolate(text, |name| match name.strip_prefix("env:") {
+            Some(var) => env::var(var).ok().map(Cow::Owned),
+            None => (self.env.param_value(name))
+                .or_else(|| self.env.global_value(name))
+                .map(Cow::Borrowed),
+        })
+    }
+
+    /// Print a warning returned by an operation, if there is one.
+    fn emit_warning(&mut self, warning: Option<String>) -> Result<(), RuntimeError> {
+        match warning {
+            Some(warning) => {
+                self.emit(&format!("warning: {}", warning))?;
+                self.warnings.push(warning);
+                Ok(())
+            }
+            None => Ok(()),
+        }
     }
 
-    fn execute_replace(
+    /// Execute a single statement, returning what it produced instead of printing
+    /// it. Statements without a structured result (and anything a block runs)
+    /// still print as they go.
+    pub fn execute(&mut self, stmt: &Statement) -> Result<ExecResult, RuntimeError> {
+        self.prepared(stmt, Self::dispatch)
+    }
+
+    /// Start a statement for a stepping session: a block statement (`while`,
+    /// `if`, `try`, `call`, `include`) only enters its block, which is returned
+    /// for the session to run; anything else runs as with `execute`.
+    pub(crate) fn enter(&mut self, stmt: &Statement) -> Result<Entered, RuntimeError> {
+        self.prepared(stmt, Self::enter_prepared)
+    }
+
+    /// Fill in a statement's strings and resolve its paths, check them, and pass
+    /// the result to `run`.
+    fn prepared<T>(
         &mut self,
-        var_name: &str,
-        pattern: &str,
-        replacement: &str,
-    ) -> Result<(), RuntimeError> {
-        self.env.replace_file(var_name, pattern, replacement)
+        stmt: &Statement,
+        run: impl FnOnce(&mut Self, &Statement) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        let rewritten;
+        let stmt: &Statement = match self.rewrite_strings(stmt) {
+            Some(copy) => {
+                rewritten = copy;
+                &rewritten
+            }
+            None => stmt,
+        };
+        if self.sandbox_root.is_some() {
+            for path in stmt.paths() {
+                self.resolve_path(path)?;
+            }
+        }
+        if stmt.requires_native_fs() && !self.env.fs.is_native() {
+            return Err(RuntimeError::new(format!(
+                "'{}' needs a native filesystem",
+                stmt.keyword()
+            )));
+        }
+        run(self, stmt)
     }
 
-    fn execute_linecount(&mut self, var_name: &str) -> Result<(), RuntimeError> {
-        let count = self.env.line_count(var_name)?;
-        println!("{} lines", count);
-        Ok(())
+    /// `enter` for a statement `prepared` has checked.
+    fn enter_prepared(&mut self, stmt: &Statement) -> Result<Entered, RuntimeError> {
+        let block = match stmt {
+            Statement::While(s) => {
+                let mut iterations = 0;
+                if !self.loop_again(&s.condition, s.line, &mut iterations)? {
+                    return Ok(Entered::Ran(ExecResult::Unit));
+                }
+                let block = Block::While {
+                    condition: s.condition.clone(),
+                    line: s.line,
+                    iterations,
+                };
+                (s.body.clone(), block)
+            }
+            Statement::If(s) => {
+                if !self.evaluate_condition(&s.condition)? {
+                    return Ok(Entered::Ran(ExecResult::Unit));
+                }
+                (s.body.clone(), Block::If)
+            }
+            Statement::Try(s) => {
+                self.try_depth += 1;
+                let block = Block::Try {
+                    handler: s.handler.clone(),
+                };
+                (s.body.clone(), block)
+            }
+            Statement::Call(s) => (self.enter_call(&s.name, &s.args)?.statements, Block::Call),
+            Statement::Include(s) => {
+                let (ast, previous_dir) = self.enter_include(&s.path)?;
+                (ast.statements, Block::Include { previous_dir })
+            }
+            _ => return self.dispatch(stmt).map(Entered::Ran),
+        };
+        Ok(Entered::Block(block.0, block.1))
     }
 
-    fn execute_copy(&mut self, source: &str, destination: &str) -> Result<(), RuntimeError> {
-        copy_file(source, destination).map_err(|e| {
-            RuntimeError::new(format!(
-                "Failed to copy file '{}' to '{}': {}",
-                source, destination, e
-            ))
-        })?;
-        Ok(())
+    /// Undo what entering a block set up, once the block is done or abandoned.
+    pub(crate) fn leave(&mut self, block: Block) {
+        match block {
+            Block::Script | Block::If | Block::While { .. } => {}
+            Block::Try { .. } => self.try_depth -= 1,
+            Block::Handler => self.env.pop_scope(),
+            Block::Call => self.leave_call(),
+            Block::Include { previous_dir } => self.leave_script(previous_dir),
+        }
     }
 
-    fn execute_move(&mut self, source: &str, destination: &str) -> Result<(), RuntimeError> {
-        move_file(source, destination).map_err(|e| {
-            RuntimeError::new(format!(
-                "Failed to move file '{}' to '{}': {}",
-                source, destination, e
-            ))
-        })?;
-        Ok(())
+    /// Run a statement whose strings and paths `execute` has already prepared.
+    fn dispatch(&mut self, stmt: &Statement) -> Result<ExecResult, RuntimeError> {
+        match stmt {
+            Statement::Open(s) => self.execute_open(s),
+            Statement::Read(s) => self.execute_read(&s.var_name),
+            Statement::Write(s) => self.execute_write(&s.var_name, &s.text, s.overwrite),
+            Statement::Append(s) => self.execute_append(&s.var_name, &s.text),
+            Statement::Show(s) => return self.execute_show(&s.var_name),
+            Statement::Close(s) => self.execute_close(s),
+            Statement::Truncate(s) => self.execute_truncate(&s.var_name),
+            Statement::Search(s) => return self.execute_search(s),
+            Statement::Grep(s) => self.execute_grep(s),
+            Statement::Find(s) => self.execute_find(&s.root, &s.filters),
+            Statement::Symlink(s) => self.execute_symlink(&s.target, &s.link_path, s.overwrite),
+            Statement::HardLink(s) => self.execute_hardlink(&s.target, &s.link_path, s.overwrite),
+            Statement::ReadLink(s) => return self.execute_readlink(&s.link_path),
+            Statement::Chmod(s) => self.execute_chmod(&s.path, &s.mode),
+            Statement::Du(s) => self.execute_du(&s.path, s.per_child),
+            Statement::Extract(s) => return self.execute_extract(&s.var_name, &s.pattern),
+            Statement::Replace(s) => self.execute_replace(s),
+            Statement::LineCount(s) => return self.execute_linecount(s),
+            Statement::Copy(s) => self.execute_copy(s),
+            Statement::Move(s) => self.execute_move(s),
+            Statement::Remove(s) => self.execute_remove(&s.filename, s.recursive, s.if_any),
+            Statement::Rename(s) => self.execute_rename(&s.var_name, &s.new_filename),
+            Statement::ListDir(s) => return self.execute_listdir(&s.path, s.recursive, s.long),
+            Statement::Mkdir(s) => self.execute_mkdir(&s.path),
+            Statement::Rmdir(s) => self.execute_rmdir(&s.path),
+            Statement::Head(s) => return self.execute_head(&s.var_name, s.count),
+            Statement::Tail(s) => return self.execute_tail(&s.var_name, s.count),
+            Statement::InsertLine(s) => self.execute_insertline(&s.var_name, s.line, &s.text),
+            Statement::GetLine(s) => return self.execute_getline(&s.var_name, s.from, s.to),
+            Statement::DeleteLine(s) => self.execute_deleteline(&s.var_name, s.from, s.to),
+            Statement::Exists(s) => return self.execute_exists(&s.path),
+            Statement::Stat(s) => self.execute_stat(&s.target),
+            Statement::Touch(s) => self.execute_touch(&s.filename),
+            Statement::Save(s) => self.execute_save(&s.var_name),
+            Statement::Revert(s) => self.execute_revert(&s.var_name),
+            Statement::RestoreBackup(s) => self.execute_restorebackup(&s.var_name),
+            Statement::TempFile(s) => self.execute_tempfile(&s.var_name),
+            Statement::Buffer(s) => self.env.open_buffer(s.var_name.clone()),
+            Statement::SaveAs(s) => self.env.save_as(&s.var_name, &s.path),
+            Statement::CopyTo(s) => self.execute_copyto(&s.source, &s.destination),
+            Statement::AppendFrom(s) => self.execute_appendfrom(&s.destination, &s.source),
+            Statement::Set(s) => self.execute_set(&s.option, &s.value),
+            Statement::CloseAll(_) => self.execute_closeall(),
+            Statement::Unset(s) => self.execute_unset(&s.var_name, s.force),
+            Statement::HexDump(s) => self.execute_hexdump(&s.var_name),
+            Statement::Size(s) => self.execute_size(&s.var_name),
+            Statement::SetLineEnd(s) => self.execute_setlineend(&s.var_name, s.line_ending),
+            Statement::SortLines(s) => self.execute_sortlines(&s.var_name, s.numeric),
+            Statement::UniqLines(s) => self.execute_uniqlines(&s.var_name, s.all),
+            Statement::ReverseLines(s) => self.execute_reverselines(&s.var_name),
+            Statement::NumberLines(s) => self.execute_numberlines(&s.var_name),
+            Statement::TrimLines(s) => self.execute_trimlines(&s.var_name, s.leading),
+            Statement::Diff(s) => self.execute_diff(&s.left, &s.right),
+            Statement::Hash(s) => self.execute_hash(&s.target, &s.algorithm),
+            Statement::CheckHash(s) => self.execute_checkhash(&s.target, &s.algorithm, &s.expected),
+            Statement::Encode(s) => self.execute_encode(&s.var_name, s.codec),
+            Statement::Decode(s) => self.execute_decode(&s.var_name, s.codec),
+            Statement::Concat(s) => self.execute_concat(&s.destination, &s.sources),
+            Statement::Split(s) => self.execute_split(s),
+            Statement::ZipCreate(s) => self.execute_zipcreate(&s.archive, &s.source),
+            Statement::ZipExtract(s) => self.execute_zipextract(&s.archive, &s.destination),
+            Statement::Watch(s) => self.execute_watch(s),
+            Statement::TailFollow(s) => self.execute_tailfollow(s),
+            Statement::While(s) => self.execute_while(s),
+            Statement::If(s) => self.execute_if(s),
+            Statement::Assert(s) => self.execute_assert(s),
+            Statement::Prompt(s) => self.execute_prompt(s),
+            Statement::Echo(s) => return Ok(ExecResult::Text(s.text.clone())),
+            Statement::Sleep(s) => self.execute_sleep(s.millis),
+            Statement::Include(s) => self.execute_include(&s.path),
+            Statement::Def(s) => self.execute_def(s),
+            Statement::Call(s) => self.execute_call(&s.name, &s.args),
+            Statement::Try(s) => self.execute_try(s),
+            Statement::Cd(s) => self.execute_cd(&s.path),
+            Statement::Pwd(_) => return self.execute_pwd(),
+            Statement::Undo(s) => self.execute_undo(s.count),
+            Statement::History(_) => return self.execute_history(),
+            Statement::SaveSession(s) => self.execute_savesession(&s.path, s.full),
+            Statement::LoadSession(s) => self.execute_loadsession(&s.path, s.merge),
+            Statement::DumpEnv(s) => return self.execute_dumpenv(s.var_name.as_deref()),
+            Statement::Help(s) => self.execute_help(s.topic.as_deref()),
+            Statement::ReadRange(s) => self.execute_readrange(s),
+            Statement::GetEnv(s) => self.execute_getenv(s),
+            Statement::Pipe(s) => return self.execute_pipe(s),
+            Statement::Let(s) => self.execute_let(s),
+            // Only pauses a session; see `breakpoint_prompt`.
+            Statement::Breakpoint(_) => Ok(()),
+            Statement::Parallel(s) => self.execute_parallel(s),
+            Statement::Exit(s) => self.execute_exit(s.code),
+        }?;
+        Ok(ExecResult::Unit)
     }
 
-    fn execute_remove(&mut self, filename: &str) -> Result<(), RuntimeError> {
-        remove_file(filename).map_err(|e| {
-            RuntimeError::new(format!("Failed to remove file '{}': {}", filename, e))
-        })?;
-        Ok(())
+    fn execute_open(&mut self, stmt: &OpenStmt) -> Result<(), RuntimeError> {
+        if stmt.target != FileTarget::Disk {
+            self.env
+                .open_stream(stmt.var_name.clone(), stmt.target, &stmt.flags)?;
+            let name = &self.env.files[&stmt.var_name].filename;
+            self.hooks.on_file_opened(&stmt.var_name, name);
+            return Ok(());
+        }
+        let warning =
+            self.env
+                .open_file(stmt.var_name.clone(), stmt.filename.clone(), &stmt.flags)?;
+        self.hooks.on_file_opened(&stmt.var_name, &stmt.filename);
+        self.emit_warning(warning)
     }
 
-    fn execute_rename(&mut self, var_name: &str, new_filename: &str) -> Result<(), RuntimeError> {
-        self.env.rename_file(var_name, new_filename)
+    fn execute_read(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        let warning = self.env.read_file_content(var_name)?;
+        self.emit_warning(warning)
     }
 
-    fn execute_listdir(&mut self, path: &str) -> Result<(), RuntimeError> {
-        let listing = list_directory(path).map_err(|e| {
-            RuntimeError::new(format!("Failed to list directory '{}': {}", path, e))
-        })?;
-        if listing.is_empty() {
-            println!("(empty directory)");
-        } else {
-            for fname in listing {
-                println!("{}", fname);
+    fn execute_readrange(&mut self, stmt: &ReadRangeStmt) -> Result<(), RuntimeError> {
+        let warning =
+            self.env
+                .read_file_range(&stmt.var_name, stmt.offset as u64, stmt.length as u64)?;
+        self.emit_warning(warning)
+    }
+
+    fn execute_getenv(&mut self, stmt: &GetEnvStmt) -> Result<(), RuntimeError> {
+        match (env::var(&stmt.name), &stmt.default) {
+            (Ok(value), _) => self.env.set_global_from_env(&stmt.var_name, value),
+            (Err(env::VarError::NotPresent), Some(default)) => {
+                self.env.set_global(&stmt.var_name, default.clone())
+            }
+            (Err(env::VarError::NotPresent), None) => {
+                return Err(RuntimeError::new(format!(
+                    "Environment variable '{}' is not set (add default \"text\" to fall back)",
+                    stmt.name
+                )))
+            }
+            (Err(e), _) => {
+                return Err(RuntimeError::with_source(
+                    format!("Cannot read environment variable '{}'", stmt.name),
+                    e,
+                ))
             }
         }
         Ok(())
     }
 
-    fn execute_dumpenv(&mut self) -> Result<(), RuntimeError> {
-        self.env.dump();
+    /// Print the prompt (without a newline), read a line, and bind it, trimmed.
+    fn execute_prompt(&mut self, stmt: &PromptStmt) -> Result<(), RuntimeError> {
+        self.print(&stmt.text)?;
+        self.flush()?;
+        let line = self.env.read_input_line()?;
+        let answer = match (line.as_deref().map(str::trim), &stmt.default) {
+            (Some(""), Some(default)) | (None, Some(default)) => default.clone(),
+            (Some(answer), _) => answer.to_string(),
+            (None, None) => {
+                return Err(RuntimeError::new(format!(
+                    "No answer to prompt for '{}': input has ended (add default \"text\" to \
+                     fall back)",
+                    stmt.var_name
+                )))
+            }
+        };
+        self.env.set_global(&stmt.var_name, answer);
         Ok(())
     }
 
-    fn execute_help(&mut self) -> Result<(), RuntimeError> {
-        println!("{}", help_text());
-        Ok(())
+    fn execute_write(
+        &mut self,
+        var_name: &str,
+        text: &str,
+        overwrite: bool,
+    ) -> Result<(), RuntimeError> {
+        self.env.write_file_content(var_name, text, overwrite)?;
+        self.print_stdout()
     }
 
-    fn execute_exit(&mut self) -> Result<(), RuntimeError> {
-        self.stop = true;
+    fn execute_copyto(&mut self, source: &str, destination: &str) -> Result<(), RuntimeError> {
+        self.env.copy_content(source, destination)?;
+        self.print_stdout()
+    }
+
+    fn execute_appendfrom(&mut self, destination: &str, source: &str) -> Result<(), RuntimeError> {
+        self.env.append_content_from(destination, source)?;
+        self.print_stdout()
+    }
+
+    /// Print what was written to `stdout` variables.
+    fn print_stdout(&mut self) -> Result<(), RuntimeError> {
+        for text in self.env.take_stdout() {
+            self.print(&text)?;
+        }
         Ok(())
     }
+
+    fn execute_append(&mut self, var_name: &str, text: &str) -> Result<(), RuntimeError> {
+        self.env.append_file_content(var_name, text)?;
+        self.print_stdout()
+    }
+
+    fn execute_show(&mut self, var_name: &str) -> Result<ExecResult, RuntimeError> {
+        let content = self.env.get_file_content(var_name)?;
+        Ok(ExecResult::Text(content.to_string()))
+    }
+
+    fn execute_close(&mut self, stmt: &CloseStmt) -> Result<(), RuntimeError> {
+        if stmt.keep {
+            let filename = self.env.filename_of(&stmt.var_name)?;
+            self.env.keep_temp_file(&filename);
+        }
+        self.env.close_file(&stmt.var_name, stmt.discard)
+    }
+
+    fn execute_tempfile(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        let default_dir = match &self.sandbox_root {
+            Some(root) => root.clone(),
+            None => env::temp_dir(),
+        };
+        let path = self.env.temp_file_path(default_dir);
+        self.confine(Path::new(&path))?;
+        self.env.create_temp_file(&path)?;
+        let warning =
+            self.env
+                .open_file(var_name.to_string(), path.clone(), &OpenFlags::default())?;
+        self.hooks.on_file_opened(var_name, &path);
+        self.emit_warning(warning)
+    }
+
+    fn execute_truncate(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        if self.confirm && self.interactive {
+            // Buffers and stdin have no file to name, only the variable.
+            let action = match self.env.filename_of(var_name) {
+                Ok(filename) => format!("truncate \"{}\"", filename),
+                Err(_) => format!("truncate {}", var_name),
+            };
+            if !self.confirmed(&action)? {
+                return Ok(());
+            }
+        }
+        self.env.truncate_file(var_name)
+    }
+
+    /// With `set confirm on` in an interactive session, ask whether to go ahead
+    /// with `action` and read the answer; anything but y or yes prints
+    /// "skipped" and returns false. Otherwise always true.
+    fn confirmed(&mut self, action: &str) -> Result<bool, RuntimeError> {
+        if !(self.confirm && self.interactive) {
+            return Ok(true);
+        }
+        self.print(&format!("really {}? [y/N] ", action))?;
+        self.flush()?;
+        let answer = self.env.read_input_line()?.unwrap_or_default();
+        if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Ok(true);
+        }
+        self.emit("skipped")?;
+        Ok(false)
+    }
+
+    fn execute_search(&mut self, stmt: &SearchStmt) -> Result<ExecResult, RuntimeError> {
+        let options = &stmt.options;
+        if options.context > 0
+            && self
+                .env
+                .streams_from_disk(&stmt.var_name, options.streaming)?
+        {
+            return Err(RuntimeError::new(format!(
+                "'context' can't be used while '{}' is streamed from disk; use 'read {}' first",
+                stmt.var_name, stmt.var_name
+            )));
+        }
+        let matches = self.env.search_file(
+            &stmt.var_name,
+            &stmt.pattern,
+            options.ignore_case,
+            options.streaming,
+        )?;
+        if options.count_only {
+            return Ok(ExecResult::Text(matches.len().to_string()));
+        }
+        if options.context == 0 || matches.is_empty() {
+            let matches = matches.into_iter().map(|m| (m.line, m.text)).collect();
+            return Ok(ExecResult::Matches(matches));
+        }
+        // grep-style context: matching lines as "N: text", surrounding lines as
+        // "N- text", and "--" between groups that don't touch.
+        let content = self.env.get_file_content(&stmt.var_name)?;
+        let lines: Vec<&str> = content.lines().collect();
+        let numbers: Vec<usize> = matches.iter().map(|m| m.line).collect();
+        let windows = context_windows(&numbers, options.context, lines.len());
+        let mut output = Vec::new();
+        for (i, (first, last)) in windows.into_iter().enumerate() {
+            if i > 0 {
+                output.push("--".to_string());
+            }
+            for line in first..=last {
+                let sep = if numbers.binary_search(&line).is_ok() {
+                    ":"
+                } else {
+                    "-"
+                };
+                output.push(format!("{}{} {}", line, sep, lines[line - 1]));
+            }
+        }
+        Ok(ExecResult::Lines(output))
+    }
+
+    fn execute_grep(&mut self, stmt: &GrepStmt) -> Result<(), RuntimeError> {
+        let re = RegexBuilder::new(&stmt.pattern)
+            .case_insensitive(stmt.ignore_case)
+            .build()
+            .map_err(|e| {
+                RuntimeError::with_source(format!("Invalid regex '{}'", stmt.pattern), e)
+            })?;
+        let results = grep_directory(&stmt.path, &re, stmt.include.as_deref(), &self.interrupt);
+        self.check_interrupt()?;
+        let results = results.map_err(|e| {
+            RuntimeError::with_source(format!("Failed to search '{}'", stmt.path), e)
+        })?;
+        if stmt.verbose {
+            for path in &results.skipped_binary {
+                self.emit(&format!("skipped binary file {}", path))?;
+            }
+        }
+        if results.matches.is_empty() {
+            return self.emit("No matches found.");
+        }
+        for m in results.matches {
+            self.emit(&format!("{}:{}: {}", m.path, m.line, m.text))?;
+        }
+        Ok(())
+    }
+
+    fn execute_find(&mut self, root: &str, filters: &[FindFilter]) -> Result<(), RuntimeError> {
+        let paths = find_files(root, filters, &self.interrupt);
+        self.check_interrupt()?;
+        let paths = paths
+            .map_err(|e| RuntimeError::with_source(format!("Failed to search '{}'", root), e))?;
+        for path in paths {
+            self.emit(&path)?;
+        }
+        Ok(())
+    }
+
+    fn execute_symlink(
+        &mut self,
+        target: &str,
+        link_path: &str,
+        overwrite: bool,
+    ) -> Result<(), RuntimeError> {
+        if self.sandbox_root.is_some() {
+            let link_dir = Path::new(link_path).parent().unwrap_or(Path::new(""));
+            self.resolve_path(&link_dir.join(target).to_string_lossy())?;
+        }
+        let action = format!("symlink \"{}\" -> \"{}\"", link_path, target);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        self.clear_link_path(link_path, overwrite)?;
+        create_symlink(target, link_path).map_err(|e| {
+            RuntimeError::with_source(
+                format!("Failed to create symlink '{}' -> '{}'", link_path, target),
+                e,
+            )
+        })?;
+        self.env.journal.record(action, None);
+        Ok(())
+    }
+
+    fn execute_hardlink(
+        &mut self,
+        target: &str,
+        link_path: &str,
+        overwrite: bool,
+    ) -> Result<(), RuntimeError> {
+        let action = format!("hardlink \"{}\" to \"{}\"", link_path, target);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        self.clear_link_path(link_path, overwrite)?;
+        create_hardlink(target, link_path).map_err(|e| {
+            RuntimeError::with_source(
+                format!("Failed to create hard link '{}' to '{}'", link_path, target),
+                e,
+            )
+        })?;
+        self.env.journal.record(action, None);
+        Ok(())
+    }
+
+    /// Make way for a new link: an existing path is an error, unless `overwrite`
+    /// is given and the path is itself a symlink, which is then removed.
+    fn clear_link_path(&self, link_path: &str, overwrite: bool) -> Result<(), RuntimeError> {
+        let is_link = is_symlink(link_path);
+        if !is_link && !Path::new(link_path).exists() {
+            return Ok(());
+        }
+        if !overwrite {
+            return Err(RuntimeError::new(format!(
+                "'{}' already exists (add 'overwrite' to replace a link)",
+                link_path
+            )));
+        }
+        if !is_link {
+            return Err(RuntimeError::new(format!(
+                "Refusing to overwrite '{}': it is not a symlink",
+                link_path
+            )));
+        }
+        remove_file(link_path).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to remove link '{}'", link_path), e)
+        })
+    }
+
+    fn execute_readlink(&mut self, link_path: &str) -> Result<ExecResult, RuntimeError> {
+        if !is_symlink(link_path) {
+            return Err(RuntimeError::new(format!(
+                "'{}' is not a symbolic link",
+                link_path
+            )));
+        }
+        let target = read_link(link_path).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to read link '{}'", link_path), e)
+        })?;
+        Ok(ExecResult::Text(target))
+    }
+
+    fn execute_chmod(&mut self, path: &str, spec: &str) -> Result<(), RuntimeError> {
+        let stat_error = |e| RuntimeError::with_source(format!("Failed to stat '{}'", path), e);
+        let stat = stat_path(path).map_err(stat_error)?;
+        let current = stat
+            .mode
+            .unwrap_or(if stat.readonly { 0o444 } else { 0o666 });
+        let mode = parse_mode(spec, current)
+            .map_err(|e| RuntimeError::new(format!("Invalid mode '{}': {}", spec, e)))?;
+        let action = format!("chmod \"{}\" to {:04o}", path, mode);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        set_mode(path, mode).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to change mode of '{}'", path), e)
+        })?;
+        self.env.journal.record(action, None);
+        let stat = stat_path(path).map_err(stat_error)?;
+        match stat.mode {
+            Some(mode) => self.emit(&format!("{}: {:04o}", path, mode)),
+            None => self.emit(&format!("{}: readonly {}", path, stat.readonly)),
+        }
+    }
+
+    fn execute_du(&mut self, path: &str, per_child: bool) -> Result<(), RuntimeError> {
+        let to_error = |e| RuntimeError::with_source(format!("Failed to measure '{}'", path), e);
+        if per_child && Path::new(path).is_dir() {
+            for (child, size) in directory_sizes(path).map_err(to_error)? {
+                self.emit(&format!("{}  {}", format_size(size), child))?;
+            }
+        }
+        let total = directory_size(path).map_err(to_error)?;
+        self.emit(&format!("{}  {}", format_size(total), path))
+    }
+
+    fn execute_extract(
+        &mut self,
+        var_name: &str,
+        pattern: &str,
+    ) -> Result<ExecResult, RuntimeError> {
+        let matches = self.env.extract_from_file(var_name, pattern)?;
+        if matches.is_empty() {
+            return Ok(ExecResult::Matches(Vec::new()));
+        }
+        let lines = matches
+            .into_iter()
+            .map(|m| format!("{}:{}: {}", m.line, m.col, m.text))
+            .collect();
+        Ok(ExecResult::Lines(lines))
+    }
+
+    fn execute_let(&mut self, stmt: &LetStmt) -> Result<(), RuntimeError> {
+        let result = self.dispatch(&stmt.value)?;
+        self.env.set_global(&stmt.var_name, result_value(&result));
+        Ok(())
+    }
+
+    /// Run the producer with its results captured, and send them, one per line,
+    /// to the sink. "No matches" and "empty directory" placeholders aren't sent.
+    fn execute_pipe(&mut self, stmt: &PipeStmt) -> Result<ExecResult, RuntimeError> {
+        let result = self.dispatch(&stmt.producer)?;
+        let text = match &result {
+            ExecResult::Matches(matches) if matches.is_empty() => String::new(),
+            ExecResult::Paths(paths) if paths.is_empty() => String::new(),
+            result => render(result),
+        };
+        match &stmt.sink {
+            PipeSink::Append(var) => self.execute_append(var, &text)?,
+            PipeSink::Write(var) => self.execute_write(var, &text, false)?,
+            PipeSink::Show => return Ok(result),
+        }
+        Ok(ExecResult::Unit)
+    }
+
+    fn execute_replace(&mut self, stmt: &ReplaceStmt) -> Result<(), RuntimeError> {
+        let count = self.env.replace_file(
+            &stmt.var_name,
+            &stmt.pattern,
+            &stmt.replacement,
+            &stmt.options,
+        )?;
+        if count == 0 {
+            self.emit("no matches, file unchanged")
+        } else {
+            self.emit(&format!("{} replacements", count))
+        }
+    }
+
+    fn execute_linecount(&mut self, stmt: &LineCountStmt) -> Result<ExecResult, RuntimeError> {
+        Ok(ExecResult::Count(
+            self.env.line_count(&stmt.var_name, stmt.streaming)?,
+        ))
+    }
+
+    /// Under `set noclobber on`, refuse to copy or move onto an existing path
+    /// unless the statement says `overwrite`.
+    fn check_clobber(
+        &self,
+        operation: &str,
+        destination: &str,
+        overwrite: bool,
+    ) -> Result<(), RuntimeError> {
+        if self.env.no_clobber
+            && !overwrite
+            && self.env.fs.path_kind(destination) != PathKind::Missing
+        {
+            return Err(RuntimeError::new(format!(
+                "Cannot {} onto '{}': it already exists (add 'overwrite' to replace it)",
+                operation, destination
+            )));
+        }
+        Ok(())
+    }
+
+    fn execute_copy(&mut self, stmt: &CopyStmt) -> Result<(), RuntimeError> {
+        let (source, destination) = (stmt.source.as_str(), stmt.destination.as_str());
+        self.check_clobber("copy", destination, stmt.overwrite)?;
+        let recursive = stmt.recursive;
+        if self.skip_for_dry_run(&format!("copy \"{}\" to \"{}\"", source, destination))? {
+            return Ok(());
+        }
+        if self.env.fs.path_kind(source) == PathKind::Directory {
+            if !recursive {
+                return Err(RuntimeError::new(format!(
+                    "Cannot copy '{}': it is a directory (use 'copy \"{}\" \"{}\" recursive')",
+                    source, source, destination
+                )));
+            }
+            let len = directory_size(source).unwrap_or(0);
+            self.env.count_write(destination, len)?;
+            let summary = copy_dir_recursive(source, destination).map_err(|e| {
+                RuntimeError::with_source(
+                    format!("Failed to copy directory '{}' to '{}'", source, destination),
+                    e,
+                )
+            })?;
+            let description = format!("copy \"{}\" to \"{}\" recursive", source, destination);
+            self.env.journal.record(description, None);
+            for link in &summary.skipped_symlinks {
+                self.emit_warning(Some(format!("skipped symlink '{}'", link)))?;
+            }
+            return self.emit(&format!(
+                "copied {} files ({} bytes)",
+                summary.files, summary.bytes
+            ));
+        }
+        let len = self.env.fs.metadata(source).map_or(0, |meta| meta.len);
+        self.env.count_write(destination, len)?;
+        let description = format!("copy \"{}\" to \"{}\"", source, destination);
+        let journaled = self
+            .env
+            .journal
+            .snapshot(&*self.env.fs, description, destination);
+        let bytes = self.env.fs.copy(source, destination).map_err(|e| {
+            RuntimeError::with_source(
+                format!("Failed to copy file '{}' to '{}'", source, destination),
+                e,
+            )
+        })?;
+        self.env.journal.extend(journaled);
+        self.env.record_write(destination, bytes as usize);
+        Ok(())
+    }
+
+    fn execute_move(&mut self, stmt: &MoveStmt) -> Result<(), RuntimeError> {
+        let (source, destination) = (stmt.source.as_str(), stmt.destination.as_str());
+        self.check_clobber("move", destination, stmt.overwrite)?;
+        if self.skip_for_dry_run(&format!("move \"{}\" to \"{}\"", source, destination))? {
+            return Ok(());
+        }
+        let description = format!("move \"{}\" to \"{}\"", source, destination);
+        let journaled = self
+            .env
+            .journal
+            .moved(&*self.env.fs, description, source, destination);
+        let strategy = self.env.fs.rename(source, destination).map_err(|e| {
+            RuntimeError::with_source(
+                format!("Failed to move file '{}' to '{}'", source, destination),
+                e,
+            )
+        })?;
+        self.env.journal.extend(journaled);
+        match strategy {
+            MoveStrategy::Renamed => Ok(()),
+            MoveStrategy::CopiedAndRemoved => self.emit(&format!(
+                "moved '{}' to '{}' by copying and removing it (different filesystems)",
+                source, destination
+            )),
+        }
+    }
+
+    fn execute_remove(
+        &mut self,
+        filename: &str,
+        recursive: bool,
+        if_any: bool,
+    ) -> Result<(), RuntimeError> {
+        if is_glob_pattern(filename) {
+            return self.execute_remove_glob(filename, recursive, if_any);
+        }
+        self.remove_path(filename, recursive).map(|_| ())
+    }
+
+    /// Remove every path matching a glob pattern, reporting how many were removed.
+    fn execute_remove_glob(
+        &mut self,
+        pattern: &str,
+        recursive: bool,
+        if_any: bool,
+    ) -> Result<(), RuntimeError> {
+        let matches = glob_paths(pattern).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to expand pattern '{}'", pattern), e)
+        })?;
+        if matches.is_empty() && !if_any {
+            return Err(RuntimeError::new(format!(
+                "No files match '{}' (add 'ifany' to allow this)",
+                pattern
+            )));
+        }
+        if !recursive {
+            if let Some(dir) = matches.iter().find(|m| Path::new(m).is_dir()) {
+                return Err(RuntimeError::new(format!(
+                    "Cannot remove '{}': it is a directory (add 'recursive' to remove directories)",
+                    dir
+                )));
+            }
+        }
+        let mut removed = 0;
+        for path in &matches {
+            if self.remove_path(path, recursive)? {
+                removed += 1;
+            }
+        }
+        if self.env.dry_run {
+            return Ok(());
+        }
+        self.emit(&format!("removed {} files", removed))
+    }
+
+    /// Whether `path` resolves to the working directory or one of its ancestors
+    /// (the filesystem root included), whether the script's own (`cd`) or the
+    /// process's. A symbolic link is not resolved, since only the link is removed.
+    fn encloses_working_dir(&self, path: &str) -> bool {
+        let fs = &self.env.fs;
+        if fs.is_native() && is_symlink(path) {
+            return false;
+        }
+        let Ok(target) = fs.canonicalize(path) else {
+            return false;
+        };
+        let process_dir = fs.canonicalize(".").ok();
+        let script_dir = self.env.working_dir().ok();
+        [process_dir, script_dir]
+            .iter()
+            .flatten()
+            .any(|dir| dir.starts_with(&target))
+            || target.parent().is_none()
+    }
+
+    /// Remove a file (or, with `recursive`, a directory tree). Returns whether it
+    /// was removed: not in dry-run mode or when the user declines.
+    fn remove_path(&mut self, filename: &str, recursive: bool) -> Result<bool, RuntimeError> {
+        if recursive {
+            if self.encloses_working_dir(filename) {
+                return Err(RuntimeError::new(format!(
+                    "Refusing to recursively remove '{}': it contains the working directory",
+                    filename
+                )));
+            }
+            let action = format!("remove \"{}\" recursive", filename);
+            if self.skip_for_dry_run(&action)? || !self.confirmed(&action)? {
+                return Ok(false);
+            }
+            self.env.fs.remove_dir_all(filename).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to remove '{}'", filename), e)
+            })?;
+            self.env.journal.record(action, None);
+            return Ok(true);
+        }
+        if self.env.fs.path_kind(filename) == PathKind::Directory {
+            return Err(RuntimeError::new(format!(
+                "Cannot remove '{}': it is a directory (use 'remove \"{}\" recursive')",
+                filename, filename
+            )));
+        }
+        let action = format!("remove \"{}\"", filename);
+        if self.skip_for_dry_run(&action)? || !self.confirmed(&action)? {
+            return Ok(false);
+        }
+        let journaled = self.env.journal.snapshot(&*self.env.fs, action, filename);
+        self.env.fs.remove_file(filename).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to remove file '{}'", filename), e)
+        })?;
+        self.env.journal.extend(journaled);
+        Ok(true)
+    }
+
+    fn execute_rename(&mut self, var_name: &str, new_filename: &str) -> Result<(), RuntimeError> {
+        self.env.rename_file(var_name, new_filename)
+    }
+
+    fn execute_listdir(
+        &mut self,
+        path: &str,
+        recursive: bool,
+        long: bool,
+    ) -> Result<ExecResult, RuntimeError> {
+        if is_glob_pattern(path) {
+            let matches = glob_paths(path).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to expand pattern '{}'", path), e)
+            })?;
+            if matches.is_empty() {
+                return Ok(ExecResult::Text("(no matches)".to_string()));
+            }
+            if long {
+                let entries = describe_entries(Path::new(""), matches).map_err(|e| {
+                    RuntimeError::with_source(format!("Failed to list matches of '{}'", path), e)
+                })?;
+                return Ok(long_listing(entries));
+            }
+            return Ok(ExecResult::Paths(matches));
+        }
+        if long {
+            let entries = list_directory_detailed(path, recursive).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to list directory '{}'", path), e)
+            })?;
+            return Ok(long_listing(entries));
+        }
+        let listing = if recursive {
+            list_directory_recursive(path)
+        } else {
+            self.env.fs.read_dir(path)
+        };
+        let listing = listing.map_err(|e| {
+            RuntimeError::with_source(format!("Failed to list directory '{}'", path), e)
+        })?;
+        Ok(ExecResult::Paths(listing))
+    }
+
+    fn execute_mkdir(&mut self, path: &str) -> Result<(), RuntimeError> {
+        let action = format!("mkdir \"{}\"", path);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        let created = self.env.fs.path_kind(path) == PathKind::Missing;
+        self.env.fs.create_dir_all(path).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to create directory '{}'", path), e)
+        })?;
+        if created {
+            let undo = UndoAction::RemoveDir(path.to_string());
+            self.env.journal.record(action, Some(undo));
+        }
+        Ok(())
+    }
+
+    fn execute_rmdir(&mut self, path: &str) -> Result<(), RuntimeError> {
+        let action = format!("rmdir \"{}\"", path);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        self.env.fs.remove_dir(path).map_err(|e| {
+            if e.kind() == io::ErrorKind::DirectoryNotEmpty {
+                RuntimeError::new(format!(
+                    "Failed to remove directory '{}': directory is not empty",
+                    path
+                ))
+            } else {
+                RuntimeError::with_source(format!("Failed to remove directory '{}'", path), e)
+            }
+        })?;
+        let undo = UndoAction::CreateDir(path.to_string());
+        self.env.journal.record(action, Some(undo));
+        Ok(())
+    }
+
+    fn execute_head(&mut self, var_name: &str, count: usize) -> Result<ExecResult, RuntimeError> {
+        Ok(ExecResult::Lines(self.env.head_lines(var_name, count)?))
+    }
+
+    fn execute_getline(
+        &mut self,
+        var_name: &str,
+        from: usize,
+        to: usize,
+    ) -> Result<ExecResult, RuntimeError> {
+        Ok(ExecResult::Lines(self.env.get_lines(var_name, from, to)?))
+    }
+
+    fn execute_tail(&mut self, var_name: &str, count: usize) -> Result<ExecResult, RuntimeError> {
+        Ok(ExecResult::Lines(self.env.tail_lines(var_name, count)?))
+    }
+
+    fn execute_insertline(
+        &mut self,
+        var_name: &str,
+        line: usize,
+        text: &str,
+    ) -> Result<(), RuntimeError> {
+        self.env.insert_line(var_name, line, text)
+    }
+
+    fn execute_deleteline(
+        &mut self,
+        var_name: &str,
+        from: usize,
+        to: usize,
+    ) -> Result<(), RuntimeError> {
+        self.env.delete_lines(var_name, from, to)
+    }
+
+    fn execute_exists(&mut self, path: &str) -> Result<ExecResult, RuntimeError> {
+        let kind = self.env.fs.path_kind(path);
+        Ok(ExecResult::Text(kind.as_str().to_string()))
+    }
+
+    fn execute_stat(&mut self, target: &FileRef) -> Result<(), RuntimeError> {
+        let path = match target {
+            FileRef::Var(var_name) => self.env.filename_of(var_name)?,
+            FileRef::Path(path) => path.clone(),
+        };
+        let stat = stat_path(&path)
+            .map_err(|e| RuntimeError::with_source(format!("Failed to stat '{}'", path), e))?;
+        self.emit(&format!("path: {}", path))?;
+        self.emit(&format!("type: {}", stat.kind.as_str()))?;
+        self.emit(&format!("size: {} bytes", stat.size))?;
+        self.emit(&format!("modified: {}", format_rfc3339(stat.modified)))?;
+        match stat.mode {
+            Some(mode) => self.emit(&format!("permissions: {:04o}", mode)),
+            None => self.emit(&format!("readonly: {}", stat.readonly)),
+        }
+    }
+
+    fn execute_touch(&mut self, filename: &str) -> Result<(), RuntimeError> {
+        if let Some(parent) = Path::new(filename).parent() {
+            if !parent.as_os_str().is_empty()
+                && self.env.fs.path_kind(&parent.to_string_lossy()) != PathKind::Directory
+            {
+                return Err(RuntimeError::new(format!(
+                    "Failed to touch file '{}': directory '{}' does not exist",
+                    filename,
+                    parent.display()
+                )));
+            }
+        }
+        let action = format!("touch \"{}\"", filename);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        if self.env.fs.path_kind(filename) == PathKind::Missing {
+            let undo = UndoAction::Restore {
+                path: filename.to_string(),
+                content: None,
+            };
+            self.env.journal.record(action, Some(undo));
+        }
+        self.env.fs.touch(filename).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to touch file '{}'", filename), e)
+        })
+    }
+
+    fn execute_save(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        self.env.save_file(var_name)
+    }
+
+    fn execute_revert(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        self.execute_read(var_name)
+    }
+
+    fn execute_restorebackup(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        let warning = self.env.restore_backup(var_name)?;
+        self.emit_warning(warning)
+    }
+
+    fn execute_set(&mut self, option: &str, value: &str) -> Result<(), RuntimeError> {
+        match option.to_lowercase().as_str() {
+            "buffered" => self.env.buffered_default = parse_switch(option, value)?,
+            "dryrun" => self.env.dry_run = parse_switch(option, value)?,
+            "backup" => self.env.backup = parse_switch(option, value)?,
+            "noclobber" => self.env.no_clobber = parse_switch(option, value)?,
+            "keepcontent" => self.env.keep_content = parse_switch(option, value)?,
+            "trace" => self.trace = parse_switch(option, value)?,
+            "confirm" => self.confirm = parse_switch(option, value)?,
+            "journal" => {
+                self.env.journal.enabled = parse_switch(option, value)?;
+                self.env.journal.clear();
+            }
+            "onerror" => {
+                self.error_policy = match value.to_lowercase().as_str() {
+                    "abort" => ErrorPolicy::Abort,
+                    "continue" => ErrorPolicy::Continue,
+                    _ => {
+                        return Err(RuntimeError::new(format!(
+                            "Invalid value '{}' for 'set onerror' (expected abort or continue)",
+                            value
+                        )));
+                    }
+                };
+            }
+            "tempdir" => {
+                let dir = self.env.resolve_path(value);
+                self.resolve_path(&dir)?;
+                self.env.temp_dir = Some(PathBuf::from(dir));
+            }
+            "locktimeout" => {
+                let millis: u64 = value.trim().parse().map_err(|e| {
+                    RuntimeError::with_source(
+                        format!("Invalid value '{}' for 'set locktimeout'", value),
+                        e,
+                    )
+                })?;
+                self.env.lock_timeout = Duration::from_millis(millis);
+            }
+            "maxloop" => {
+                self.max_loop_iterations = value.trim().parse().map_err(|e| {
+                    RuntimeError::with_source(
+                        format!("Invalid value '{}' for 'set maxloop'", value),
+                        e,
+                    )
+                })?;
+            }
+            _ => {
+                return Err(RuntimeError::new(format!("Unknown setting '{}'", option)));
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_closeall(&mut self) -> Result<(), RuntimeError> {
+        let closed = self.env.close_all()?;
+        self.emit(&format!("closed {} files", closed))
+    }
+
+    fn execute_unset(&mut self, var_name: &str, force: bool) -> Result<(), RuntimeError> {
+        self.env.unset(var_name, force)
+    }
+
+    fn execute_hexdump(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        let bytes = self.env.get_file_bytes(var_name)?;
+        for line in hex_dump(&bytes) {
+            self.emit(&line)?;
+        }
+        Ok(())
+    }
+
+    fn execute_size(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        let size = self.env.byte_size(var_name)?;
+        self.emit(&format!("{} bytes", size))
+    }
+
+    fn execute_setlineend(
+        &mut self,
+        var_name: &str,
+        line_ending: LineEnding,
+    ) -> Result<(), RuntimeError> {
+        self.env.set_line_ending(var_name, line_ending)
+    }
+
+    fn execute_sortlines(&mut self, var_name: &str, numeric: bool) -> Result<(), RuntimeError> {
+        self.env.sort_file_lines(var_name, numeric)
+    }
+
+    fn execute_uniqlines(&mut self, var_name: &str, all: bool) -> Result<(), RuntimeError> {
+        self.env.uniq_file_lines(var_name, all)
+    }
+
+    fn execute_reverselines(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        self.env.reverse_file_lines(var_name)
+    }
+
+    fn execute_numberlines(&mut self, var_name: &str) -> Result<(), RuntimeError> {
+        self.env.number_file_lines(var_name)
+    }
+
+    fn execute_trimlines(&mut self, var_name: &str, leading: bool) -> Result<(), RuntimeError> {
+        self.env.trim_file_lines(var_name, leading)
+    }
+
+    fn execute_diff(&mut self, left: &FileRef, right: &FileRef) -> Result<(), RuntimeError> {
+        let (left_name, left_text) = self.file_ref_text(left)?;
+        let (right_name, right_text) = self.file_ref_text(right)?;
+        let left_lines: Vec<&str> = left_text.lines().collect();
+        let right_lines: Vec<&str> = right_text.lines().collect();
+        let hunks = diff_lines(&left_lines, &right_lines, 3);
+        if hunks.is_empty() {
+            return self.emit("files are identical");
+        }
+        self.emit(&format!("--- {}", left_name))?;
+        self.emit(&format!("+++ {}", right_name))?;
+        for line in hunks {
+            self.emit(&line)?;
+        }
+        Ok(())
+    }
+
+    fn execute_hash(&mut self, target: &FileRef, algorithm: &str) -> Result<(), RuntimeError> {
+        let digest = self.file_digest(target, algorithm)?;
+        self.emit(&digest)
+    }
+
+    fn execute_checkhash(
+        &mut self,
+        target: &FileRef,
+        algorithm: &str,
+        expected: &str,
+    ) -> Result<(), RuntimeError> {
+        let digest = self.file_digest(target, algorithm)?;
+        let expected = expected.trim().to_lowercase();
+        if digest != expected {
+            return Err(RuntimeError::new(format!(
+                "{} mismatch: expected {}, got {}",
+                algorithm, expected, digest
+            )));
+        }
+        self.emit("ok")
+    }
+
+    /// Hash the on-disk file behind a variable or path.
+    fn file_digest(&self, target: &FileRef, algorithm: &str) -> Result<String, RuntimeError> {
+        let algo = HashAlgorithm::from_name(algorithm).ok_or_else(|| {
+            RuntimeError::new(format!(
+                "Unknown hash algorithm '{}' (supported: {})",
+                algorithm,
+                HashAlgorithm::NAMES
+            ))
+        })?;
+        let path = match target {
+            FileRef::Var(var_name) => self.env.filename_of(var_name)?,
+            FileRef::Path(path) => path.clone(),
+        };
+        hash_file(&path, algo)
+            .map_err(|e| RuntimeError::with_source(format!("Failed to hash '{}'", path), e))
+    }
+
+    /// The filename and text content behind a variable (its in-memory content)
+    /// or a path (read from disk).
+    fn file_ref_text(&self, target: &FileRef) -> Result<(String, String), RuntimeError> {
+        match target {
+            FileRef::Var(var_name) => {
+                let text = self.env.get_file_content(var_name)?.to_string();
+                Ok((self.env.files[var_name].filename.clone(), text))
+            }
+            FileRef::Path(path) => {
+                let text = self.env.fs.read_to_string(path).map_err(|e| {
+                    RuntimeError::with_source(format!("Failed to read file '{}'", path), e)
+                })?;
+                Ok((path.clone(), normalize_line_endings(&text)))
+            }
+        }
+    }
+
+    fn execute_encode(&mut self, var_name: &str, codec: ContentCodec) -> Result<(), RuntimeError> {
+        self.env.encode_file_content(var_name, codec)
+    }
+
+    fn execute_decode(&mut self, var_name: &str, codec: ContentCodec) -> Result<(), RuntimeError> {
+        self.env.decode_file_content(var_name, codec)
+    }
+
+    fn execute_concat(
+        &mut self,
+        destination: &str,
+        sources: &[String],
+    ) -> Result<(), RuntimeError> {
+        let action = format!("concat into \"{}\"", destination);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        let len = (sources.iter())
+            .map(|source| self.env.fs.metadata(source).map_or(0, |meta| meta.len))
+            .sum();
+        self.env.count_write(destination, len)?;
+        let journaled = self
+            .env
+            .journal
+            .snapshot(&*self.env.fs, action, destination);
+        let bytes = concat_files(destination, sources).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to concatenate into '{}'", destination), e)
+        })?;
+        self.env.journal.extend(journaled);
+        self.env.record_write(destination, bytes as usize);
+        self.emit(&format!("wrote {} bytes to {}", bytes, destination))
+    }
+
+    fn execute_split(&mut self, stmt: &SplitStmt) -> Result<(), RuntimeError> {
+        let chunks = self.env.split_file(&stmt.var_name, stmt.mode, stmt.size)?;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let filename = format!("{}{:03}", stmt.prefix, i);
+            let action = format!("write chunk \"{}\"", filename);
+            if self.skip_for_dry_run(&action)? {
+                continue;
+            }
+            self.env.count_write(&filename, chunk.len() as u64)?;
+            let journaled = self.env.journal.snapshot(&*self.env.fs, action, &filename);
+            self.env.fs.write(&filename, chunk).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to write chunk '{}'", filename), e)
+            })?;
+            self.env.journal.extend(journaled);
+            self.env.record_write(&filename, chunk.len());
+        }
+        if self.env.dry_run {
+            return Ok(());
+        }
+        self.emit(&format!("wrote {} chunks", chunks.len()))
+    }
+
+    fn execute_zipcreate(&mut self, archive: &str, source: &str) -> Result<(), RuntimeError> {
+        let action = format!("create archive \"{}\" from \"{}\"", archive, source);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        let count = zip_create(archive, source).map_err(|e| {
+            RuntimeError::with_source(
+                format!("Failed to create archive '{}' from '{}'", archive, source),
+                e,
+            )
+        })?;
+        self.env.journal.record(action, None);
+        self.emit(&format!("added {} files to {}", count, archive))
+    }
+
+    fn execute_zipextract(&mut self, archive: &str, destination: &str) -> Result<(), RuntimeError> {
+        let action = format!("extract \"{}\" into \"{}\"", archive, destination);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        let len = zip_extracted_size(archive).unwrap_or(0);
+        self.env.count_write(destination, len)?;
+        let count = zip_extract(archive, destination).map_err(|e| {
+            RuntimeError::with_source(
+                format!("Failed to extract '{}' into '{}'", archive, destination),
+                e,
+            )
+        })?;
+        self.env.journal.record(action, None);
+        self.emit(&format!("extracted {} files", count))
+    }
+
+    /// Run the statements of a `parallel` block at once, on up to one thread per
+    /// core, each on an interpreter of its own with this one's settings. Each
+    /// statement's output is printed in one piece when it finishes; failures
+    /// are reported together once all are done. In dry-run mode, with the
+    /// journal or a write limit on, or when `remove` may ask for confirmation,
+    /// they run one after another instead, since those need this interpreter.
+    fn execute_parallel(&mut self, stmt: &ParallelStmt) -> Result<(), RuntimeError> {
+        if self.env.dry_run
+            || self.env.journal.enabled
+            || self.env.max_write_bytes.is_some()
+            || (self.confirm && self.interactive)
+        {
+            return self.execute_block(&stmt.body);
+        }
+        let mut failures = Vec::new();
+        let mut tasks = Vec::new();
+        for (index, located) in stmt.body.iter().enumerate() {
+            self.begin_statement(located)?;
+            match self.prepared(&located.statement, |_, stmt| Ok(stmt.clone())) {
+                Ok(statement) => tasks.push((index, statement)),
+                Err(e) => {
+                    failures.push((index, e.message()));
+                    self.hooks.on_statement_end(&located.statement, &Err(e));
+                }
+            }
+        }
+
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        let settings = self.worker_settings();
+        let interrupt = Arc::clone(&self.interrupt);
+        let next_task = AtomicUsize::new(0);
+        let (sender, reports) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..workers.min(tasks.len()) {
+                let sender = sender.clone();
+                let (tasks, next_task, interrupt, settings) =
+                    (&tasks, &next_task, &interrupt, &settings);
+                scope.spawn(move || {
+                    while let Some((index, statement)) =
+                        tasks.get(next_task.fetch_add(1, AtomicOrdering::Relaxed))
+                    {
+                        let report = if interrupt.load(AtomicOrdering::Relaxed) {
+                            TaskReport::failed("interrupted".to_string())
+                        } else {
+                            run_parallel_task(statement, settings)
+                        };
+                        if sender.send((*index, report)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+            for (index, report) in reports {
+                let located = &stmt.body[index];
+                self.print(&report.output)?;
+                for (path, bytes) in report.written {
+                    self.hooks.on_file_written(&path, bytes);
+                }
+                let result = match report.error {
+                    None => Ok(ExecResult::Unit),
+                    Some(message) => Err(RuntimeError::new(message)),
+                };
+                self.hooks.on_statement_end(&located.statement, &result);
+                if let Err(e) = result {
+                    failures.push((index, e.message()));
+                }
+            }
+            Ok::<(), RuntimeError>(())
+        })?;
+
+        self.check_interrupt()?;
+        if failures.is_empty() {
+            return Ok(());
+        }
+        failures.sort();
+        let lines: Vec<String> = failures
+            .iter()
+            .map(|(index, message)| format!("  line {}: {}", stmt.body[*index].line, message))
+            .collect();
+        Err(RuntimeError::new(format!(
+            "{} of {} statements in the parallel block failed:\n{}",
+            failures.len(),
+            stmt.body.len(),
+            lines.join("\n")
+        )))
+    }
+
+    /// Poll a file's modification time and size, running the body whenever they
+    /// change (a missing file counts as changed once it appears). Runs until
+    /// `maxruns` is reached, the body executes `exit`, or the process is interrupted.
+    fn execute_watch(&mut self, stmt: &WatchStmt) -> Result<(), RuntimeError> {
+        let snapshot = |path: &str| {
+            fs::metadata(path)
+                .ok()
+                .map(|meta| (meta.modified().ok(), meta.len()))
+        };
+        let mut last = snapshot(&stmt.path);
+        let mut runs = 0;
+        while stmt.max_runs.is_none_or(|max| runs < max) && !self.stop {
+            self.pause(stmt.interval_ms)?;
+            let current = snapshot(&stmt.path);
+            if current == last {
+                continue;
+            }
+            last = current;
+            if last.is_none() {
+                // Deleted: wait for it to come back.
+                continue;
+            }
+            self.execute_block(&stmt.body)?;
+            self.flush()?;
+            runs += 1;
+        }
+        Ok(())
+    }
+
+    /// Print lines appended to a file, starting from its current end. Only the
+    /// bytes past the last offset are read on each poll; if the file shrinks it
+    /// was truncated or rotated, so following restarts from the beginning.
+    fn execute_tailfollow(&mut self, stmt: &TailFollowStmt) -> Result<(), RuntimeError> {
+        let file_len = |path: &str| fs::metadata(path).ok().map(|meta| meta.len());
+        let deadline = stmt
+            .max_seconds
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+        let mut offset = file_len(&stmt.path).unwrap_or(0);
+        let mut pending = Vec::new();
+        while deadline.is_none_or(|deadline| Instant::now() < deadline) {
+            self.pause(stmt.interval_ms)?;
+            let Some(len) = file_len(&stmt.path) else {
+                continue;
+            };
+            if len < offset {
+                self.emit("--- file truncated ---")?;
+                offset = 0;
+                pending.clear();
+            }
+            if len == offset {
+                continue;
+            }
+            let bytes = read_from_offset(&stmt.path, offset).map_err(|e| {
+                RuntimeError::with_source(format!("Failed to read '{}'", stmt.path), e)
+            })?;
+            offset += bytes.len() as u64;
+            pending.extend_from_slice(&bytes);
+            // Hold back a partial last line until the rest of it arrives.
+            if let Some(end) = pending.iter().rposition(|&b| b == b'\n') {
+                let complete: Vec<u8> = pending.drain(..=end).collect();
+                let text = String::from_utf8_lossy(&complete);
+                for line in text.lines() {
+                    self.emit(line)?;
+                }
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the body for as long as the condition holds, re-checking it against
+    /// the current state before every iteration.
+    fn execute_while(&mut self, stmt: &WhileStmt) -> Result<(), RuntimeError> {
+        let mut iterations = 0;
+        while self.loop_again(&stmt.condition, stmt.line, &mut iterations)? {
+            self.execute_block(&stmt.body)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a `while` loop on `line` should run its body (again), counting
+    /// the iteration against `set maxloop`.
+    pub(crate) fn loop_again(
+        &mut self,
+        condition: &Condition,
+        line: usize,
+        iterations: &mut usize,
+    ) -> Result<bool, RuntimeError> {
+        if self.stop || !self.evaluate_condition(condition)? {
+            return Ok(false);
+        }
+        self.check_interrupt()?;
+        if *iterations == self.max_loop_iterations {
+            return Err(RuntimeError::new(format!(
+                "while loop on line {} exceeded {} iterations (raise the limit with 'set maxloop')",
+                line, self.max_loop_iterations
+            )));
+        }
+        *iterations += 1;
+        Ok(true)
+    }
+
+    fn execute_if(&mut self, stmt: &IfStmt) -> Result<(), RuntimeError> {
+        if self.evaluate_condition(&stmt.condition)? {
+            self.execute_block(&stmt.body)?;
+        }
+        Ok(())
+    }
+
+    fn execute_assert(&mut self, stmt: &AssertStmt) -> Result<(), RuntimeError> {
+        if self.evaluate_condition(&stmt.condition)? {
+            return Ok(());
+        }
+        let details = self.describe_condition(&stmt.condition)?;
+        let mut message = format!("Assertion failed on line {}: {}", stmt.line, stmt.condition);
+        if !details.is_empty() {
+            message.push_str(&format!(" ({})", details.join(", ")));
+        }
+        Err(RuntimeError::new(message))
+    }
+
+    /// The actual values behind a condition, for a failed `assert`: each side of
+    /// a comparison that isn't a literal, what is at a path, or how many lines
+    /// of a variable match a pattern.
+    fn describe_condition(&self, condition: &Condition) -> Result<Vec<String>, RuntimeError> {
+        Ok(match condition {
+            Condition::Contains { var_name, pattern } => {
+                let matches =
+                    self.env
+                        .search_file(var_name, &self.fill_in(pattern), false, false)?;
+                match matches.first() {
+                    Some(m) => vec![format!(
+                        "{} matches on line {}: {}",
+                        var_name, m.line, m.text
+                    )],
+                    None => vec![format!("{} has no matching line", var_name)],
+                }
+            }
+            Condition::Exists { path } => {
+                let path = self.fill_in(path);
+                let kind = match self.env.fs.path_kind(&self.env.resolve_path(&path)) {
+                    PathKind::File => "a file",
+                    PathKind::Directory => "a directory",
+                    PathKind::Missing => "missing",
+                };
+                vec![format!("{:?} is {}", path, kind)]
+            }
+            Condition::Not(inner) => self.describe_condition(inner)?,
+            Condition::Compare { left, right, .. } => {
+                let mut details = Vec::new();
+                for operand in [left, right] {
+                    let literal = match operand {
+                        Operand::Text(text) => !text.contains("${"),
+                        Operand::Number(_) => true,
+                        _ => false,
+                    };
+                    if !literal {
+                        details.push(format!("{} is {:?}", operand, self.operand_value(operand)?));
+                    }
+                }
+                details
+            }
+        })
+    }
+
+    /// Test a condition. Its strings get `${name}` filled in each time, so a
+    /// loop sees variables that change while it runs.
+    fn evaluate_condition(&self, condition: &Condition) -> Result<bool, RuntimeError> {
+        match condition {
+            Condition::Contains { var_name, pattern } => Ok(!self
+                .env
+                .search_file(var_name, &self.fill_in(pattern), false, false)?
+                .is_empty()),
+            Condition::Exists { path } => {
+                let path = &self.env.resolve_path(&self.fill_in(path));
+                self.resolve_path(path)?;
+                Ok(self.env.fs.path_kind(path) != PathKind::Missing)
+            }
+            Condition::Not(inner) => Ok(!self.evaluate_condition(inner)?),
+            Condition::Compare { left, op, right } => {
+                let left = self.operand_value(left)?;
+                let right = self.operand_value(right)?;
+                Ok(op.holds(compare_values(&left, &right)))
+            }
+        }
+    }
+
+    fn operand_value(&self, operand: &Operand) -> Result<String, RuntimeError> {
+        Ok(match operand {
+            Operand::Text(text) => self.fill_in(text),
+            Operand::Number(n) => n.to_string(),
+            Operand::LineCount(var) => self.env.line_count(var, false)?.to_string(),
+            Operand::ByteCount(var) => self.env.byte_size(var)?.to_string(),
+        })
+    }
+
+    fn execute_sleep(&mut self, millis: u64) -> Result<(), RuntimeError> {
+        self.flush()?;
+        self.pause(millis)
+    }
+
+    /// Wait for `millis` milliseconds, in short slices so a stop request or an
+    /// interrupt isn't held up by a long wait.
+    fn pause(&mut self, millis: u64) -> Result<(), RuntimeError> {
+        let mut remaining = millis;
+        while remaining > 0 && !self.stop {
+            self.check_interrupt()?;
+            let slice = remaining.min(SLEEP_SLICE_MS);
+            thread::sleep(Duration::from_millis(slice));
+            remaining -= slice;
+        }
+        self.check_interrupt()
+    }
+
+    /// Where an included script is: next to the including script (or in the
+    /// working directory at the top level), else in the first directory of
+    /// `FILE_LANG_PATH` that has it.
+    fn include_path(&self, path: &str) -> PathBuf {
+        let local = match self.script_stack.last().and_then(|script| script.parent()) {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(self.env.resolve_path(path)),
+        };
+        if Path::new(path).is_absolute()
+            || self.env.fs.path_kind(&local.to_string_lossy()) != PathKind::Missing
+        {
+            return local;
+        }
+        let Some(search_path) = env::var_os("FILE_LANG_PATH") else {
+            return local;
+        };
+        env::split_paths(&search_path)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(|dir| dir.join(path))
+            .find(|candidate| {
+                self.env.fs.path_kind(&candidate.to_string_lossy()) != PathKind::Missing
+            })
+            .unwrap_or(local)
+    }
+
+    fn execute_include(&mut self, path: &str) -> Result<(), RuntimeError> {
+        let (ast, previous_dir) = self.enter_include(path)?;
+        let result = self.execute_block(&ast.statements);
+        self.leave_script(previous_dir);
+        result
+    }
+
+    /// Find an included script, refuse include cycles, and enter the script
+    /// (see `enter_script`).
+    fn enter_include(&mut self, path: &str) -> Result<(AST, Option<PathBuf>), RuntimeError> {
+        let resolved = self.include_path(path);
+        self.resolve_path(&resolved.to_string_lossy())?;
+        let canonical = self
+            .env
+            .fs
+            .canonicalize(&resolved.to_string_lossy())
+            .map_err(|e| {
+                RuntimeError::with_source(format!("Failed to include '{}'", resolved.display()), e)
+            })?;
+        let is_cycle = self.script_stack.iter().any(|script| {
+            self.env
+                .fs
+                .canonicalize(&script.to_string_lossy())
+                .is_ok_and(|s| s == canonical)
+        });
+        if is_cycle {
+            let chain: Vec<String> = self
+                .script_stack
+                .iter()
+                .chain([&resolved])
+                .map(|script| script.display().to_string())
+                .collect();
+            return Err(RuntimeError::new(format!(
+                "Include cycle: {}",
+                chain.join(" -> ")
+            )));
+        }
+
+        let including = match self.script_stack.last() {
+            Some(script) => format!("'{}'", script.display()),
+            None => "the top-level script".to_string(),
+        };
+        match self.enter_script(&resolved) {
+            Ok(entered) => Ok(entered),
+            Err(FileLangError::Runtime(e)) => Err(e),
+            Err(e) => Err(RuntimeError::with_source(
+                format!("In '{}' included from {}", resolved.display(), including),
+                e,
+            )),
+        }
+    }
+
+    fn execute_def(&mut self, stmt: &DefStmt) -> Result<(), RuntimeError> {
+        let function = Function {
+            params: stmt.params.clone(),
+            body: stmt.body.clone(),
+        };
+        self.functions.insert(stmt.name.clone(), Rc::new(function));
+        Ok(())
+    }
+
+    /// Run a function's body with its parameters bound to `args`. `${param}` in
+    /// the body's strings is replaced before the body is parsed and executed.
+    fn execute_call(&mut self, name: &str, args: &[String]) -> Result<(), RuntimeError> {
+        let ast = self.enter_call(name, args)?;
+        let result = self.execute_block(&ast.statements);
+        self.leave_call();
+        result
+    }
+
+    /// Bind a function's parameters to `args` and parse its body, which
+    /// `leave_call` ends.
+    fn enter_call(&mut self, name: &str, args: &[String]) -> Result<AST, RuntimeError> {
+        let function = self
+            .functions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| RuntimeError::new(format!("Function '{}' is not defined", name)))?;
+        if args.len() != function.params.len() {
+            return Err(RuntimeError::new(format!(
+                "Function '{}' takes {} arguments but {} were given",
+                name,
+                function.params.len(),
+                args.len()
+            )));
+        }
+        if self.call_depth == MAX_CALL_DEPTH {
+            return Err(RuntimeError::new(format!(
+                "Calling '{}' exceeded the maximum call depth of {}",
+                name, MAX_CALL_DEPTH
+            )));
+        }
+
+        let params = function.params.iter().cloned().zip(args.iter().cloned());
+        self.env.push_scope(params.collect());
+        let ast = self.parse_tokens(&format!("function '{}'", name), &function.body);
+        if ast.is_err() {
+            self.env.pop_scope();
+        } else {
+            self.call_depth += 1;
+        }
+        ast
+    }
+
+    fn leave_call(&mut self) {
+        self.call_depth -= 1;
+        self.env.pop_scope();
+    }
+
+    /// Run the body; if it fails, run the handler with `${error}` set to the
+    /// error message. Errors in the handler propagate.
+    fn execute_try(&mut self, stmt: &TryStmt) -> Result<(), RuntimeError> {
+        self.try_depth += 1;
+        let result = self.execute_block(&stmt.body);
+        self.try_depth -= 1;
+        let Err(error) = result else {
+            return Ok(());
+        };
+        if self.stopping() {
+            return Err(error);
+        }
+        let ast = self.enter_handler(&stmt.handler, &error)?;
+        let result = self.execute_block(&ast.statements);
+        self.env.pop_scope();
+        result
+    }
+
+    /// Bind `${error}` to the message of the error a `try` body failed with
+    /// and parse the handler, whose scope is popped when it is done.
+    pub(crate) fn enter_handler(
+        &mut self,
+        handler: &[Token],
+        error: &RuntimeError,
+    ) -> Result<AST, RuntimeError> {
+        // The handler still sees the parameters of the function it is in.
+        let mut scope = self.env.innermost_scope().cloned().unwrap_or_default();
+        scope.insert("error".to_string(), error.message());
+        self.env.push_scope(scope);
+        let ast = self.parse_tokens("onerror block", handler);
+        if ast.is_err() {
+            self.env.pop_scope();
+        }
+        ast
+    }
+
+    /// Parse a block stored as tokens (a function body or `onerror` block).
+    fn parse_tokens(&self, context: &str, body: &[Token]) -> Result<AST, RuntimeError> {
+        Parser::new(body.to_vec())
+            .parse()
+            .map_err(|e| RuntimeError::with_source(format!("Failed to parse {}", context), e))
+    }
+
+    fn execute_cd(&mut self, path: &str) -> Result<(), RuntimeError> {
+        if self.env.fs.path_kind(path) != PathKind::Directory {
+            return Err(RuntimeError::new(format!(
+                "Cannot change directory to '{}': not a directory",
+                path
+            )));
+        }
+        self.env.current_dir = normalize_lexically(Path::new(path));
+        Ok(())
+    }
+
+    fn execute_pwd(&mut self) -> Result<ExecResult, RuntimeError> {
+        Ok(ExecResult::Text(
+            self.env.working_dir()?.display().to_string(),
+        ))
+    }
+
+    fn execute_undo(&mut self, count: usize) -> Result<(), RuntimeError> {
+        if self.env.dry_run {
+            let planned: Vec<String> = (self.env.journal.entries().iter().rev().take(count))
+                .map(|entry| format!("undo {}", entry.description))
+                .collect();
+            for action in planned {
+                self.skip_for_dry_run(&action)?;
+            }
+            return Ok(());
+        }
+        for undone in 0..count {
+            match self.env.undo_last()? {
+                Some(description) => self.emit(&format!("undid {}", description))?,
+                None if undone == 0 && !self.env.journal.enabled => {
+                    return Err(RuntimeError::new(
+                        "Nothing to undo: the journal is off (use 'set journal on')".to_string(),
+                    ));
+                }
+                None if undone == 0 => {
+                    return Err(RuntimeError::new("Nothing to undo".to_string()));
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_history(&mut self) -> Result<ExecResult, RuntimeError> {
+        let entries = self.env.journal.entries();
+        if entries.is_empty() {
+            let text = if self.env.journal.enabled {
+                "(journal is empty)"
+            } else {
+                "(journal is off)"
+            };
+            return Ok(ExecResult::Text(text.to_string()));
+        }
+        let lines = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| match entry.undo {
+                Some(_) => format!("{:>4}  {}", i + 1, entry.description),
+                None => format!("{:>4}  {} (not undoable)", i + 1, entry.description),
+            })
+            .collect();
+        Ok(ExecResult::Lines(lines))
+    }
+
+    fn execute_savesession(&mut self, path: &str, full: bool) -> Result<(), RuntimeError> {
+        let action = format!("save session to \"{}\"", path);
+        if self.skip_for_dry_run(&action)? {
+            return Ok(());
+        }
+        let session = self.env.to_session(full);
+        let json = serde_json::to_string_pretty(&session).map_err(|e| {
+            RuntimeError::with_source("Failed to serialize the session".to_string(), e)
+        })?;
+        self.env.count_write(path, json.len() as u64)?;
+        let journaled = self.env.journal.snapshot(&*self.env.fs, action, path);
+        self.env.fs.write(path, json.as_bytes()).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to write session file '{}'", path), e)
+        })?;
+        self.env.journal.extend(journaled);
+        self.env.record_write(path, json.len());
+        if full {
+            return Ok(());
+        }
+        let mut unsaved: Vec<&String> = session
+            .files
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(var_name, _)| var_name)
+            .collect();
+        unsaved.sort();
+        for var_name in unsaved {
+            self.emit_warning(Some(format!(
+                "'{}' has unsaved changes, which the session only keeps with 'full'",
+                var_name
+            )))?;
+        }
+        Ok(())
+    }
+
+    fn execute_loadsession(&mut self, path: &str, merge: bool) -> Result<(), RuntimeError> {
+        let json = self.env.fs.read_to_string(path).map_err(|e| {
+            RuntimeError::with_source(format!("Failed to read session file '{}'", path), e)
+        })?;
+        let session: Session = serde_json::from_str(&json).map_err(|e| {
+            RuntimeError::with_source(format!("Invalid session file '{}'", path), e)
+        })?;
+        if self.sandbox_root.is_some() {
+            // The session's paths come from a file the script may have written.
+            let filenames = (session.files.values())
+                .filter(|saved| saved.target == FileTarget::Disk)
+                .map(|saved| saved.filename.as_str());
+            let current_dir = session.current_dir.to_string_lossy();
+            let current_dir = Some(&*current_dir).filter(|dir| !dir.is_empty());
+            for session_path in filenames.chain(current_dir) {
+                self.resolve_path(session_path)?;
+            }
+        }
+        for warning in self.env.from_session(session, merge)? {
+            self.emit_warning(Some(warning))?;
+        }
+        Ok(())
+    }
+
+    fn execute_dumpenv(&mut self, var_name: Option<&str>) -> Result<ExecResult, RuntimeError> {
+        let dump = match var_name {
+            Some(var) => self.env.dump_variable(var)?,
+            None => self.env.dump(),
+        };
+        Ok(ExecResult::Text(dump))
+    }
+
+    fn execute_help(&mut self, topic: Option<&str>) -> Result<(), RuntimeError> {
+        let text = match topic {
+            Some(name) => command_help(name).map_err(RuntimeError::new)?,
+            None => help_text(),
+        };
+        self.emit(&text)
+    }
+
+    fn execute_exit(&mut self, code: i32) -> Result<(), RuntimeError> {
+        self.stop = true;
+        self.exit_code = Some(code);
+        Ok(())
+    }
+}
+
+/// An `ls -l` style listing: size (right-aligned), mtime, and name.
+fn long_listing(entries: Vec<DetailedEntry>) -> ExecResult {
+    if entries.is_empty() {
+        return ExecResult::Paths(Vec::new());
+    }
+    let sizes: Vec<String> = entries
+        .iter()
+        .map(|e| match e.size {
+            Some(size) => size.to_string(),
+            None => "-".to_string(),
+        })
+        .collect();
+    let width = sizes.iter().map(|s| s.len()).max().unwrap_or(0);
+    let lines = entries
+        .iter()
+        .zip(sizes)
+        .map(|(entry, size)| {
+            format!(
+                "{:>width$}  {}  {}",
+                size,
+                format_rfc3339(entry.modified),
+                entry.name,
+                width = width
+            )
+        })
+        .collect();
+    ExecResult::Lines(lines)
+}
+
+/// Parse the value of an on/off setting.
+fn parse_switch(option: &str, value: &str) -> Result<bool, RuntimeError> {
+    match value.to_lowercase().as_str() {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(RuntimeError::new(format!(
+            "Setting '{}' expects 'on' or 'off', got '{}'",
+            option, value
+        ))),
+    }
 }
@@ -0,0 +1,191 @@
+/*!
+ * journal.rs
+ *
+ * The undo journal kept while `set journal on`:
+ * - Each operation that changes the disk adds a JournalEntry describing it, along
+ *   with the UndoAction that reverses it (content a write replaced, where a file
+ *   was moved from, that a copy created its destination, ...).
+ * - `undo` applies the most recent entries' actions, newest first; `history` lists them.
+ *
+ * Operations with no feasible inverse (recursive removes and copies, links,
+ * permission changes, archives, or files too large to stash) are still recorded
+ * so `history` shows them, but marked as not undoable.
+ */
+
+use crate::filesystem::FileSystem;
+use crate::utils::PathKind;
+use std::io;
+
+/// Files larger than this are not stashed; overwriting or removing them can't be undone.
+pub const MAX_STASH_BYTES: u64 = 16 * 1024 * 1024;
+
+/// How to reverse one journaled operation.
+pub enum UndoAction {
+    /// Put a file's earlier content back, or delete it if it didn't exist before.
+    Restore {
+        path: String,
+        content: Option<Vec<u8>>,
+    },
+    /// Move a file back from `from` to `to`, then put back the file the move
+    /// replaced at `from`, if any.
+    MoveBack {
+        from: String,
+        to: String,
+        replaced: Option<Vec<u8>>,
+    },
+    /// Remove a directory the operation created.
+    RemoveDir(String),
+    /// Recreate a directory the operation removed.
+    CreateDir(String),
+}
+
+impl UndoAction {
+    /// Reverse the operation on disk.
+    pub fn apply(&self, fs: &dyn FileSystem) -> io::Result<()> {
+        match self {
+            UndoAction::Restore {
+                path,
+                content: Some(content),
+            } => fs.write(path, content),
+            UndoAction::Restore {
+                path,
+                content: None,
+            } => {
+                if fs.path_kind(path) == PathKind::Missing {
+                    Ok(())
+                } else {
+                    fs.remove_file(path)
+                }
+            }
+            UndoAction::MoveBack { from, to, replaced } => {
+                fs.rename(from, to)?;
+                match replaced {
+                    Some(content) => fs.write(from, content),
+                    None => Ok(()),
+                }
+            }
+            UndoAction::RemoveDir(path) => fs.remove_dir(path),
+            UndoAction::CreateDir(path) => fs.create_dir_all(path),
+        }
+    }
+
+    /// The file whose content the action changes, for reloading variables open on it.
+    pub fn restored_path(&self) -> Option<&str> {
+        match self {
+            UndoAction::Restore { path, .. } => Some(path),
+            UndoAction::MoveBack { to, .. } => Some(to),
+            UndoAction::RemoveDir(_) | UndoAction::CreateDir(_) => None,
+        }
+    }
+}
+
+/// One operation in the journal.
+pub struct JournalEntry {
+    /// What was done, e.g. `write to file "a.txt"`.
+    pub description: String,
+    /// How to reverse it, or None if it can't be undone.
+    pub undo: Option<UndoAction>,
+}
+
+#[derive(Default)]
+pub struct Journal {
+    /// Record operations (`set journal on`).
+    pub enabled: bool,
+    /// Journaled operations, oldest first.
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// An entry for an operation reversed by `undo` (None if it can't be),
+    /// unless the journal is off.
+    pub fn entry(&self, description: String, undo: Option<UndoAction>) -> Option<JournalEntry> {
+        self.enabled.then_some(JournalEntry { description, undo })
+    }
+
+    /// Record an operation right away, e.g. one that can't be undone.
+    pub fn record(&mut self, description: String, undo: Option<UndoAction>) {
+        self.extend(self.entry(description, undo));
+    }
+
+    /// An entry that puts `path` back the way it is now, taken just before an
+    /// operation writes, creates, or removes the file.
+    pub fn snapshot(
+        &self,
+        fs: &dyn FileSystem,
+        description: String,
+        path: &str,
+    ) -> Option<JournalEntry> {
+        if !self.enabled {
+            return None;
+        }
+        let undo = match fs.path_kind(path) {
+            PathKind::Missing => Some(None),
+            PathKind::File => stash(fs, path).map(Some),
+            PathKind::Directory => None,
+        };
+        self.entry(
+            description,
+            undo.map(|content| UndoAction::Restore {
+                path: path.to_string(),
+                content,
+            }),
+        )
+    }
+
+    /// An entry that moves `destination` back to `source`, taken just before
+    /// the move, keeping whatever file the move will replace.
+    pub fn moved(
+        &self,
+        fs: &dyn FileSystem,
+        description: String,
+        source: &str,
+        destination: &str,
+    ) -> Option<JournalEntry> {
+        if !self.enabled {
+            return None;
+        }
+        let replaced = match fs.path_kind(destination) {
+            PathKind::Missing => Some(None),
+            PathKind::File => stash(fs, destination).map(Some),
+            PathKind::Directory => None,
+        };
+        self.entry(
+            description,
+            replaced.map(|replaced| UndoAction::MoveBack {
+                from: destination.to_string(),
+                to: source.to_string(),
+                replaced,
+            }),
+        )
+    }
+
+    /// Remove and return the most recent entry.
+    pub fn pop(&mut self) -> Option<JournalEntry> {
+        self.entries.pop()
+    }
+
+    /// Journaled operations, oldest first.
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Forget every journaled operation.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Extend<JournalEntry> for Journal {
+    fn extend<I: IntoIterator<Item = JournalEntry>>(&mut self, entries: I) {
+        self.entries.extend(entries);
+    }
+}
+
+/// A file's current content, unless it is too large to keep or can't be read.
+fn stash(fs: &dyn FileSystem, path: &str) -> Option<Vec<u8>> {
+    let metadata = fs.metadata(path).ok()?;
+    if metadata.len > MAX_STASH_BYTES {
+        return None;
+    }
+    fs.read(path).ok()
+}
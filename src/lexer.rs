@@ -5,22 +5,152 @@
  *
  * It supports keywords:
  * open, read, write, append, show, close, exit, as, truncate, search, replace,
- * linecount, copy, move, remove, rename, listdir, dumpenv, help
+ * linecount, copy, move, remove, rename, listdir, dumpenv, help, mkdir, rmdir,
+ * recursive, head, tail, insertline, deleteline, exists, stat, touch, save, revert,
+ * set, closeall, unset, hexdump, size, setlineend, extract, sortlines, uniqlines,
+ * reverselines, numberlines, trimlines, getline, diff, hash, checkhash, encode,
+ * decode, zipcreate, zipextract, concat, split, grep, find, du, symlink, hardlink,
+ * readlink, chmod, watch, do, end, tailfollow, while, echo, sleep, include,
+ * def, call, try, onerror, cd, pwd, restorebackup, tempfile, buffer, saveas,
+ * copyto, appendfrom, readrange, getenv, let, if, assert, prompt, undo, history,
+ * savesession, loadsession, breakpoint, parallel
  *
- * Identifiers: used for variables.
- * Strings: double-quoted strings for filenames, patterns, and text.
- * EndOfStatement: newline or semicolon
- * Comments: lines starting with '#' are ignored until newline.
+ * Punctuation: `(`, `)`, and `,` for function parameters and arguments,
+ * `|` to pipe a command's results into a variable, and `=` in `let`.
+ *
+ * Comparison operators in conditions: `==`, `!=`, `<`, `<=`, `>`, `>=`.
+ *
+ * Identifiers: used for variables. They start with a letter or underscore and
+ * continue with letters, digits, and underscores.
+ * Strings: double-quoted strings for filenames, patterns, and text. Triple-quoted
+ * strings (`"""..."""`) may contain `"`; a newline right after the opening `"""`
+ * is dropped, and in a run of quotes at the end the last three close the string.
+ * Raw strings (`r"..."`, or `r#"..."#` to allow `"` inside) never treat `\` as
+ * special, like Rust's.
+ * Numbers: bare integers like `10` or `-3`.
+ * EndOfStatement: newline or semicolon (every newline is its own token, so a
+ * blank line shows up as two in a row)
+ * Eof: the end of the input, always the last token
+ * Comments: lines starting with '#' are ignored until newline, or kept as
+ * Comment tokens when the lexer is created `with_trivia(true)`.
  */
 
 use crate::errors::LexError;
 use crate::tokens::{Token, TokenKind};
-use crate::utils::is_identifier_char;
+use crate::utils::{is_identifier_char, is_identifier_start};
+
+/// Opens and closes a string that may contain quotes and newlines.
+const TRIPLE_QUOTE: &str = "\"\"\"";
+
+/// Every keyword and the token it lexes to. Keywords are case-insensitive.
+const KEYWORDS: &[(&str, TokenKind)] = &[
+    ("open", TokenKind::Open),
+    ("read", TokenKind::Read),
+    ("write", TokenKind::Write),
+    ("append", TokenKind::Append),
+    ("show", TokenKind::Show),
+    ("close", TokenKind::Close),
+    ("exit", TokenKind::Exit),
+    ("as", TokenKind::As),
+    ("truncate", TokenKind::Truncate),
+    ("search", TokenKind::Search),
+    ("replace", TokenKind::Replace),
+    ("linecount", TokenKind::LineCount),
+    ("copy", TokenKind::Copy),
+    ("move", TokenKind::Move),
+    ("remove", TokenKind::Remove),
+    ("rename", TokenKind::Rename),
+    ("listdir", TokenKind::ListDir),
+    ("dumpenv", TokenKind::DumpEnv),
+    ("help", TokenKind::Help),
+    ("mkdir", TokenKind::Mkdir),
+    ("rmdir", TokenKind::Rmdir),
+    ("recursive", TokenKind::Recursive),
+    ("do", TokenKind::Do),
+    ("end", TokenKind::End),
+    ("head", TokenKind::Head),
+    ("tail", TokenKind::Tail),
+    ("insertline", TokenKind::InsertLine),
+    ("deleteline", TokenKind::DeleteLine),
+    ("exists", TokenKind::Exists),
+    ("stat", TokenKind::Stat),
+    ("touch", TokenKind::Touch),
+    ("save", TokenKind::Save),
+    ("revert", TokenKind::Revert),
+    ("set", TokenKind::Set),
+    ("closeall", TokenKind::CloseAll),
+    ("unset", TokenKind::Unset),
+    ("hexdump", TokenKind::HexDump),
+    ("size", TokenKind::Size),
+    ("setlineend", TokenKind::SetLineEnd),
+    ("extract", TokenKind::Extract),
+    ("sortlines", TokenKind::SortLines),
+    ("uniqlines", TokenKind::UniqLines),
+    ("reverselines", TokenKind::ReverseLines),
+    ("numberlines", TokenKind::NumberLines),
+    ("trimlines", TokenKind::TrimLines),
+    ("getline", TokenKind::GetLine),
+    ("diff", TokenKind::Diff),
+    ("hash", TokenKind::Hash),
+    ("checkhash", TokenKind::CheckHash),
+    ("encode", TokenKind::Encode),
+    ("decode", TokenKind::Decode),
+    ("zipcreate", TokenKind::ZipCreate),
+    ("zipextract", TokenKind::ZipExtract),
+    ("concat", TokenKind::Concat),
+    ("split", TokenKind::Split),
+    ("grep", TokenKind::Grep),
+    ("find", TokenKind::Find),
+    ("du", TokenKind::Du),
+    ("symlink", TokenKind::Symlink),
+    ("hardlink", TokenKind::HardLink),
+    ("readlink", TokenKind::ReadLink),
+    ("chmod", TokenKind::Chmod),
+    ("watch", TokenKind::Watch),
+    ("tailfollow", TokenKind::TailFollow),
+    ("while", TokenKind::While),
+    ("echo", TokenKind::Echo),
+    ("sleep", TokenKind::Sleep),
+    ("include", TokenKind::Include),
+    ("def", TokenKind::Def),
+    ("call", TokenKind::Call),
+    ("try", TokenKind::Try),
+    ("onerror", TokenKind::OnError),
+    ("cd", TokenKind::Cd),
+    ("pwd", TokenKind::Pwd),
+    ("restorebackup", TokenKind::RestoreBackup),
+    ("tempfile", TokenKind::TempFile),
+    ("buffer", TokenKind::Buffer),
+    ("saveas", TokenKind::SaveAs),
+    ("copyto", TokenKind::CopyTo),
+    ("appendfrom", TokenKind::AppendFrom),
+    ("readrange", TokenKind::ReadRange),
+    ("getenv", TokenKind::GetEnv),
+    ("let", TokenKind::Let),
+    ("if", TokenKind::If),
+    ("assert", TokenKind::Assert),
+    ("prompt", TokenKind::Prompt),
+    ("undo", TokenKind::Undo),
+    ("history", TokenKind::History),
+    ("savesession", TokenKind::SaveSession),
+    ("loadsession", TokenKind::LoadSession),
+    ("breakpoint", TokenKind::Breakpoint),
+    ("parallel", TokenKind::Parallel),
+];
+
+/// Every keyword, e.g. for suggesting one in place of a misspelled command.
+pub fn keywords() -> impl Iterator<Item = &'static str> {
+    KEYWORDS.iter().map(|(keyword, _)| *keyword)
+}
 
 pub struct Lexer<'a> {
     input: &'a str,
     pos: usize,
     length: usize,
+    /// The 1-based line of `pos`.
+    line: usize,
+    /// Emit comments as tokens instead of skipping them.
+    trivia: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -29,62 +159,165 @@ impl<'a> Lexer<'a> {
             input,
             pos: 0,
             length: input.len(),
+            line: 1,
+            trivia: false,
         }
     }
 
+    /// Keep comments as `Comment` tokens, for tools like formatters that need
+    /// them. The parser skips them, so the AST is the same either way.
+    pub fn with_trivia(mut self, trivia: bool) -> Self {
+        self.trivia = trivia;
+        self
+    }
+
     pub fn lex(&mut self) -> Result<Vec<Token>, LexError> {
         let mut tokens = Vec::new();
+        while self.lex_next(&mut tokens)? {}
+        tokens.push(Token::new(TokenKind::Eof, self.pos, self.line));
+        Ok(tokens)
+    }
 
-        while !self.is_at_end() {
-            self.skip_whitespace();
-            if self.is_at_end() {
-                break;
+    /// Lex the whole input, recording each error and skipping past the
+    /// offending character instead of stopping at the first one.
+    pub fn lex_with_recovery(&mut self) -> (Vec<Token>, Vec<LexError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            match self.lex_next(&mut tokens) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    if e.position() == Some(self.pos) {
+                        self.advance();
+                    }
+                    errors.push(e);
+                }
             }
+        }
+        tokens.push(Token::new(TokenKind::Eof, self.pos, self.line));
+        (tokens, errors)
+    }
 
-            let c = self.peek_char();
-            if c == '"' {
-                let start = self.pos;
-                let string_val = self.lex_string()?;
-                tokens.push(Token::new(TokenKind::String(string_val), start));
-                continue;
-            }
+    /// Lex the next token (skipping whitespace and comments) onto `tokens`.
+    /// Returns false at the end of the input. Errors carry the position of the
+    /// token that failed.
+    fn lex_next(&mut self, tokens: &mut Vec<Token>) -> Result<bool, LexError> {
+        self.skip_whitespace();
+        if self.is_at_end() {
+            return Ok(false);
+        }
+        let start = self.pos;
+        self.lex_token(tokens).map_err(|e| e.at(start))?;
+        Ok(true)
+    }
 
-            if c.is_alphabetic() {
-                let start = self.pos;
-                let ident = self.lex_identifier();
-                let kind = self.ident_to_keyword_or_identifier(&ident);
-                tokens.push(Token::new(kind, start));
-                continue;
-            }
+    fn lex_token(&mut self, tokens: &mut Vec<Token>) -> Result<(), LexError> {
+        let line = self.line;
+        let c = self.peek_char();
+        if c == '"' {
+            let start = self.pos;
+            let string_val = if self.input[self.pos..].starts_with(TRIPLE_QUOTE) {
+                self.lex_triple_quoted_string()?
+            } else {
+                self.lex_string()?
+            };
+            tokens.push(Token::new(TokenKind::String(string_val), start, line));
+            return Ok(());
+        }
 
-            if c == '\n' {
-                let start = self.pos;
-                self.pos += 1;
-                tokens.push(Token::new(TokenKind::EndOfStatement, start));
-                continue;
-            }
+        if c == 'r' && self.starts_raw_string() {
+            let start = self.pos;
+            let string_val = self.lex_raw_string()?;
+            tokens.push(Token::new(TokenKind::String(string_val), start, line));
+            return Ok(());
+        }
 
-            if c == ';' {
-                let start = self.pos;
-                self.pos += 1;
-                tokens.push(Token::new(TokenKind::EndOfStatement, start));
-                continue;
-            }
+        if is_identifier_start(c) {
+            let start = self.pos;
+            let ident = self.lex_identifier();
+            let kind = self.ident_to_keyword_or_identifier(&ident);
+            tokens.push(Token::new(kind, start, line));
+            return Ok(());
+        }
 
-            if c == '#' {
-                self.lex_comment();
-                continue;
+        if c.is_ascii_digit() || (c == '-' && self.next_is_digit()) {
+            let start = self.pos;
+            let number = self.lex_number()?;
+            tokens.push(Token::new(TokenKind::Number(number), start, line));
+            return Ok(());
+        }
+
+        if c == '\n' {
+            let start = self.pos;
+            self.advance();
+            tokens.push(Token::new(TokenKind::EndOfStatement, start, line));
+            return Ok(());
+        }
+
+        if c == ';' {
+            let start = self.pos;
+            self.advance();
+            tokens.push(Token::new(TokenKind::EndOfStatement, start, line));
+            return Ok(());
+        }
+
+        let operator = match (c, self.input[self.pos..].chars().nth(1)) {
+            ('=', Some('=')) => Some(TokenKind::EqualEqual),
+            ('!', Some('=')) => Some(TokenKind::NotEqual),
+            ('<', Some('=')) => Some(TokenKind::LessEqual),
+            ('>', Some('=')) => Some(TokenKind::GreaterEqual),
+            _ => None,
+        };
+        if let Some(kind) = operator {
+            let start = self.pos;
+            self.advance();
+            self.advance();
+            tokens.push(Token::new(kind, start, line));
+            return Ok(());
+        }
+
+        let punctuation = match c {
+            '(' => Some(TokenKind::LParen),
+            ')' => Some(TokenKind::RParen),
+            ',' => Some(TokenKind::Comma),
+            '|' => Some(TokenKind::Pipe),
+            '=' => Some(TokenKind::Equals),
+            '<' => Some(TokenKind::Less),
+            '>' => Some(TokenKind::Greater),
+            _ => None,
+        };
+        if let Some(kind) = punctuation {
+            let start = self.pos;
+            self.advance();
+            tokens.push(Token::new(kind, start, line));
+            return Ok(());
+        }
+
+        if c == '#' {
+            let start = self.pos;
+            let comment = self.lex_comment();
+            if self.trivia {
+                tokens.push(Token::new(TokenKind::Comment(comment), start, line));
             }
+            return Ok(());
+        }
 
+        let after_name = self.input[..self.pos]
+            .chars()
+            .next_back()
+            .is_some_and(is_identifier_char);
+        if (c == '-' || c == '.') && after_name {
             return Err(LexError::new(format!(
-                "Unexpected character '{}' at position {}",
+                "Unexpected character '{}' at position {}: names may only contain letters, \
+                 digits, and '_' (quote paths, e.g. \"file.txt\")",
                 c, self.pos
             )));
         }
-
-     
-        tokens.push(Token::new(TokenKind::EndOfStatement, self.pos));
-        Ok(tokens)
+        Err(LexError::new(format!(
+            "Unexpected character '{}' at position {}",
+            c, self.pos
+        )))
     }
 
     fn is_at_end(&self) -> bool {
@@ -99,6 +332,9 @@ impl<'a> Lexer<'a> {
         let c = self.input[self.pos..].chars().next().unwrap();
         let char_len = c.len_utf8();
         self.pos += char_len;
+        if c == '\n' {
+            self.line += 1;
+        }
         c
     }
 
@@ -120,7 +356,7 @@ impl<'a> Lexer<'a> {
         while !self.is_at_end() {
             let c = self.peek_char();
             if c == '"' {
-                self.advance(); 
+                self.advance();
                 return Ok(result);
             } else {
                 result.push(c);
@@ -133,6 +369,69 @@ impl<'a> Lexer<'a> {
         )))
     }
 
+    /// Lex a `"""` string: everything up to the closing `"""`, newlines and
+    /// quotes included, except a newline right after the opening delimiter.
+    fn lex_triple_quoted_string(&mut self) -> Result<String, LexError> {
+        let start = self.pos;
+        self.pos += TRIPLE_QUOTE.len();
+        if self.input[self.pos..].starts_with("\r\n") {
+            self.pos += 1;
+        }
+        if self.input[self.pos..].starts_with('\n') {
+            self.advance();
+        }
+        let mut result = String::new();
+        while !self.is_at_end() {
+            if self.input[self.pos..].starts_with(TRIPLE_QUOTE) {
+                // Quotes before the last three of a run belong to the string.
+                while self.input[self.pos + 1..].starts_with(TRIPLE_QUOTE) {
+                    result.push(self.advance());
+                }
+                self.pos += TRIPLE_QUOTE.len();
+                return Ok(result);
+            }
+            result.push(self.advance());
+        }
+        Err(LexError::new(format!(
+            "Unterminated triple-quoted string starting at position {}",
+            start
+        )))
+    }
+
+    /// Whether the input at `r` starts a raw string: `r"` or `r#...#"`, so a
+    /// variable named `r` still lexes as an identifier.
+    fn starts_raw_string(&self) -> bool {
+        self.input[self.pos + 1..]
+            .trim_start_matches('#')
+            .starts_with('"')
+    }
+
+    /// Lex `r"..."` or `r#"..."#` (any number of `#`): everything up to a `"`
+    /// followed by as many `#` as opened the string, backslashes included.
+    fn lex_raw_string(&mut self) -> Result<String, LexError> {
+        let start = self.pos;
+        self.advance(); // consume r
+        let mut hashes = 0;
+        while self.peek_char() == '#' {
+            self.advance();
+            hashes += 1;
+        }
+        self.advance(); // consume "
+        let closer = format!("\"{}", "#".repeat(hashes));
+        match self.input[self.pos..].find(&closer) {
+            Some(len) => {
+                let text = &self.input[self.pos..self.pos + len];
+                self.line += text.matches('\n').count();
+                self.pos += len + closer.len();
+                Ok(text.to_string())
+            }
+            None => Err(LexError::new(format!(
+                "Unterminated raw string starting at position {}",
+                start
+            ))),
+        }
+    }
+
     fn lex_identifier(&mut self) -> String {
         let mut result = String::new();
         while !self.is_at_end() && is_identifier_char(self.peek_char()) {
@@ -141,39 +440,64 @@ impl<'a> Lexer<'a> {
         result
     }
 
-    fn lex_comment(&mut self) {
+    /// Whether the character after the current one is an ASCII digit.
+    fn next_is_digit(&self) -> bool {
+        self.input[self.pos..]
+            .chars()
+            .nth(1)
+            .is_some_and(|c| c.is_ascii_digit())
+    }
+
+    fn lex_number(&mut self) -> Result<i64, LexError> {
+        let start = self.pos;
+        let mut text = String::new();
+        text.push(self.advance()); // digit or leading '-'
+        while !self.is_at_end() && self.peek_char().is_ascii_digit() {
+            text.push(self.advance());
+        }
+        if !self.is_at_end() && is_identifier_char(self.peek_char()) {
+            while !self.is_at_end() && is_identifier_char(self.peek_char()) {
+                text.push(self.advance());
+            }
+            return Err(LexError::new(format!(
+                "Invalid name or number '{}' at position {}: names must start with a letter \
+                 or '_'",
+                text, start
+            )));
+        }
+        text.parse::<i64>().map_err(|_| {
+            LexError::new(format!(
+                "Number '{}' at position {} is out of range",
+                text, start
+            ))
+        })
+    }
+
+    /// Consume a comment up to (not including) the newline, returning its text
+    /// after the `#`.
+    fn lex_comment(&mut self) -> String {
+        self.advance(); // consume #
+        let mut result = String::new();
         while !self.is_at_end() {
             let c = self.peek_char();
             if c == '\n' {
                 break;
             } else {
+                result.push(c);
                 self.advance();
             }
         }
+        result
     }
 
     fn ident_to_keyword_or_identifier(&self, ident: &str) -> TokenKind {
-        match ident.to_lowercase().as_str() {
-            "open" => TokenKind::Open,
-            "read" => TokenKind::Read,
-            "write" => TokenKind::Write,
-            "append" => TokenKind::Append,
-            "show" => TokenKind::Show,
-            "close" => TokenKind::Close,
-            "exit" => TokenKind::Exit,
-            "as" => TokenKind::As,
-            "truncate" => TokenKind::Truncate,
-            "search" => TokenKind::Search,
-            "replace" => TokenKind::Replace,
-            "linecount" => TokenKind::LineCount,
-            "copy" => TokenKind::Copy,
-            "move" => TokenKind::Move,
-            "remove" => TokenKind::Remove,
-            "rename" => TokenKind::Rename,
-            "listdir" => TokenKind::ListDir,
-            "dumpenv" => TokenKind::DumpEnv,
-            "help" => TokenKind::Help,
-            _ => TokenKind::Identifier(ident.to_string()),
-        }
+        let lower = ident.to_lowercase();
+        KEYWORDS
+            .iter()
+            .find(|(keyword, _)| *keyword == lower)
+            .map_or_else(
+                || TokenKind::Identifier(ident.to_string()),
+                |(_, kind)| kind.clone(),
+            )
     }
 }
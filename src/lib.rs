@@ -1,11 +1,20 @@
-
-
 pub mod ast;
+#[cfg(feature = "async")]
+pub mod async_interpreter;
 pub mod commands;
 pub mod environment;
 pub mod errors;
+pub mod execution;
+pub mod filesystem;
+pub mod hooks;
 pub mod interpreter;
+pub mod journal;
 pub mod lexer;
 pub mod parser;
+pub mod repl;
+pub mod script;
+pub mod session;
 pub mod tokens;
 pub mod utils;
+
+pub use script::{run_script, run_script_with, ScriptOptions, ScriptOutput};
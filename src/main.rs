@@ -6,11 +6,41 @@
  * replacing, listing directories, copying/moving files, removing files, dumping
  * environment variables, and more.
  *
- * Usage:
+ * Usage (see USAGE below, or --help, for every option):
  *   cargo run -- script.txt
+ *   cargo run -- script.txt -- a b         (the script sees "${arg1}", "${arg2}", "${argcount}")
+ *   cargo run -- -c 'echo "hi"'            (run a script given on the command line)
+ *   cargo run -- setup.txt main.txt        (run several scripts, sharing one environment)
+ *   cargo run -- --quiet script.txt        (discard the script's output)
+ *   cargo run -- --keep-going script.txt   (report failing statements and carry on)
+ *   cargo run -- --dry-run script.txt      (print filesystem changes instead of making them)
+ *   cargo run -- --backup script.txt       (copy each file to "<name>.bak" before first writing it)
+ *   cargo run -- --trace script.txt        (print each statement to stderr as it runs)
+ *   cargo run -- --profile script.txt      (print statement timings to stderr at the end)
+ *   cargo run -- --sandbox DIR script.txt  (reject paths outside DIR)
+ *   cargo run -- --limit-statements 1000 --limit-output 65536 script.txt
+ *                                          (stop scripts that run or print too much)
+ *   cargo run -- --paths script-relative scripts/deploy.txt
+ *                                          (resolve relative paths from the script's directory)
+ *   cargo run -- --dump-ast script.txt     (print the parsed AST as JSON without running it)
+ *   cargo run -- --dump-tokens script.txt  (print the tokens, comments included, as JSON)
+ *   cargo run -- --check script.txt        (report every syntax error without running it)
+ *   cargo run -- --no-pretty-errors script.txt
+ *                                          (report syntax errors on one line, without the source)
+ *   cargo run -- --fmt script.txt          (print the script in canonical format)
+ *   cargo run -- --fmt --write script.txt  (reformat the script file in place)
+ *
+ * Ctrl-C stops the running statement with an "interrupted" runtime error, so
+ * temporary files are still cleaned up; a second Ctrl-C before the first is
+ * noticed exits at once. At the prompt, Ctrl-C discards the line being typed
+ * and shows a fresh prompt.
+ *
+ * Exit status: the code given to `exit` (0 by default), or 1 for a runtime
+ * error, 2 for a lexing error (or a bad command line or unreadable script),
+ * and 3 for a parsing error.
  * or
  *   cargo run
- *   (then type commands directly)
+ *   (then type commands at the prompt; see repl.rs)
  *
  * Example:
  *   open "example.txt" as f
@@ -27,50 +57,476 @@
  *   exit
  */
 
-use file_lang::{interpreter::Interpreter, lexer::Lexer, parser::Parser};
+use file_lang::errors::{render_diagnostic, FileLangError};
+use file_lang::interpreter::{ErrorPolicy, Interpreter, Limits, PathMode};
+use file_lang::repl::{history_path, run_repl, History, PROMPT};
+use file_lang::{lexer::Lexer, parser::Parser};
 use std::env;
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut source = String::new();
-
-    if args.len() > 1 {
-        let filename = &args[1];
-        let file = File::open(filename).expect("Unable to open input script file.");
-        let mut reader = BufReader::new(file);
-        reader
-            .read_to_string(&mut source)
-            .expect("Unable to read from input file.");
-    } else {
-        let mut stdin = std::io::stdin();
-        let mut buffer = Vec::new();
-        let _ = std::io::Read::read_to_end(&mut stdin, &mut buffer).expect("Failed to read stdin.");
-        source = String::from_utf8(buffer).expect("Invalid UTF-8 in stdin input.");
+const USAGE: &str = "\
+Usage: file_lang [options] [script | -c \"commands\"]... [-- args...]
+
+Scripts and -c commands run in command-line order through one interpreter, so
+files opened by one are visible to the next; `exit` in any of them stops the
+rest. With neither, commands are read from stdin (at a prompt, if it is a
+terminal). Arguments after `--` are available as \"${arg1}\", \"${arg2}\", ...,
+and \"${argcount}\".
+
+Options:
+  -c TEXT          Run TEXT as a script (may be repeated)
+  --check          Report every syntax error without running the script
+  --no-pretty-errors
+                   Report lexing and parsing errors on a single line instead of
+                   showing the source line with a caret under the error
+  --fmt [--write]  Print the script in canonical format (--write: in place)
+  --dump-ast       Print the parsed AST as JSON without running the script
+  --dump-tokens    Print the tokens, comments included, as JSON
+  --keep-going     Report failing statements and carry on
+  --dry-run        Print filesystem changes instead of making them
+  --backup         Copy each existing file to <name>.bak before first writing it
+  --trace          Print each statement to stderr as it runs
+  --profile        Print statement timings to stderr at the end
+  --sandbox DIR    Reject paths outside DIR
+  --limit-read BYTES
+                   Refuse to read a file of more than BYTES into memory
+  --limit-write BYTES
+                   Stop writing to files after BYTES in total
+  --limit-statements N
+                   Stop after running N statements (loop passes included)
+  --limit-output BYTES
+                   Cut off the output after BYTES and stop
+  --paths MODE     Resolve relative paths from the directory the interpreter was
+                   started in (cwd, the default) or from each script's
+                   directory (script-relative)
+  -q, --quiet      Discard the script's output (errors are still reported)
+  -h, --help       Print this help
+  -V, --version    Print the version
+";
+
+/// One script to run: a file, or text given with `-c`.
+enum Unit {
+    Script(String),
+    Command(String),
+}
+
+/// What to do, from the command line.
+#[derive(Default)]
+struct Options {
+    /// Scripts and `-c` texts, in command-line order.
+    units: Vec<Unit>,
+    /// Arguments after `--`.
+    script_args: Vec<String>,
+    sandbox: Option<String>,
+    limits: Limits,
+    path_mode: PathMode,
+    keep_going: bool,
+    dry_run: bool,
+    backup: bool,
+    trace: bool,
+    profile: bool,
+    quiet: bool,
+    dump_ast: bool,
+    dump_tokens: bool,
+    check: bool,
+    /// Report syntax errors without the source line and caret.
+    plain_errors: bool,
+    format: bool,
+    write: bool,
+    help: bool,
+    version: bool,
+}
+
+/// Parse the command line. Every argument that is not an option is a script;
+/// everything after `--` belongs to the scripts.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => break,
+            "-c" => options
+                .units
+                .push(Unit::Command(args.next().ok_or("-c needs a script")?)),
+            "--sandbox" => {
+                options.sandbox = Some(args.next().ok_or("--sandbox needs a directory")?)
+            }
+            "--limit-read" => options.limits.max_read_bytes = Some(limit(&arg, args.next())?),
+            "--limit-write" => options.limits.max_write_bytes = Some(limit(&arg, args.next())?),
+            "--limit-statements" => options.limits.max_statements = Some(limit(&arg, args.next())?),
+            "--limit-output" => options.limits.max_output_bytes = Some(limit(&arg, args.next())?),
+            "--paths" => {
+                options.path_mode = match args.next().as_deref() {
+                    Some("cwd") => PathMode::Cwd,
+                    Some("script-relative") => PathMode::ScriptRelative,
+                    _ => return Err("--paths needs 'cwd' or 'script-relative'".to_string()),
+                }
+            }
+            "--keep-going" => options.keep_going = true,
+            "--dry-run" => options.dry_run = true,
+            "--backup" => options.backup = true,
+            "--trace" => options.trace = true,
+            "--profile" => options.profile = true,
+            "-q" | "--quiet" => options.quiet = true,
+            "--dump-ast" => options.dump_ast = true,
+            "--dump-tokens" => options.dump_tokens = true,
+            "--check" => options.check = true,
+            "--no-pretty-errors" => options.plain_errors = true,
+            "--fmt" => options.format = true,
+            "--write" => options.write = true,
+            "-h" | "--help" => options.help = true,
+            "-V" | "--version" => options.version = true,
+            _ if arg.starts_with('-') => {
+                return Err(format!("Unknown option '{}' (see --help)", arg));
+            }
+            _ => options.units.push(Unit::Script(arg)),
+        }
     }
+    options.script_args = args.collect();
+    Ok(options)
+}
+
+/// The number given after a `--limit-*` option.
+fn limit(option: &str, value: Option<String>) -> Result<u64, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("{} needs a number", option))
+}
 
-    let mut lexer = Lexer::new(&source);
-    let tokens = match lexer.lex() {
-        Ok(toks) => toks,
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
         Err(e) => {
-            eprintln!("Lexing error: {}", e);
-            std::process::exit(1);
+            eprintln!("{}", e);
+            std::process::exit(2);
         }
     };
+    if options.help {
+        print!("{}", USAGE);
+        return;
+    }
+    if options.version {
+        println!("file_lang {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
 
-    let mut parser = Parser::new(tokens);
-    let ast = match parser.parse() {
-        Ok(ast) => ast,
-        Err(e) => {
-            eprintln!("Parsing error: {}", e);
+    let commands = options
+        .units
+        .iter()
+        .filter(|unit| matches!(unit, Unit::Command(_)))
+        .count();
+    let mut units = Vec::new();
+    let mut command_number = 0;
+    for unit in &options.units {
+        units.push(match unit {
+            Unit::Script(script) => {
+                let source = fs::read_to_string(script).unwrap_or_else(|e| {
+                    eprintln!("Cannot read script '{}': {}", script, e);
+                    std::process::exit(2);
+                });
+                (Some(script.clone()), Some(script.as_str()), source)
+            }
+            Unit::Command(command) => {
+                command_number += 1;
+                let name = if commands == 1 {
+                    "-c".to_string()
+                } else {
+                    format!("-c #{}", command_number)
+                };
+                (Some(name), None, command.clone())
+            }
+        });
+    }
+    let interactive = units.is_empty() && io::stdin().is_terminal();
+    if units.is_empty() && !interactive {
+        units.push((None, None, read_stdin()));
+    }
+
+    if options.check {
+        let statuses: Vec<i32> = units
+            .iter()
+            .map(|(name, _, source)| check_syntax(name.as_deref(), source, !options.plain_errors))
+            .collect();
+        std::process::exit(statuses.into_iter().find(|&s| s != 0).unwrap_or(0));
+    }
+    if options.format {
+        for (name, script, source) in &units {
+            let formatted = match to_source(source) {
+                Ok(formatted) => formatted,
+                Err(e) => std::process::exit(report_error(
+                    name.as_deref(),
+                    source,
+                    e,
+                    !options.plain_errors,
+                )),
+            };
+            match (options.write, script) {
+                (true, Some(script)) => {
+                    if let Err(e) = fs::write(script, formatted) {
+                        eprintln!("Failed to write script '{}': {}", script, e);
+                        std::process::exit(1);
+                    }
+                }
+                (true, None) => {
+                    eprintln!("--write needs a script file");
+                    std::process::exit(2);
+                }
+                (false, _) => print!("{}", formatted),
+            }
+        }
+        return;
+    }
+    if options.dump_ast || options.dump_tokens {
+        for (name, _, source) in &units {
+            if let Err(e) = dump(source, options.dump_tokens) {
+                std::process::exit(report_error(
+                    name.as_deref(),
+                    source,
+                    e,
+                    !options.plain_errors,
+                ));
+            }
+        }
+        return;
+    }
+
+    let mut interpreter = if options.quiet {
+        Interpreter::with_output(Box::new(io::sink()))
+    } else {
+        Interpreter::new()
+    };
+    if options.keep_going {
+        interpreter.set_error_policy(ErrorPolicy::Continue);
+    }
+    interpreter.set_dry_run(options.dry_run);
+    interpreter.set_backup(options.backup);
+    interpreter.set_trace(options.trace);
+    interpreter.set_interactive(io::stdin().is_terminal());
+    let interrupt = Arc::new(AtomicBool::new(false));
+    install_interrupt_handler(Arc::clone(&interrupt));
+    interpreter.set_interrupt_flag(interrupt);
+    interpreter.set_profile(options.profile);
+    interpreter.set_path_mode(options.path_mode);
+    interpreter.set_limits(options.limits);
+    interpreter.set_script_args(&options.script_args);
+    if let Some(root) = &options.sandbox {
+        if let Err(e) = interpreter.set_sandbox_root(root) {
+            eprintln!("Runtime error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if interactive {
+        let mut history = History::load(history_path());
+        let result = run_repl(
+            &mut interpreter,
+            &mut PromptInput(io::BufReader::new(io::stdin())),
+            &mut io::stderr(),
+            &mut history,
+        );
+        if let Err(e) = result.and_then(|()| history.save()) {
+            eprintln!("Error: {}", e);
             std::process::exit(1);
         }
+        std::process::exit(interpreter.exit_code().unwrap_or(0));
+    }
+
+    // Every unit shares the interpreter; an error or `exit` stops the rest.
+    let mut failure = None;
+    for (name, script, source) in &units {
+        let result = match script {
+            Some(script) => interpreter.run_file(script),
+            None => interpreter.run_source(source),
+        };
+        if let Err(e) = result {
+            failure = Some((name.as_deref(), source, e));
+            break;
+        }
+        if interpreter.exit_code().is_some() {
+            break;
+        }
+    }
+
+    if options.profile {
+        eprint!("{}", interpreter.profile_report());
+    }
+
+    if let Some((name, source, e)) = failure {
+        std::process::exit(report_error(name, source, e, !options.plain_errors));
+    }
+    if let Some(code) = interpreter.exit_code() {
+        std::process::exit(code);
+    }
+}
+
+/// The flag the Ctrl-C handler sets, shared with the interpreter.
+static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Whether the REPL is waiting for a line, so that Ctrl-C only starts a fresh
+/// prompt.
+static AT_PROMPT: AtomicBool = AtomicBool::new(false);
+
+/// The REPL's input, noting in AT_PROMPT when it waits for the user.
+struct PromptInput<R>(R);
+
+impl<R: Read> Read for PromptInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: BufRead> BufRead for PromptInput<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        AT_PROMPT.store(true, Ordering::SeqCst);
+        let result = self.0.fill_buf();
+        AT_PROMPT.store(false, Ordering::SeqCst);
+        result
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.0.consume(amount)
+    }
+}
+
+/// Make Ctrl-C set `flag` instead of killing the process. At the prompt it
+/// also prints a fresh prompt (the terminal has discarded the line typed so
+/// far). Otherwise, if the flag is still set from an earlier Ctrl-C (the
+/// interpreter hasn't stopped yet), exit at once.
+#[cfg(unix)]
+fn install_interrupt_handler(flag: Arc<AtomicBool>) {
+    use std::os::raw::c_int;
+
+    const SIGINT: c_int = 2;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        fn _exit(status: c_int) -> !;
+        fn write(fd: c_int, buf: *const u8, count: usize) -> isize;
+    }
+
+    extern "C" fn on_interrupt(_signum: c_int) {
+        let Some(flag) = INTERRUPT.get() else {
+            return;
+        };
+        if AT_PROMPT.load(Ordering::SeqCst) {
+            flag.store(true, Ordering::SeqCst);
+            for text in ["\n", PROMPT] {
+                unsafe { write(2, text.as_ptr(), text.len()) };
+            }
+            return;
+        }
+        if flag.swap(true, Ordering::SeqCst) {
+            // Only async-signal-safe calls are allowed here, so no cleanup.
+            unsafe { _exit(130) };
+        }
+    }
+
+    if INTERRUPT.set(flag).is_ok() {
+        unsafe { signal(SIGINT, on_interrupt) };
+    }
+}
+
+#[cfg(not(unix))]
+fn install_interrupt_handler(_flag: Arc<AtomicBool>) {}
+
+/// Print an error, prefixed with the name of the unit it came from, and return
+/// the exit status for it. With `pretty`, a lexing or parsing error is shown
+/// under the source line it is on, with a caret pointing at it.
+fn report_error(name: Option<&str>, source: &str, e: FileLangError, pretty: bool) -> i32 {
+    let prefix = name.map(|name| format!("{}: ", name)).unwrap_or_default();
+    if let (true, Some(pos)) = (pretty, e.position()) {
+        let (phase, status) = match e {
+            FileLangError::Lex(_) => ("Lexing", 2),
+            _ => ("Parsing", 3),
+        };
+        let (line, column) = line_column(source, pos);
+        eprintln!("{}{} error at {}:{}", prefix, phase, line, column);
+        eprint!("{}", render_diagnostic(source, pos, &e.to_string()));
+        return status;
+    }
+    match e {
+        FileLangError::Lex(e) => {
+            eprintln!("{}Lexing error: {}", prefix, e);
+            2
+        }
+        FileLangError::Parse(e) => {
+            eprintln!("{}Parsing error: {}", prefix, e);
+            3
+        }
+        FileLangError::Runtime(e) => {
+            eprintln!("{}Runtime error: {}", prefix, e);
+            1
+        }
+    }
+}
+
+fn read_stdin() -> String {
+    let mut stdin = std::io::stdin();
+    let mut buffer = Vec::new();
+    let _ = std::io::Read::read_to_end(&mut stdin, &mut buffer).expect("Failed to read stdin.");
+    String::from_utf8(buffer).expect("Invalid UTF-8 in stdin input.")
+}
+
+/// Print the tokens, or the parsed AST, of a script as pretty JSON.
+fn dump(source: &str, tokens_only: bool) -> Result<(), FileLangError> {
+    let tokens = Lexer::new(source).with_trivia(tokens_only).lex()?;
+    let json = if tokens_only {
+        serde_json::to_string_pretty(&tokens)
+    } else {
+        serde_json::to_string_pretty(&Parser::new(tokens).parse()?)
     };
+    println!("{}", json.expect("the AST always serializes to JSON"));
+    Ok(())
+}
 
-    let mut interpreter = Interpreter::new();
-    if let Err(e) = interpreter.run(&ast) {
-        eprintln!("Runtime error: {}", e);
-        std::process::exit(1);
+/// Parse a script and print it back in canonical form.
+fn to_source(source: &str) -> Result<String, FileLangError> {
+    let tokens = Lexer::new(source).lex()?;
+    Ok(Parser::new(tokens).parse()?.to_source())
+}
+
+/// Print every lexing and parsing error in a script, in source order, and
+/// return the exit status: 0 if there were none, else 2 (lexing) or 3 (parsing).
+/// With `pretty`, each is followed by its source line and a caret under it.
+fn check_syntax(name: Option<&str>, source: &str, pretty: bool) -> i32 {
+    let (tokens, lex_errors) = Lexer::new(source).lex_with_recovery();
+    let (_, parse_errors) = Parser::new(tokens).parse_with_recovery();
+    let status = if !lex_errors.is_empty() {
+        2
+    } else if !parse_errors.is_empty() {
+        3
+    } else {
+        0
+    };
+    let mut errors: Vec<(usize, String)> = lex_errors
+        .iter()
+        .map(|e| (e.position().unwrap_or(0), e.to_string()))
+        .chain(
+            parse_errors
+                .iter()
+                .map(|e| (e.position().unwrap_or(0), e.to_string())),
+        )
+        .collect();
+    errors.sort_by_key(|(pos, _)| *pos);
+    for (pos, message) in errors {
+        let (line, column) = line_column(source, pos);
+        match name {
+            Some(name) => eprintln!("{}:{}:{}: {}", name, line, column, message),
+            None => eprintln!("{}:{}: {}", line, column, message),
+        }
+        if pretty {
+            eprint!("{}", render_diagnostic(source, pos, ""));
+        }
     }
+    status
+}
+
+/// The 1-based line and column (in characters) of a byte offset.
+fn line_column(source: &str, pos: usize) -> (usize, usize) {
+    let before = &source[..pos.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    (line, before[line_start..].chars().count() + 1)
 }
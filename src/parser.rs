@@ -6,66 +6,367 @@
  * This parser recognizes the extended grammar defined above.
  * It consumes tokens produced by the lexer and builds the AST.
  *
+ * Statements that run other statements (e.g. `watch`) take a block:
+ * `do`, then statements on their own lines, then `end`.
+ *
+ * Trailing statement modifiers (e.g. `ifany`) are plain identifiers matched by
+ * `match_word`, so they don't reserve names that scripts may use as variables.
+ *
  * If parsing fails, returns a ParseError.
  */
 
 use crate::ast::*;
 use crate::errors::ParseError;
+use crate::lexer::keywords;
 use crate::tokens::{Token, TokenKind};
+use crate::utils::{
+    closest_match, parse_duration, ContentCodec, Encoding, FindFilter, LineEnding, ReplaceOptions,
+    SplitMode,
+};
+
+/// How many edits away a misspelled command may be for a keyword to be suggested.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
 
 pub struct Parser {
+    /// The tokens to parse, always ending with `Eof`.
     tokens: Vec<Token>,
     pos: usize,
     length: usize,
+    /// Record a statement that fails to parse and carry on with the next one.
+    recover: bool,
+    /// Errors recorded while recovering.
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
-    /// Create a new parser with a list of tokens.
+    /// Create a new parser with a list of tokens. Comment tokens are dropped, and
+    /// an `Eof` token is added if the list doesn't end with one.
     pub fn new(tokens: Vec<Token>) -> Self {
+        let mut tokens: Vec<Token> = tokens
+            .into_iter()
+            .filter(|token| !matches!(token.kind, TokenKind::Comment(_)))
+            .collect();
+        match tokens.last() {
+            Some(token) if matches!(token.kind, TokenKind::Eof) => {}
+            last => {
+                let (pos, line) = last.map_or((0, 1), |token| (token.pos, token.line));
+                tokens.push(Token::new(TokenKind::Eof, pos, line));
+            }
+        }
+        let length = tokens.len();
         Self {
             tokens,
             pos: 0,
-            length: 0,
+            length,
+            recover: false,
+            errors: Vec::new(),
         }
     }
 
     /// Parse the entire token stream into an AST.
     pub fn parse(&mut self) -> Result<AST, ParseError> {
-        self.length = self.tokens.len();
+        let statements = self.parse_statements(None)?;
+        Ok(AST { statements })
+    }
+
+    /// Parse the entire token stream, recording each statement that fails to
+    /// parse and skipping to the next end of statement instead of stopping.
+    /// Every error carries the source position it was found at.
+    pub fn parse_with_recovery(&mut self) -> (AST, Vec<ParseError>) {
+        self.recover = true;
+        let result = self.parse();
+        self.recover = false;
+        let mut errors = std::mem::take(&mut self.errors);
+        let statements = match result {
+            Ok(ast) => ast.statements,
+            Err(e) => {
+                let pos = e.position().unwrap_or(self.peek_token().pos);
+                errors.push(e.at(pos));
+                Vec::new()
+            }
+        };
+        (AST { statements }, errors)
+    }
+
+    /// Parse statements until the end of input or, inside a block, until the
+    /// `closer` keyword (e.g. `end`) that closes it, which is consumed.
+    fn parse_statements(
+        &mut self,
+        closer: Option<(TokenKind, &str)>,
+    ) -> Result<Vec<Located>, ParseError> {
         let mut statements = Vec::new();
 
-        while !self.is_at_end() {
+        loop {
+            if self.is_at_end() {
+                if let Some((_, keyword)) = closer {
+                    return Err(
+                        self.end_of_input(&format!("Expected '{}' to close block", keyword))
+                    );
+                }
+                break;
+            }
             if self.check_end_of_statement() {
                 self.consume_end_of_statement()?;
                 continue;
             }
+            if let Some((kind, _)) = &closer {
+                if self.match_token(std::slice::from_ref(kind)) {
+                    break;
+                }
+            }
+
+            let line = self.peek_token().line;
+            let start = self.pos;
+            match self.parse_whole_statement(&closer) {
+                Ok(statement) => {
+                    let end_line = self.last_line();
+                    statements.push(Located {
+                        line,
+                        end_line,
+                        statement,
+                    });
+                }
+                Err(e) => {
+                    // Most errors are about the token just consumed. If that ended
+                    // the statement, step back onto it so the next line is kept.
+                    let failed = &self.tokens[self.pos.saturating_sub(1).max(start)];
+                    let pos = e.position().unwrap_or(failed.pos);
+                    if !self.recover {
+                        return Err(e.at(pos));
+                    }
+                    if matches!(failed.kind, TokenKind::EndOfStatement) && self.pos > start {
+                        self.pos -= 1;
+                    }
+                    self.errors.push(e.at(pos));
+                    while !self.check_end_of_statement() {
+                        self.advance();
+                    }
+                }
+            }
+            self.consume_end_of_statement().ok();
+        }
+
+        Ok(statements)
+    }
+
+    /// Parse a statement that must be followed by the end of the statement (or
+    /// the keyword closing the enclosing block).
+    fn parse_whole_statement(
+        &mut self,
+        closer: &Option<(TokenKind, &str)>,
+    ) -> Result<Statement, ParseError> {
+        let mut statement = self.parse_statement()?;
+        if self.check_token(&TokenKind::Pipe) {
+            statement = self.parse_pipe(statement)?;
+        }
+        let closes_block = closer
+            .as_ref()
+            .is_some_and(|(kind, _)| self.check_token(kind));
+        if !self.is_at_end() && !self.check_end_of_statement() && !closes_block {
+            return Err(ParseError::new(format!(
+                "Expected end of statement at position {} but found {:?}",
+                self.current_position(),
+                self.peek_token().kind
+            ))
+            .at(self.peek_token().pos));
+        }
+        Ok(statement)
+    }
 
-            let stmt = self.parse_statement()?;
-            statements.push(stmt);
-            if !self.is_at_end() && !self.check_end_of_statement() {
+    /// Parse the sink after `|`: `append var`, `write var`, or `show`.
+    fn parse_pipe(&mut self, producer: Statement) -> Result<Statement, ParseError> {
+        let pos = self.peek_token().pos;
+        if !producer.can_pipe() {
+            return Err(ParseError::new(format!(
+                "'{}' has no results to pipe; only search, listdir, getline, head, tail, \
+                 and extract can appear before '|'",
+                producer.keyword()
+            ))
+            .at(pos));
+        }
+        self.advance();
+        let sink = if self.match_token(&[TokenKind::Append]) {
+            PipeSink::Append(
+                self.consume_expect_identifier("Expected variable name after 'append'")?,
+            )
+        } else if self.match_token(&[TokenKind::Write]) {
+            PipeSink::Write(self.consume_expect_identifier("Expected variable name after 'write'")?)
+        } else if self.match_token(&[TokenKind::Show]) {
+            PipeSink::Show
+        } else {
+            return Err(ParseError::new(format!(
+                "Expected 'append var', 'write var', or 'show' after '|' at position {}",
+                pos
+            )));
+        };
+        Ok(Statement::Pipe(PipeStmt {
+            producer: Box::new(producer),
+            sink,
+        }))
+    }
+
+    /// Parse a condition: `contains var "pattern"`, `exists "path"`, or `not` and
+    /// another condition.
+    fn parse_condition(&mut self, command: &str) -> Result<Condition, ParseError> {
+        if self.match_word("not") {
+            let inner = self.parse_condition(command)?;
+            return Ok(Condition::Not(Box::new(inner)));
+        }
+        if self.match_word("contains") {
+            let var_name =
+                self.consume_expect_identifier("Expected variable name after 'contains'")?;
+            let pattern = self.consume_expect_string("Expected pattern string after variable")?;
+            return Ok(Condition::Contains { var_name, pattern });
+        }
+        if self.match_token(&[TokenKind::Exists]) {
+            let path = self.consume_expect_string("Expected path string after 'exists'")?;
+            return Ok(Condition::Exists { path });
+        }
+        if let Some(left) = self.parse_operand()? {
+            let op = self.parse_compare_op()?;
+            let Some(right) = self.parse_operand()? else {
+                if self.is_at_end() {
+                    return Err(self.end_of_input("Expected value after comparison operator"));
+                }
                 return Err(ParseError::new(format!(
-                    "Expected end of statement at position {} but found {:?}",
-                    self.current_position(),
+                    "Expected value after '{}' (a string, number, linecount var, or bytecount \
+                     var): got {:?}",
+                    op.as_str(),
                     self.peek_token().kind
                 )));
+            };
+            return Ok(Condition::Compare { left, op, right });
+        }
+        if self.is_at_end() {
+            return Err(self.end_of_input(&format!("Expected condition after '{}'", command)));
+        }
+        Err(ParseError::new(format!(
+            "Expected condition after '{}' (contains, exists, not, or a comparison): got {:?}",
+            command,
+            self.peek_token().kind
+        )))
+    }
+
+    /// Parse one side of a comparison, if the next tokens are one.
+    fn parse_operand(&mut self) -> Result<Option<Operand>, ParseError> {
+        if self.is_at_end() {
+            return Ok(None);
+        }
+        if self.match_token(&[TokenKind::LineCount]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'linecount'")?;
+            return Ok(Some(Operand::LineCount(var)));
+        }
+        if self.match_word("bytecount") {
+            let var = self.consume_expect_identifier("Expected variable name after 'bytecount'")?;
+            return Ok(Some(Operand::ByteCount(var)));
+        }
+        let operand = match &self.peek_token().kind {
+            TokenKind::String(text) => Operand::Text(text.clone()),
+            TokenKind::Number(n) => Operand::Number(*n),
+            _ => return Ok(None),
+        };
+        self.advance();
+        Ok(Some(operand))
+    }
+
+    fn parse_compare_op(&mut self) -> Result<CompareOp, ParseError> {
+        let ops = [
+            (TokenKind::EqualEqual, CompareOp::Eq),
+            (TokenKind::NotEqual, CompareOp::Ne),
+            (TokenKind::LessEqual, CompareOp::Le),
+            (TokenKind::Less, CompareOp::Lt),
+            (TokenKind::GreaterEqual, CompareOp::Ge),
+            (TokenKind::Greater, CompareOp::Gt),
+        ];
+        for (kind, op) in ops {
+            if self.match_token(&[kind]) {
+                return Ok(op);
             }
-            self.consume_end_of_statement().ok();
         }
+        if self.is_at_end() {
+            return Err(self.end_of_input("Expected comparison operator"));
+        }
+        Err(ParseError::new(format!(
+            "Expected comparison operator (==, !=, <, <=, >, >=): got {:?}",
+            self.peek_token().kind
+        )))
+    }
 
-        Ok(AST { statements })
+    /// Parse `do`, a block of statements, and the closing `end`.
+    fn parse_block(&mut self, command: &str) -> Result<Vec<Located>, ParseError> {
+        self.consume_expect_token(
+            TokenKind::Do,
+            &format!("Expected 'do' to start '{}' block", command),
+        )?;
+        self.parse_statements(Some((TokenKind::End, "end")))
+    }
+
+    /// Parse a block up to `closer` to check its syntax, returning its tokens
+    /// (with an end of statement in place of the closer) so it can be parsed
+    /// again each time it runs.
+    fn parse_token_block(&mut self, closer: (TokenKind, &str)) -> Result<Vec<Token>, ParseError> {
+        let start = self.pos;
+        self.parse_statements(Some(closer))?;
+        let mut body = self.tokens[start..self.pos - 1].to_vec();
+        let end = &self.tokens[self.pos - 1];
+        body.push(Token::new(TokenKind::EndOfStatement, end.pos, end.line));
+        Ok(body)
     }
 
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
         if self.match_token(&[TokenKind::Open]) {
-            let filename = self.consume_expect_string("Expected filename string after 'open'")?;
+            let (filename, target) = if self.match_word("stdin") {
+                (String::new(), FileTarget::Stdin)
+            } else if self.match_word("stdout") {
+                (String::new(), FileTarget::Stdout)
+            } else {
+                let filename =
+                    self.consume_expect_string("Expected filename string after 'open'")?;
+                (filename, FileTarget::Disk)
+            };
             self.consume_expect_token(
                 TokenKind::As,
                 "Expected 'as' after filename in open statement",
             )?;
             let var = self.consume_expect_identifier("Expected variable name after 'as'")?;
+            let mut flags = OpenFlags::default();
+            loop {
+                if self.match_word("readonly") {
+                    flags.mode = OpenMode::ReadOnly;
+                } else if self.match_word("writable") {
+                    flags.mode = OpenMode::Writable;
+                } else if self.match_word("appendonly") {
+                    flags.mode = OpenMode::AppendOnly;
+                } else if self.match_word("nocreate") {
+                    flags.no_create = true;
+                } else if self.match_word("buffered") {
+                    flags.buffered = true;
+                } else if self.match_word("locked") {
+                    flags.lock = Some(LockKind::Exclusive);
+                } else if self.match_word("shared") {
+                    flags.lock = Some(LockKind::Shared);
+                    flags.mode = OpenMode::ReadOnly;
+                } else if self.match_word("nomem") {
+                    flags.nomem = true;
+                } else if self.match_word("encoding") {
+                    let name =
+                        self.consume_expect_string("Expected encoding name after 'encoding'")?;
+                    let encoding = Encoding::from_name(&name).ok_or_else(|| {
+                        ParseError::new(format!(
+                            "Unknown encoding '{}' (expected utf-8, latin-1, utf-16le, or utf-16be)",
+                            name
+                        ))
+                    })?;
+                    flags.encoding = Some(encoding);
+                } else {
+                    break;
+                }
+            }
             return Ok(Statement::Open(OpenStmt {
                 filename,
                 var_name: var,
+                flags,
+                target,
             }));
         }
 
@@ -74,12 +375,27 @@ impl Parser {
             return Ok(Statement::Read(ReadStmt { var_name: var }));
         }
 
+        if self.match_token(&[TokenKind::ReadRange]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'readrange'")?;
+            let offset =
+                self.consume_expect_count("Expected byte offset after variable in 'readrange'")?;
+            let length =
+                self.consume_expect_count("Expected byte count after offset in 'readrange'")?;
+            return Ok(Statement::ReadRange(ReadRangeStmt {
+                var_name: var,
+                offset,
+                length,
+            }));
+        }
+
         if self.match_token(&[TokenKind::Write]) {
             let var = self.consume_expect_identifier("Expected variable name after 'write'")?;
             let text = self.consume_expect_string("Expected string after variable in 'write'")?;
+            let overwrite = self.match_word("overwrite");
             return Ok(Statement::Write(WriteStmt {
                 var_name: var,
                 text,
+                overwrite,
             }));
         }
 
@@ -99,7 +415,121 @@ impl Parser {
 
         if self.match_token(&[TokenKind::Close]) {
             let var = self.consume_expect_identifier("Expected variable name after 'close'")?;
-            return Ok(Statement::Close(CloseStmt { var_name: var }));
+            let mut discard = false;
+            let mut keep = false;
+            loop {
+                if self.match_word("discard") {
+                    discard = true;
+                } else if self.match_word("keep") {
+                    keep = true;
+                } else {
+                    break;
+                }
+            }
+            return Ok(Statement::Close(CloseStmt {
+                var_name: var,
+                discard,
+                keep,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Buffer]) {
+            self.consume_expect_token(TokenKind::As, "Expected 'as' after 'buffer'")?;
+            let var = self.consume_expect_identifier("Expected variable name after 'as'")?;
+            return Ok(Statement::Buffer(BufferStmt { var_name: var }));
+        }
+
+        if self.match_token(&[TokenKind::SaveAs]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'saveas'")?;
+            let path =
+                self.consume_expect_string("Expected filename after variable in 'saveas'")?;
+            return Ok(Statement::SaveAs(SaveAsStmt {
+                var_name: var,
+                path,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::CopyTo]) {
+            let source =
+                self.consume_expect_identifier("Expected source variable after 'copyto'")?;
+            let destination = self.consume_expect_identifier(
+                "Expected destination variable after source in 'copyto'",
+            )?;
+            return Ok(Statement::CopyTo(CopyToStmt {
+                source,
+                destination,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::AppendFrom]) {
+            let destination =
+                self.consume_expect_identifier("Expected destination variable after 'appendfrom'")?;
+            let source = self.consume_expect_identifier(
+                "Expected source variable after destination in 'appendfrom'",
+            )?;
+            return Ok(Statement::AppendFrom(AppendFromStmt {
+                destination,
+                source,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::TempFile]) {
+            self.consume_expect_token(TokenKind::As, "Expected 'as' after 'tempfile'")?;
+            let var = self.consume_expect_identifier("Expected variable name after 'as'")?;
+            return Ok(Statement::TempFile(TempFileStmt { var_name: var }));
+        }
+
+        if self.match_token(&[TokenKind::Let]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'let'")?;
+            self.consume_expect_token(TokenKind::Equals, "Expected '=' after variable in 'let'")?;
+            let pos = self.peek_token().pos;
+            let value = self.parse_statement()?;
+            if !value.produces_result() {
+                return Err(ParseError::new(format!(
+                    "'{}' has no result to bind; 'let' takes search, listdir, getline, head, \
+                     tail, extract, linecount, show, readlink, exists, or pwd",
+                    value.keyword()
+                ))
+                .at(pos));
+            }
+            return Ok(Statement::Let(LetStmt {
+                var_name: var,
+                value: Box::new(value),
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Prompt]) {
+            let text = self.consume_expect_string("Expected prompt text after 'prompt'")?;
+            self.consume_expect_token(TokenKind::As, "Expected 'as' after text in 'prompt'")?;
+            let var = self.consume_expect_identifier("Expected variable name after 'as'")?;
+            let default = if self.match_word("default") {
+                Some(self.consume_expect_string("Expected text after 'default'")?)
+            } else {
+                None
+            };
+            return Ok(Statement::Prompt(PromptStmt {
+                text,
+                var_name: var,
+                default,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::GetEnv]) {
+            let name = self.consume_expect_string(
+                "Expected environment variable name in double quotes after 'getenv'",
+            )?;
+            self.consume_expect_token(TokenKind::As, "Expected 'as' after name in 'getenv'")?;
+            let var = self.consume_expect_identifier("Expected variable name after 'as'")?;
+            let default = if self.match_word("default") {
+                Some(self.consume_expect_string("Expected text after 'default'")?)
+            } else {
+                None
+            };
+            return Ok(Statement::GetEnv(GetEnvStmt {
+                name,
+                var_name: var,
+                default,
+            }));
         }
 
         if self.match_token(&[TokenKind::Truncate]) {
@@ -111,9 +541,142 @@ impl Parser {
             let var = self.consume_expect_identifier("Expected variable name after 'search'")?;
             let pattern =
                 self.consume_expect_string("Expected pattern string after variable in 'search'")?;
+            let mut options = SearchOptions::default();
+            loop {
+                if self.match_word("ignorecase") {
+                    options.ignore_case = true;
+                } else if self.match_word("count") {
+                    options.count_only = true;
+                } else if self.match_word("context") {
+                    options.context =
+                        self.consume_expect_count("Expected line count after 'context'")?;
+                } else if self.match_word("streaming") {
+                    options.streaming = true;
+                } else {
+                    break;
+                }
+            }
             return Ok(Statement::Search(SearchStmt {
                 var_name: var,
                 pattern,
+                options,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Grep]) {
+            let pattern = self.consume_expect_string("Expected pattern string after 'grep'")?;
+            self.expect_word("in", "Expected 'in' after pattern in 'grep'")?;
+            let path = self.consume_expect_string("Expected path string after 'in'")?;
+            let mut stmt = GrepStmt {
+                pattern,
+                path,
+                ignore_case: false,
+                include: None,
+                verbose: false,
+            };
+            loop {
+                if self.match_word("ignorecase") {
+                    stmt.ignore_case = true;
+                } else if self.match_token(&[TokenKind::Include]) {
+                    // `include` is also the keyword of the include statement.
+                    stmt.include =
+                        Some(self.consume_expect_string("Expected glob string after 'include'")?);
+                } else if self.match_word("verbose") {
+                    stmt.verbose = true;
+                } else {
+                    break;
+                }
+            }
+            return Ok(Statement::Grep(stmt));
+        }
+
+        if self.match_token(&[TokenKind::Find]) {
+            let root = self.consume_expect_string("Expected path string after 'find'")?;
+            let mut filters = Vec::new();
+            loop {
+                if self.match_word("name") {
+                    let glob = self.consume_expect_string("Expected glob string after 'name'")?;
+                    filters.push(FindFilter::Name(glob));
+                } else if self.match_word("larger") {
+                    let size =
+                        self.consume_expect_count("Expected size in bytes after 'larger'")?;
+                    filters.push(FindFilter::Larger(size as u64));
+                } else if self.match_word("olderthan") {
+                    let text =
+                        self.consume_expect_string("Expected age string after 'olderthan'")?;
+                    let age = parse_duration(&text).ok_or_else(|| {
+                        ParseError::new(format!(
+                            "Invalid age '{}' (expected a number with s, m, h, or d, e.g. \"7d\")",
+                            text
+                        ))
+                    })?;
+                    filters.push(FindFilter::OlderThan(age));
+                } else {
+                    break;
+                }
+            }
+            return Ok(Statement::Find(FindStmt { root, filters }));
+        }
+
+        if self.match_token(&[TokenKind::Symlink]) {
+            let target = self.consume_expect_string("Expected target string after 'symlink'")?;
+            let link_path = self.consume_expect_string("Expected link path string after target")?;
+            let overwrite = self.match_word("overwrite");
+            return Ok(Statement::Symlink(SymlinkStmt {
+                target,
+                link_path,
+                overwrite,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::HardLink]) {
+            let target = self.consume_expect_string("Expected target string after 'hardlink'")?;
+            let link_path = self.consume_expect_string("Expected link path string after target")?;
+            let overwrite = self.match_word("overwrite");
+            return Ok(Statement::HardLink(HardLinkStmt {
+                target,
+                link_path,
+                overwrite,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::ReadLink]) {
+            let link_path =
+                self.consume_expect_string("Expected link path string after 'readlink'")?;
+            return Ok(Statement::ReadLink(ReadLinkStmt { link_path }));
+        }
+
+        if self.match_token(&[TokenKind::Chmod]) {
+            let path = self.consume_expect_string("Expected path string after 'chmod'")?;
+            let mode = self.consume_expect_string("Expected mode string after path in 'chmod'")?;
+            return Ok(Statement::Chmod(ChmodStmt { path, mode }));
+        }
+
+        if self.match_token(&[TokenKind::Du]) {
+            let path = self.consume_expect_string("Expected path string after 'du'")?;
+            let mut per_child = false;
+            if self.match_word("depth") {
+                match self.consume_expect_count("Expected depth after 'depth'")? {
+                    0 => {}
+                    1 => per_child = true,
+                    depth => {
+                        return Err(ParseError::new(format!(
+                            "Unsupported depth {} in 'du' (only 0 and 1 are supported)",
+                            depth
+                        )))
+                    }
+                }
+            }
+            return Ok(Statement::Du(DuStmt { path, per_child }));
+        }
+
+        if self.match_token(&[TokenKind::Extract]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'extract'")?;
+            let pattern =
+                self.consume_expect_string("Expected pattern string after variable in 'extract'")?;
+            return Ok(Statement::Extract(ExtractStmt {
+                var_name: var,
+                pattern,
             }));
         }
 
@@ -123,25 +686,57 @@ impl Parser {
                 self.consume_expect_string("Expected pattern string after variable in 'replace'")?;
             let replacement = self
                 .consume_expect_string("Expected replacement string after pattern in 'replace'")?;
+            let mut options = ReplaceOptions::default();
+            loop {
+                if self.match_word("first") {
+                    options.first_only = true;
+                } else if self.match_word("literal") {
+                    options.literal = true;
+                } else if self.match_word("lines") {
+                    let from = self.consume_expect_count("Expected first line after 'lines'")?;
+                    let to = self.consume_expect_count("Expected last line after 'lines'")?;
+                    options.lines = Some((from, to));
+                } else {
+                    break;
+                }
+            }
             return Ok(Statement::Replace(ReplaceStmt {
                 var_name: var,
                 pattern,
                 replacement,
+                options,
             }));
         }
 
         if self.match_token(&[TokenKind::LineCount]) {
             let var = self.consume_expect_identifier("Expected variable name after 'linecount'")?;
-            return Ok(Statement::LineCount(LineCountStmt { var_name: var }));
+            let streaming = self.match_word("streaming");
+            return Ok(Statement::LineCount(LineCountStmt {
+                var_name: var,
+                streaming,
+            }));
         }
 
         if self.match_token(&[TokenKind::Copy]) {
             let src = self.consume_expect_string("Expected source filename after 'copy'")?;
             let dst =
                 self.consume_expect_string("Expected destination filename after source in 'copy'")?;
+            let mut recursive = false;
+            let mut overwrite = false;
+            loop {
+                if self.match_token(&[TokenKind::Recursive]) {
+                    recursive = true;
+                } else if self.match_word("overwrite") {
+                    overwrite = true;
+                } else {
+                    break;
+                }
+            }
             return Ok(Statement::Copy(CopyStmt {
                 source: src,
                 destination: dst,
+                recursive,
+                overwrite,
             }));
         }
 
@@ -149,15 +744,32 @@ impl Parser {
             let src = self.consume_expect_string("Expected source filename after 'move'")?;
             let dst =
                 self.consume_expect_string("Expected destination filename after source in 'move'")?;
+            let overwrite = self.match_word("overwrite");
             return Ok(Statement::Move(MoveStmt {
                 source: src,
                 destination: dst,
+                overwrite,
             }));
         }
 
         if self.match_token(&[TokenKind::Remove]) {
             let fname = self.consume_expect_string("Expected filename after 'remove'")?;
-            return Ok(Statement::Remove(RemoveStmt { filename: fname }));
+            let mut recursive = false;
+            let mut if_any = false;
+            loop {
+                if self.match_token(&[TokenKind::Recursive]) {
+                    recursive = true;
+                } else if self.match_word("ifany") {
+                    if_any = true;
+                } else {
+                    break;
+                }
+            }
+            return Ok(Statement::Remove(RemoveStmt {
+                filename: fname,
+                recursive,
+                if_any,
+            }));
         }
 
         if self.match_token(&[TokenKind::Rename]) {
@@ -172,21 +784,569 @@ impl Parser {
 
         if self.match_token(&[TokenKind::ListDir]) {
             let path = self.consume_expect_string("Expected directory path after 'listdir'")?;
-            return Ok(Statement::ListDir(ListDirStmt { path }));
+            let mut recursive = false;
+            let mut long = false;
+            loop {
+                if self.match_token(&[TokenKind::Recursive]) {
+                    recursive = true;
+                } else if self.match_word("long") {
+                    long = true;
+                } else {
+                    break;
+                }
+            }
+            return Ok(Statement::ListDir(ListDirStmt {
+                path,
+                recursive,
+                long,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Mkdir]) {
+            let path = self.consume_expect_string("Expected directory path after 'mkdir'")?;
+            return Ok(Statement::Mkdir(MkdirStmt { path }));
+        }
+
+        if self.match_token(&[TokenKind::Rmdir]) {
+            let path = self.consume_expect_string("Expected directory path after 'rmdir'")?;
+            return Ok(Statement::Rmdir(RmdirStmt { path }));
+        }
+
+        if self.match_token(&[TokenKind::Head]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'head'")?;
+            let count =
+                self.consume_expect_count("Expected line count after variable in 'head'")?;
+            return Ok(Statement::Head(HeadStmt {
+                var_name: var,
+                count,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Tail]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'tail'")?;
+            let count =
+                self.consume_expect_count("Expected line count after variable in 'tail'")?;
+            return Ok(Statement::Tail(TailStmt {
+                var_name: var,
+                count,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::InsertLine]) {
+            let var =
+                self.consume_expect_identifier("Expected variable name after 'insertline'")?;
+            let line =
+                self.consume_expect_count("Expected line number after variable in 'insertline'")?;
+            let text = self
+                .consume_expect_string("Expected text string after line number in 'insertline'")?;
+            return Ok(Statement::InsertLine(InsertLineStmt {
+                var_name: var,
+                line,
+                text,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::GetLine]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'getline'")?;
+            let from =
+                self.consume_expect_count("Expected line number after variable in 'getline'")?;
+            let to = if self.check_number() {
+                self.consume_expect_count("Expected end line number in 'getline'")?
+            } else {
+                from
+            };
+            return Ok(Statement::GetLine(GetLineStmt {
+                var_name: var,
+                from,
+                to,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::DeleteLine]) {
+            let var =
+                self.consume_expect_identifier("Expected variable name after 'deleteline'")?;
+            let from =
+                self.consume_expect_count("Expected line number after variable in 'deleteline'")?;
+            let to = if self.check_number() {
+                self.consume_expect_count("Expected end line number in 'deleteline'")?
+            } else {
+                from
+            };
+            return Ok(Statement::DeleteLine(DeleteLineStmt {
+                var_name: var,
+                from,
+                to,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Exists]) {
+            let path = self.consume_expect_string("Expected path after 'exists'")?;
+            return Ok(Statement::Exists(ExistsStmt { path }));
+        }
+
+        if self.match_token(&[TokenKind::Stat]) {
+            let target = self.consume_expect_file_ref("Expected variable or path after 'stat'")?;
+            return Ok(Statement::Stat(StatStmt { target }));
+        }
+
+        if self.match_token(&[TokenKind::Touch]) {
+            let filename = self.consume_expect_string("Expected filename after 'touch'")?;
+            return Ok(Statement::Touch(TouchStmt { filename }));
+        }
+
+        if self.match_token(&[TokenKind::Save]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'save'")?;
+            return Ok(Statement::Save(SaveStmt { var_name: var }));
+        }
+
+        if self.match_token(&[TokenKind::Revert]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'revert'")?;
+            return Ok(Statement::Revert(RevertStmt { var_name: var }));
+        }
+
+        if self.match_token(&[TokenKind::RestoreBackup]) {
+            let var =
+                self.consume_expect_identifier("Expected variable name after 'restorebackup'")?;
+            return Ok(Statement::RestoreBackup(RestoreBackupStmt {
+                var_name: var,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Set]) {
+            // `onerror` is also a keyword (of `try`), so it's matched separately.
+            let option = if self.match_token(&[TokenKind::OnError]) {
+                "onerror".to_string()
+            } else {
+                self.consume_expect_identifier("Expected setting name after 'set'")?
+            };
+            let value = self
+                .consume_expect_word_or_string(&format!("Expected value after 'set {}'", option))?;
+            return Ok(Statement::Set(SetStmt { option, value }));
+        }
+
+        if self.match_token(&[TokenKind::CloseAll]) {
+            return Ok(Statement::CloseAll(CloseAllStmt {}));
+        }
+
+        if self.match_token(&[TokenKind::Unset]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'unset'")?;
+            let force = self.match_word("force");
+            return Ok(Statement::Unset(UnsetStmt {
+                var_name: var,
+                force,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::HexDump]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'hexdump'")?;
+            return Ok(Statement::HexDump(HexDumpStmt { var_name: var }));
+        }
+
+        if self.match_token(&[TokenKind::Size]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'size'")?;
+            return Ok(Statement::Size(SizeStmt { var_name: var }));
+        }
+
+        if self.match_token(&[TokenKind::SetLineEnd]) {
+            let var =
+                self.consume_expect_identifier("Expected variable name after 'setlineend'")?;
+            let name = self.consume_expect_string("Expected \"lf\" or \"crlf\" after variable")?;
+            let line_ending = LineEnding::from_name(&name).ok_or_else(|| {
+                ParseError::new(format!(
+                    "Unknown line ending '{}' (expected lf or crlf)",
+                    name
+                ))
+            })?;
+            return Ok(Statement::SetLineEnd(SetLineEndStmt {
+                var_name: var,
+                line_ending,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::SortLines]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'sortlines'")?;
+            let numeric = self.match_word("numeric");
+            return Ok(Statement::SortLines(SortLinesStmt {
+                var_name: var,
+                numeric,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::UniqLines]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'uniqlines'")?;
+            let all = self.match_word("all");
+            return Ok(Statement::UniqLines(UniqLinesStmt { var_name: var, all }));
+        }
+
+        if self.match_token(&[TokenKind::ReverseLines]) {
+            let var =
+                self.consume_expect_identifier("Expected variable name after 'reverselines'")?;
+            return Ok(Statement::ReverseLines(ReverseLinesStmt { var_name: var }));
+        }
+
+        if self.match_token(&[TokenKind::NumberLines]) {
+            let var =
+                self.consume_expect_identifier("Expected variable name after 'numberlines'")?;
+            return Ok(Statement::NumberLines(NumberLinesStmt { var_name: var }));
+        }
+
+        if self.match_token(&[TokenKind::TrimLines]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'trimlines'")?;
+            let leading = self.match_word("leading");
+            return Ok(Statement::TrimLines(TrimLinesStmt {
+                var_name: var,
+                leading,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Diff]) {
+            let left =
+                self.consume_expect_file_ref("Expected variable or path string after 'diff'")?;
+            let right =
+                self.consume_expect_file_ref("Expected second variable or path string in 'diff'")?;
+            return Ok(Statement::Diff(DiffStmt { left, right }));
+        }
+
+        if self.match_token(&[TokenKind::Hash]) {
+            let target =
+                self.consume_expect_file_ref("Expected variable or path string after 'hash'")?;
+            let algorithm = self.consume_expect_string("Expected algorithm string in 'hash'")?;
+            return Ok(Statement::Hash(HashStmt { target, algorithm }));
+        }
+
+        if self.match_token(&[TokenKind::CheckHash]) {
+            let target =
+                self.consume_expect_file_ref("Expected variable or path string after 'checkhash'")?;
+            let algorithm =
+                self.consume_expect_string("Expected algorithm string in 'checkhash'")?;
+            let expected = self
+                .consume_expect_string("Expected digest string after algorithm in 'checkhash'")?;
+            return Ok(Statement::CheckHash(CheckHashStmt {
+                target,
+                algorithm,
+                expected,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Encode]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'encode'")?;
+            let codec = self.consume_expect_codec("encode")?;
+            return Ok(Statement::Encode(EncodeStmt {
+                var_name: var,
+                codec,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Decode]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'decode'")?;
+            let codec = self.consume_expect_codec("decode")?;
+            return Ok(Statement::Decode(DecodeStmt {
+                var_name: var,
+                codec,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Concat]) {
+            let destination =
+                self.consume_expect_string("Expected destination filename string after 'concat'")?;
+            self.expect_word("from", "Expected 'from' after destination in 'concat'")?;
+            let mut sources = Vec::new();
+            while !self.check_end_of_statement() {
+                sources.push(self.consume_expect_string("Expected source filename string")?);
+            }
+            if sources.is_empty() {
+                return Err(ParseError::new(
+                    "Expected at least one source filename after 'from'".to_string(),
+                ));
+            }
+            return Ok(Statement::Concat(ConcatStmt {
+                destination,
+                sources,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Split]) {
+            let var = self.consume_expect_identifier("Expected variable name after 'split'")?;
+            let mode = if self.match_word("lines") {
+                SplitMode::Lines
+            } else if self.match_word("bytes") {
+                SplitMode::Bytes
+            } else {
+                return Err(ParseError::new(
+                    "Expected 'lines' or 'bytes' after variable in 'split'".to_string(),
+                ));
+            };
+            let size = self.consume_expect_count("Expected chunk size in 'split'")?;
+            if size == 0 {
+                return Err(ParseError::new(
+                    "Chunk size in 'split' must be greater than zero".to_string(),
+                ));
+            }
+            self.expect_word("into", "Expected 'into' after chunk size in 'split'")?;
+            let prefix =
+                self.consume_expect_string("Expected filename prefix string after 'into'")?;
+            return Ok(Statement::Split(SplitStmt {
+                var_name: var,
+                mode,
+                size,
+                prefix,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::ZipCreate]) {
+            let archive =
+                self.consume_expect_string("Expected archive filename string after 'zipcreate'")?;
+            let source = self.consume_expect_string("Expected source path string after archive")?;
+            return Ok(Statement::ZipCreate(ZipCreateStmt { archive, source }));
+        }
+
+        if self.match_token(&[TokenKind::ZipExtract]) {
+            let archive =
+                self.consume_expect_string("Expected archive filename string after 'zipextract'")?;
+            let destination =
+                self.consume_expect_string("Expected destination directory string after archive")?;
+            return Ok(Statement::ZipExtract(ZipExtractStmt {
+                archive,
+                destination,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::Watch]) {
+            let path = self.consume_expect_string("Expected path string after 'watch'")?;
+            let mut interval_ms = 500;
+            let mut max_runs = None;
+            loop {
+                if self.match_word("every") {
+                    interval_ms =
+                        self.consume_expect_count("Expected milliseconds after 'every'")? as u64;
+                } else if self.match_word("maxruns") {
+                    max_runs = Some(self.consume_expect_count("Expected count after 'maxruns'")?);
+                } else {
+                    break;
+                }
+            }
+            let body = self.parse_block("watch")?;
+            return Ok(Statement::Watch(WatchStmt {
+                path,
+                interval_ms,
+                max_runs,
+                body,
+            }));
+        }
+
+        if self.match_token(&[TokenKind::TailFollow]) {
+            let path = self.consume_expect_string("Expected path string after 'tailfollow'")?;
+            let mut interval_ms = 200;
+            let mut max_seconds = None;
+            loop {
+                if self.match_word("every") {
+                    interval_ms =
+                        self.consume_expect_count("Expected milliseconds after 'every'")? as u64;
+                } else if self.match_word("maxseconds") {
+                    max_seconds = Some(
+                        self.consume_expect_count("Expected seconds after 'maxseconds'")? as u64,
+                    );
+                } else {
+                    break;
+                }
+            }
+            return Ok(Statement::TailFollow(TailFollowStmt {
+                path,
+                interval_ms,
+                max_seconds,
+            }));
+        }
+
+        if self.check_token(&TokenKind::While) {
+            let line = self.advance().line;
+            let condition = self.parse_condition("while")?;
+            let body = self.parse_block("while")?;
+            return Ok(Statement::While(WhileStmt {
+                condition,
+                body,
+                line,
+            }));
+        }
+
+        if self.check_token(&TokenKind::Assert) {
+            let line = self.advance().line;
+            let condition = self.parse_condition("assert")?;
+            return Ok(Statement::Assert(AssertStmt { condition, line }));
+        }
+
+        if self.match_token(&[TokenKind::If]) {
+            let condition = self.parse_condition("if")?;
+            let body = self.parse_block("if")?;
+            return Ok(Statement::If(IfStmt { condition, body }));
+        }
+
+        if self.match_token(&[TokenKind::Echo]) {
+            let text = self.consume_expect_string("Expected text string after 'echo'")?;
+            return Ok(Statement::Echo(EchoStmt { text }));
+        }
+
+        if self.match_token(&[TokenKind::Sleep]) {
+            let millis = self.consume_expect_count("Expected milliseconds after 'sleep'")? as u64;
+            return Ok(Statement::Sleep(SleepStmt { millis }));
+        }
+
+        if self.match_token(&[TokenKind::Include]) {
+            let path = self.consume_expect_string("Expected script path string after 'include'")?;
+            return Ok(Statement::Include(IncludeStmt { path }));
+        }
+
+        if self.match_token(&[TokenKind::Def]) {
+            let name = self.consume_expect_identifier("Expected function name after 'def'")?;
+            self.consume_expect_token(TokenKind::LParen, "Expected '(' after function name")?;
+            let mut params: Vec<String> = Vec::new();
+            if !self.match_token(&[TokenKind::RParen]) {
+                loop {
+                    let param = self.consume_expect_identifier("Expected parameter name")?;
+                    if params.contains(&param) {
+                        return Err(ParseError::new(format!(
+                            "Duplicate parameter '{}' in function '{}'",
+                            param, name
+                        )));
+                    }
+                    params.push(param);
+                    if self.match_token(&[TokenKind::RParen]) {
+                        break;
+                    }
+                    self.consume_expect_token(
+                        TokenKind::Comma,
+                        "Expected ',' or ')' after parameter",
+                    )?;
+                }
+            }
+            // Each call binds the arguments to the parameters for the body.
+            self.match_token(&[TokenKind::Do]);
+            let body = self.parse_token_block((TokenKind::End, "end"))?;
+            return Ok(Statement::Def(DefStmt { name, params, body }));
+        }
+
+        if self.match_token(&[TokenKind::Try]) {
+            self.match_token(&[TokenKind::Do]);
+            let body = self.parse_statements(Some((TokenKind::OnError, "onerror")))?;
+            // `${error}` is bound when the handler runs.
+            let handler = self.parse_token_block((TokenKind::End, "end"))?;
+            return Ok(Statement::Try(TryStmt { body, handler }));
+        }
+
+        if self.match_token(&[TokenKind::Call]) {
+            let name = self.consume_expect_identifier("Expected function name after 'call'")?;
+            self.consume_expect_token(TokenKind::LParen, "Expected '(' after function name")?;
+            let mut args = Vec::new();
+            if !self.match_token(&[TokenKind::RParen]) {
+                loop {
+                    args.push(self.consume_expect_argument("Expected argument string")?);
+                    if self.match_token(&[TokenKind::RParen]) {
+                        break;
+                    }
+                    self.consume_expect_token(
+                        TokenKind::Comma,
+                        "Expected ',' or ')' after argument",
+                    )?;
+                }
+            }
+            return Ok(Statement::Call(CallStmt { name, args }));
+        }
+
+        if self.match_token(&[TokenKind::Cd]) {
+            let path = self.consume_expect_string("Expected directory after 'cd'")?;
+            return Ok(Statement::Cd(CdStmt { path }));
+        }
+
+        if self.match_token(&[TokenKind::Pwd]) {
+            return Ok(Statement::Pwd(PwdStmt {}));
+        }
+
+        if self.match_token(&[TokenKind::Undo]) {
+            let count = if self.check_string() || self.check_number() {
+                self.consume_expect_count("Expected a count after 'undo'")?
+            } else {
+                1
+            };
+            return Ok(Statement::Undo(UndoStmt { count }));
+        }
+
+        if self.match_token(&[TokenKind::History]) {
+            return Ok(Statement::History(HistoryStmt {}));
+        }
+
+        if self.match_token(&[TokenKind::SaveSession]) {
+            let path =
+                self.consume_expect_string("Expected session file path after 'savesession'")?;
+            let full = self.match_word("full");
+            return Ok(Statement::SaveSession(SaveSessionStmt { path, full }));
+        }
+
+        if self.match_token(&[TokenKind::LoadSession]) {
+            let path =
+                self.consume_expect_string("Expected session file path after 'loadsession'")?;
+            let merge = self.match_word("merge");
+            return Ok(Statement::LoadSession(LoadSessionStmt { path, merge }));
+        }
+
+        if self.match_token(&[TokenKind::Parallel]) {
+            self.match_token(&[TokenKind::Do]);
+            let body = self.parse_statements(Some((TokenKind::End, "end")))?;
+            if let Some(located) = body.iter().find(|l| !l.statement.runs_in_parallel()) {
+                return Err(ParseError::new(format!(
+                    "'{}' on line {} can't run in a parallel block: only copy, move, remove, \
+                     mkdir, touch, concat, zipcreate, zipextract, and hash or checkhash of a \
+                     path can",
+                    located.statement.keyword(),
+                    located.line
+                )));
+            }
+            return Ok(Statement::Parallel(ParallelStmt { body }));
+        }
+
+        if self.match_token(&[TokenKind::Breakpoint]) {
+            return Ok(Statement::Breakpoint(BreakpointStmt {}));
         }
 
         if self.match_token(&[TokenKind::DumpEnv]) {
-            return Ok(Statement::DumpEnv(DumpEnvStmt {}));
+            let var_name = if self.check_end_of_statement() {
+                None
+            } else {
+                Some(self.consume_expect_identifier("Expected variable name after 'dumpenv'")?)
+            };
+            return Ok(Statement::DumpEnv(DumpEnvStmt { var_name }));
         }
 
         if self.match_token(&[TokenKind::Help]) {
-            return Ok(Statement::Help(HelpStmt {}));
+            let topic = if self.check_string() {
+                Some(self.consume_expect_string("Expected command name after 'help'")?)
+            } else {
+                None
+            };
+            return Ok(Statement::Help(HelpStmt { topic }));
         }
 
         if self.match_token(&[TokenKind::Exit]) {
-            return Ok(Statement::Exit(ExitStmt {}));
+            let code = if self.check_number() {
+                let pos = self.peek_token().pos;
+                let code = self.consume_expect_number("Expected exit status after 'exit'")?;
+                i32::try_from(code).map_err(|_| {
+                    ParseError::new(format!(
+                        "Exit status {} at position {} is out of range",
+                        code, pos
+                    ))
+                })?
+            } else {
+                0
+            };
+            return Ok(Statement::Exit(ExitStmt { code }));
         }
 
+        if let TokenKind::Identifier(word) = &self.peek_token().kind {
+            let message = match closest_match(word, keywords(), MAX_SUGGESTION_DISTANCE) {
+                Some(keyword) => format!("Unknown command '{}'; did you mean '{}'?", word, keyword),
+                None => format!("Unknown command '{}'", word),
+            };
+            return Err(ParseError::new(message).at(self.peek_token().pos));
+        }
         Err(ParseError::new(format!(
             "Unexpected token {:?} at position {}",
             self.peek_token().kind,
@@ -194,15 +1354,39 @@ impl Parser {
         )))
     }
 
+    /// Whether only the `Eof` token is left.
     fn is_at_end(&self) -> bool {
-        self.pos >= self.length
+        self.pos + 1 >= self.length
     }
 
+    /// The next token, which is `Eof` at the end of the input.
     fn peek_token(&self) -> &Token {
-        if self.is_at_end() {
-            &self.tokens[self.length - 1]
-        } else {
-            &self.tokens[self.pos]
+        &self.tokens[self.pos]
+    }
+
+    /// The error for input that ends where `err_msg` expected more, placed at
+    /// the last real token (not a line break) so it points at what was cut short.
+    fn end_of_input(&self, err_msg: &str) -> ParseError {
+        let last = self.tokens[..self.pos]
+            .iter()
+            .rev()
+            .find(|token| !matches!(token.kind, TokenKind::EndOfStatement))
+            .unwrap_or(&self.tokens[self.pos]);
+        ParseError::incomplete(format!("Unexpected end of input: {}", err_msg)).at(last.pos)
+    }
+
+    /// The line the most recently consumed token ends on.
+    fn last_line(&self) -> usize {
+        // A following line break or the end of input is on the statement's last
+        // line, which a string's text alone can't tell when it dropped a newline.
+        let next = self.peek_token();
+        if matches!(next.kind, TokenKind::EndOfStatement | TokenKind::Eof) {
+            return next.line;
+        }
+        let token = &self.tokens[self.pos.saturating_sub(1)];
+        match &token.kind {
+            TokenKind::String(text) => token.line + text.matches('\n').count(),
+            _ => token.line,
         }
     }
 
@@ -210,10 +1394,13 @@ impl Parser {
         self.pos
     }
 
+    /// Consume the next token and return it. At the end of the input this
+    /// returns `Eof` without moving past it.
     fn advance(&mut self) -> &Token {
-        if !self.is_at_end() {
-            self.pos += 1;
+        if self.is_at_end() {
+            return &self.tokens[self.pos];
         }
+        self.pos += 1;
         &self.tokens[self.pos - 1]
     }
 
@@ -230,15 +1417,55 @@ impl Parser {
         false
     }
 
+    /// Consume an identifier used as a statement modifier, compared
+    /// case-insensitively like keywords.
+    fn match_word(&mut self, word: &str) -> bool {
+        if self.is_at_end() {
+            return false;
+        }
+        if let TokenKind::Identifier(s) = &self.peek_token().kind {
+            if s.eq_ignore_ascii_case(word) {
+                self.advance();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Consume a specific bare word (like `from`), or fail with `err_msg`.
+    fn expect_word(&mut self, word: &str, err_msg: &str) -> Result<(), ParseError> {
+        if self.match_word(word) {
+            Ok(())
+        } else if self.is_at_end() {
+            Err(self.end_of_input(err_msg))
+        } else {
+            Err(ParseError::new(format!(
+                "{}: got {:?}",
+                err_msg,
+                self.peek_token().kind
+            )))
+        }
+    }
+
+    /// Whether the next token is of the given kind (without consuming it).
+    fn check_token(&self, kind: &TokenKind) -> bool {
+        !self.is_at_end() && self.peek_token().kind.eq_ignore_value(kind)
+    }
+
     fn check_end_of_statement(&self) -> bool {
         if self.is_at_end() {
             return true;
         }
-        let tk = &self.peek_token().kind;
-        match tk {
-            TokenKind::EndOfStatement => true,
-            _ => false,
-        }
+        matches!(self.peek_token().kind, TokenKind::EndOfStatement)
+    }
+
+    fn check_string(&self) -> bool {
+        !self.is_at_end() && matches!(self.peek_token().kind, TokenKind::String(_))
+    }
+
+    /// Whether the next token could be a number (bare or quoted).
+    fn check_number(&self) -> bool {
+        self.check_string() || self.check_token(&TokenKind::Number(0))
     }
 
     fn consume_end_of_statement(&mut self) -> Result<(), ParseError> {
@@ -255,7 +1482,7 @@ impl Parser {
 
     fn consume_expect_string(&mut self, err_msg: &str) -> Result<String, ParseError> {
         if self.is_at_end() {
-            return Err(ParseError::new(err_msg.to_string()));
+            return Err(self.end_of_input(err_msg));
         }
         let tk = self.advance();
         match &tk.kind {
@@ -264,13 +1491,47 @@ impl Parser {
         }
     }
 
+    /// Consume an integer, written either bare (`10`) or, for older scripts,
+    /// as a numeric string (`"10"`).
+    fn consume_expect_number(&mut self, err_msg: &str) -> Result<i64, ParseError> {
+        if self.is_at_end() {
+            return Err(self.end_of_input(err_msg));
+        }
+        let tk = self.advance();
+        match &tk.kind {
+            TokenKind::Number(n) => Ok(*n),
+            TokenKind::String(s) => s.trim().parse::<i64>().map_err(|e| {
+                ParseError::with_source(
+                    format!(
+                        "{}: '{}' at position {} is not a valid number",
+                        err_msg, s, tk.pos
+                    ),
+                    e,
+                )
+            }),
+            _ => Err(ParseError::new(format!("{}: got {:?}", err_msg, tk.kind))),
+        }
+    }
+
+    /// Consume a non-negative integer such as a line number, count, or size.
+    fn consume_expect_count(&mut self, err_msg: &str) -> Result<usize, ParseError> {
+        let pos = self.peek_token().pos;
+        let n = self.consume_expect_number(err_msg)?;
+        usize::try_from(n).map_err(|_| {
+            ParseError::new(format!(
+                "{}: {} at position {} must not be negative",
+                err_msg, n, pos
+            ))
+        })
+    }
+
     fn consume_expect_token(
         &mut self,
         expected: TokenKind,
         err_msg: &str,
     ) -> Result<TokenKind, ParseError> {
         if self.is_at_end() {
-            return Err(ParseError::new(err_msg.to_string()));
+            return Err(self.end_of_input(err_msg));
         }
         let tk = self.advance();
         if tk.kind.eq_ignore_value(&expected) {
@@ -280,9 +1541,62 @@ impl Parser {
         }
     }
 
+    /// Consume either an identifier (a file variable) or a string (a path).
+    fn consume_expect_file_ref(&mut self, err_msg: &str) -> Result<FileRef, ParseError> {
+        if self.is_at_end() {
+            return Err(self.end_of_input(err_msg));
+        }
+        let tk = self.advance();
+        match &tk.kind {
+            TokenKind::Identifier(s) => Ok(FileRef::Var(s.clone())),
+            TokenKind::String(s) => Ok(FileRef::Path(s.clone())),
+            _ => Err(ParseError::new(format!("{}: got {:?}", err_msg, tk.kind))),
+        }
+    }
+
+    /// Consume a codec name string ("base64" or "hex") for `encode`/`decode`.
+    fn consume_expect_codec(&mut self, command: &str) -> Result<ContentCodec, ParseError> {
+        let name = self.consume_expect_string(&format!(
+            "Expected \"base64\" or \"hex\" after variable in '{}'",
+            command
+        ))?;
+        ContentCodec::from_name(&name).ok_or_else(|| {
+            ParseError::new(format!(
+                "Unknown codec '{}' in '{}' (expected base64 or hex)",
+                name, command
+            ))
+        })
+    }
+
+    /// Consume a bare word (like `on`) or a string literal, returning its text.
+    fn consume_expect_word_or_string(&mut self, err_msg: &str) -> Result<String, ParseError> {
+        if self.is_at_end() {
+            return Err(self.end_of_input(err_msg));
+        }
+        let tk = self.advance();
+        match &tk.kind {
+            TokenKind::Identifier(s) | TokenKind::String(s) => Ok(s.clone()),
+            TokenKind::Number(n) => Ok(n.to_string()),
+            _ => Err(ParseError::new(format!("{}: got {:?}", err_msg, tk.kind))),
+        }
+    }
+
+    /// Consume a function argument: a string or a number.
+    fn consume_expect_argument(&mut self, err_msg: &str) -> Result<String, ParseError> {
+        if self.is_at_end() {
+            return Err(self.end_of_input(err_msg));
+        }
+        let tk = self.advance();
+        match &tk.kind {
+            TokenKind::String(s) => Ok(s.clone()),
+            TokenKind::Number(n) => Ok(n.to_string()),
+            _ => Err(ParseError::new(format!("{}: got {:?}", err_msg, tk.kind))),
+        }
+    }
+
     fn consume_expect_identifier(&mut self, err_msg: &str) -> Result<String, ParseError> {
         if self.is_at_end() {
-            return Err(ParseError::new(err_msg.to_string()));
+            return Err(self.end_of_input(err_msg));
         }
         let tk = self.advance();
         match &tk.kind {
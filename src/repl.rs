@@ -0,0 +1,193 @@
+/*!
+ * repl.rs
+ *
+ * The interactive prompt `file_lang` starts when run from a terminal with no
+ * scripts to run:
+ * - Each line typed runs through one interpreter, so variables and functions
+ *   persist from line to line, and errors are reported without leaving the prompt.
+ * - Entered lines are kept in a History, saved between sessions in
+ *   `~/.file_lang_history` (or `$XDG_STATE_HOME/file_lang/history`, or the file
+ *   named by `FILE_LANG_HISTFILE`), capped at MAX_HISTORY lines.
+ * - `complete` offers the keywords and variable names that could finish the
+ *   word being typed, for front ends with tab completion.
+ *
+ * A line that leaves a statement or block unfinished (`while ... do` without
+ * its `end`) switches to the CONTINUATION_PROMPT, and lines are collected until
+ * the whole construct parses; two blank lines in a row abandon it.
+ *
+ * Lines are read with the terminal's own line editing; end of input (Ctrl-D)
+ * or `exit` leaves the prompt. An interrupt (Ctrl-C) while waiting for a line
+ * abandons any unfinished statement.
+ */
+
+use crate::errors::FileLangError;
+use crate::interpreter::Interpreter;
+use crate::lexer::keywords;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+/// Shown before each line is read.
+pub const PROMPT: &str = "file_lang> ";
+
+/// Shown while reading the rest of an unfinished statement or block.
+pub const CONTINUATION_PROMPT: &str = "....> ";
+
+/// How many lines the history file keeps.
+pub const MAX_HISTORY: usize = 1000;
+
+/// Lines entered at the prompt, oldest first.
+#[derive(Default)]
+pub struct History {
+    /// Where the history is saved (nowhere when None).
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl History {
+    /// Load the history saved at `path`, starting empty if there is none yet.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        let mut history = Self { path, entries };
+        history.trim();
+        history
+    }
+
+    /// Remember a line, unless it is blank or repeats the previous one.
+    pub fn add(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+        self.trim();
+    }
+
+    /// The remembered lines, oldest first.
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Write the history to its file, creating the directory it goes in.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut text = self.entries.join("\n");
+        text.push('\n');
+        fs::write(path, text)
+    }
+
+    /// Drop the oldest lines past MAX_HISTORY.
+    fn trim(&mut self) {
+        let excess = self.entries.len().saturating_sub(MAX_HISTORY);
+        self.entries.drain(..excess);
+    }
+}
+
+/// Where the history is kept: `FILE_LANG_HISTFILE` if set, then
+/// `$XDG_STATE_HOME/file_lang/history`, then `~/.file_lang_history`.
+pub fn history_path() -> Option<PathBuf> {
+    let var = |name| env::var_os(name).filter(|value| !value.is_empty());
+    if let Some(path) = var("FILE_LANG_HISTFILE") {
+        return Some(PathBuf::from(path));
+    }
+    if let Some(dir) = var("XDG_STATE_HOME") {
+        return Some(PathBuf::from(dir).join("file_lang").join("history"));
+    }
+    var("HOME").map(|home| PathBuf::from(home).join(".file_lang_history"))
+}
+
+/// Completions for the word ending at byte `pos` of `line`: the keywords, and
+/// the interpreter's variables, it could be the start of. Returns where the
+/// word starts and the candidates, sorted.
+pub fn complete(interpreter: &Interpreter, line: &str, pos: usize) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |i| i + 1);
+    let word = &before[start..];
+    let in_string = before[..start].matches('"').count() % 2 == 1;
+    if word.is_empty() || in_string {
+        return (start, Vec::new());
+    }
+    let mut candidates: Vec<String> = keywords()
+        .map(str::to_string)
+        .chain(interpreter.variable_names())
+        .filter(|candidate| candidate.starts_with(word))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+/// Read lines from `input` and run each statement as soon as it is complete,
+/// until input ends or the script runs `exit`. The prompts and errors go to
+/// `console`; entered lines are added to `history`.
+pub fn run_repl(
+    interpreter: &mut Interpreter,
+    input: &mut dyn BufRead,
+    console: &mut dyn Write,
+    history: &mut History,
+) -> io::Result<()> {
+    // The lines of an unfinished statement, and whether the last one was blank.
+    let mut pending = String::new();
+    let mut blank = false;
+    loop {
+        let prompt = if pending.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        write!(console, "{}", prompt)?;
+        console.flush()?;
+        interpreter.clear_interrupt();
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(console)?;
+            return Ok(());
+        }
+        // The line was typed after the interrupt, at a fresh prompt.
+        if interpreter.interrupted() {
+            interpreter.clear_interrupt();
+            pending.clear();
+            blank = false;
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.trim().is_empty() {
+            if blank && !pending.is_empty() {
+                pending.clear();
+                writeln!(console, "(abandoned)")?;
+            }
+            blank = true;
+            continue;
+        }
+        blank = false;
+        history.add(line);
+        pending.push_str(line);
+        pending.push('\n');
+        let result = interpreter.run_source(&pending);
+        if result.as_ref().is_err_and(FileLangError::is_incomplete) {
+            continue;
+        }
+        pending.clear();
+        if let Err(e) = result {
+            let phase = match e {
+                FileLangError::Lex(_) => "Lexing",
+                FileLangError::Parse(_) => "Parsing",
+                FileLangError::Runtime(_) => "Runtime",
+            };
+            writeln!(console, "{} error: {}", phase, e)?;
+        }
+        if interpreter.exit_code().is_some() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,109 @@
+/*!
+ * script.rs
+ *
+ * Convenience functions for running a script from source text in one call,
+ * capturing what it prints instead of writing to stdout.
+ */
+
+use crate::errors::FileLangError;
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// What a script produced.
+#[derive(Debug, Default)]
+pub struct ScriptOutput {
+    /// Everything the script printed (empty when `ScriptOptions::output` was given).
+    pub stdout: String,
+    /// The status passed to `exit`, if the script used it.
+    pub exit_code: Option<i32>,
+    /// Warnings printed along the way, without the `warning: ` prefix.
+    pub warnings: Vec<String>,
+}
+
+/// Settings for `run_script_with`.
+#[derive(Default)]
+pub struct ScriptOptions {
+    /// Directory to run in: the script's relative paths are resolved from it, as
+    /// after `cd`. The process working directory is left alone.
+    pub working_dir: Option<PathBuf>,
+    /// Confine the script's paths to this directory (see `--sandbox`). A relative
+    /// root is taken from `working_dir`.
+    pub sandbox_root: Option<PathBuf>,
+    /// Report filesystem changes instead of making them.
+    pub dry_run: bool,
+    /// Send output here instead of capturing it in `ScriptOutput::stdout`.
+    pub output: Option<Box<dyn Write>>,
+    /// What `open stdin` reads (standard input by default).
+    pub input: Option<Box<dyn Read>>,
+}
+
+/// A writer that collects into a buffer the caller keeps a handle to.
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(pub(crate) Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run a script and return its captured output.
+///
+/// ```
+/// let output = file_lang::run_script(
+///     "echo \"one\"\n\
+///      echo \"two\"\n\
+///      exit 3",
+/// )
+/// .unwrap();
+/// assert_eq!(output.stdout, "one\ntwo\n");
+/// assert_eq!(output.exit_code, Some(3));
+/// ```
+pub fn run_script(source: &str) -> Result<ScriptOutput, FileLangError> {
+    run_script_with(source, ScriptOptions::default())
+}
+
+/// Run a script with the given options.
+pub fn run_script_with(
+    source: &str,
+    options: ScriptOptions,
+) -> Result<ScriptOutput, FileLangError> {
+    let buffer = SharedBuffer::default();
+    let out = options.output.unwrap_or_else(|| Box::new(buffer.clone()));
+    let mut interpreter = Interpreter::with_output(out);
+    interpreter.set_dry_run(options.dry_run);
+    if let Some(input) = options.input {
+        interpreter.set_input(input);
+    }
+    if let Some(dir) = &options.working_dir {
+        interpreter.set_working_dir(dir)?;
+    }
+    if let Some(root) = &options.sandbox_root {
+        let root = match &options.working_dir {
+            Some(dir) => dir.join(root),
+            None => root.clone(),
+        };
+        interpreter.set_sandbox_root(root)?;
+    }
+
+    let tokens = Lexer::new(source).lex()?;
+    let ast = Parser::new(tokens).parse()?;
+    interpreter.run(&ast)?;
+
+    let stdout = String::from_utf8_lossy(&buffer.0.borrow()).into_owned();
+    Ok(ScriptOutput {
+        stdout,
+        exit_code: interpreter.exit_code(),
+        warnings: interpreter.warnings().to_vec(),
+    })
+}
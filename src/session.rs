@@ -0,0 +1,47 @@
+/*!
+ * session.rs
+ *
+ * A snapshot of an environment that `savesession` writes as JSON and `loadsession`
+ * reads back: each variable's file, open state, mode, and format, the string
+ * variables, and the working directory.
+ *
+ * File content is left out unless the session is saved `full`; otherwise it is
+ * read from disk again when the session is loaded. Locks are never saved.
+ * Built with `Environment::to_session` and applied with `Environment::from_session`.
+ */
+
+use crate::ast::{FileTarget, OpenMode};
+use crate::environment::Content;
+use crate::utils::{Encoding, LineEnding};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    /// The directory relative paths were resolved from (`cd`).
+    pub current_dir: PathBuf,
+    /// String variables, leaving out those read from the process environment.
+    pub globals: BTreeMap<String, String>,
+    /// File variables by name.
+    pub files: BTreeMap<String, SessionEntry>,
+}
+
+/// The saved state of one file variable.
+#[derive(Serialize, Deserialize)]
+pub struct SessionEntry {
+    pub filename: String,
+    pub is_open: bool,
+    pub mode: OpenMode,
+    pub buffered: bool,
+    pub nomem: bool,
+    pub target: FileTarget,
+    pub encoding: Encoding,
+    pub bom: bool,
+    pub line_ending: LineEnding,
+    /// Whether the content had changes not yet saved to disk.
+    pub dirty: bool,
+    /// The in-memory content, kept only by `savesession ... full`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<Content>,
+}
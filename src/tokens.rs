@@ -4,23 +4,28 @@
  * Defines the Token and TokenKind types used by the lexer and parser.
  */
 
+use serde::{Deserialize, Serialize};
+
 /// A token consists of a kind and a position.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Token {
     /// The kind of the token.
     pub kind: TokenKind,
     /// The position in the input stream (for error messages).
     pub pos: usize,
+    /// The 1-based source line the token starts on.
+    pub line: usize,
 }
 
 impl Token {
     /// Create a new token.
-    pub fn new(kind: TokenKind, pos: usize) -> Self {
-        Self { kind, pos }
+    pub fn new(kind: TokenKind, pos: usize, line: usize) -> Self {
+        Self { kind, pos, line }
     }
 }
 
 /// The kind of token. Some tokens carry values (like strings or identifiers).
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum TokenKind {
     // Keywords
     Open,
@@ -42,47 +47,230 @@ pub enum TokenKind {
     ListDir,
     DumpEnv,
     Help,
+    Mkdir,
+    Rmdir,
+    Recursive,
+    Do,
+    End,
+    Head,
+    Tail,
+    InsertLine,
+    DeleteLine,
+    Exists,
+    Stat,
+    Touch,
+    Save,
+    Revert,
+    Set,
+    CloseAll,
+    Unset,
+    HexDump,
+    Size,
+    SetLineEnd,
+    Extract,
+    SortLines,
+    UniqLines,
+    ReverseLines,
+    NumberLines,
+    TrimLines,
+    GetLine,
+    Diff,
+    Hash,
+    CheckHash,
+    Encode,
+    Decode,
+    ZipCreate,
+    ZipExtract,
+    Concat,
+    Split,
+    Grep,
+    Find,
+    Du,
+    Symlink,
+    HardLink,
+    ReadLink,
+    Chmod,
+    Watch,
+    TailFollow,
+    While,
+    Echo,
+    Sleep,
+    Include,
+    Def,
+    Call,
+    Try,
+    OnError,
+    Cd,
+    Pwd,
+    RestoreBackup,
+    TempFile,
+    Buffer,
+    SaveAs,
+    CopyTo,
+    AppendFrom,
+    ReadRange,
+    GetEnv,
+    Let,
+    If,
+    Assert,
+    Prompt,
+    Undo,
+    History,
+    SaveSession,
+    LoadSession,
+    Breakpoint,
+    Parallel,
+
+    // Punctuation
+    LParen,
+    RParen,
+    Comma,
+    Pipe,
+    Equals,
+
+    // Comparison operators
+    EqualEqual,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
 
     // Values
     Identifier(String),
     String(String),
+    Number(i64),
+
+    // Trivia, only produced by a lexer with `with_trivia(true)`
+    /// A comment's text after the `#`.
+    Comment(String),
 
     // End of statement
     EndOfStatement,
+    /// The end of the input, always the last token the lexer produces.
+    Eof,
 }
 
 impl TokenKind {
     /// Check if two TokenKinds are equal ignoring the embedded values.
     pub fn eq_ignore_value(&self, other: &TokenKind) -> bool {
         use TokenKind::*;
-        match (self, other) {
-            (Open, Open) => true,
-            (Read, Read) => true,
-            (Write, Write) => true,
-            (Append, Append) => true,
-            (Close, Close) => true,
-            (Show, Show) => true,
-            (Exit, Exit) => true,
-            (As, As) => true,
-            (Truncate, Truncate) => true,
-            (Search, Search) => true,
-            (Replace, Replace) => true,
-            (LineCount, LineCount) => true,
-            (Copy, Copy) => true,
-            (Move, Move) => true,
-            (Remove, Remove) => true,
-            (Rename, Rename) => true,
-            (ListDir, ListDir) => true,
-            (DumpEnv, DumpEnv) => true,
-            (Help, Help) => true,
-            (Identifier(_), Identifier(_)) => true,
-            (String(_), String(_)) => true,
-            (EndOfStatement, EndOfStatement) => true,
-            _ => false,
-        }
+        matches!(
+            (self, other),
+            (Open, Open)
+                | (Read, Read)
+                | (Write, Write)
+                | (Append, Append)
+                | (Close, Close)
+                | (Show, Show)
+                | (Exit, Exit)
+                | (As, As)
+                | (Truncate, Truncate)
+                | (Search, Search)
+                | (Replace, Replace)
+                | (LineCount, LineCount)
+                | (Copy, Copy)
+                | (Move, Move)
+                | (Remove, Remove)
+                | (Rename, Rename)
+                | (ListDir, ListDir)
+                | (DumpEnv, DumpEnv)
+                | (Help, Help)
+                | (Mkdir, Mkdir)
+                | (Rmdir, Rmdir)
+                | (Recursive, Recursive)
+                | (Do, Do)
+                | (End, End)
+                | (Head, Head)
+                | (Tail, Tail)
+                | (InsertLine, InsertLine)
+                | (DeleteLine, DeleteLine)
+                | (Exists, Exists)
+                | (Stat, Stat)
+                | (Touch, Touch)
+                | (Save, Save)
+                | (Revert, Revert)
+                | (Set, Set)
+                | (CloseAll, CloseAll)
+                | (Unset, Unset)
+                | (HexDump, HexDump)
+                | (Size, Size)
+                | (SetLineEnd, SetLineEnd)
+                | (Extract, Extract)
+                | (SortLines, SortLines)
+                | (UniqLines, UniqLines)
+                | (ReverseLines, ReverseLines)
+                | (NumberLines, NumberLines)
+                | (TrimLines, TrimLines)
+                | (GetLine, GetLine)
+                | (Diff, Diff)
+                | (Hash, Hash)
+                | (CheckHash, CheckHash)
+                | (Encode, Encode)
+                | (Decode, Decode)
+                | (ZipCreate, ZipCreate)
+                | (ZipExtract, ZipExtract)
+                | (Concat, Concat)
+                | (Split, Split)
+                | (Grep, Grep)
+                | (Find, Find)
+                | (Du, Du)
+                | (Symlink, Symlink)
+                | (HardLink, HardLink)
+                | (ReadLink, ReadLink)
+                | (Chmod, Chmod)
+                | (Watch, Watch)
+                | (TailFollow, TailFollow)
+                | (While, While)
+                | (Echo, Echo)
+                | (Sleep, Sleep)
+                | (Include, Include)
+                | (Def, Def)
+                | (Call, Call)
+                | (Try, Try)
+                | (OnError, OnError)
+                | (Cd, Cd)
+                | (Pwd, Pwd)
+                | (RestoreBackup, RestoreBackup)
+                | (TempFile, TempFile)
+                | (Buffer, Buffer)
+                | (SaveAs, SaveAs)
+                | (CopyTo, CopyTo)
+                | (AppendFrom, AppendFrom)
+                | (ReadRange, ReadRange)
+                | (GetEnv, GetEnv)
+                | (Let, Let)
+                | (If, If)
+                | (Assert, Assert)
+                | (Prompt, Prompt)
+                | (Undo, Undo)
+                | (History, History)
+                | (SaveSession, SaveSession)
+                | (LoadSession, LoadSession)
+                | (Breakpoint, Breakpoint)
+                | (Parallel, Parallel)
+                | (LParen, LParen)
+                | (RParen, RParen)
+                | (Comma, Comma)
+                | (Pipe, Pipe)
+                | (Equals, Equals)
+                | (EqualEqual, EqualEqual)
+                | (NotEqual, NotEqual)
+                | (Less, Less)
+                | (LessEqual, LessEqual)
+                | (Greater, Greater)
+                | (GreaterEqual, GreaterEqual)
+                | (Identifier(_), Identifier(_))
+                | (String(_), String(_))
+                | (Number(_), Number(_))
+                | (Comment(_), Comment(_))
+                | (EndOfStatement, EndOfStatement)
+                | (Eof, Eof)
+        )
     }
 
-    /// Clone a token kind, taking values from `other` if needed (for identifiers or strings).
+    /// Clone a token kind, taking values from `other` if needed (for identifiers,
+    /// strings, numbers, or comments).
     pub fn clone_with_value_from(&self, other: &TokenKind) -> TokenKind {
         match self {
             TokenKind::Identifier(_) => {
@@ -99,6 +287,20 @@ impl TokenKind {
                     self.clone()
                 }
             }
+            TokenKind::Number(_) => {
+                if let TokenKind::Number(n) = other {
+                    TokenKind::Number(*n)
+                } else {
+                    self.clone()
+                }
+            }
+            TokenKind::Comment(_) => {
+                if let TokenKind::Comment(s) = other {
+                    TokenKind::Comment(s.clone())
+                } else {
+                    self.clone()
+                }
+            }
             _ => self.clone(),
         }
     }
@@ -7,14 +7,86 @@
  * - Helpers for regex-based searching.
  */
 
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fs;
-use std::fs::OpenOptions;
-use std::io;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-/// Check if a character can be part of an identifier.
+/// Check if a character can start an identifier: a letter or underscore.
+pub fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+/// Check if a character can be part of an identifier after the first: a letter,
+/// digit, or underscore. `-` and `.` are not, so an unquoted `file.txt` is an
+/// error rather than a variable name.
 pub fn is_identifier_char(c: char) -> bool {
-    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The Levenshtein distance between two strings: how many single-character
+/// insertions, deletions, or substitutions turn one into the other.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != *cb);
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The candidate closest to `word` by `edit_distance` (ignoring case), if it is
+/// at most `max_distance` edits away. Ties go to the earliest candidate.
+pub fn closest_match<'a>(
+    word: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+    max_distance: usize,
+) -> Option<&'a str> {
+    let word = word.to_lowercase();
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&word, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Replace each `${name}` in `text` for which `lookup` has a value. Unknown
+/// names are left as written, so regex replacements like `${1}` still work.
+pub fn interpolate<S: AsRef<str>>(text: &str, lookup: impl Fn(&str) -> Option<S>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after
+            .find('}')
+            .and_then(|end| Some((end, lookup(&after[..end])?)))
+        {
+            Some((end, value)) => {
+                result.push_str(value.as_ref());
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
 }
 
 /// List the files in a directory. Returns a vector of filenames.
@@ -30,9 +102,874 @@ pub fn list_directory(path: &str) -> io::Result<Vec<String>> {
     Ok(results)
 }
 
-/// Move (rename) a file from src to dst.
-pub fn move_file(src: &str, dst: &str) -> io::Result<()> {
-    fs::rename(src, dst)
+/// List a directory tree depth-first. Paths are relative to `path`, sorted within
+/// each directory, and directories carry a trailing `/`. Symlinked directories
+/// are followed once; a directory already visited (by canonical path) is listed
+/// but not descended into again, so symlink cycles terminate.
+pub fn list_directory_recursive(path: &str) -> io::Result<Vec<String>> {
+    let root = Path::new(path);
+    let mut results = Vec::new();
+    let mut visited = HashSet::new();
+    visited.insert(fs::canonicalize(root)?);
+    walk_directory(root, Path::new(""), &mut visited, &mut results)?;
+    Ok(results)
+}
+
+fn walk_directory(
+    dir: &Path,
+    relative: &Path,
+    visited: &mut HashSet<PathBuf>,
+    results: &mut Vec<String>,
+) -> io::Result<()> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        names.push(entry?.file_name());
+    }
+    names.sort();
+    for name in names {
+        let full = dir.join(&name);
+        let rel = relative.join(&name);
+        let rel_str = rel.to_string_lossy().to_string();
+        if full.is_dir() {
+            results.push(format!("{}/", rel_str));
+            if visited.insert(fs::canonicalize(&full)?) {
+                walk_directory(&full, &rel, visited, results)?;
+            }
+        } else {
+            results.push(rel_str);
+        }
+    }
+    Ok(())
+}
+
+/// Fail with `ErrorKind::Interrupted` once `interrupt` is set, so a long walk
+/// over a directory tree stops between entries.
+fn check_interrupt(interrupt: &AtomicBool) -> io::Result<()> {
+    if interrupt.load(Ordering::Relaxed) {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+    }
+    Ok(())
+}
+
+/// A line matched by `grep_directory`.
+pub struct GrepMatch {
+    /// Path relative to the searched directory.
+    pub path: String,
+    /// 1-based line number.
+    pub line: usize,
+    pub text: String,
+}
+
+/// The outcome of `grep_directory`.
+pub struct GrepResults {
+    /// Matches sorted by path, then line.
+    pub matches: Vec<GrepMatch>,
+    /// Files skipped because their content isn't valid UTF-8.
+    pub skipped_binary: Vec<String>,
+}
+
+/// Search every regular file below `root` for lines matching `re`. With
+/// `include`, only files whose name matches that glob are searched. Symlink
+/// cycles are handled as in `list_directory_recursive`.
+pub fn grep_directory(
+    root: &str,
+    re: &Regex,
+    include: Option<&str>,
+    interrupt: &AtomicBool,
+) -> io::Result<GrepResults> {
+    let mut files: Vec<String> = list_directory_recursive(root)?
+        .into_iter()
+        .filter(|name| !name.ends_with('/'))
+        .collect();
+    files.sort();
+    let mut results = GrepResults {
+        matches: Vec::new(),
+        skipped_binary: Vec::new(),
+    };
+    for name in files {
+        check_interrupt(interrupt)?;
+        let full = Path::new(root).join(&name);
+        let file_name = full.file_name().unwrap_or_default().to_string_lossy();
+        if include.is_some_and(|glob| !wildcard_match(glob, &file_name)) || !full.is_file() {
+            continue;
+        }
+        let text = match String::from_utf8(fs::read(&full)?) {
+            Ok(text) => text,
+            Err(_) => {
+                results.skipped_binary.push(name);
+                continue;
+            }
+        };
+        for (i, line) in text.lines().enumerate() {
+            if re.is_match(line) {
+                results.matches.push(GrepMatch {
+                    path: name.clone(),
+                    line: i + 1,
+                    text: line.to_string(),
+                });
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// A condition an entry must meet to be reported by `find_files`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum FindFilter {
+    /// The entry's name matches a glob like "*.log".
+    Name(String),
+    /// A file larger than this many bytes (directories never match).
+    Larger(u64),
+    /// Last modified longer ago than this.
+    OlderThan(Duration),
+}
+
+/// Parse an age like "30s", "15m", "12h", or "7d".
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let unit = text.chars().last()?;
+    let seconds_per_unit = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let amount: u64 = text[..text.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(amount.checked_mul(seconds_per_unit)?))
+}
+
+/// Recursively list the entries below `root` that meet every filter, sorted and
+/// relative to the root (directories carry a trailing `/`). Entries that vanish
+/// or can't be stat'ed during the walk are skipped.
+pub fn find_files(
+    root: &str,
+    filters: &[FindFilter],
+    interrupt: &AtomicBool,
+) -> io::Result<Vec<String>> {
+    let root = Path::new(root);
+    let mut visited = HashSet::new();
+    visited.insert(fs::canonicalize(root)?);
+    let now = SystemTime::now();
+    let mut results = Vec::new();
+    find_walk(
+        root,
+        Path::new(""),
+        filters,
+        now,
+        interrupt,
+        &mut visited,
+        &mut results,
+    )?;
+    results.sort();
+    Ok(results)
+}
+
+fn find_walk(
+    dir: &Path,
+    relative: &Path,
+    filters: &[FindFilter],
+    now: SystemTime,
+    interrupt: &AtomicBool,
+    visited: &mut HashSet<PathBuf>,
+    results: &mut Vec<String>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)?.flatten() {
+        check_interrupt(interrupt)?;
+        let name = entry.file_name();
+        let full = dir.join(&name);
+        let rel = relative.join(&name);
+        let Ok(meta) = fs::metadata(&full) else {
+            continue;
+        };
+        let matches = filters.iter().all(|filter| match filter {
+            FindFilter::Name(glob) => wildcard_match(glob, &name.to_string_lossy()),
+            FindFilter::Larger(size) => meta.is_file() && meta.len() > *size,
+            FindFilter::OlderThan(age) => meta
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|elapsed| elapsed > *age),
+        });
+        let rel_str = rel.to_string_lossy().to_string();
+        if meta.is_dir() {
+            if matches {
+                results.push(format!("{}/", rel_str));
+            }
+            let descend = fs::canonicalize(&full).is_ok_and(|canonical| visited.insert(canonical));
+            if descend {
+                // A directory that can't be read (removed, no permission) is skipped.
+                let _ = find_walk(&full, &rel, filters, now, interrupt, visited, results);
+            }
+        } else if matches {
+            results.push(rel_str);
+        }
+    }
+    Ok(())
+}
+
+/// One entry of a detailed directory listing.
+pub struct DetailedEntry {
+    /// Name relative to the listed directory.
+    pub name: String,
+    /// Size in bytes, or `None` for directories.
+    pub size: Option<u64>,
+    pub modified: SystemTime,
+}
+
+/// List a directory (optionally recursively) with size and mtime per entry,
+/// sorted by name.
+pub fn list_directory_detailed(path: &str, recursive: bool) -> io::Result<Vec<DetailedEntry>> {
+    let mut names = if recursive {
+        list_directory_recursive(path)?
+    } else {
+        list_directory(path)?
+    };
+    if !recursive {
+        names.sort();
+    }
+    describe_entries(Path::new(path), names)
+}
+
+/// Size and mtime for each of `names`, which are relative to `root`.
+pub fn describe_entries(root: &Path, names: Vec<String>) -> io::Result<Vec<DetailedEntry>> {
+    let mut results = Vec::new();
+    for name in names {
+        let meta = fs::metadata(root.join(&name))?;
+        results.push(DetailedEntry {
+            size: if meta.is_dir() {
+                None
+            } else {
+                Some(meta.len())
+            },
+            modified: meta.modified()?,
+            name,
+        });
+    }
+    Ok(results)
+}
+
+/// Create a directory and any missing parents. Succeeds if it already exists.
+pub fn make_directory(path: &str) -> io::Result<()> {
+    fs::create_dir_all(path)
+}
+
+/// Remove an empty directory.
+pub fn remove_directory(path: &str) -> io::Result<()> {
+    fs::remove_dir(path)
+}
+
+/// Remove a directory and everything inside it.
+pub fn remove_directory_all(path: &str) -> io::Result<()> {
+    fs::remove_dir_all(path)
+}
+
+/// Remove `.` components from a path, and `..` together with the component
+/// before it, without looking at the disk. A leading `..` is kept.
+pub fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(result.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                result.pop();
+            }
+            Component::ParentDir if result.has_root() => {}
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+/// The relative path leading from directory `base` to `path`, both absolute and
+/// resolved: "../b" from "/x/a" to "/x/b", and empty when they are the same.
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let shared = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut result = PathBuf::new();
+    for _ in base.components().skip(shared) {
+        result.push("..");
+    }
+    for component in path.components().skip(shared) {
+        result.push(component);
+    }
+    result
+}
+
+/// Canonicalize a path that may not exist yet: the deepest existing ancestor is
+/// canonicalized (resolving symlinks) and the remaining components are appended,
+/// with `..` removing the component before it. A dangling symlink on the way is
+/// an error, since where it leads can't be checked.
+pub fn canonicalize_lenient(path: &Path) -> io::Result<PathBuf> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    let mut resolved = loop {
+        match fs::canonicalize(existing) {
+            Ok(canonical) => break canonical,
+            Err(e) => {
+                if fs::symlink_metadata(existing).is_ok() {
+                    return Err(e);
+                }
+                match (existing.parent(), existing.components().next_back()) {
+                    (Some(parent), Some(last)) => {
+                        rest.push(last.as_os_str().to_os_string());
+                        existing = parent;
+                    }
+                    _ => return Err(e),
+                }
+            }
+        }
+    };
+    for name in rest.iter().rev() {
+        resolved.push(name);
+    }
+    let mut normalized = PathBuf::new();
+    for component in resolved.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    Ok(normalized)
+}
+
+/// What, if anything, exists at a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathKind {
+    File,
+    Directory,
+    Missing,
+}
+
+impl PathKind {
+    /// The name printed by the `exists` statement.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PathKind::File => "file",
+            PathKind::Directory => "directory",
+            PathKind::Missing => "missing",
+        }
+    }
+}
+
+/// Check whether a path exists and whether it is a file or a directory.
+pub fn path_exists(path: &str) -> PathKind {
+    match fs::metadata(path) {
+        Ok(meta) if meta.is_dir() => PathKind::Directory,
+        Ok(_) => PathKind::File,
+        Err(_) => PathKind::Missing,
+    }
+}
+
+/// The type of a filesystem entry as reported by `stat`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+impl FileKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::File => "file",
+            FileKind::Directory => "directory",
+            FileKind::Symlink => "symlink",
+        }
+    }
+}
+
+/// Metadata about a path, as reported by `stat`.
+pub struct FileStat {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub kind: FileKind,
+    /// Permission bits (Unix only).
+    pub mode: Option<u32>,
+    pub readonly: bool,
+}
+
+/// Collect metadata for a path without following a final symlink.
+pub fn stat_path(path: &str) -> io::Result<FileStat> {
+    let meta = fs::symlink_metadata(path)?;
+    let file_type = meta.file_type();
+    let kind = if file_type.is_symlink() {
+        FileKind::Symlink
+    } else if file_type.is_dir() {
+        FileKind::Directory
+    } else {
+        FileKind::File
+    };
+    Ok(FileStat {
+        size: meta.len(),
+        modified: meta.modified()?,
+        kind,
+        mode: permission_mode(&meta),
+        readonly: meta.permissions().readonly(),
+    })
+}
+
+#[cfg(unix)]
+fn permission_mode(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn permission_mode(_meta: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Total size in bytes of the files under a path (or of a single file).
+/// Symlinks are not followed and hard-linked files are counted once.
+pub fn directory_size(path: &str) -> io::Result<u64> {
+    tree_size(Path::new(path), &mut HashSet::new())
+}
+
+/// The size of each entry directly inside a directory, largest first, as
+/// (path, bytes) pairs. Hard links are counted once across all entries.
+pub fn directory_sizes(path: &str) -> io::Result<Vec<(String, u64)>> {
+    let mut seen = HashSet::new();
+    let mut sizes = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let size = tree_size(&entry.path(), &mut seen)?;
+        sizes.push((entry.path().to_string_lossy().to_string(), size));
+    }
+    sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(sizes)
+}
+
+fn tree_size(path: &Path, seen: &mut HashSet<(u64, u64)>) -> io::Result<u64> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.file_type().is_symlink() {
+        Ok(0)
+    } else if meta.is_dir() {
+        let mut total = 0;
+        for entry in fs::read_dir(path)? {
+            total += tree_size(&entry?.path(), seen)?;
+        }
+        Ok(total)
+    } else if first_link(&meta, seen) {
+        Ok(meta.len())
+    } else {
+        Ok(0)
+    }
+}
+
+/// Whether this is the first time a file is seen, by (device, inode) for files
+/// with more than one hard link.
+#[cfg(unix)]
+fn first_link(meta: &fs::Metadata, seen: &mut HashSet<(u64, u64)>) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink() <= 1 || seen.insert((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn first_link(_meta: &fs::Metadata, _seen: &mut HashSet<(u64, u64)>) -> bool {
+    true
+}
+
+/// Format a size for people: "1023 B", "1.0 KiB", "14.3 MiB", ...
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Parse a permission spec into a mode: either octal like "644" or "0755", or
+/// symbolic clauses like "+x", "u+x", "go-w", or "a=r,u+w" applied to `current`.
+pub fn parse_mode(spec: &str, current: u32) -> Result<u32, String> {
+    let spec = spec.trim();
+    if spec.is_empty() {
+        return Err("empty mode".to_string());
+    }
+    if spec.chars().all(|c| c.is_ascii_digit()) {
+        if spec.len() > 4 {
+            return Err(format!("octal mode '{}' has more than 4 digits", spec));
+        }
+        return u32::from_str_radix(spec, 8)
+            .map_err(|_| format!("'{}' is not a valid octal mode", spec));
+    }
+    let mut mode = current;
+    for clause in spec.split(',') {
+        let op_at = clause
+            .find(['+', '-', '='])
+            .ok_or_else(|| format!("missing +, -, or = in '{}'", clause))?;
+        let (who, rest) = clause.split_at(op_at);
+        let mut who_mask = 0;
+        for c in who.chars() {
+            who_mask |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return Err(format!("invalid class '{}' in '{}'", c, clause)),
+            };
+        }
+        if who.is_empty() {
+            who_mask = 0o777;
+        }
+        let op = rest.chars().next().unwrap_or_default();
+        let mut perms = 0;
+        for c in rest[1..].chars() {
+            perms |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => return Err(format!("invalid permission '{}' in '{}'", c, clause)),
+            };
+        }
+        let bits = perms & who_mask;
+        mode = match op {
+            '+' => mode | bits,
+            '-' => mode & !bits,
+            _ => (mode & !who_mask) | bits,
+        };
+    }
+    Ok(mode)
+}
+
+/// Set a path's permission bits.
+#[cfg(unix)]
+pub fn set_mode(path: &str, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+/// Set a path's permissions as well as the platform allows: only the owner's
+/// write bit is honored, as the readonly flag.
+#[cfg(not(unix))]
+pub fn set_mode(path: &str, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+/// Format a timestamp as an RFC 3339 string in UTC, e.g. "2024-01-31T13:05:09Z".
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let days = secs.div_euclid(86_400);
+    let rem = secs.rem_euclid(86_400);
+    let (hour, minute, second) = (rem / 3600, (rem % 3600) / 60, rem % 60);
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Create a file if it is missing and set its modification time to now.
+/// Existing content is left untouched.
+pub fn touch_file(path: &str) -> io::Result<()> {
+    let file = OpenOptions::new().append(true).create(true).open(path)?;
+    file.set_modified(SystemTime::now())
+}
+
+/// Open a file and take an advisory lock on it, shared or exclusive, waiting up
+/// to `timeout` for a conflicting lock to be released. The lock lasts as long as
+/// the returned handle. An exclusive lock creates a missing file.
+pub fn lock_file(path: &str, shared: bool, timeout: Duration) -> io::Result<File> {
+    let file = if shared {
+        File::open(path)?
+    } else {
+        OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        let attempt = if shared {
+            file.try_lock_shared()
+        } else {
+            file.try_lock()
+        };
+        match attempt {
+            Ok(()) => return Ok(file),
+            Err(TryLockError::WouldBlock) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "the file is locked by someone else",
+                    ));
+                }
+                std::thread::sleep(LOCK_POLL_INTERVAL.min(deadline - now));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+    }
+}
+
+/// How often `lock_file` retries while waiting for a lock.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Check whether a path contains glob wildcards (`*`, `?`, or `[`).
+pub fn is_glob_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Expand a glob pattern into the sorted list of existing paths that match it.
+///
+/// Supports `*` and `?` within a path component, `[abc]`/`[a-z]`/`[!a]` character
+/// classes, and `**` as a whole component matching any number of directories.
+/// Names starting with `.` only match components that also start with `.`.
+pub fn glob_paths(pattern: &str) -> io::Result<Vec<String>> {
+    let mut base = PathBuf::new();
+    let mut parts = Vec::new();
+    for component in Path::new(pattern).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir if parts.is_empty() => {
+                base.push(component.as_os_str())
+            }
+            _ => parts.push(component.as_os_str().to_string_lossy().to_string()),
+        }
+    }
+    let mut results = Vec::new();
+    glob_walk(&base, &parts, &mut results)?;
+    let mut paths: Vec<String> = results
+        .into_iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+fn glob_walk(base: &Path, parts: &[String], results: &mut Vec<PathBuf>) -> io::Result<()> {
+    let Some((part, rest)) = parts.split_first() else {
+        if !base.as_os_str().is_empty() && fs::symlink_metadata(base).is_ok() {
+            results.push(base.to_path_buf());
+        }
+        return Ok(());
+    };
+
+    if !is_glob_pattern(part) {
+        return glob_walk(&base.join(part), rest, results);
+    }
+
+    let dir = if base.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // Not a directory or not readable: nothing below it can match.
+        Err(_) => return Ok(()),
+    };
+    let mut children = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_dir = entry.file_type()?.is_dir();
+        children.push((name, is_dir));
+    }
+    children.sort();
+
+    if part == "**" {
+        // Zero directories...
+        glob_walk(base, rest, results)?;
+        // ...or descend one more (symlinked directories are not followed).
+        for (name, is_dir) in children {
+            if is_dir && !name.starts_with('.') {
+                glob_walk(&base.join(name), parts, results)?;
+            }
+        }
+        return Ok(());
+    }
+
+    for (name, _) in children {
+        if name.starts_with('.') && !part.starts_with('.') {
+            continue;
+        }
+        if wildcard_match(part, &name) {
+            glob_walk(&base.join(name), rest, results)?;
+        }
+    }
+    Ok(())
+}
+
+/// Match a single path component against a pattern with `*`, `?`, and `[...]`.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position to resume from after the most recent `*`.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    backtrack = Some((p, n));
+                    p += 1;
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+                '[' => {
+                    if let Some((matched, next)) = match_class(&pattern, p, name[n]) {
+                        if matched {
+                            p = next;
+                            n += 1;
+                            continue;
+                        }
+                    } else if name[n] == '[' {
+                        p += 1;
+                        n += 1;
+                        continue;
+                    }
+                }
+                c if c == name[n] => {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        match backtrack {
+            Some((star_p, star_n)) => {
+                backtrack = Some((star_p, star_n + 1));
+                p = star_p + 1;
+                n = star_n + 1;
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Match `c` against the character class starting at `pattern[start]` (a `[`).
+/// Returns whether it matched and the index just past the closing `]`, or
+/// `None` if the class is unterminated.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        if pattern[i] == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            if pattern[i] <= c && c <= pattern[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if pattern[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+        first = false;
+    }
+    None
+}
+
+/// How `move_file` relocated a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveStrategy {
+    /// A plain rename on the same filesystem.
+    Renamed,
+    /// Copied to the destination and then removed from the source, because the
+    /// destination is on a different filesystem.
+    CopiedAndRemoved,
+}
+
+/// Move (rename) a file or directory from src to dst, falling back to copy and
+/// delete when they are on different filesystems.
+pub fn move_file(src: &str, dst: &str) -> io::Result<MoveStrategy> {
+    match fs::rename(src, dst) {
+        Ok(()) => Ok(MoveStrategy::Renamed),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            move_by_copy(src, dst)?;
+            Ok(MoveStrategy::CopiedAndRemoved)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Move src to dst by copying and then removing the source. The copy is made
+/// under a temporary name next to dst and renamed into place, so a failed copy
+/// never leaves a partial destination behind. Permissions are preserved.
+pub fn move_by_copy(src: &str, dst: &str) -> io::Result<()> {
+    let dst_path = Path::new(dst);
+    let name = dst_path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "destination has no file name")
+    })?;
+    let temp = dst_path.with_file_name(format!(
+        ".{}.file-lang-tmp-{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    let temp_str = temp.to_string_lossy().to_string();
+
+    let is_dir = fs::metadata(src)?.is_dir();
+    let copied = if is_dir {
+        copy_dir_recursive(src, &temp_str).map(|_| ())
+    } else {
+        fs::copy(src, &temp).map(|_| ())
+    };
+    if let Err(e) = copied.and_then(|_| fs::rename(&temp, dst_path)) {
+        let _ = if is_dir {
+            fs::remove_dir_all(&temp)
+        } else {
+            fs::remove_file(&temp)
+        };
+        return Err(e);
+    }
+
+    if is_dir {
+        fs::remove_dir_all(src)
+    } else {
+        fs::remove_file(src)
+    }
 }
 
 /// Copy a file from src to dst.
@@ -40,32 +977,332 @@ pub fn copy_file(src: &str, dst: &str) -> io::Result<u64> {
     fs::copy(src, dst)
 }
 
+/// What a recursive directory copy did.
+pub struct CopySummary {
+    pub files: usize,
+    pub bytes: u64,
+    /// Symlinks found in the source tree, which are not copied.
+    pub skipped_symlinks: Vec<String>,
+}
+
+/// Copy a directory tree from src to dst, creating directories as needed.
+/// Symlinks are skipped and reported. Fails if dst lies inside src.
+pub fn copy_dir_recursive(src: &str, dst: &str) -> io::Result<CopySummary> {
+    let src_abs = fs::canonicalize(src)?;
+    if absolute_path(Path::new(dst))?.starts_with(&src_abs) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "destination is inside the source directory",
+        ));
+    }
+    let mut summary = CopySummary {
+        files: 0,
+        bytes: 0,
+        skipped_symlinks: Vec::new(),
+    };
+    copy_tree(Path::new(src), Path::new(dst), &mut summary)?;
+    Ok(summary)
+}
+
+fn copy_tree(src: &Path, dst: &Path, summary: &mut CopySummary) -> io::Result<()> {
+    fs::create_dir_all(dst)?;
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(src)? {
+        entries.push(entry?);
+    }
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        let from = entry.path();
+        let to = dst.join(entry.file_name());
+        if file_type.is_symlink() {
+            summary
+                .skipped_symlinks
+                .push(from.to_string_lossy().to_string());
+        } else if file_type.is_dir() {
+            copy_tree(&from, &to, summary)?;
+        } else {
+            summary.bytes += fs::copy(&from, &to)?;
+            summary.files += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Concatenate `sources` in order into `destination`, which is created or
+/// overwritten. Every source is checked before anything is written, and the
+/// destination may not be one of the sources. Returns the number of bytes written.
+pub fn concat_files(destination: &str, sources: &[String]) -> io::Result<u64> {
+    let dest_abs = absolute_path(Path::new(destination))?;
+    for source in sources {
+        if !Path::new(source).is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("source '{}' does not exist or is not a file", source),
+            ));
+        }
+        if fs::canonicalize(source)? == dest_abs {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("destination '{}' is also a source", destination),
+            ));
+        }
+    }
+    let mut out = fs::File::create(destination)?;
+    let mut total = 0;
+    for source in sources {
+        total += io::copy(&mut fs::File::open(source)?, &mut out)?;
+    }
+    Ok(total)
+}
+
+/// Create a deflate-compressed zip archive from a file or a directory tree, storing
+/// paths relative to the source. Symlinks are skipped, as is the archive itself if
+/// it lies inside the source directory. Returns the number of files stored.
+pub fn zip_create(archive: &str, source: &str) -> io::Result<usize> {
+    let source_path = Path::new(source);
+    let source_meta = fs::metadata(source_path)?;
+    let file = fs::File::create(archive)?;
+    let archive_abs = fs::canonicalize(archive)?;
+    let mut writer = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    let mut count = 0;
+    if source_meta.is_dir() {
+        zip_add_dir(
+            &mut writer,
+            options,
+            source_path,
+            "",
+            &archive_abs,
+            &mut count,
+        )?;
+    } else {
+        let name = source_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| source.to_string());
+        writer.start_file(name, options).map_err(io::Error::other)?;
+        io::copy(&mut fs::File::open(source_path)?, &mut writer)?;
+        count += 1;
+    }
+    writer.finish().map_err(io::Error::other)?;
+    Ok(count)
+}
+
+fn zip_add_dir(
+    writer: &mut zip::ZipWriter<fs::File>,
+    options: zip::write::SimpleFileOptions,
+    dir: &Path,
+    prefix: &str,
+    archive_abs: &Path,
+    count: &mut usize,
+) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        entries.push(entry?);
+    }
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if file_type.is_symlink() {
+            continue;
+        } else if file_type.is_dir() {
+            writer
+                .add_directory(format!("{}/", name), options)
+                .map_err(io::Error::other)?;
+            zip_add_dir(
+                writer,
+                options,
+                &path,
+                &format!("{}/", name),
+                archive_abs,
+                count,
+            )?;
+        } else if fs::canonicalize(&path)? != archive_abs {
+            writer.start_file(name, options).map_err(io::Error::other)?;
+            io::copy(&mut fs::File::open(&path)?, writer)?;
+            *count += 1;
+        }
+    }
+    Ok(())
+}
+
+/// The total size of the files in a zip archive once extracted.
+pub fn zip_extracted_size(archive: &str) -> io::Result<u64> {
+    let mut zip = zip::ZipArchive::new(fs::File::open(archive)?).map_err(io::Error::other)?;
+    let mut total = 0;
+    for i in 0..zip.len() {
+        total += zip.by_index(i).map_err(io::Error::other)?.size();
+    }
+    Ok(total)
+}
+
+/// Extract a zip archive into a directory, creating it as needed. Every entry
+/// name is checked before anything is written: absolute paths and `..`
+/// components are rejected. Returns the number of files extracted.
+pub fn zip_extract(archive: &str, dest: &str) -> io::Result<usize> {
+    let mut zip = zip::ZipArchive::new(fs::File::open(archive)?).map_err(io::Error::other)?;
+    let mut paths = Vec::with_capacity(zip.len());
+    for i in 0..zip.len() {
+        let entry = zip.by_index(i).map_err(io::Error::other)?;
+        let name = entry.name().map_err(io::Error::other)?.to_string();
+        let relative = safe_entry_path(&name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("refusing to extract unsafe entry '{}'", name),
+            )
+        })?;
+        paths.push((Path::new(dest).join(relative), entry.is_dir()));
+    }
+    fs::create_dir_all(dest)?;
+    let mut count = 0;
+    for (i, (path, is_dir)) in paths.into_iter().enumerate() {
+        if is_dir {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut entry = zip.by_index(i).map_err(io::Error::other)?;
+        io::copy(&mut entry, &mut fs::File::create(&path)?)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The relative path a zip entry name refers to, or `None` if it is absolute or
+/// climbs out of the destination with `..`.
+fn safe_entry_path(name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    let mut path = PathBuf::new();
+    for component in Path::new(&name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Make a path absolute with symlinks resolved, even if its last components
+/// don't exist yet.
+fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+    if let Ok(canonical) = fs::canonicalize(path) {
+        return Ok(canonical);
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            Ok(absolute_path(parent)?.join(name))
+        }
+        (_, Some(name)) => Ok(std::env::current_dir()?.join(name)),
+        _ => std::path::absolute(path),
+    }
+}
+
+/// Create a symbolic link at `link` pointing to `target`.
+#[cfg(unix)]
+pub fn create_symlink(target: &str, link: &str) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Create a symbolic link at `link` pointing to `target`, using the directory or
+/// file variant depending on what the target is. Creating symlinks needs a
+/// privilege on Windows unless Developer Mode is on.
+#[cfg(windows)]
+pub fn create_symlink(target: &str, link: &str) -> io::Result<()> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+    // A relative target is resolved from the link's directory.
+    let resolved = match Path::new(link).parent() {
+        Some(parent) => parent.join(target),
+        None => PathBuf::from(target),
+    };
+    let result = if resolved.is_dir() {
+        symlink_dir(target, link)
+    } else {
+        symlink_file(target, link)
+    };
+    // ERROR_PRIVILEGE_NOT_HELD
+    result.map_err(|e| match e.raw_os_error() {
+        Some(1314) => io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "creating symlinks requires administrator rights or Developer Mode",
+        ),
+        _ => e,
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn create_symlink(_target: &str, _link: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+/// Create a hard link at `link` to the existing file `target`.
+pub fn create_hardlink(target: &str, link: &str) -> io::Result<()> {
+    fs::hard_link(target, link)
+}
+
+/// The target a symbolic link points to.
+pub fn read_link(link: &str) -> io::Result<String> {
+    Ok(fs::read_link(link)?.to_string_lossy().to_string())
+}
+
+/// Whether a path is a symbolic link (without following it).
+pub fn is_symlink(path: &str) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink())
+}
+
+/// Read everything in a file from byte `offset` to its current end.
+pub fn read_from_offset(path: &str, offset: u64) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Read up to `len` bytes of a file starting at byte `offset`, without reading
+/// the rest. Fewer bytes come back if the file ends first (none past the end).
+pub fn read_file_range(path: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.take(len).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
 /// Remove a file.
 pub fn remove_file(path: &str) -> io::Result<()> {
     fs::remove_file(path)
 }
 
-/// Write a string to a file (overwriting).
-pub fn write_to_file(filename: &str, content: &str) -> io::Result<()> {
+/// Write content to a file (overwriting).
+pub fn write_to_file(filename: &str, content: impl AsRef<[u8]>) -> io::Result<()> {
     let mut file = OpenOptions::new()
         .write(true)
         .truncate(true)
         .create(true)
         .open(filename)?;
     use std::io::Write;
-    file.write_all(content.as_bytes())?;
+    file.write_all(content.as_ref())?;
     Ok(())
 }
 
-/// Append a string to a file.
-pub fn append_to_file(filename: &str, content: &str) -> io::Result<()> {
+/// Append content to a file.
+pub fn append_to_file(filename: &str, content: impl AsRef<[u8]>) -> io::Result<()> {
     let mut file = OpenOptions::new()
-        .write(true)
         .append(true)
         .create(true)
         .open(filename)?;
     use std::io::Write;
-    file.write_all(content.as_bytes())?;
+    file.write_all(content.as_ref())?;
     Ok(())
 }
 
@@ -74,20 +1311,813 @@ pub fn read_file_content(filename: &str) -> io::Result<String> {
     fs::read_to_string(filename)
 }
 
-/// Search for a regex pattern in a text. Returns vector of (line_number, line) for matches.
-pub fn search_in_text(text: &str, pattern: &str) -> Result<Vec<(usize, String)>, String> {
-    let re = Regex::new(pattern).map_err(|e| e.to_string())?;
+/// Read the entire content of a file as raw bytes.
+pub fn read_file_bytes(filename: &str) -> io::Result<Vec<u8>> {
+    fs::read(filename)
+}
+
+/// A text encoding supported by `open ... encoding "name"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    /// Look up an encoding by name, e.g. "utf-8", "latin-1", "utf-16le".
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Latin1 => "latin-1",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+        }
+    }
+
+    /// The byte order mark for this encoding (empty for Latin-1).
+    pub fn bom(&self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+            Encoding::Latin1 => &[],
+            Encoding::Utf16Le => &[0xFF, 0xFE],
+            Encoding::Utf16Be => &[0xFE, 0xFF],
+        }
+    }
+}
+
+/// An error converting between bytes and text, with the byte offset it occurred at.
+#[derive(Debug)]
+pub struct CodecError {
+    pub offset: usize,
+    pub msg: String,
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at byte offset {}", self.msg, self.offset)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// Detect a UTF-16 byte order mark at the start of some bytes.
+pub fn detect_bom(bytes: &[u8]) -> Option<Encoding> {
+    [Encoding::Utf16Le, Encoding::Utf16Be]
+        .into_iter()
+        .find(|e| bytes.starts_with(e.bom()))
+}
+
+/// Decode bytes (without a byte order mark) into text.
+pub fn decode_text(bytes: &[u8], encoding: Encoding) -> Result<String, CodecError> {
+    match encoding {
+        Encoding::Utf8 => std::str::from_utf8(bytes)
+            .map(|s| s.to_string())
+            .map_err(|e| CodecError {
+                offset: e.valid_up_to(),
+                msg: "invalid UTF-8".to_string(),
+            }),
+        Encoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            if !bytes.len().is_multiple_of(2) {
+                return Err(CodecError {
+                    offset: bytes.len() - 1,
+                    msg: "truncated UTF-16 code unit".to_string(),
+                });
+            }
+            let units = bytes.chunks(2).map(|pair| match encoding {
+                Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                _ => u16::from_be_bytes([pair[0], pair[1]]),
+            });
+            let mut text = String::with_capacity(bytes.len() / 2);
+            let mut offset = 0;
+            for c in char::decode_utf16(units) {
+                match c {
+                    Ok(c) => {
+                        offset += c.len_utf16() * 2;
+                        text.push(c);
+                    }
+                    Err(_) => {
+                        return Err(CodecError {
+                            offset,
+                            msg: "unpaired UTF-16 surrogate".to_string(),
+                        })
+                    }
+                }
+            }
+            Ok(text)
+        }
+    }
+}
+
+/// Encode text into bytes (without a byte order mark).
+pub fn encode_text(text: &str, encoding: Encoding) -> Result<Vec<u8>, CodecError> {
+    match encoding {
+        Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        Encoding::Latin1 => text
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                u8::try_from(c as u32).map_err(|_| CodecError {
+                    offset: i,
+                    msg: format!("character '{}' cannot be encoded as latin-1", c),
+                })
+            })
+            .collect(),
+        Encoding::Utf16Le => Ok(text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()),
+        Encoding::Utf16Be => Ok(text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect()),
+    }
+}
+
+/// The line ending a text file is written with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Look up a line ending by name: "lf" or "crlf".
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "lf",
+            LineEnding::CrLf => "crlf",
+        }
+    }
+}
+
+/// Detect the dominant line ending of some text. Returns the ending used by the
+/// majority of lines (LF on a tie) and whether both kinds occur.
+pub fn detect_line_ending(text: &str) -> (LineEnding, bool) {
+    let total = text.matches('\n').count();
+    let crlf = text.matches("\r\n").count();
+    let lf = total - crlf;
+    let ending = if crlf > lf {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Lf
+    };
+    (ending, crlf > 0 && lf > 0)
+}
+
+/// Convert every CRLF line ending to LF.
+pub fn normalize_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n")
+}
+
+/// Convert LF-only text to the given line ending.
+pub fn apply_line_ending(text: &str, ending: LineEnding) -> Cow<'_, str> {
+    match ending {
+        LineEnding::Lf => Cow::Borrowed(text),
+        LineEnding::CrLf => Cow::Owned(text.replace('\n', "\r\n")),
+    }
+}
+
+/// A binary-to-text codec for `encode` and `decode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentCodec {
+    Base64,
+    Hex,
+}
+
+impl ContentCodec {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "base64" => Some(ContentCodec::Base64),
+            "hex" => Some(ContentCodec::Hex),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentCodec::Base64 => "base64",
+            ContentCodec::Hex => "hex",
+        }
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as standard padded base64 or lowercase hex.
+pub fn encode_content(bytes: &[u8], codec: ContentCodec) -> String {
+    match codec {
+        ContentCodec::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        ContentCodec::Base64 => {
+            let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+            for chunk in bytes.chunks(3) {
+                let n = chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+                for i in 0..4 {
+                    if i <= chunk.len() {
+                        let index = (n >> (18 - 6 * i)) & 0x3f;
+                        out.push(BASE64_ALPHABET[index as usize] as char);
+                    } else {
+                        out.push('=');
+                    }
+                }
+            }
+            out
+        }
+    }
+}
+
+/// Decode base64 or hex text back into bytes. Whitespace (such as line breaks)
+/// is ignored; errors report the byte offset of the offending character.
+pub fn decode_content(text: &str, codec: ContentCodec) -> Result<Vec<u8>, CodecError> {
+    let digits: Vec<(usize, u8)> = text
+        .bytes()
+        .enumerate()
+        .filter(|(_, b)| !b.is_ascii_whitespace())
+        .collect();
+    match codec {
+        ContentCodec::Hex => {
+            if !digits.len().is_multiple_of(2) {
+                return Err(CodecError {
+                    offset: text.len(),
+                    msg: "odd number of hex digits".to_string(),
+                });
+            }
+            let value = |&(offset, b): &(usize, u8)| {
+                (b as char).to_digit(16).ok_or_else(|| CodecError {
+                    offset,
+                    msg: format!("invalid hex digit '{}'", b as char),
+                })
+            };
+            digits
+                .chunks(2)
+                .map(|pair| Ok((value(&pair[0])? << 4 | value(&pair[1])?) as u8))
+                .collect()
+        }
+        ContentCodec::Base64 => {
+            if !digits.len().is_multiple_of(4) {
+                return Err(CodecError {
+                    offset: text.len(),
+                    msg: "base64 length is not a multiple of 4".to_string(),
+                });
+            }
+            let mut out = Vec::with_capacity(digits.len() / 4 * 3);
+            let last_chunk = digits.len() / 4;
+            for (c, chunk) in digits.chunks(4).enumerate() {
+                let mut n = 0u32;
+                let mut padding = 0;
+                for (i, &(offset, b)) in chunk.iter().enumerate() {
+                    let value = if b == b'=' && c + 1 == last_chunk && i >= 2 {
+                        padding += 1;
+                        0
+                    } else if padding > 0 {
+                        return Err(CodecError {
+                            offset,
+                            msg: "data after base64 padding".to_string(),
+                        });
+                    } else {
+                        BASE64_ALPHABET
+                            .iter()
+                            .position(|&a| a == b)
+                            .ok_or_else(|| CodecError {
+                                offset,
+                                msg: format!("invalid base64 character '{}'", b as char),
+                            })? as u32
+                    };
+                    n = n << 6 | value;
+                }
+                let bytes = n.to_be_bytes();
+                out.extend_from_slice(&bytes[1..4 - padding]);
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Format bytes as a canonical hex+ASCII dump (like `hexdump -C`), one string
+/// per 16-byte row, followed by the total length as a final offset.
+pub fn hex_dump(bytes: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(b) => hex.push_str(&format!("{:02x} ", b)),
+                None => hex.push_str("   "),
+            }
+            if i == 7 {
+                hex.push(' ');
+            }
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        lines.push(format!("{:08x}  {} |{}|", row * 16, hex, ascii));
+    }
+    if !bytes.is_empty() {
+        lines.push(format!("{:08x}", bytes.len()));
+    }
+    lines
+}
+
+/// Split text into lines, also reporting whether it ended with a newline.
+pub fn split_lines(text: &str) -> (Vec<String>, bool) {
+    let lines = text.lines().map(|l| l.to_string()).collect();
+    (lines, text.ends_with('\n'))
+}
+
+/// Join lines back into text, adding a final newline if requested.
+/// The inverse of `split_lines`.
+pub fn join_lines(lines: &[String], trailing_newline: bool) -> String {
+    let mut text = lines.join("\n");
+    if trailing_newline && !lines.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+/// A line matched by `search_in_text`.
+pub struct LineMatch {
+    /// 1-based line number.
+    pub line: usize,
+    pub text: String,
+}
+
+/// A digest algorithm supported by `hash`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha1,
+    Md5,
+}
+
+impl HashAlgorithm {
+    /// The names accepted by `from_name`, for error messages.
+    pub const NAMES: &'static str = "sha256, sha1, md5";
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "md5" => Some(HashAlgorithm::Md5),
+            _ => None,
+        }
+    }
+}
+
+/// Compute the lowercase hex digest of a file, reading it in chunks.
+pub fn hash_file(path: &str, algorithm: HashAlgorithm) -> io::Result<String> {
+    match algorithm {
+        HashAlgorithm::Sha256 => hash_reader::<sha2::Sha256>(path),
+        HashAlgorithm::Sha1 => hash_reader::<sha1::Sha1>(path),
+        HashAlgorithm::Md5 => hash_reader::<md5::Md5>(path),
+    }
+}
+
+fn hash_reader<D: Digest>(path: &str) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = D::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// One step of a line diff, holding the positions in both inputs where it applies.
+enum DiffOp {
+    Equal(usize, usize),
+    Delete(usize, usize),
+    Insert(usize, usize),
+}
+
+impl DiffOp {
+    fn positions(&self) -> (usize, usize) {
+        match *self {
+            DiffOp::Equal(i, j) | DiffOp::Delete(i, j) | DiffOp::Insert(i, j) => (i, j),
+        }
+    }
+}
+
+/// Compute a unified diff of two lists of lines, with `context` unchanged lines
+/// around each change. Returns the `@@` hunk headers and `-`/`+`/` ` lines (no
+/// `---`/`+++` file headers); identical inputs give an empty list.
+pub fn diff_lines(a: &[&str], b: &[&str], context: usize) -> Vec<String> {
+    let ops = diff_ops(a, b);
+    // Group changes into hunks, merging those whose context windows touch.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        if matches!(op, DiffOp::Equal(..)) {
+            continue;
+        }
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    let mut out = Vec::new();
+    for (start, end) in hunks {
+        let hunk = &ops[start..end];
+        let (a_before, b_before) = hunk[0].positions();
+        let a_len = hunk
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Insert(..)))
+            .count();
+        let b_len = hunk
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Delete(..)))
+            .count();
+        // An empty side is numbered by the line before it, as in `diff -u`.
+        let a_start = if a_len == 0 { a_before } else { a_before + 1 };
+        let b_start = if b_len == 0 { b_before } else { b_before + 1 };
+        out.push(format!(
+            "@@ -{},{} +{},{} @@",
+            a_start, a_len, b_start, b_len
+        ));
+        for op in hunk {
+            out.push(match *op {
+                DiffOp::Equal(i, _) => format!(" {}", a[i]),
+                DiffOp::Delete(i, _) => format!("-{}", a[i]),
+                DiffOp::Insert(_, j) => format!("+{}", b[j]),
+            });
+        }
+    }
+    out
+}
+
+/// The edit script turning `a` into `b`, from a longest common subsequence.
+fn diff_ops(a: &[&str], b: &[&str]) -> Vec<DiffOp> {
+    // lcs[i][j] is the LCS length of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::new();
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push(DiffOp::Equal(i, j));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(DiffOp::Delete(i, j));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(i, j));
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// How `split` measures chunk size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SplitMode {
+    Lines,
+    Bytes,
+}
+
+/// Split content into chunks of at most `size` lines or bytes, which concatenate
+/// back to the original. For `text` content, byte chunks never end in the middle
+/// of a UTF-8 code point (a chunk may exceed `size` only if one character does).
+pub fn split_chunks(content: &[u8], mode: SplitMode, size: usize, text: bool) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    match mode {
+        SplitMode::Lines => {
+            let mut start = 0;
+            let mut lines = 0;
+            for (i, &b) in content.iter().enumerate() {
+                if b == b'\n' {
+                    lines += 1;
+                    if lines == size {
+                        chunks.push(&content[start..=i]);
+                        start = i + 1;
+                        lines = 0;
+                    }
+                }
+            }
+            if start < content.len() {
+                chunks.push(&content[start..]);
+            }
+        }
+        SplitMode::Bytes => {
+            let is_continuation = |i: usize| text && i < content.len() && content[i] & 0xC0 == 0x80;
+            let mut start = 0;
+            while start < content.len() {
+                let mut end = (start + size).min(content.len());
+                while end > start && is_continuation(end) {
+                    end -= 1;
+                }
+                if end == start {
+                    end = start + 1;
+                    while is_continuation(end) {
+                        end += 1;
+                    }
+                }
+                chunks.push(&content[start..end]);
+                start = end;
+            }
+        }
+    }
+    chunks
+}
+
+/// Prefix every line with its number as `N: `, right-aligned to the width of the
+/// largest line number. A missing final newline stays missing.
+pub fn number_lines(text: &str) -> String {
+    let (lines, trailing_newline) = split_lines(text);
+    let width = lines.len().to_string().len();
+    let numbered: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| format!("{:>width$}: {}", i + 1, line, width = width))
+        .collect();
+    join_lines(&numbered, trailing_newline)
+}
+
+/// Strip trailing whitespace from every line, and with `leading` leading
+/// whitespace too. A missing final newline stays missing.
+pub fn trim_lines(text: &str, leading: bool) -> String {
+    let (lines, trailing_newline) = split_lines(text);
+    let trimmed: Vec<String> = lines
+        .iter()
+        .map(|line| {
+            if leading {
+                line.trim().to_string()
+            } else {
+                line.trim_end().to_string()
+            }
+        })
+        .collect();
+    join_lines(&trimmed, trailing_newline)
+}
+
+/// Sort lines lexicographically, or with `numeric` by the integer each line starts
+/// with (after leading whitespace). Lines without a leading integer sort after the
+/// numbered ones. The sort is stable, so equal lines keep their order.
+pub fn sort_lines(lines: &mut [String], numeric: bool) {
+    if numeric {
+        lines.sort_by_key(|line| {
+            let key = leading_integer(line);
+            (key.is_none(), key)
+        });
+    } else {
+        lines.sort();
+    }
+}
+
+/// The integer at the start of a line, e.g. `-12` in "  -12 apples".
+fn leading_integer(line: &str) -> Option<i128> {
+    let trimmed = line.trim_start();
+    let digits_start = usize::from(trimmed.starts_with(['-', '+']));
+    let end = trimmed[digits_start..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(trimmed.len(), |i| i + digits_start);
+    trimmed[..end].parse().ok()
+}
+
+/// Remove adjacent duplicate lines, or with `all` every repeat of an earlier
+/// line, keeping the first occurrence.
+pub fn uniq_lines(lines: Vec<String>, all: bool) -> Vec<String> {
+    if all {
+        let mut seen = HashSet::new();
+        lines
+            .into_iter()
+            .filter(|line| seen.insert(line.clone()))
+            .collect()
+    } else {
+        let mut lines = lines;
+        lines.dedup();
+        lines
+    }
+}
+
+/// Search for a regex pattern in a text, line by line. Returns the matching lines in order.
+pub fn search_in_text(
+    text: &str,
+    pattern: &str,
+    ignore_case: bool,
+) -> Result<Vec<LineMatch>, regex::Error> {
+    let re = RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()?;
     let mut results = Vec::new();
     for (i, line) in text.lines().enumerate() {
         if re.is_match(line) {
-            results.push((i + 1, line.to_string()));
+            results.push(LineMatch {
+                line: i + 1,
+                text: line.to_string(),
+            });
         }
     }
     Ok(results)
 }
 
-/// Replace a regex pattern in a text with a replacement. Returns the replaced string.
-pub fn replace_in_text(text: &str, pattern: &str, replacement: &str) -> Result<String, String> {
-    let re = Regex::new(pattern).map_err(|e| e.to_string())?;
-    Ok(re.replace_all(text, replacement).to_string())
+/// Search a file for a regex line by line without loading it whole. Lines are
+/// split exactly as `search_in_text` splits the file's normalized content.
+pub fn stream_search(path: &str, pattern: &Regex) -> io::Result<Vec<LineMatch>> {
+    let mut results = Vec::new();
+    for_each_stream_line(path, |number, line| {
+        if pattern.is_match(line) {
+            results.push(LineMatch {
+                line: number,
+                text: line.to_string(),
+            });
+        }
+    })?;
+    Ok(results)
+}
+
+/// Count a file's lines without loading it whole, matching `str::lines` on the
+/// file's normalized content.
+pub fn stream_line_count(path: &str) -> io::Result<usize> {
+    let mut count = 0;
+    for_each_stream_line(path, |_, _| count += 1)?;
+    Ok(count)
+}
+
+/// Call `f` with each 1-based line number and line of a UTF-8 file. Each line
+/// loses its "\n" plus up to two "\r": one for CRLF normalization and one more
+/// that `str::lines` strips.
+fn for_each_stream_line(path: &str, mut f: impl FnMut(usize, &str)) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    let mut number = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        number += 1;
+        let mut text = line.as_str();
+        if let Some(stripped) = text.strip_suffix('\n') {
+            text = stripped;
+            for _ in 0..2 {
+                text = text.strip_suffix('\r').unwrap_or(text);
+            }
+        }
+        f(number, text);
+    }
+}
+
+/// A regex match found by `extract_matches`.
+pub struct MatchInfo {
+    /// 1-based line the match starts on.
+    pub line: usize,
+    /// 1-based column (in characters) the match starts at.
+    pub col: usize,
+    pub text: String,
+}
+
+/// Find every non-overlapping match of a regex in the whole text. If the pattern
+/// has capture groups, group 1 is reported instead of the whole match (matches
+/// where group 1 didn't participate are skipped).
+pub fn extract_matches(text: &str, pattern: &str) -> Result<Vec<MatchInfo>, regex::Error> {
+    let re = Regex::new(pattern)?;
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let mut results = Vec::new();
+    for caps in re.captures_iter(text) {
+        let m = if re.captures_len() > 1 {
+            match caps.get(1) {
+                Some(m) => m,
+                None => continue,
+            }
+        } else {
+            caps.get(0).expect("group 0 always matches")
+        };
+        let line = line_starts.partition_point(|&start| start <= m.start());
+        let line_start = line_starts[line - 1];
+        results.push(MatchInfo {
+            line,
+            col: text[line_start..m.start()].chars().count() + 1,
+            text: m.as_str().to_string(),
+        });
+    }
+    Ok(results)
+}
+
+/// Group matching line numbers (1-based, ascending) with `context` lines around
+/// each, merging windows that overlap or touch. Returns inclusive (first, last)
+/// line ranges, clamped to `total_lines`.
+pub fn context_windows(
+    matches: &[usize],
+    context: usize,
+    total_lines: usize,
+) -> Vec<(usize, usize)> {
+    let mut windows: Vec<(usize, usize)> = Vec::new();
+    for &line in matches {
+        let first = line.saturating_sub(context).max(1);
+        let last = (line + context).min(total_lines);
+        match windows.last_mut() {
+            Some(prev) if first <= prev.1 + 1 => prev.1 = prev.1.max(last),
+            _ => windows.push((first, last)),
+        }
+    }
+    windows
+}
+
+/// How `replace_in_text` applies a pattern.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ReplaceOptions {
+    /// Replace only the first occurrence.
+    pub first_only: bool,
+    /// Only replace within these lines (1-based, inclusive); other lines are untouched.
+    pub lines: Option<(usize, usize)>,
+    /// Treat the pattern and replacement as plain text rather than a regex and
+    /// a `$1`-style template.
+    pub literal: bool,
+}
+
+/// Replace a regex pattern in a text with a replacement. Outside literal mode the
+/// replacement may refer to capture groups as `$1` or `${name}`, and `$$` is a
+/// literal dollar sign. Returns the replaced string and the number of replacements.
+pub fn replace_in_text(
+    text: &str,
+    pattern: &str,
+    replacement: &str,
+    options: &ReplaceOptions,
+) -> Result<(String, usize), regex::Error> {
+    let re = if options.literal {
+        Regex::new(&regex::escape(pattern))?
+    } else {
+        Regex::new(pattern)?
+    };
+    let mut limit = if options.first_only { 1 } else { usize::MAX };
+    let mut count = 0;
+    let mut replace = |haystack: &str| -> String {
+        let found = re.find_iter(haystack).take(limit).count();
+        if found == 0 {
+            return haystack.to_string();
+        }
+        count += found;
+        let n = if options.first_only { 1 } else { 0 };
+        limit -= found;
+        if options.literal {
+            re.replacen(haystack, n, regex::NoExpand(replacement))
+                .into_owned()
+        } else {
+            re.replacen(haystack, n, replacement).into_owned()
+        }
+    };
+    let new_text = match options.lines {
+        None => replace(text),
+        Some((from, to)) => {
+            let mut out = String::with_capacity(text.len());
+            for (i, line) in text.split_inclusive('\n').enumerate() {
+                let body = line.strip_suffix('\n').unwrap_or(line);
+                if (from..=to).contains(&(i + 1)) {
+                    out.push_str(&replace(body));
+                } else {
+                    out.push_str(body);
+                }
+                if line.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+            out
+        }
+    };
+    Ok((new_text, count))
 }